@@ -51,17 +51,6 @@ pub fn get_cookie_value(headers: &HeaderMap, name: &str) -> Option<String> {
         })
 }
 
-pub fn is_authenticated(headers: &HeaderMap, secret: &str) -> Option<String> {
-    get_cookie_value(headers, "auth").and_then(|signed_value| {
-        if verify_signed_cookie(secret, &signed_value) {
-            // Extract username from signed value
-            signed_value.split(':').next().map(|s| s.to_string())
-        } else {
-            None
-        }
-    })
-}
-
 #[derive(Deserialize)]
 pub struct GalleryPreviewQuery {
     count: Option<usize>,
@@ -96,6 +85,7 @@ pub async fn gallery_preview_handler_for_named(
 pub async fn gallery_composite_preview_handler_for_named(
     State(app_state): State<crate::AppState>,
     Path((gallery_name, path)): Path<(String, String)>,
+    headers: HeaderMap,
 ) -> Result<Response, StatusCode> {
     let gallery = app_state.galleries.get(&gallery_name).ok_or_else(|| {
         tracing::error!("Gallery '{}' not found", gallery_name);
@@ -115,17 +105,32 @@ pub async fn gallery_composite_preview_handler_for_named(
 
     // Try to serve from cache first
     if let Ok(cached_response) = gallery
-        .serve_cached_image(&cache_filename, "composite", "")
+        .serve_cached_image(&cache_filename, "composite", &headers)
         .await
     {
         // Only return if it's not a 404 (i.e., cache exists)
         if cached_response.status() != StatusCode::NOT_FOUND {
+            gallery.note_cache_hit(&cache_filename, &gallery_path).await;
             return Ok(cached_response);
         }
         // Otherwise, fall through to generate the composite
     }
 
-    // Not in cache, need to generate it
+    // Not in cache, need to generate it, unless a request already doing so
+    // finishes while we wait
+    let cache_path = gallery.get_config().cache_directory.join(&cache_filename);
+    let _flight = crate::gallery::image_processing::workers::claim(&cache_path).await;
+    if cache_path.exists() {
+        gallery.note_cache_hit(&cache_filename, &gallery_path).await;
+        return gallery
+            .serve_cached_image(&cache_filename, "composite", &headers)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to serve composite: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            });
+    }
+
     // List directory to get images
     let (_, images, _) = gallery
         .list_directory(&gallery_path, 0)
@@ -135,8 +140,12 @@ pub async fn gallery_composite_preview_handler_for_named(
             StatusCode::NOT_FOUND
         })?;
 
-    // Take up to 4 images for a 2x2 grid
-    let preview_images: Vec<_> = images.into_iter().take(4).collect();
+    // Take up to 4 images for a 2x2 grid, leaving out videos
+    let preview_images: Vec<_> = images
+        .into_iter()
+        .filter(|image| image.video.is_none())
+        .take(4)
+        .collect();
 
     if preview_images.is_empty() {
         return Err(StatusCode::NOT_FOUND);
@@ -144,7 +153,7 @@ pub async fn gallery_composite_preview_handler_for_named(
 
     // Create composite image in a blocking task
     let source_dir = gallery.source_directory().to_path_buf();
-    let composite_result = tokio::task::spawn_blocking(move || {
+    let composite_result = crate::gallery::image_processing::workers::run_blocking(move || {
         crate::composite::create_composite_preview(source_dir, preview_images)
     })
     .await
@@ -159,13 +168,17 @@ pub async fn gallery_composite_preview_handler_for_named(
     })?;
 
     // Store in cache and serve
-    gallery
+    let response = gallery
         .store_and_serve_composite(&composite_cache_key, composite_image)
         .await
         .map_err(|e| {
             tracing::error!("Failed to store composite in cache: {}", e);
             StatusCode::INTERNAL_SERVER_ERROR
-        })
+        })?;
+    gallery
+        .track_cached_file(&cache_filename, &gallery_path)
+        .await;
+    Ok(response)
 }
 
 #[derive(Debug, Serialize)]
@@ -186,11 +199,17 @@ pub async fn refresh_static_versions(
         }));
     }
 
-    // Check if user is authenticated
-    if !crate::login::is_authenticated(&headers, &app_state.config.app.cookie_secret) {
+    // Only admins may refresh
+    if !crate::login::is_admin(
+        &headers,
+        &app_state.sessions,
+        app_state.user_database_manager.as_ref(),
+    )
+    .await
+    {
         return Ok(Json(RefreshResponse {
             success: false,
-            message: "Authentication required".to_string(),
+            message: "Admin role required".to_string(),
         }));
     }
 
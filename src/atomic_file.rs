@@ -0,0 +1,34 @@
+// Writing files so readers never see them half-written: the new contents go
+// to a temporary file next to the target, which is then renamed over it
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::fs;
+
+/// A unique file next to `path` to write into before renaming it over `path`
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    path.with_file_name(name)
+}
+
+/// Write to a temporary file first so a crash can't leave a truncated file.
+/// The temporary name is unique, so concurrent writers never share one.
+pub(crate) async fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), std::io::Error> {
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent).await?;
+    }
+    let temp_path = temp_path(path);
+    if let Err(e) = fs::write(&temp_path, contents).await {
+        let _ = fs::remove_file(&temp_path).await;
+        return Err(e);
+    }
+    fs::rename(&temp_path, path).await
+}
@@ -1,10 +1,26 @@
 use image::{DynamicImage, ImageBuffer, Rgba};
 use std::path::{Path, PathBuf};
 
-/// Load an image from a path, with AVIF support
+/// Load an image from a path, with AVIF support, turned upright according to
+/// its EXIF orientation
 fn load_image_with_avif_support(
     path: &Path,
 ) -> Result<DynamicImage, Box<dyn std::error::Error + Send + Sync>> {
+    let orientation = crate::gallery::image_processing::orientation::read_orientation(path);
+    load_image_unoriented(path).map(|img| orientation.apply(img))
+}
+
+fn load_image_unoriented(
+    path: &Path,
+) -> Result<DynamicImage, Box<dyn std::error::Error + Send + Sync>> {
+    // JPEG XL needs libjxl, which also turns the image upright, leaving the
+    // (absent) EXIF orientation nothing to do
+    #[cfg(feature = "jxl")]
+    if crate::gallery::image_processing::formats::jxl::is_jxl_file(path) {
+        let (img, _icc_profile) = crate::gallery::image_processing::formats::jxl::read_jxl(path)?;
+        return Ok(img);
+    }
+
     // First try the standard image::open
     match image::open(path) {
         Ok(img) => Ok(img),
@@ -126,6 +142,8 @@ mod tests {
             dimensions: Some((800, 600)),
             capture_date: None,
             is_new: false,
+            placeholder: None,
+            video: None,
         }
     }
 
@@ -0,0 +1,341 @@
+use crate::AppState;
+use crate::gallery::encode_folder_path;
+use crate::sitemap::{sitemap_base_url, xml_escape};
+use axum::{
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::time::SystemTime;
+
+/// Number of entries included in each feed
+const FEED_ENTRY_LIMIT: usize = 50;
+
+struct FeedEntry {
+    title: String,
+    url: String,
+    summary: Option<String>,
+    content_html: String,
+    published: DateTime<Utc>,
+    updated: DateTime<Utc>,
+}
+
+struct Feed {
+    title: String,
+    /// URL of the HTML page this feed mirrors
+    link: String,
+    /// URL of the feed document itself
+    self_link: String,
+    entries: Vec<FeedEntry>,
+}
+
+/// Handler for {url_prefix}/feed.xml on a posts system
+pub async fn posts_atom_handler(
+    State(app_state): State<AppState>,
+    Path(posts_name): Path<String>,
+) -> Response {
+    match build_posts_feed(&app_state, &posts_name, "feed.xml").await {
+        Some(feed) => feed_response(render_atom(&feed), "application/atom+xml"),
+        None => (StatusCode::NOT_FOUND, "Posts section not found").into_response(),
+    }
+}
+
+/// Handler for {url_prefix}/rss.xml on a posts system
+pub async fn posts_rss_handler(
+    State(app_state): State<AppState>,
+    Path(posts_name): Path<String>,
+) -> Response {
+    match build_posts_feed(&app_state, &posts_name, "rss.xml").await {
+        Some(feed) => feed_response(render_rss(&feed), "application/rss+xml"),
+        None => (StatusCode::NOT_FOUND, "Posts section not found").into_response(),
+    }
+}
+
+/// Handler for {url_prefix}/feed.xml on a gallery, listing recently added images.
+/// Only images that anonymous visitors can see are included.
+pub async fn gallery_atom_handler(
+    State(app_state): State<AppState>,
+    Path(gallery_name): Path<String>,
+) -> Response {
+    let gallery = match app_state.galleries.get(&gallery_name) {
+        Some(gallery) => gallery,
+        None => return (StatusCode::NOT_FOUND, "Gallery not found").into_response(),
+    };
+
+    let base_url = sitemap_base_url(&app_state);
+    let gallery_config = gallery.get_config();
+    let prefix = gallery_config.url_prefix.trim_end_matches('/');
+
+    let mut images: Vec<_> = gallery
+        .list_public_entries()
+        .await
+        .into_iter()
+        .filter(|entry| !entry.is_directory)
+        .filter_map(|entry| entry.last_modified.map(|modified| (entry.path, modified)))
+        .collect();
+    images.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    images.truncate(FEED_ENTRY_LIMIT);
+
+    let entries = images
+        .into_iter()
+        .map(|(path, modified)| {
+            let encoded_path = encode_folder_path(&path);
+            let url = format!("{}{}/detail/{}", base_url, prefix, encoded_path);
+            let image_url = format!("{}{}/image/{}?size=gallery", base_url, prefix, encoded_path);
+            let name = path.rsplit('/').next().unwrap_or(&path).to_string();
+            let content_html = format!(
+                r#"<a href="{}"><img src="{}" alt="{}"></a>"#,
+                xml_escape(&url),
+                xml_escape(&image_url),
+                xml_escape(&name)
+            );
+            let modified = DateTime::<Utc>::from(modified);
+
+            FeedEntry {
+                title: name,
+                url,
+                summary: None,
+                content_html,
+                published: modified,
+                updated: modified,
+            }
+        })
+        .collect();
+
+    let feed = Feed {
+        title: format!("{} - {}", app_state.config.app.name, gallery_config.name),
+        link: format!("{}{}", base_url, prefix),
+        self_link: format!("{}{}/feed.xml", base_url, prefix),
+        entries,
+    };
+
+    feed_response(render_atom(&feed), "application/atom+xml")
+}
+
+async fn build_posts_feed(app_state: &AppState, posts_name: &str, file_name: &str) -> Option<Feed> {
+    let posts_manager = app_state.posts_managers.get(posts_name)?;
+    let base_url = sitemap_base_url(app_state);
+    let prefix = posts_manager
+        .get_config()
+        .url_prefix
+        .trim_end_matches('/')
+        .to_string();
+
+    let mut posts = posts_manager.get_all_posts().await;
+    posts.truncate(FEED_ENTRY_LIMIT);
+
+    let entries = posts
+        .into_iter()
+        .map(|post| FeedEntry {
+            url: format!("{}{}/{}", base_url, prefix, post.slug),
+            content_html: absolutize_urls(&post.html_content, &base_url),
+            summary: Some(post.summary).filter(|s| !s.is_empty()),
+            published: post.date,
+            updated: post
+                .last_modified
+                .map(DateTime::<Utc>::from)
+                .unwrap_or(post.date),
+            title: post.title,
+        })
+        .collect();
+
+    Some(Feed {
+        title: format!("{} - {}", app_state.config.app.name, posts_name),
+        link: format!("{}{}", base_url, prefix),
+        self_link: format!("{}{}/{}", base_url, prefix, file_name),
+        entries,
+    })
+}
+
+/// Rewrite root-relative `href` and `src` attributes so links keep working when
+/// the HTML is shown by a feed reader on another origin.
+fn absolutize_urls(html: &str, base_url: &str) -> String {
+    let mut result = html.to_string();
+    for attribute in ["href=\"", "src=\""] {
+        let mut rewritten = String::with_capacity(result.len());
+        let mut rest = result.as_str();
+        while let Some(index) = rest.find(attribute) {
+            let value_start = index + attribute.len();
+            rewritten.push_str(&rest[..value_start]);
+            rest = &rest[value_start..];
+            // Protocol-relative URLs ("//host/...") are already absolute
+            if rest.starts_with('/') && !rest.starts_with("//") {
+                rewritten.push_str(base_url);
+            }
+        }
+        rewritten.push_str(rest);
+        result = rewritten;
+    }
+    result
+}
+
+fn render_atom(feed: &Feed) -> String {
+    let updated = feed
+        .entries
+        .iter()
+        .map(|entry| entry.updated)
+        .max()
+        .unwrap_or_else(|| DateTime::<Utc>::from(SystemTime::UNIX_EPOCH));
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+    );
+    xml.push_str(&format!("  <title>{}</title>\n", xml_escape(&feed.title)));
+    xml.push_str(&format!("  <id>{}</id>\n", xml_escape(&feed.link)));
+    xml.push_str(&format!(
+        "  <link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>\n",
+        xml_escape(&feed.link)
+    ));
+    xml.push_str(&format!(
+        "  <link rel=\"self\" type=\"application/atom+xml\" href=\"{}\"/>\n",
+        xml_escape(&feed.self_link)
+    ));
+    xml.push_str(&format!(
+        "  <updated>{}</updated>\n",
+        format_atom_date(updated)
+    ));
+
+    for entry in &feed.entries {
+        xml.push_str("  <entry>\n");
+        xml.push_str(&format!(
+            "    <title>{}</title>\n",
+            xml_escape(&entry.title)
+        ));
+        xml.push_str(&format!("    <id>{}</id>\n", xml_escape(&entry.url)));
+        xml.push_str(&format!(
+            "    <link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>\n",
+            xml_escape(&entry.url)
+        ));
+        xml.push_str(&format!(
+            "    <published>{}</published>\n",
+            format_atom_date(entry.published)
+        ));
+        xml.push_str(&format!(
+            "    <updated>{}</updated>\n",
+            format_atom_date(entry.updated)
+        ));
+        if let Some(summary) = &entry.summary {
+            xml.push_str(&format!("    <summary>{}</summary>\n", xml_escape(summary)));
+        }
+        xml.push_str(&format!(
+            "    <content type=\"html\">{}</content>\n",
+            xml_escape(&entry.content_html)
+        ));
+        xml.push_str("  </entry>\n");
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn render_rss(feed: &Feed) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n",
+    );
+    xml.push_str("  <channel>\n");
+    xml.push_str(&format!("    <title>{}</title>\n", xml_escape(&feed.title)));
+    xml.push_str(&format!("    <link>{}</link>\n", xml_escape(&feed.link)));
+    xml.push_str(&format!(
+        "    <description>{}</description>\n",
+        xml_escape(&feed.title)
+    ));
+    xml.push_str(&format!(
+        "    <atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+        xml_escape(&feed.self_link)
+    ));
+    if let Some(newest) = feed.entries.iter().map(|entry| entry.updated).max() {
+        xml.push_str(&format!(
+            "    <lastBuildDate>{}</lastBuildDate>\n",
+            newest.to_rfc2822()
+        ));
+    }
+
+    for entry in &feed.entries {
+        xml.push_str("    <item>\n");
+        xml.push_str(&format!(
+            "      <title>{}</title>\n",
+            xml_escape(&entry.title)
+        ));
+        xml.push_str(&format!("      <link>{}</link>\n", xml_escape(&entry.url)));
+        xml.push_str(&format!(
+            "      <guid isPermaLink=\"true\">{}</guid>\n",
+            xml_escape(&entry.url)
+        ));
+        xml.push_str(&format!(
+            "      <pubDate>{}</pubDate>\n",
+            entry.published.to_rfc2822()
+        ));
+        xml.push_str(&format!(
+            "      <description>{}</description>\n",
+            xml_escape(&entry.content_html)
+        ));
+        xml.push_str("    </item>\n");
+    }
+
+    xml.push_str("  </channel>\n</rss>\n");
+    xml
+}
+
+fn format_atom_date(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn feed_response(body: String, content_type: &str) -> Response {
+    (
+        StatusCode::OK,
+        [(
+            header::CONTENT_TYPE,
+            format!("{}; charset=utf-8", content_type),
+        )],
+        body,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_absolutize_urls() {
+        let html = r#"<a href="/gallery/detail/a.jpg"><img src="/gallery/image/a.jpg?size=gallery"></a> <a href="https://other.example/x">x</a> <img src="//cdn.example/y.png">"#;
+        let result = absolutize_urls(html, "https://example.com");
+
+        assert!(result.contains(r#"href="https://example.com/gallery/detail/a.jpg""#));
+        assert!(result.contains(r#"src="https://example.com/gallery/image/a.jpg?size=gallery""#));
+        assert!(result.contains(r#"href="https://other.example/x""#));
+        assert!(result.contains(r#"src="//cdn.example/y.png""#));
+    }
+
+    #[test]
+    fn test_render_atom_and_rss() {
+        let date = DateTime::parse_from_rfc3339("2024-01-15T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let feed = Feed {
+            title: "Site - blog".to_string(),
+            link: "https://example.com/blog".to_string(),
+            self_link: "https://example.com/blog/feed.xml".to_string(),
+            entries: vec![FeedEntry {
+                title: "Fish & Chips".to_string(),
+                url: "https://example.com/blog/fish".to_string(),
+                summary: Some("Tasty".to_string()),
+                content_html: "<p>Hello</p>".to_string(),
+                published: date,
+                updated: date,
+            }],
+        };
+
+        let atom = render_atom(&feed);
+        assert!(atom.contains("<title>Fish &amp; Chips</title>"));
+        assert!(atom.contains("<updated>2024-01-15T00:00:00Z</updated>"));
+        assert!(atom.contains("<content type=\"html\">&lt;p&gt;Hello&lt;/p&gt;</content>"));
+
+        let rss = render_rss(&feed);
+        assert!(rss.contains("<link>https://example.com/blog/fish</link>"));
+        assert!(rss.contains("<pubDate>Mon, 15 Jan 2024 00:00:00 +0000</pubDate>"));
+    }
+}
@@ -0,0 +1,459 @@
+use axum::{
+    body::Body,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use std::io::SeekFrom;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use tracing::debug;
+
+/// Requests asking for more ranges than this get the whole file instead
+const MAX_RANGES: usize = 32;
+
+/// Serve a file from disk with `ETag` and `Last-Modified` validators, answering
+/// `If-None-Match`/`If-Modified-Since` with 304 and `Range` (honoring
+/// `If-Range`) with single or multipart 206 responses.
+pub async fn serve_file(
+    path: &Path,
+    content_type: &str,
+    cache_control: &str,
+    request_headers: &HeaderMap,
+) -> Response {
+    let file = match File::open(path).await {
+        Ok(file) => file,
+        Err(e) => {
+            debug!("Failed to open file {:?}: {}", path, e);
+            return (StatusCode::NOT_FOUND, "File not found").into_response();
+        }
+    };
+    let metadata = match file.metadata().await {
+        Ok(metadata) => metadata,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let len = metadata.len();
+    let modified = metadata.modified().ok();
+    let etag = modified.and_then(|modified| etag_for(modified, len));
+
+    let mut headers = HeaderMap::new();
+    if let Ok(value) = HeaderValue::from_str(cache_control) {
+        headers.insert(header::CACHE_CONTROL, value);
+    }
+    if let Some(etag) = etag.as_deref()
+        && let Ok(value) = HeaderValue::from_str(etag)
+    {
+        headers.insert(header::ETAG, value);
+    }
+    if let Some(modified) = modified
+        && let Ok(value) = HeaderValue::from_str(&httpdate::fmt_http_date(modified))
+    {
+        headers.insert(header::LAST_MODIFIED, value);
+    }
+
+    if is_not_modified(request_headers, etag.as_deref(), modified) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    let range = request_headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .filter(|_| if_range_matches(request_headers, etag.as_deref(), modified))
+        .map(|value| parse_range(value, len))
+        .unwrap_or(RangeRequest::Full);
+
+    match range {
+        RangeRequest::Full => {
+            insert_header(&mut headers, header::CONTENT_TYPE, content_type);
+            insert_header(&mut headers, header::CONTENT_LENGTH, &len.to_string());
+            let body = Body::from_stream(ReaderStream::new(file));
+            (StatusCode::OK, headers, body).into_response()
+        }
+        RangeRequest::Unsatisfiable => {
+            insert_header(
+                &mut headers,
+                header::CONTENT_RANGE,
+                &format!("bytes */{}", len),
+            );
+            (StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response()
+        }
+        RangeRequest::Ranges(ranges) if ranges.len() == 1 => {
+            let (start, end) = ranges[0];
+            let reader = match open_range(file, start, end).await {
+                Ok(reader) => reader,
+                Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            };
+            insert_header(&mut headers, header::CONTENT_TYPE, content_type);
+            insert_header(
+                &mut headers,
+                header::CONTENT_RANGE,
+                &format!("bytes {}-{}/{}", start, end, len),
+            );
+            insert_header(
+                &mut headers,
+                header::CONTENT_LENGTH,
+                &(end - start + 1).to_string(),
+            );
+            let body = Body::from_stream(ReaderStream::new(reader));
+            (StatusCode::PARTIAL_CONTENT, headers, body).into_response()
+        }
+        RangeRequest::Ranges(ranges) => {
+            let boundary = format!("{:016x}", rand::random::<u64>());
+            let mut reader: Box<dyn AsyncRead + Send + Unpin> = Box::new(tokio::io::empty());
+            let mut body_len = 0u64;
+
+            // Each part reads through its own handle, since clones would
+            // share a single file position
+            for (start, end) in ranges {
+                let part_header = format!(
+                    "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    boundary, content_type, start, end, len
+                );
+                let part = match File::open(path).await {
+                    Ok(part_file) => match open_range(part_file, start, end).await {
+                        Ok(part) => part,
+                        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+                    },
+                    Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+                };
+                body_len += part_header.len() as u64 + (end - start + 1);
+                reader = Box::new(
+                    reader
+                        .chain(std::io::Cursor::new(part_header.into_bytes()))
+                        .chain(part),
+                );
+            }
+            let closing = format!("\r\n--{}--\r\n", boundary);
+            body_len += closing.len() as u64;
+            reader = Box::new(reader.chain(std::io::Cursor::new(closing.into_bytes())));
+
+            insert_header(
+                &mut headers,
+                header::CONTENT_TYPE,
+                &format!("multipart/byteranges; boundary={}", boundary),
+            );
+            insert_header(&mut headers, header::CONTENT_LENGTH, &body_len.to_string());
+            let body = Body::from_stream(ReaderStream::new(reader));
+            (StatusCode::PARTIAL_CONTENT, headers, body).into_response()
+        }
+    }
+}
+
+fn insert_header(headers: &mut HeaderMap, name: header::HeaderName, value: &str) {
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(name, value);
+    }
+}
+
+/// Strong validator built from modification time and size
+fn etag_for(modified: SystemTime, len: u64) -> Option<String> {
+    let duration = modified.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("\"{}-{}\"", duration.as_secs(), len))
+}
+
+/// HTTP dates have one-second resolution
+fn truncate_to_seconds(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|duration| duration.as_secs())
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: header::HeaderName) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Tags compare weakly here: `W/"x"` matches `"x"`
+fn etag_list_matches(list: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    list.split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+/// `If-None-Match` takes precedence; `If-Modified-Since` only counts without it
+fn is_not_modified(
+    request_headers: &HeaderMap,
+    etag: Option<&str>,
+    modified: Option<SystemTime>,
+) -> bool {
+    if let Some(if_none_match) = header_str(request_headers, header::IF_NONE_MATCH) {
+        return etag.is_some_and(|etag| etag_list_matches(if_none_match, etag));
+    }
+
+    if let Some(since) = header_str(request_headers, header::IF_MODIFIED_SINCE)
+        && let Ok(since) = httpdate::parse_http_date(since)
+        && let (Some(modified), Some(since)) = (
+            modified.and_then(truncate_to_seconds),
+            truncate_to_seconds(since),
+        )
+    {
+        return modified <= since;
+    }
+
+    false
+}
+
+/// A `Range` only applies if `If-Range` is absent or still names this exact
+/// version of the file (strong tag comparison, or an equal date)
+fn if_range_matches(
+    request_headers: &HeaderMap,
+    etag: Option<&str>,
+    modified: Option<SystemTime>,
+) -> bool {
+    let Some(if_range) = header_str(request_headers, header::IF_RANGE) else {
+        return true;
+    };
+
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return etag.is_some_and(|etag| etag == if_range);
+    }
+
+    match httpdate::parse_http_date(if_range) {
+        Ok(date) => {
+            let modified = modified.and_then(truncate_to_seconds);
+            modified.is_some() && modified == truncate_to_seconds(date)
+        }
+        Err(_) => false,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RangeRequest {
+    /// No usable `Range` header, serve everything
+    Full,
+    /// Byte ranges, inclusive, in ascending order without overlaps
+    Ranges(Vec<(u64, u64)>),
+    /// Valid syntax but nothing inside the file
+    Unsatisfiable,
+}
+
+/// Parse a `Range: bytes=...` header against a file of `len` bytes.
+/// Malformed headers and other units are ignored, overlapping or adjacent
+/// ranges are merged.
+fn parse_range(value: &str, len: u64) -> RangeRequest {
+    let Some((unit, specs)) = value.split_once('=') else {
+        return RangeRequest::Full;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return RangeRequest::Full;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let Some((start, end)) = spec.split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let range = if start.is_empty() {
+            // Suffix range: the last `end` bytes
+            let Ok(suffix) = end.parse::<u64>() else {
+                return RangeRequest::Full;
+            };
+            if suffix == 0 || len == 0 {
+                continue;
+            }
+            (len.saturating_sub(suffix), len - 1)
+        } else {
+            let Ok(start) = start.parse::<u64>() else {
+                return RangeRequest::Full;
+            };
+            let end = if end.is_empty() {
+                u64::MAX
+            } else {
+                match end.parse::<u64>() {
+                    Ok(end) if end >= start => end,
+                    _ => return RangeRequest::Full,
+                }
+            };
+            if start >= len {
+                continue;
+            }
+            (start, end.min(len - 1))
+        };
+        ranges.push(range);
+    }
+
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+    if ranges.len() > MAX_RANGES {
+        return RangeRequest::Full;
+    }
+
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    RangeRequest::Ranges(merged)
+}
+
+async fn open_range(
+    mut file: File,
+    start: u64,
+    end: u64,
+) -> std::io::Result<tokio::io::Take<File>> {
+    file.seek(SeekFrom::Start(start)).await?;
+    Ok(file.take(end - start + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(
+            parse_range("bytes=0-9", 100),
+            RangeRequest::Ranges(vec![(0, 9)])
+        );
+        assert_eq!(
+            parse_range("bytes=90-", 100),
+            RangeRequest::Ranges(vec![(90, 99)])
+        );
+        assert_eq!(
+            parse_range("bytes=-10", 100),
+            RangeRequest::Ranges(vec![(90, 99)])
+        );
+        assert_eq!(
+            parse_range("bytes=-500", 100),
+            RangeRequest::Ranges(vec![(0, 99)])
+        );
+        assert_eq!(
+            parse_range("bytes=50-500", 100),
+            RangeRequest::Ranges(vec![(50, 99)])
+        );
+        assert_eq!(
+            parse_range("bytes=0-4, 20-29", 100),
+            RangeRequest::Ranges(vec![(0, 4), (20, 29)])
+        );
+        // Overlapping and adjacent ranges are merged
+        assert_eq!(
+            parse_range("bytes=20-29,0-9,10-14,25-40", 100),
+            RangeRequest::Ranges(vec![(0, 14), (20, 40)])
+        );
+
+        assert_eq!(parse_range("bytes=100-", 100), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 100), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), RangeRequest::Unsatisfiable);
+
+        assert_eq!(parse_range("items=0-9", 100), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=9-0", 100), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=abc", 100), RangeRequest::Full);
+    }
+
+    #[test]
+    fn test_conditional_headers() {
+        let modified = UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let etag = etag_for(modified, 42).unwrap();
+        assert_eq!(etag, "\"1700000000-42\"");
+
+        let mut headers = HeaderMap::new();
+        assert!(!is_not_modified(&headers, Some(&etag), Some(modified)));
+
+        headers.insert(
+            header::IF_NONE_MATCH,
+            "\"other\", W/\"1700000000-42\"".parse().unwrap(),
+        );
+        assert!(is_not_modified(&headers, Some(&etag), Some(modified)));
+
+        // If-None-Match wins over a matching If-Modified-Since
+        headers.insert(header::IF_NONE_MATCH, "\"other\"".parse().unwrap());
+        headers.insert(
+            header::IF_MODIFIED_SINCE,
+            httpdate::fmt_http_date(modified).parse().unwrap(),
+        );
+        assert!(!is_not_modified(&headers, Some(&etag), Some(modified)));
+        headers.remove(header::IF_NONE_MATCH);
+        assert!(is_not_modified(&headers, Some(&etag), Some(modified)));
+
+        let earlier = modified - std::time::Duration::from_secs(60);
+        headers.insert(
+            header::IF_MODIFIED_SINCE,
+            httpdate::fmt_http_date(earlier).parse().unwrap(),
+        );
+        assert!(!is_not_modified(&headers, Some(&etag), Some(modified)));
+
+        // If-Range needs a strong match
+        let mut headers = HeaderMap::new();
+        assert!(if_range_matches(&headers, Some(&etag), Some(modified)));
+        headers.insert(header::IF_RANGE, etag.parse().unwrap());
+        assert!(if_range_matches(&headers, Some(&etag), Some(modified)));
+        headers.insert(header::IF_RANGE, "W/\"1700000000-42\"".parse().unwrap());
+        assert!(!if_range_matches(&headers, Some(&etag), Some(modified)));
+        headers.insert(
+            header::IF_RANGE,
+            httpdate::fmt_http_date(earlier).parse().unwrap(),
+        );
+        assert!(!if_range_matches(&headers, Some(&etag), Some(modified)));
+    }
+
+    #[tokio::test]
+    async fn test_serve_file_ranges() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("data.bin");
+        let data: Vec<u8> = (0..100u8).collect();
+        tokio::fs::write(&path, &data).await.unwrap();
+
+        let body_bytes = |response: Response| async move {
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap()
+        };
+
+        let response = serve_file(&path, "image/jpeg", "public", &HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+        let etag = response.headers()[header::ETAG].clone();
+        assert_eq!(body_bytes(response).await.as_ref(), data.as_slice());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag);
+        let response = serve_file(&path, "image/jpeg", "public", &headers).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, "bytes=10-19".parse().unwrap());
+        let response = serve_file(&path, "image/jpeg", "public", &headers).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 10-19/100");
+        assert_eq!(body_bytes(response).await.as_ref(), &data[10..20]);
+
+        headers.insert(header::RANGE, "bytes=0-1,98-".parse().unwrap());
+        let response = serve_file(&path, "image/jpeg", "public", &headers).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let content_type = response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let content_length: usize = response.headers()[header::CONTENT_LENGTH]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap()
+            .to_string();
+        let body = body_bytes(response).await;
+        assert_eq!(body.len(), content_length);
+        let expected = format!(
+            "\r\n--{b}\r\nContent-Type: image/jpeg\r\nContent-Range: bytes 0-1/100\r\n\r\n\x00\x01\
+             \r\n--{b}\r\nContent-Type: image/jpeg\r\nContent-Range: bytes 98-99/100\r\n\r\nbc\
+             \r\n--{b}--\r\n",
+            b = boundary
+        );
+        assert_eq!(body.as_ref(), expected.as_bytes());
+
+        headers.insert(header::RANGE, "bytes=200-".parse().unwrap());
+        let response = serve_file(&path, "image/jpeg", "public", &headers).await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */100");
+    }
+}
@@ -0,0 +1,507 @@
+use super::Gallery;
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, Timelike};
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::warn;
+
+const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x0807_4b50;
+const CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0201_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4b50;
+
+/// Sizes and CRC follow each entry's data (bit 3), names are UTF-8 (bit 11)
+const ENTRY_FLAGS: u16 = 0x0808;
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+
+/// Images are already compressed, so entries are stored as-is
+const METHOD_STORED: u16 = 0;
+
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+struct CentralDirectoryEntry {
+    name: String,
+    dos_time: u16,
+    dos_date: u16,
+    crc: u32,
+    size: u64,
+    offset: u64,
+    /// Sizes are in the ZIP64 extra field, as the local header announced
+    zip64_size: bool,
+}
+
+/// Minimal streaming ZIP writer. Entries are written as they are read, with
+/// their sizes in a trailing data descriptor, so nothing is buffered beyond a
+/// copy buffer and the central directory. Entries and archives past 4 GiB get
+/// ZIP64 records.
+pub(crate) struct ZipWriter<W> {
+    writer: W,
+    offset: u64,
+    entries: Vec<CentralDirectoryEntry>,
+}
+
+impl<W: AsyncWrite + Unpin> ZipWriter<W> {
+    pub(crate) fn new(writer: W) -> Self {
+        Self {
+            writer,
+            offset: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes).await?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Copy everything from `reader` into the archive as `name`. The local
+    /// header has to say up front whether the sizes need ZIP64 fields, so
+    /// `expected_size` is what the reader is about to yield.
+    pub(crate) async fn add_entry<R: AsyncRead + Unpin>(
+        &mut self,
+        name: &str,
+        modified: NaiveDateTime,
+        expected_size: u64,
+        mut reader: R,
+    ) -> io::Result<()> {
+        let (dos_time, dos_date) = dos_date_time(modified);
+        let offset = self.offset;
+        let zip64_size = expected_size >= u32::MAX as u64;
+
+        let mut header = Vec::with_capacity(50 + name.len());
+        header.extend_from_slice(&LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes());
+        header.extend_from_slice(
+            &(if zip64_size {
+                VERSION_ZIP64
+            } else {
+                VERSION_DEFAULT
+            })
+            .to_le_bytes(),
+        );
+        header.extend_from_slice(&ENTRY_FLAGS.to_le_bytes());
+        header.extend_from_slice(&METHOD_STORED.to_le_bytes());
+        header.extend_from_slice(&dos_time.to_le_bytes());
+        header.extend_from_slice(&dos_date.to_le_bytes());
+        // CRC, compressed and uncompressed size live in the data descriptor
+        header.extend_from_slice(&0u32.to_le_bytes());
+        if zip64_size {
+            header.extend_from_slice(&u32::MAX.to_le_bytes());
+            header.extend_from_slice(&u32::MAX.to_le_bytes());
+        } else {
+            header.extend_from_slice(&[0; 8]);
+        }
+        header.extend_from_slice(&name_length(name)?.to_le_bytes());
+        header.extend_from_slice(&(if zip64_size { 20u16 } else { 0 }).to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+        if zip64_size {
+            header.extend_from_slice(&ZIP64_EXTRA_FIELD_ID.to_le_bytes());
+            header.extend_from_slice(&16u16.to_le_bytes());
+            header.extend_from_slice(&[0; 16]);
+        }
+        self.write(&header).await?;
+
+        let mut crc = flate2::Crc::new();
+        let mut size: u64 = 0;
+        let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+        loop {
+            let read = reader.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            crc.update(&buffer[..read]);
+            size += read as u64;
+            self.write(&buffer[..read]).await?;
+        }
+
+        // Only reachable when the file grew while it was being sent
+        if size >= u32::MAX as u64 && !zip64_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} grew past 4 GiB while being archived", name),
+            ));
+        }
+
+        let mut descriptor = Vec::with_capacity(24);
+        descriptor.extend_from_slice(&DATA_DESCRIPTOR_SIGNATURE.to_le_bytes());
+        descriptor.extend_from_slice(&crc.sum().to_le_bytes());
+        if zip64_size {
+            descriptor.extend_from_slice(&size.to_le_bytes());
+            descriptor.extend_from_slice(&size.to_le_bytes());
+        } else {
+            descriptor.extend_from_slice(&(size as u32).to_le_bytes());
+            descriptor.extend_from_slice(&(size as u32).to_le_bytes());
+        }
+        self.write(&descriptor).await?;
+
+        self.entries.push(CentralDirectoryEntry {
+            name: name.to_string(),
+            dos_time,
+            dos_date,
+            crc: crc.sum(),
+            size,
+            offset,
+            zip64_size,
+        });
+        Ok(())
+    }
+
+    /// Write the central directory and hand back the underlying writer
+    pub(crate) async fn finish(mut self) -> io::Result<W> {
+        let central_directory_start = self.offset;
+
+        let entries = std::mem::take(&mut self.entries);
+        for entry in &entries {
+            let zip64_offset = entry.offset >= u32::MAX as u64;
+            let zip64 = entry.zip64_size || zip64_offset;
+            let version = if zip64 {
+                VERSION_ZIP64
+            } else {
+                VERSION_DEFAULT
+            };
+
+            // ZIP64 values in their fixed order, for each field marked 0xFFFFFFFF
+            let mut extra = Vec::with_capacity(28);
+            if zip64 {
+                let mut values = Vec::new();
+                if entry.zip64_size {
+                    values.extend_from_slice(&entry.size.to_le_bytes());
+                    values.extend_from_slice(&entry.size.to_le_bytes());
+                }
+                if zip64_offset {
+                    values.extend_from_slice(&entry.offset.to_le_bytes());
+                }
+                extra.extend_from_slice(&ZIP64_EXTRA_FIELD_ID.to_le_bytes());
+                extra.extend_from_slice(&(values.len() as u16).to_le_bytes());
+                extra.extend_from_slice(&values);
+            }
+            let stored_size = if entry.zip64_size {
+                u32::MAX
+            } else {
+                entry.size as u32
+            };
+
+            let mut header = Vec::with_capacity(46 + entry.name.len() + extra.len());
+            header.extend_from_slice(&CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+            header.extend_from_slice(&VERSION_ZIP64.to_le_bytes()); // version made by
+            header.extend_from_slice(&version.to_le_bytes()); // version needed
+            header.extend_from_slice(&ENTRY_FLAGS.to_le_bytes());
+            header.extend_from_slice(&METHOD_STORED.to_le_bytes());
+            header.extend_from_slice(&entry.dos_time.to_le_bytes());
+            header.extend_from_slice(&entry.dos_date.to_le_bytes());
+            header.extend_from_slice(&entry.crc.to_le_bytes());
+            header.extend_from_slice(&stored_size.to_le_bytes());
+            header.extend_from_slice(&stored_size.to_le_bytes());
+            header.extend_from_slice(&name_length(&entry.name)?.to_le_bytes());
+            header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+            header.extend_from_slice(&0u16.to_le_bytes()); // comment length
+            header.extend_from_slice(&0u16.to_le_bytes()); // disk number
+            header.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+            header.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+            header.extend_from_slice(&clamp_u32(entry.offset).to_le_bytes());
+            header.extend_from_slice(entry.name.as_bytes());
+            header.extend_from_slice(&extra);
+            self.write(&header).await?;
+        }
+
+        let central_directory_size = self.offset - central_directory_start;
+        let count = entries.len() as u64;
+        let needs_zip64 = count >= u16::MAX as u64
+            || central_directory_start >= u32::MAX as u64
+            || central_directory_size >= u32::MAX as u64;
+
+        let mut trailer = Vec::with_capacity(98);
+        if needs_zip64 {
+            let zip64_record_offset = self.offset;
+            trailer.extend_from_slice(&ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+            trailer.extend_from_slice(&44u64.to_le_bytes()); // size of the remaining record
+            trailer.extend_from_slice(&VERSION_ZIP64.to_le_bytes());
+            trailer.extend_from_slice(&VERSION_ZIP64.to_le_bytes());
+            trailer.extend_from_slice(&0u32.to_le_bytes()); // this disk
+            trailer.extend_from_slice(&0u32.to_le_bytes()); // central directory disk
+            trailer.extend_from_slice(&count.to_le_bytes());
+            trailer.extend_from_slice(&count.to_le_bytes());
+            trailer.extend_from_slice(&central_directory_size.to_le_bytes());
+            trailer.extend_from_slice(&central_directory_start.to_le_bytes());
+
+            trailer
+                .extend_from_slice(&ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE.to_le_bytes());
+            trailer.extend_from_slice(&0u32.to_le_bytes());
+            trailer.extend_from_slice(&zip64_record_offset.to_le_bytes());
+            trailer.extend_from_slice(&1u32.to_le_bytes()); // total disks
+        }
+
+        let count16 = u16::try_from(count).unwrap_or(u16::MAX);
+        trailer.extend_from_slice(&END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+        trailer.extend_from_slice(&0u16.to_le_bytes()); // this disk
+        trailer.extend_from_slice(&0u16.to_le_bytes()); // central directory disk
+        trailer.extend_from_slice(&count16.to_le_bytes());
+        trailer.extend_from_slice(&count16.to_le_bytes());
+        trailer.extend_from_slice(&clamp_u32(central_directory_size).to_le_bytes());
+        trailer.extend_from_slice(&clamp_u32(central_directory_start).to_le_bytes());
+        trailer.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        self.write(&trailer).await?;
+
+        self.writer.flush().await?;
+        Ok(self.writer)
+    }
+}
+
+fn name_length(name: &str) -> io::Result<u16> {
+    u16::try_from(name.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "ZIP entry name too long"))
+}
+
+/// Values that don't fit are marked 0xFFFFFFFF and stored in ZIP64 records
+fn clamp_u32(value: u64) -> u32 {
+    u32::try_from(value).unwrap_or(u32::MAX)
+}
+
+/// MS-DOS time and date fields; DOS dates start in 1980
+fn dos_date_time(datetime: NaiveDateTime) -> (u16, u16) {
+    if datetime.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let year = (datetime.year() - 1980).min(127) as u16;
+    let time = ((datetime.hour() as u16) << 11)
+        | ((datetime.minute() as u16) << 5)
+        | (datetime.second() as u16 / 2);
+    let date = (year << 9) | ((datetime.month() as u16) << 5) | datetime.day() as u16;
+    (time, date)
+}
+
+/// Keep entry names unique, turning a second `a.jpg` into `a (2).jpg`
+fn unique_name(used: &mut HashSet<String>, name: String) -> String {
+    if used.insert(name.clone()) {
+        return name;
+    }
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) => (stem.to_string(), format!(".{}", extension)),
+        None => (name.clone(), String::new()),
+    };
+    (2..)
+        .map(|n| format!("{} ({}){}", stem, n, extension))
+        .find(|candidate| used.insert(candidate.clone()))
+        .expect("unbounded range always yields a free name")
+}
+
+impl Gallery {
+    /// Stream `images` (gallery-relative paths) into a ZIP archive, as
+    /// originals or resized to `size`, each followed by its sidecar caption as
+    /// a `.txt` file. Images that can't be read or resized are left out.
+    pub(crate) async fn write_archive<W: AsyncWrite + Unpin>(
+        &self,
+        images: &[String],
+        size: Option<&str>,
+        writer: W,
+    ) -> io::Result<W> {
+        let mut zip = ZipWriter::new(writer);
+        let mut used_names = HashSet::new();
+
+        for relative_path in images {
+            let original_path = self.config.source_directory.join(relative_path);
+            let Some(file_name) = Path::new(relative_path)
+                .file_name()
+                .and_then(|name| name.to_str())
+            else {
+                continue;
+            };
+
+            // Videos are archived as they are, whatever the size
+            let size = size.filter(|_| !super::is_video_file(relative_path));
+            let (path, name) = match size {
+                None => match self.original_file(&original_path, relative_path).await {
+                    Ok(path) => (path, file_name.to_string()),
+                    Err(e) => {
+                        warn!("Leaving {} out of archive: {}", relative_path, e);
+                        continue;
+                    }
+                },
+                Some(size) => {
+                    // Archives are for keeping, so stick to widely supported formats
+                    let output_format = self.determine_output_format("", relative_path);
+                    match self
+                        .get_resized_image(&original_path, relative_path, size, output_format)
+                        .await
+                    {
+                        Ok(resized_path) => {
+                            let stem = file_name
+                                .rsplit_once('.')
+                                .map_or(file_name, |(stem, _)| stem);
+                            (
+                                resized_path,
+                                format!("{}.{}", stem, output_format.extension()),
+                            )
+                        }
+                        Err(e) => {
+                            warn!("Leaving {} out of archive: {}", relative_path, e);
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            let opened = match tokio::fs::File::open(&path).await {
+                Ok(file) => file.metadata().await.map(|metadata| (file, metadata.len())),
+                Err(e) => Err(e),
+            };
+            let (file, file_size) = match opened {
+                Ok(opened) => opened,
+                Err(e) => {
+                    warn!("Leaving {} out of archive: {}", relative_path, e);
+                    continue;
+                }
+            };
+            let modified = tokio::fs::metadata(&original_path)
+                .await
+                .and_then(|metadata| metadata.modified())
+                .map(|time| DateTime::<Local>::from(time).naive_local())
+                .unwrap_or_else(|_| Local::now().naive_local());
+
+            let name = unique_name(&mut used_names, name);
+            zip.add_entry(&name, modified, file_size, file).await?;
+
+            if let Some(caption) = self.read_sidecar_text(relative_path).await
+                && !caption.trim().is_empty()
+            {
+                let stem = name
+                    .rsplit_once('.')
+                    .map_or(name.as_str(), |(stem, _)| stem);
+                let caption_name = unique_name(&mut used_names, format!("{}.txt", stem));
+                zip.add_entry(
+                    &caption_name,
+                    modified,
+                    caption.len() as u64,
+                    caption.as_bytes(),
+                )
+                .await?;
+            }
+        }
+
+        zip.finish().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_zip_writer_layout() {
+        let modified =
+            NaiveDateTime::parse_from_str("2024-06-15 10:30:20", "%Y-%m-%d %H:%M:%S").unwrap();
+        let mut zip = ZipWriter::new(Vec::new());
+        zip.add_entry("photo.jpg", modified, 11, &b"image bytes"[..])
+            .await
+            .unwrap();
+        zip.add_entry("photo.txt", modified, 9, &b"A caption"[..])
+            .await
+            .unwrap();
+        let bytes = zip.finish().await.unwrap();
+
+        // First local header, stored, with the name and data right after it
+        assert_eq!(u32_at(&bytes, 0), LOCAL_FILE_HEADER_SIGNATURE);
+        assert_eq!(u16_at(&bytes, 6), ENTRY_FLAGS);
+        assert_eq!(u16_at(&bytes, 8), METHOD_STORED);
+        assert_eq!(&bytes[30..39], b"photo.jpg");
+        assert_eq!(&bytes[39..50], b"image bytes");
+
+        // Data descriptor carries the CRC and size
+        let mut crc = flate2::Crc::new();
+        crc.update(b"image bytes");
+        assert_eq!(u32_at(&bytes, 50), DATA_DESCRIPTOR_SIGNATURE);
+        assert_eq!(u32_at(&bytes, 54), crc.sum());
+        assert_eq!(u32_at(&bytes, 58), 11);
+
+        // End of central directory points back at two entries
+        let eocd = bytes.len() - 22;
+        assert_eq!(u32_at(&bytes, eocd), END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        assert_eq!(u16_at(&bytes, eocd + 10), 2);
+        let central_directory = u32_at(&bytes, eocd + 16) as usize;
+        assert_eq!(
+            u32_at(&bytes, central_directory),
+            CENTRAL_DIRECTORY_SIGNATURE
+        );
+        assert_eq!(u32_at(&bytes, central_directory + 16), crc.sum());
+        assert_eq!(
+            &bytes[central_directory + 46..central_directory + 55],
+            b"photo.jpg"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_zip_writer_large_entry_uses_zip64_sizes() {
+        let modified =
+            NaiveDateTime::parse_from_str("2024-06-15 10:30:20", "%Y-%m-%d %H:%M:%S").unwrap();
+        let mut zip = ZipWriter::new(Vec::new());
+        // Announced as 4 GiB, so the headers are laid out for ZIP64 sizes
+        zip.add_entry("video.mp4", modified, u32::MAX as u64, &b"video"[..])
+            .await
+            .unwrap();
+        let bytes = zip.finish().await.unwrap();
+
+        // Local header marks the sizes as deferred to a 16-byte ZIP64 extra
+        assert_eq!(u16_at(&bytes, 4), VERSION_ZIP64);
+        assert_eq!(u32_at(&bytes, 18), u32::MAX);
+        assert_eq!(u32_at(&bytes, 22), u32::MAX);
+        assert_eq!(u16_at(&bytes, 28), 20);
+        assert_eq!(u16_at(&bytes, 39), ZIP64_EXTRA_FIELD_ID);
+        assert_eq!(u16_at(&bytes, 41), 16);
+        assert_eq!(&bytes[59..64], b"video");
+
+        // Data descriptor carries 8-byte sizes
+        assert_eq!(u32_at(&bytes, 64), DATA_DESCRIPTOR_SIGNATURE);
+        assert_eq!(u64::from_le_bytes(bytes[72..80].try_into().unwrap()), 5);
+        assert_eq!(u64::from_le_bytes(bytes[80..88].try_into().unwrap()), 5);
+
+        // Central directory repeats both sizes in its ZIP64 extra
+        let eocd = bytes.len() - 22;
+        let central_directory = u32_at(&bytes, eocd + 16) as usize;
+        assert_eq!(u16_at(&bytes, central_directory + 6), VERSION_ZIP64);
+        assert_eq!(u32_at(&bytes, central_directory + 20), u32::MAX);
+        assert_eq!(u16_at(&bytes, central_directory + 30), 20);
+        let extra = central_directory + 46 + 9;
+        assert_eq!(u16_at(&bytes, extra), ZIP64_EXTRA_FIELD_ID);
+        assert_eq!(u16_at(&bytes, extra + 2), 16);
+        assert_eq!(
+            u64::from_le_bytes(bytes[extra + 4..extra + 12].try_into().unwrap()),
+            5
+        );
+    }
+
+    #[test]
+    fn test_dos_date_time() {
+        let datetime =
+            NaiveDateTime::parse_from_str("2024-06-15 10:30:20", "%Y-%m-%d %H:%M:%S").unwrap();
+        let (time, date) = dos_date_time(datetime);
+        assert_eq!(time, (10 << 11) | (30 << 5) | 10);
+        assert_eq!(date, (44 << 9) | (6 << 5) | 15);
+
+        let old =
+            NaiveDateTime::parse_from_str("1975-01-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(dos_date_time(old), (0, (1 << 5) | 1));
+    }
+
+    #[test]
+    fn test_unique_name() {
+        let mut used = HashSet::new();
+        assert_eq!(unique_name(&mut used, "a.jpg".to_string()), "a.jpg");
+        assert_eq!(unique_name(&mut used, "a.jpg".to_string()), "a (2).jpg");
+        assert_eq!(unique_name(&mut used, "a.jpg".to_string()), "a (3).jpg");
+        assert_eq!(unique_name(&mut used, "notes".to_string()), "notes");
+        assert_eq!(unique_name(&mut used, "notes".to_string()), "notes (2)");
+    }
+}
@@ -1,12 +1,25 @@
 use super::image_processing::OutputFormat;
 use super::{CacheMetadata, Gallery, ImageMetadata};
+use crate::MetadataPolicy;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use tracing::{debug, error, info};
 
+/// Bumped when `ImageMetadata` gains fields, so existing entries are
+/// re-extracted instead of keeping defaults
+const METADATA_CACHE_FORMAT: u32 = 4;
+
+/// Bumped when resized images come out differently (e.g. EXIF rotation), so
+/// derivatives cached by older versions are regenerated under new names
+const DERIVATIVE_CACHE_FORMAT: u32 = 2;
+
 impl Gallery {
     pub async fn initialize_and_check_version(&self) -> Result<(), super::GalleryError> {
-        let current_version = env!("CARGO_PKG_VERSION");
+        let current_version = format!(
+            "{}+metadata.{}",
+            env!("CARGO_PKG_VERSION"),
+            METADATA_CACHE_FORMAT
+        );
 
         let mut metadata = self.cache_metadata.write().await;
         let needs_refresh = metadata.version != current_version;
@@ -21,9 +34,10 @@ impl Gallery {
             let mut cache = self.metadata_cache.write().await;
             cache.clear();
             drop(cache);
+            self.clear_search_index().await;
 
             // Update version and trigger refresh
-            metadata.version = current_version.to_string();
+            metadata.version = current_version;
             metadata.last_full_refresh = std::time::SystemTime::now();
             drop(metadata);
 
@@ -57,8 +71,6 @@ impl Gallery {
     }
 
     pub fn start_periodic_cache_save(gallery: super::SharedGallery, interval_minutes: u64) {
-        use std::sync::atomic::Ordering;
-
         tokio::spawn(async move {
             let mut interval =
                 tokio::time::interval(tokio::time::Duration::from_secs(interval_minutes * 60));
@@ -82,13 +94,17 @@ impl Gallery {
                         info!("Periodic metadata cache save completed");
                     }
                 }
+
+                if gallery.cache_index_dirty.load(Ordering::Relaxed)
+                    && let Err(e) = gallery.save_cache_index().await
+                {
+                    error!("Failed to save cache index: {}", e);
+                }
             }
         });
     }
 
     pub(crate) async fn save_metadata_cache(&self) -> Result<(), super::GalleryError> {
-        use std::sync::atomic::Ordering;
-
         let cache_file = self.config.cache_directory.join("metadata_cache.json");
         let cache = self.metadata_cache.read().await;
 
@@ -116,9 +132,10 @@ impl Gallery {
         // Create cache directory if it doesn't exist
         tokio::fs::create_dir_all(&self.config.cache_directory).await?;
 
-        // Save both caches
+        // Save the caches and the index of cached files
         self.save_metadata_cache().await?;
         self.save_cache_metadata().await?;
+        self.save_cache_index().await?;
 
         info!("Saved gallery caches to disk");
         Ok(())
@@ -132,7 +149,8 @@ impl Gallery {
         format!("{:x}", hasher.finalize())
     }
 
-    /// Generate a cache key for regular images with size, format, and watermark status
+    /// Generate a cache key for regular images with size, format, and watermark
+    /// status, plus the settings the image is made with
     pub(crate) fn generate_image_cache_key(
         &self,
         path: &str,
@@ -141,11 +159,49 @@ impl Gallery {
         has_watermark: bool,
     ) -> String {
         let cache_key = if has_watermark {
-            format!("{}_{}_{}", size, format, "watermarked")
+            format!(
+                "{}_{}_watermarked_v{}",
+                size, format, DERIVATIVE_CACHE_FORMAT
+            )
         } else {
-            format!("{}_{}", size, format)
+            format!("{}_{}_v{}", size, format, DERIVATIVE_CACHE_FORMAT)
         };
-        self.generate_cache_key(path, &cache_key)
+        let settings = self.derivative_settings(size, format, has_watermark);
+        self.generate_cache_key(path, &format!("{}{}", cache_key, settings))
+    }
+
+    /// The settings a resized image is made with: dimensions, crop mode,
+    /// encoder quality, metadata policy and watermark text. Part of the
+    /// cache key, so changing any of them in the config gives new file names
+    /// instead of serving stale files. Empty for cache entries that aren't
+    /// sizes, such as posters and rewritten originals.
+    fn derivative_settings(&self, size: &str, format: &str, has_watermark: bool) -> String {
+        let Ok((dimensions, size_config)) = self.parse_size(size) else {
+            return String::new();
+        };
+
+        let (jpeg_quality, webp_quality, avif_quality) = self.encoder_qualities(&size_config);
+        let quality = match format {
+            // JPEG XL shares JPEG's quality scale
+            "jpg" | "jxl" => jpeg_quality.to_string(),
+            "webp" => webp_quality.to_string(),
+            "avif" => avif_quality.to_string(),
+            _ => String::new(),
+        };
+        let watermark = match &self.config.copyright_holder {
+            Some(holder) if has_watermark => holder.as_str(),
+            _ => "",
+        };
+
+        format!(
+            "_{}x{}_{:?}_q{}_{}_{}",
+            dimensions.width,
+            dimensions.height,
+            size_config.crop,
+            quality,
+            self.config.metadata.derivatives.as_str(),
+            watermark
+        )
     }
 
     /// Generate a cache filename for storing in filesystem with watermark status
@@ -170,94 +226,119 @@ impl Gallery {
         format!("composite_{}", safe_path)
     }
 
-    /// Pre-generate cache for a single image
-    pub async fn pregenerate_image_cache(
-        &self,
-        relative_path: &str,
-    ) -> Result<(), super::GalleryError> {
-        if !self.is_image(relative_path) {
-            return Ok(());
-        }
-
-        let full_path = self.config.source_directory.join(relative_path);
-        if !full_path.exists() {
-            return Ok(());
-        }
-
-        let sizes = vec!["thumbnail", "gallery", "medium", "large"];
-        let formats = vec![
+    /// Cache file names an image or video's derivatives can have: every
+    /// size and format, originals with rewritten metadata and video posters
+    pub(crate) fn cache_filenames(&self, relative_path: &str) -> Vec<String> {
+        let sizes = self.config.image_sizes();
+        let formats = [
             OutputFormat::Jpeg,
             OutputFormat::WebP,
+            OutputFormat::Png,
             #[cfg(feature = "avif")]
             OutputFormat::Avif,
+            #[cfg(feature = "jxl")]
+            OutputFormat::Jxl,
         ];
 
-        for size in &sizes {
-            for &format in &formats {
-                // Skip WebP for PNGs to preserve transparency
-                if format == OutputFormat::WebP && relative_path.to_lowercase().ends_with(".png") {
-                    continue;
+        let mut filenames = Vec::new();
+        for size in sizes.keys() {
+            for size in [size.clone(), format!("{}@2x", size)] {
+                for format in &formats {
+                    for has_watermark in [false, true] {
+                        filenames.push(self.generate_cache_filename(
+                            relative_path,
+                            &size,
+                            format.extension(),
+                            has_watermark,
+                        ));
+                    }
                 }
+            }
+        }
 
-                match self
-                    .get_resized_image(&full_path, relative_path, size, format)
-                    .await
-                {
-                    Ok(_) => debug!(
-                        "Pre-generated {} {} for {}",
-                        size,
-                        format.extension(),
-                        relative_path
-                    ),
-                    Err(e) => error!(
-                        "Failed to pre-generate {} {} for {}: {}",
-                        size,
-                        format.extension(),
-                        relative_path,
-                        e
-                    ),
-                }
+        // Originals with rewritten metadata
+        let extension = std::path::Path::new(relative_path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        for policy in [
+            MetadataPolicy::Strip,
+            MetadataPolicy::CopyrightOnly,
+            MetadataPolicy::StripGps,
+        ] {
+            filenames.push(self.generate_cache_filename(
+                relative_path,
+                &format!("original-{}", policy.as_str()),
+                &extension,
+                false,
+            ));
+        }
+        // Original JPEGs recompressed as JPEG XL, under any policy
+        #[cfg(feature = "jxl")]
+        if matches!(extension.as_str(), "jpg" | "jpeg") {
+            for policy in [
+                MetadataPolicy::Strip,
+                MetadataPolicy::CopyrightOnly,
+                MetadataPolicy::StripGps,
+                MetadataPolicy::KeepAll,
+            ] {
+                filenames.push(self.generate_cache_filename(
+                    relative_path,
+                    &format!("original-{}", policy.as_str()),
+                    OutputFormat::Jxl.extension(),
+                    false,
+                ));
             }
         }
 
-        Ok(())
+        // Posters standing in for a video
+        filenames.extend(self.poster_cache_filenames(relative_path));
+        filenames
     }
 
-    /// Pre-generate cache for all images in the gallery
-    pub async fn pregenerate_all_images_cache(self: Arc<Self>) -> Result<(), super::GalleryError> {
-        info!(
-            "Starting cache pre-generation for gallery '{}'",
-            self.config.name
-        );
-
-        // Get all image paths from metadata cache
-        let image_paths: Vec<String> = {
-            let metadata_cache = self.metadata_cache.read().await;
-            metadata_cache.keys().cloned().collect()
-        };
+    /// Cache file name of a folder's composite preview
+    pub(crate) fn composite_cache_filename(&self, folder: &str) -> String {
+        let composite_key = Self::generate_composite_cache_key(folder);
+        format!("{}.jpg", self.generate_cache_key(&composite_key, "jpg"))
+    }
 
-        let total_images = image_paths.len();
-        info!("Found {} images to pre-generate cache for", total_images);
+    /// Remove all cached derivatives of an image, plus the composite preview of
+    /// every folder above it. Returns the number of files removed.
+    pub async fn evict_cached_images(&self, relative_path: &str) -> usize {
+        let mut candidates = self.cache_filenames(relative_path);
+
+        // Folder composites are built from the images below them, so every
+        // ancestor's composite is potentially stale
+        let mut folder = relative_path;
+        while !folder.is_empty() {
+            folder = folder
+                .rsplit_once('/')
+                .map(|(parent, _)| parent)
+                .unwrap_or("");
+            candidates.push(self.composite_cache_filename(folder));
+        }
 
-        for (index, image_path) in image_paths.into_iter().enumerate() {
-            if let Err(e) = self.pregenerate_image_cache(&image_path).await {
-                error!("Failed to pre-generate cache for {}: {}", image_path, e);
+        // Anything else the index knows was made from this image, such as
+        // sizes that have since been removed from the config
+        {
+            let mut index = self.cache_index.write().await;
+            for filename in index.filenames_for_source(relative_path) {
+                if !candidates.contains(&filename) {
+                    candidates.push(filename);
+                }
             }
-
-            if (index + 1) % 10 == 0 || index + 1 == total_images {
-                info!(
-                    "Pre-generated cache for {}/{} images",
-                    index + 1,
-                    total_images
-                );
+            for filename in &candidates {
+                index.remove(filename);
             }
         }
+        self.cache_index_dirty.store(true, Ordering::Relaxed);
 
-        info!(
-            "Completed cache pre-generation for gallery '{}'",
-            self.config.name
-        );
-        Ok(())
+        let removed = self.remove_cache_files(&candidates).await;
+        if removed > 0 {
+            debug!("Evicted {} cached files for {}", removed, relative_path);
+        }
+        removed
     }
 }
 
@@ -343,6 +424,62 @@ mod tests {
         assert_eq!(root_key, "composite_root");
     }
 
+    #[test]
+    fn test_cache_key_follows_settings() {
+        let config = crate::Config::default().galleries.unwrap()[0].clone();
+        let gallery = Gallery::new(config.clone());
+        let path = "vacation/beach.jpg";
+        let key = |gallery: &Gallery, size: &str, format: &str| {
+            gallery.generate_image_cache_key(path, size, format, size == "medium")
+        };
+
+        // JPEG quality only affects JPEGs
+        let mut changed = config.clone();
+        changed.jpeg_quality = Some(70);
+        let other = Gallery::new(changed);
+        assert_ne!(
+            key(&gallery, "thumbnail", "jpg"),
+            key(&other, "thumbnail", "jpg")
+        );
+        assert_eq!(
+            key(&gallery, "thumbnail", "webp"),
+            key(&other, "thumbnail", "webp")
+        );
+
+        // Dimensions only affect their own size
+        let mut changed = config.clone();
+        changed.thumbnail.width += 100;
+        let other = Gallery::new(changed);
+        assert_ne!(
+            key(&gallery, "thumbnail", "jpg"),
+            key(&other, "thumbnail", "jpg")
+        );
+        assert_ne!(
+            key(&gallery, "thumbnail@2x", "jpg"),
+            key(&other, "thumbnail@2x", "jpg")
+        );
+        assert_eq!(key(&gallery, "medium", "jpg"), key(&other, "medium", "jpg"));
+
+        // The watermark text only affects watermarked sizes
+        let mut changed = config.clone();
+        changed.copyright_holder = Some("Someone Else".to_string());
+        let other = Gallery::new(changed);
+        assert_ne!(key(&gallery, "medium", "jpg"), key(&other, "medium", "jpg"));
+        assert_eq!(key(&gallery, "large", "jpg"), key(&other, "large", "jpg"));
+
+        // As does the metadata kept in derivatives
+        let mut changed = config.clone();
+        changed.metadata.derivatives = crate::MetadataPolicy::StripGps;
+        let other = Gallery::new(changed);
+        assert_ne!(key(&gallery, "large", "webp"), key(&other, "large", "webp"));
+
+        // Posters aren't sizes, so they keep their names
+        assert_eq!(
+            gallery.poster_cache_filenames(path),
+            other.poster_cache_filenames(path)
+        );
+    }
+
     #[test]
     fn test_cache_filename_generation() {
         let default_config = crate::Config::default();
@@ -430,9 +567,17 @@ This folder should not appear in listings.
             jpeg_quality: Some(85),
             webp_quality: Some(85.0),
             pregenerate_cache: false,
+            pregenerate_parallelism: None,
             new_threshold_days: None,
             approximate_dates_for_public: false,
+            location_precision: Default::default(),
+            location_precision_for_public: Default::default(),
+            metadata: Default::default(),
+            ffmpeg_path: None,
+            max_cache_size: None,
             copyright_holder: None,
+            watch_filesystem: false,
+            sizes: Default::default(),
         };
 
         let gallery = Gallery::new(config);
@@ -505,9 +650,17 @@ Hidden folder
             jpeg_quality: Some(85),
             webp_quality: Some(85.0),
             pregenerate_cache: false,
+            pregenerate_parallelism: None,
             new_threshold_days: None,
             approximate_dates_for_public: false,
+            location_precision: Default::default(),
+            location_precision_for_public: Default::default(),
+            metadata: Default::default(),
+            ffmpeg_path: None,
+            max_cache_size: None,
             copyright_holder: None,
+            watch_filesystem: false,
+            sizes: Default::default(),
         };
 
         let gallery = Gallery::new(config);
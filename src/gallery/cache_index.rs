@@ -0,0 +1,481 @@
+use super::{Gallery, GalleryError};
+use crate::atomic_file::write_atomically;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info};
+
+const CACHE_INDEX_FILE: &str = "cache_index.json";
+
+/// Served files already used this recently keep their recorded access time,
+/// so most cache hits only need a read lock
+const ACCESS_RESOLUTION: Duration = Duration::from_secs(60);
+
+/// Temporary files younger than this are left to whoever is writing them
+const TEMP_FILE_GRACE: Duration = Duration::from_secs(3600);
+
+/// A file in the cache directory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// Image, video or folder (for composites) the file was made from,
+    /// relative to the source directory
+    pub source: String,
+    pub size: u64,
+    pub last_access: SystemTime,
+}
+
+/// Which source each cached file belongs to, how big it is and when it was
+/// last served, so the cache can be kept to a size and cleared of files
+/// nothing refers to anymore
+#[derive(Debug, Default)]
+pub(crate) struct CacheIndex {
+    entries: HashMap<String, CacheEntry>,
+    total_size: u64,
+    /// Dropped since the last save, so merging with the saved index doesn't
+    /// bring them back
+    removed: HashSet<String>,
+}
+
+impl CacheIndex {
+    pub(crate) fn from_entries(entries: HashMap<String, CacheEntry>) -> Self {
+        let total_size = entries.values().map(|entry| entry.size).sum();
+        Self {
+            entries,
+            total_size,
+            removed: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    pub(crate) fn get(&self, filename: &str) -> Option<&CacheEntry> {
+        self.entries.get(filename)
+    }
+
+    /// Add or update the entry for a cache file
+    pub(crate) fn record(&mut self, filename: &str, source: &str, size: u64, accessed: SystemTime) {
+        let entry = CacheEntry {
+            source: source.to_string(),
+            size,
+            last_access: accessed,
+        };
+        if let Some(previous) = self.entries.insert(filename.to_string(), entry) {
+            self.total_size -= previous.size;
+        }
+        self.total_size += size;
+        self.removed.remove(filename);
+    }
+
+    pub(crate) fn remove(&mut self, filename: &str) -> Option<CacheEntry> {
+        let entry = self.entries.remove(filename)?;
+        self.total_size -= entry.size;
+        self.removed.insert(filename.to_string());
+        Some(entry)
+    }
+
+    /// Cache files made from `source`
+    pub(crate) fn filenames_for_source(&self, source: &str) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.source == source)
+            .map(|(filename, _)| filename.clone())
+            .collect()
+    }
+
+    /// Drop the least recently used entries until the rest fit in
+    /// `max_size`, never dropping `keep`. Returns the dropped file names.
+    pub(crate) fn evict_lru(&mut self, max_size: u64, keep: Option<&str>) -> Vec<String> {
+        if self.total_size <= max_size {
+            return Vec::new();
+        }
+
+        let mut by_age: Vec<(SystemTime, String)> = self
+            .entries
+            .iter()
+            .filter(|(filename, _)| Some(filename.as_str()) != keep)
+            .map(|(filename, entry)| (entry.last_access, filename.clone()))
+            .collect();
+        by_age.sort();
+
+        let mut evicted = Vec::new();
+        for (_, filename) in by_age {
+            if self.total_size <= max_size {
+                break;
+            }
+            self.remove(&filename);
+            evicted.push(filename);
+        }
+        evicted
+    }
+
+    /// Fold in an index saved by another process sharing the cache
+    /// directory, keeping whichever access is more recent
+    pub(crate) fn merge(&mut self, saved: HashMap<String, CacheEntry>) {
+        for (filename, entry) in saved {
+            if self.removed.contains(&filename) {
+                continue;
+            }
+            match self.entries.get(&filename) {
+                Some(current) if current.last_access >= entry.last_access => {}
+                _ => self.record(&filename, &entry.source, entry.size, entry.last_access),
+            }
+        }
+    }
+}
+
+/// Totals for a gallery's cache directory
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CacheStats {
+    pub files: usize,
+    pub total_size: u64,
+    /// Files no current image, video or folder would produce
+    pub orphaned_files: usize,
+    pub orphaned_size: u64,
+    pub max_size: Option<u64>,
+}
+
+/// What a garbage collection run removed
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CacheCleanup {
+    pub orphans_removed: usize,
+    /// Least recently used files removed to get under `max_cache_size`
+    pub evicted: usize,
+    pub bytes_freed: u64,
+}
+
+/// Cache files are a SHA-256 hex digest plus an extension; anything else in
+/// the cache directory is left alone
+fn is_cache_filename(filename: &str) -> bool {
+    filename.split_once('.').is_some_and(|(hash, extension)| {
+        hash.len() == 64
+            && hash.bytes().all(|b| b.is_ascii_hexdigit())
+            && !extension.is_empty()
+            && extension != "json"
+    })
+}
+
+impl Gallery {
+    /// Note that a cache file was just written, evicting the least
+    /// recently used files if that takes the cache over `max_cache_size`.
+    /// Eviction goes a tenth below the limit so it isn't repeated on every
+    /// new file.
+    pub(crate) async fn track_cached_file(&self, filename: &str, source: &str) {
+        let cache_path = self.config.cache_directory.join(filename);
+        let Ok(metadata) = tokio::fs::metadata(&cache_path).await else {
+            return;
+        };
+
+        let evicted = {
+            let mut index = self.cache_index.write().await;
+            index.record(filename, source, metadata.len(), SystemTime::now());
+            match self.config.max_cache_size {
+                Some(max_size) if index.total_size() > max_size => {
+                    index.evict_lru(max_size - max_size / 10, Some(filename))
+                }
+                _ => Vec::new(),
+            }
+        };
+        self.cache_index_dirty.store(true, Ordering::Relaxed);
+
+        if !evicted.is_empty() {
+            let removed = self.remove_cache_files(&evicted).await;
+            debug!("Evicted {} least recently used cache files", removed);
+        }
+    }
+
+    /// Note that an existing cache file was served. Least recently used order
+    /// only needs to be roughly right, so the index is only updated when the
+    /// file's last recorded use is older than `ACCESS_RESOLUTION`.
+    pub(crate) async fn note_cache_hit(&self, filename: &str, source: &str) {
+        let now = SystemTime::now();
+        let recent = self
+            .cache_index
+            .read()
+            .await
+            .get(filename)
+            .is_some_and(|entry| {
+                now.duration_since(entry.last_access)
+                    .is_ok_and(|age| age < ACCESS_RESOLUTION)
+            });
+        if !recent {
+            self.track_cached_file(filename, source).await;
+        }
+    }
+
+    /// Delete files from the cache directory, returning how many existed
+    pub(crate) async fn remove_cache_files(&self, filenames: &[String]) -> usize {
+        let mut removed = 0;
+        for filename in filenames {
+            let cache_path = self.config.cache_directory.join(filename);
+            if tokio::fs::remove_file(&cache_path).await.is_ok() {
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Every cache file the gallery's current images, videos and folders can
+    /// have, mapped to the source it is made from
+    fn expected_cache_files(&self) -> HashMap<String, String> {
+        use walkdir::WalkDir;
+
+        let mut expected = HashMap::new();
+        for entry in WalkDir::new(&self.config.source_directory)
+            .follow_links(true)
+            .into_iter()
+            .filter_entry(|e| e.path() != self.config.cache_directory)
+            .filter_map(|e| e.ok())
+        {
+            let Ok(relative) = entry.path().strip_prefix(&self.config.source_directory) else {
+                continue;
+            };
+            let relative = relative.to_string_lossy().replace('\\', "/");
+
+            if entry.file_type().is_dir() {
+                expected.insert(self.composite_cache_filename(&relative), relative);
+            } else if self.is_image(&relative) {
+                for filename in self.cache_filenames(&relative) {
+                    expected.insert(filename, relative.clone());
+                }
+            }
+        }
+        expected
+    }
+
+    /// Bring the index in line with the cache directory: forget entries
+    /// whose file is gone and pick up files it doesn't know about. Returns
+    /// the directory's totals and its orphaned files with their sizes.
+    async fn scan_cache(&self) -> Result<(CacheStats, Vec<(String, u64)>), GalleryError> {
+        let mut stats = CacheStats {
+            max_size: self.config.max_cache_size,
+            ..Default::default()
+        };
+        let mut orphans = Vec::new();
+        if !self.config.cache_directory.exists() {
+            return Ok((stats, orphans));
+        }
+
+        // Walking the source tree and hashing every name blocks
+        let gallery = self.clone();
+        let expected = tokio::task::spawn_blocking(move || gallery.expected_cache_files()).await?;
+        let mut files = Vec::new();
+        let mut dir = tokio::fs::read_dir(&self.config.cache_directory).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let Ok(filename) = entry.file_name().into_string() else {
+                continue;
+            };
+            let metadata = entry.metadata().await?;
+            if metadata.is_file() && is_cache_filename(&filename) {
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                // Temporary files may still be being written
+                let recent = modified.elapsed().is_ok_and(|age| age < TEMP_FILE_GRACE);
+                if filename.ends_with(".tmp") && recent {
+                    continue;
+                }
+                files.push((filename, metadata.len(), modified));
+            }
+        }
+
+        let mut index = self.cache_index.write().await;
+        let present: HashSet<&str> = files.iter().map(|(name, _, _)| name.as_str()).collect();
+        let missing: Vec<String> = index
+            .entries
+            .keys()
+            .filter(|filename| !present.contains(filename.as_str()))
+            .cloned()
+            .collect();
+        for filename in missing {
+            index.remove(&filename);
+        }
+
+        for (filename, size, modified) in files {
+            stats.files += 1;
+            stats.total_size += size;
+            match expected.get(&filename) {
+                Some(source) => {
+                    // Files cached before they were tracked count as last
+                    // used when they were written
+                    let accessed = index
+                        .get(&filename)
+                        .map_or(modified, |entry| entry.last_access);
+                    index.record(&filename, source, size, accessed);
+                }
+                None => {
+                    stats.orphaned_files += 1;
+                    stats.orphaned_size += size;
+                    orphans.push((filename, size));
+                }
+            }
+        }
+        drop(index);
+        self.cache_index_dirty.store(true, Ordering::Relaxed);
+
+        Ok((stats, orphans))
+    }
+
+    /// Totals for the cache directory
+    pub async fn cache_stats(&self) -> Result<CacheStats, GalleryError> {
+        let (stats, _) = self.scan_cache().await?;
+        Ok(stats)
+    }
+
+    /// Delete cache files whose image, video or folder is gone (or that the
+    /// current sizes and formats no longer produce), then evict the least
+    /// recently used files until the cache fits in `max_cache_size`
+    pub async fn collect_garbage(&self) -> Result<CacheCleanup, GalleryError> {
+        let (_, orphans) = self.scan_cache().await?;
+
+        let (orphans, evicted, evicted_size) = {
+            let mut index = self.cache_index.write().await;
+            for (filename, _) in &orphans {
+                index.remove(filename);
+            }
+            let size_before = index.total_size();
+            let evicted = match self.config.max_cache_size {
+                Some(max_size) => index.evict_lru(max_size, None),
+                None => Vec::new(),
+            };
+            (orphans, evicted, size_before - index.total_size())
+        };
+
+        let orphan_size: u64 = orphans.iter().map(|(_, size)| size).sum();
+        let orphans: Vec<String> = orphans.into_iter().map(|(filename, _)| filename).collect();
+        let cleanup = CacheCleanup {
+            orphans_removed: self.remove_cache_files(&orphans).await,
+            evicted: self.remove_cache_files(&evicted).await,
+            bytes_freed: orphan_size + evicted_size,
+        };
+
+        self.save_cache_index().await?;
+        info!(
+            "Cache cleanup for gallery '{}': {} orphaned and {} evicted files, {} bytes freed",
+            self.config.name, cleanup.orphans_removed, cleanup.evicted, cleanup.bytes_freed
+        );
+        Ok(cleanup)
+    }
+
+    pub(crate) async fn save_cache_index(&self) -> Result<(), GalleryError> {
+        let index_file = self.config.cache_directory.join(CACHE_INDEX_FILE);
+        let mut index = self.cache_index.write().await;
+
+        // Another gallery instance or a `cache` command may have saved since
+        if let Ok(json) = tokio::fs::read_to_string(&index_file).await
+            && let Ok(saved) = serde_json::from_str(&json)
+        {
+            index.merge(saved);
+        }
+
+        let json = serde_json::to_string(&index.entries)?;
+        write_atomically(&index_file, json.as_bytes()).await?;
+        index.removed.clear();
+        self.cache_index_dirty.store(false, Ordering::Relaxed);
+
+        Ok(())
+    }
+}
+
+pub(crate) fn load_cache_index(
+    config: &crate::GallerySystemConfig,
+) -> Result<CacheIndex, GalleryError> {
+    let index_file = config.cache_directory.join(CACHE_INDEX_FILE);
+
+    if !index_file.exists() {
+        debug!("Cache index not found, starting with an empty index");
+        return Ok(CacheIndex::default());
+    }
+
+    let json = std::fs::read_to_string(&index_file)?;
+    let index = CacheIndex::from_entries(serde_json::from_str(&json)?);
+
+    info!("Loaded cache index with {} entries", index.len());
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut index = CacheIndex::default();
+        index.record("a.jpg", "a.jpg", 100, at(1));
+        index.record("b.jpg", "b.jpg", 100, at(2));
+        index.record("c.jpg", "c.jpg", 100, at(3));
+        // Used again, so no longer the oldest
+        index.record("a.jpg", "a.jpg", 150, at(4));
+        assert_eq!(index.total_size(), 350);
+
+        assert_eq!(index.evict_lru(400, None), Vec::<String>::new());
+        assert_eq!(index.evict_lru(200, None), vec!["b.jpg", "c.jpg"]);
+        assert_eq!(index.total_size(), 150);
+
+        // The file just written survives even when it alone is too big
+        assert_eq!(index.evict_lru(100, Some("a.jpg")), Vec::<String>::new());
+        assert_eq!(index.evict_lru(100, None), vec!["a.jpg"]);
+        assert_eq!(index.total_size(), 0);
+    }
+
+    #[test]
+    fn test_merge_keeps_latest_access() {
+        let mut index = CacheIndex::default();
+        index.record("a.jpg", "a.jpg", 100, at(10));
+        index.record("b.jpg", "b.jpg", 100, at(10));
+        index.remove("b.jpg");
+
+        let saved = HashMap::from([
+            (
+                "a.jpg".to_string(),
+                CacheEntry {
+                    source: "a.jpg".to_string(),
+                    size: 100,
+                    last_access: at(5),
+                },
+            ),
+            (
+                "b.jpg".to_string(),
+                CacheEntry {
+                    source: "b.jpg".to_string(),
+                    size: 100,
+                    last_access: at(20),
+                },
+            ),
+            (
+                "c.jpg".to_string(),
+                CacheEntry {
+                    source: "c.jpg".to_string(),
+                    size: 50,
+                    last_access: at(20),
+                },
+            ),
+        ]);
+        index.merge(saved);
+
+        assert_eq!(index.get("a.jpg").unwrap().last_access, at(10));
+        // Evicted here, so not brought back
+        assert_eq!(index.get("b.jpg"), None);
+        assert_eq!(index.get("c.jpg").unwrap().size, 50);
+        assert_eq!(index.total_size(), 150);
+    }
+
+    #[test]
+    fn test_cache_filename_pattern() {
+        let hash = "0123456789abcdef".repeat(4);
+        assert!(is_cache_filename(&format!("{}.webp", hash)));
+        assert!(is_cache_filename(&format!("{}.part", hash)));
+        assert!(!is_cache_filename(&format!("{}.json", hash)));
+        assert!(!is_cache_filename("metadata_cache.json"));
+        assert!(!is_cache_filename("photo.jpg"));
+        assert!(!is_cache_filename(&hash));
+    }
+}
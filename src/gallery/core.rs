@@ -1,8 +1,12 @@
-use super::{Gallery, GalleryError, GalleryItem, ImageInfo};
+use super::image_processing::video::video_info;
+use super::{DownloadKind, Gallery, GalleryError, GalleryItem, ImageInfo, PublicEntry, Share};
+use crate::login::UserIdentity;
 use pulldown_cmark::{Parser, html};
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::path::Path as StdPath;
 use std::time::SystemTime;
-use tracing::debug;
+use tracing::{debug, warn};
 use walkdir::WalkDir;
 
 impl Gallery {
@@ -10,13 +14,15 @@ impl Gallery {
         &self,
         relative_path: &str,
     ) -> Result<Vec<GalleryItem>, GalleryError> {
-        self.scan_directory_with_user(relative_path, None).await
+        self.scan_directory_with_user(relative_path, None, None)
+            .await
     }
 
     pub async fn scan_directory_with_user(
         &self,
         relative_path: &str,
-        user: Option<&str>,
+        user: Option<&UserIdentity>,
+        share: Option<&Share>,
     ) -> Result<Vec<GalleryItem>, GalleryError> {
         let full_path = self.config.source_directory.join(relative_path);
 
@@ -27,6 +33,7 @@ impl Gallery {
         }
 
         let mut items = Vec::new();
+        let mut modification_dates = HashMap::new();
 
         let entries = tokio::fs::read_dir(&full_path).await?;
 
@@ -62,7 +69,7 @@ impl Gallery {
 
                 let item_count = self.count_images_in_directory(&item_path).await;
                 let preview_images = self
-                    .get_directory_preview_images_for_user(&item_path, user)
+                    .get_directory_preview_images_for_user(&item_path, user, share)
                     .await;
                 let (display_name, description) = self.read_folder_metadata(&item_path).await;
                 items.push(GalleryItem {
@@ -79,6 +86,8 @@ impl Gallery {
                     dimensions: None,
                     capture_date: None,
                     is_new: false,
+                    placeholder: None,
+                    video: None,
                 });
             } else if self.is_image(&file_name) {
                 // Found image
@@ -95,13 +104,15 @@ impl Gallery {
                 );
 
                 // Get metadata from cache if available
-                let (dimensions, capture_date, modification_date) = {
+                let (dimensions, capture_date, modification_date, placeholder, duration) = {
                     let cache = self.metadata_cache.read().await;
                     if let Some(metadata) = cache.get(&item_path) {
                         (
                             Some(metadata.dimensions),
                             metadata.capture_date,
                             metadata.modification_date,
+                            metadata.placeholder.clone(),
+                            metadata.duration,
                         )
                     } else {
                         // If not in cache, try to extract it now
@@ -111,13 +122,19 @@ impl Gallery {
                                 Some(metadata.dimensions),
                                 metadata.capture_date,
                                 metadata.modification_date,
+                                metadata.placeholder,
+                                metadata.duration,
                             ),
-                            Err(_) => (None, None, None),
+                            Err(_) => (None, None, None, None, None),
                         }
                     }
                 };
 
                 let is_new = self.is_new(modification_date);
+                if let Some(modification_date) = modification_date {
+                    modification_dates.insert(item_path.clone(), modification_date);
+                }
+                let video = video_info(&file_name, duration);
 
                 items.push(GalleryItem {
                     name: file_name,
@@ -133,30 +150,34 @@ impl Gallery {
                     dimensions,
                     capture_date,
                     is_new,
+                    placeholder,
+                    video,
                 });
             }
         }
 
-        items.sort_by(|a, b| match (a.is_directory, b.is_directory) {
-            (true, false) => std::cmp::Ordering::Less,
-            (false, true) => std::cmp::Ordering::Greater,
-            _ => {
-                // For directories, sort by display_name if available, otherwise by name
-                if a.is_directory && b.is_directory {
-                    let a_sort_name = a.display_name.as_ref().unwrap_or(&a.name);
-                    let b_sort_name = b.display_name.as_ref().unwrap_or(&b.name);
-                    a_sort_name.cmp(b_sort_name)
-                } else {
-                    // For files, sort by capture date first, then by name
-                    match (&a.capture_date, &b.capture_date) {
-                        (Some(a_date), Some(b_date)) => a_date.cmp(b_date),
-                        (Some(_), None) => std::cmp::Ordering::Less,
-                        (None, Some(_)) => std::cmp::Ordering::Greater,
-                        (None, None) => a.name.cmp(&b.name),
-                    }
-                }
-            }
-        });
+        let folder_config = self
+            .read_folder_metadata_full(relative_path)
+            .await
+            .map(|m| m.config);
+        let sort = folder_config
+            .as_ref()
+            .map(|c| c.sort_order())
+            .unwrap_or_default();
+        let manual_order: HashMap<&str, usize> = folder_config
+            .as_ref()
+            .and_then(|c| c.order.as_ref())
+            .filter(|_| sort == super::FolderSort::Manual)
+            .map(|order| {
+                order
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| (name.as_str(), i))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        items.sort_by(|a, b| compare_items(a, b, sort, &manual_order, &modification_dates));
 
         debug!(
             "Found {} items total ({} directories, {} images)",
@@ -166,7 +187,7 @@ impl Gallery {
         );
 
         // Filter items by access permissions
-        let filtered_items = self.filter_items_by_access(items, user).await;
+        let filtered_items = self.filter_items_by_access(items, user, share).await;
 
         debug!(
             "After access filtering: {} items ({} directories, {} images)",
@@ -183,16 +204,17 @@ impl Gallery {
         path: &str,
         page: usize,
     ) -> Result<(Vec<GalleryItem>, Vec<GalleryItem>, usize), GalleryError> {
-        self.list_directory_with_user(path, page, None).await
+        self.list_directory_with_user(path, page, None, None).await
     }
 
     pub async fn list_directory_with_user(
         &self,
         path: &str,
         page: usize,
-        user: Option<&str>,
+        user: Option<&UserIdentity>,
+        share: Option<&Share>,
     ) -> Result<(Vec<GalleryItem>, Vec<GalleryItem>, usize), GalleryError> {
-        let items = self.scan_directory_with_user(path, user).await?;
+        let items = self.scan_directory_with_user(path, user, share).await?;
 
         // Separate directories and images
         let (directories, images): (Vec<_>, Vec<_>) =
@@ -306,7 +328,8 @@ impl Gallery {
     async fn get_directory_preview_images_for_user(
         &self,
         relative_path: &str,
-        user: Option<&str>,
+        user: Option<&UserIdentity>,
+        share: Option<&Share>,
     ) -> Vec<String> {
         let full_path = self.config.source_directory.join(relative_path);
         let mut preview_images = Vec::new();
@@ -317,6 +340,19 @@ impl Gallery {
         // Pre-load hidden folder paths
         let hidden_folders = self.collect_hidden_folders(relative_path).await;
 
+        // A pinned cover image always leads the preview
+        if max_preview_images > 0
+            && let Some(cover_path) = self
+                .cover_image_path(relative_path, user, share, &hidden_folders)
+                .await
+        {
+            preview_images.push(format!(
+                "/{}/image/{}?size=thumbnail",
+                self.config.url_prefix.trim_start_matches('/'),
+                urlencoding::encode(&cover_path)
+            ));
+        }
+
         for entry in WalkDir::new(&full_path)
             .min_depth(1)
             .max_depth(self.config.preview.max_depth)
@@ -346,7 +382,7 @@ impl Gallery {
                     "" // Image is in root folder
                 };
 
-                if !self.check_path_access(image_folder_path, user).await {
+                if !self.check_path_access(image_folder_path, user, share).await {
                     continue;
                 }
             }
@@ -365,13 +401,58 @@ impl Gallery {
                     self.config.url_prefix.trim_start_matches('/'),
                     encoded_path
                 );
-                preview_images.push(thumbnail_url);
+                if !preview_images.contains(&thumbnail_url) {
+                    preview_images.push(thumbnail_url);
+                }
             }
         }
 
         preview_images
     }
 
+    /// Resolve the `cover` image configured in a folder's `_folder.md`, if it
+    /// exists and is visible to `user`
+    async fn cover_image_path(
+        &self,
+        folder_path: &str,
+        user: Option<&UserIdentity>,
+        share: Option<&Share>,
+        hidden_folders: &[String],
+    ) -> Option<String> {
+        let cover = self
+            .read_folder_metadata_full(folder_path)
+            .await?
+            .config
+            .cover?;
+        let cover = cover.trim_matches('/');
+        if cover
+            .split('/')
+            .any(|segment| segment == ".." || segment.starts_with('.'))
+            || !self.is_image(cover)
+        {
+            return None;
+        }
+
+        let cover_path = if folder_path.is_empty() {
+            cover.to_string()
+        } else {
+            format!("{}/{}", folder_path, cover)
+        };
+        if !self.config.source_directory.join(&cover_path).is_file() {
+            return None;
+        }
+
+        let cover_folder = cover_path.rsplit_once('/').map(|(p, _)| p).unwrap_or("");
+        let in_hidden = hidden_folders.iter().any(|hidden| {
+            cover_folder == hidden.as_str() || cover_folder.starts_with(&format!("{}/", hidden))
+        });
+        if in_hidden || !self.check_path_access(cover_folder, user, share).await {
+            return None;
+        }
+
+        Some(cover_path)
+    }
+
     pub async fn get_image_info(&self, relative_path: &str) -> Result<ImageInfo, GalleryError> {
         let full_path = self.config.source_directory.join(relative_path);
 
@@ -384,7 +465,15 @@ impl Gallery {
         let file_size = cached_metadata.file_size;
         let dimensions = cached_metadata.dimensions;
 
-        let description = self.read_sidecar_markdown(relative_path).await;
+        // A sidecar caption wins over one embedded in the file
+        let description = match self.read_sidecar_markdown(relative_path).await {
+            Some(description) => Some(description),
+            None => cached_metadata
+                .descriptive_info
+                .as_ref()
+                .and_then(|info| info.caption.as_deref())
+                .map(|caption| format!("<p>{}</p>", crate::sitemap::xml_escape(caption))),
+        };
 
         let encoded_path = urlencoding::encode(relative_path);
 
@@ -439,6 +528,9 @@ impl Gallery {
             capture_date,
             is_new,
             color_profile: cached_metadata.color_profile,
+            placeholder: cached_metadata.placeholder,
+            descriptive_info: cached_metadata.descriptive_info,
+            video: video_info(relative_path, cached_metadata.duration),
         })
     }
 
@@ -508,23 +600,28 @@ impl Gallery {
                 if content.trim_start().starts_with("+++") {
                     // Parse TOML front matter
                     let parts: Vec<&str> = content.splitn(3, "+++").collect();
-
-                    if parts.len() >= 3 {
-                        let toml_content = parts[1];
-                        let markdown_content = parts[2].trim().to_string();
-
-                        match toml_edit::de::from_str::<super::FolderConfig>(toml_content) {
-                            Ok(config) => {
-                                return Some(super::FolderMetadata {
-                                    config,
-                                    description_markdown: markdown_content,
-                                });
-                            }
-                            Err(e) => {
-                                debug!("Failed to parse folder TOML config: {}", e);
-                            }
+                    let parsed = match parts.as_slice() {
+                        [_, toml_content, markdown_content] => {
+                            toml_edit::de::from_str::<super::FolderConfig>(toml_content)
+                                .map(|config| (config, markdown_content.trim().to_string()))
+                                .map_err(|e| e.to_string())
                         }
-                    }
+                        _ => Err("front matter is not closed with +++".to_string()),
+                    };
+
+                    // A folder whose access rules can't be read is locked
+                    // rather than opened to everyone
+                    let (config, description_markdown) = parsed.unwrap_or_else(|e| {
+                        warn!(
+                            "Failed to parse {:?}, hiding the folder from everyone but admins: {}",
+                            folder_md_path, e
+                        );
+                        (super::FolderConfig::locked(), String::new())
+                    });
+                    return Some(super::FolderMetadata {
+                        config,
+                        description_markdown,
+                    });
                 }
 
                 // No TOML front matter, treat entire content as markdown
@@ -534,6 +631,12 @@ impl Gallery {
                         title: None,
                         require_auth: false,
                         allowed_users: None,
+                        allowed_groups: None,
+                        download_large: None,
+                        download_original: None,
+                        sort: None,
+                        order: None,
+                        cover: None,
                     },
                     description_markdown: content,
                 })
@@ -543,6 +646,29 @@ impl Gallery {
     }
 
     async fn read_sidecar_markdown(&self, image_path: &str) -> Option<String> {
+        let content = self.read_sidecar_text(image_path).await?;
+        // A sidecar may hold nothing but front matter
+        if content.trim().is_empty() {
+            return None;
+        }
+        let parser = Parser::new(&content);
+        let mut html_output = String::new();
+        html::push_html(&mut html_output, parser);
+        Some(html_output)
+    }
+
+    /// Read the raw markdown caption stored next to an image as `<stem>.md`
+    pub(crate) async fn read_sidecar_text(&self, image_path: &str) -> Option<String> {
+        self.read_sidecar_full(image_path)
+            .await
+            .map(|sidecar| sidecar.caption_markdown)
+    }
+
+    /// Read an image's sidecar caption along with its optional TOML front matter
+    pub(crate) async fn read_sidecar_full(
+        &self,
+        image_path: &str,
+    ) -> Option<super::SidecarMetadata> {
         let path = StdPath::new(image_path);
         let stem = path.file_stem()?;
         let parent = path.parent()?;
@@ -550,15 +676,28 @@ impl Gallery {
         let md_filename = format!("{}.md", stem.to_str()?);
         let md_path = self.config.source_directory.join(parent).join(md_filename);
 
-        match tokio::fs::read_to_string(&md_path).await {
-            Ok(content) => {
-                let parser = Parser::new(&content);
-                let mut html_output = String::new();
-                html::push_html(&mut html_output, parser);
-                Some(html_output)
+        let content = tokio::fs::read_to_string(&md_path).await.ok()?;
+        if content.trim_start().starts_with("+++") {
+            let parts: Vec<&str> = content.splitn(3, "+++").collect();
+            if parts.len() >= 3 {
+                match toml_edit::de::from_str::<super::SidecarConfig>(parts[1]) {
+                    Ok(config) => {
+                        return Some(super::SidecarMetadata {
+                            config,
+                            caption_markdown: parts[2].trim().to_string(),
+                        });
+                    }
+                    Err(e) => {
+                        debug!("Failed to parse sidecar TOML for {}: {}", image_path, e);
+                    }
+                }
             }
-            Err(_) => None,
         }
+
+        Some(super::SidecarMetadata {
+            config: super::SidecarConfig::default(),
+            caption_markdown: content,
+        })
     }
 
     pub(crate) async fn get_image_metadata_cached(
@@ -581,6 +720,9 @@ impl Gallery {
                     file_size: file_metadata.len(),
                     modification_date: metadata.modification_date,
                     color_profile: metadata.color_profile.clone(),
+                    placeholder: metadata.placeholder.clone(),
+                    descriptive_info: metadata.descriptive_info.clone(),
+                    duration: metadata.duration,
                 });
             }
         }
@@ -605,6 +747,9 @@ impl Gallery {
             file_size,
             modification_date: metadata.modification_date,
             color_profile: metadata.color_profile,
+            placeholder: metadata.placeholder,
+            descriptive_info: metadata.descriptive_info,
+            duration: metadata.duration,
         })
     }
 
@@ -618,7 +763,7 @@ impl Gallery {
     pub async fn get_gallery_preview_for_user(
         &self,
         max_items: usize,
-        user: Option<&str>,
+        user: Option<&UserIdentity>,
     ) -> Result<Vec<GalleryItem>, GalleryError> {
         use rand::seq::SliceRandom;
         use rand::{Rng, rng};
@@ -658,7 +803,7 @@ impl Gallery {
         current_depth: usize,
         max_depth: usize,
         max_per_folder: usize,
-        user: Option<&'a str>,
+        user: Option<&'a UserIdentity>,
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), GalleryError>> + Send + 'a>>
     {
         Box::pin(async move {
@@ -667,7 +812,7 @@ impl Gallery {
             }
 
             // Check access control for current folder
-            if !self.check_folder_access(path, user).await {
+            if !self.check_folder_access(path, user, None).await {
                 return Ok(());
             }
 
@@ -708,7 +853,7 @@ impl Gallery {
                     }
 
                     // Check access control for this directory
-                    if !self.check_folder_access(&item_path, user).await {
+                    if !self.check_folder_access(&item_path, user, None).await {
                         continue;
                     }
 
@@ -724,13 +869,15 @@ impl Gallery {
                     .await?;
                 } else if self.is_image(&file_name) && folder_items.len() < max_per_folder {
                     // Get metadata from cache if available
-                    let (dimensions, capture_date, modification_date) = {
+                    let (dimensions, capture_date, modification_date, placeholder, duration) = {
                         let cache = self.metadata_cache.read().await;
                         if let Some(metadata) = cache.get(&item_path) {
                             (
                                 Some(metadata.dimensions),
                                 metadata.capture_date,
                                 metadata.modification_date,
+                                metadata.placeholder.clone(),
+                                metadata.duration,
                             )
                         } else {
                             // If not in cache, try to extract it now
@@ -740,13 +887,16 @@ impl Gallery {
                                     Some(metadata.dimensions),
                                     metadata.capture_date,
                                     metadata.modification_date,
+                                    metadata.placeholder,
+                                    metadata.duration,
                                 ),
-                                Err(_) => (None, None, None),
+                                Err(_) => (None, None, None, None, None),
                             }
                         }
                     };
 
                     let is_new = self.is_new(modification_date);
+                    let video = video_info(&file_name, duration);
 
                     let encoded_path = urlencoding::encode(&item_path);
                     let thumbnail_url = format!(
@@ -774,6 +924,8 @@ impl Gallery {
                         dimensions,
                         capture_date,
                         is_new,
+                        placeholder,
+                        video,
                     });
                 }
             }
@@ -828,20 +980,30 @@ impl Gallery {
     /// Check if a folder requires authentication
     pub(crate) async fn is_folder_access_restricted(&self, folder_path: &str) -> bool {
         if let Some(folder_metadata) = self.read_folder_metadata_full(folder_path).await {
-            folder_metadata.config.require_auth || folder_metadata.config.allowed_users.is_some()
+            folder_metadata.config.is_access_restricted()
         } else {
             false
         }
     }
 
-    /// Check if a user has access to a specific folder
-    pub(crate) async fn check_folder_access(&self, folder_path: &str, user: Option<&str>) -> bool {
+    /// Check if a user, or a visitor holding a share link, has access to a
+    /// specific folder
+    pub(crate) async fn check_folder_access(
+        &self,
+        folder_path: &str,
+        user: Option<&UserIdentity>,
+        share: Option<&Share>,
+    ) -> bool {
+        if share.is_some_and(|s| s.covers_folder(folder_path)) {
+            return true;
+        }
+
         // If folder doesn't have access restrictions, allow access
         if let Some(folder_metadata) = self.read_folder_metadata_full(folder_path).await {
             let config = &folder_metadata.config;
 
-            // If folder doesn't require auth and has no user restrictions, allow access
-            if !config.require_auth && config.allowed_users.is_none() {
+            // If folder doesn't require auth and has no user or group restrictions, allow access
+            if !config.is_access_restricted() {
                 return true;
             }
 
@@ -851,14 +1013,26 @@ impl Gallery {
                 None => return false,
             };
 
-            // If folder has specific allowed users, check if user is in the list
-            if let Some(allowed_users) = &config.allowed_users {
-                allowed_users.contains(&user.to_string())
-            } else if config.require_auth {
-                // Folder requires auth but no specific users listed - any authenticated user can access
-                true
+            // Admins can see every folder
+            if user.is_admin() {
+                return true;
+            }
+
+            // If folder lists allowed users or groups, the user must be named or in a group
+            if config.allowed_users.is_some() || config.allowed_groups.is_some() {
+                let in_users = config
+                    .allowed_users
+                    .iter()
+                    .flatten()
+                    .any(|allowed| *allowed == user.username);
+                let in_groups = config
+                    .allowed_groups
+                    .iter()
+                    .flatten()
+                    .any(|group| user.groups.contains(group));
+                in_users || in_groups
             } else {
-                // Default allow
+                // Folder requires auth but no specific users listed - any authenticated user can access
                 true
             }
         } else {
@@ -867,13 +1041,46 @@ impl Gallery {
         }
     }
 
+    /// Find the download permission list that applies to `folder_path`, looking
+    /// at the folder itself and then each parent. `None` means no folder sets one.
+    pub(crate) async fn download_permission(
+        &self,
+        folder_path: &str,
+        kind: DownloadKind,
+    ) -> Option<Vec<String>> {
+        let mut current = folder_path;
+        loop {
+            if let Some(list) = self
+                .read_folder_metadata_full(current)
+                .await
+                .and_then(|m| m.config.download_permission(kind).cloned())
+            {
+                return Some(list);
+            }
+            if current.is_empty() {
+                return None;
+            }
+            current = current.rsplit_once('/').map(|(p, _)| p).unwrap_or("");
+        }
+    }
+
     /// Check if a folder path or any of its parent folders have access restrictions
-    pub(crate) async fn check_path_access(&self, path: &str, user: Option<&str>) -> bool {
+    pub(crate) async fn check_path_access(
+        &self,
+        path: &str,
+        user: Option<&UserIdentity>,
+        share: Option<&Share>,
+    ) -> bool {
+        // A share opens its folder regardless of restrictions on the parents
+        if share.is_some_and(|s| s.covers_folder(path)) {
+            return true;
+        }
+
         // Check the current path and all parent paths for access restrictions
         let mut current_path = String::new();
 
         // Always check root folder access first
-        if !self.check_folder_access("", user).await {
+        if !self.check_folder_access("", user, share).await {
             return false;
         }
 
@@ -887,7 +1094,7 @@ impl Gallery {
                 current_path.push_str(part);
 
                 // Check access for this path level
-                if !self.check_folder_access(&current_path, user).await {
+                if !self.check_folder_access(&current_path, user, share).await {
                     return false;
                 }
             }
@@ -896,24 +1103,119 @@ impl Gallery {
         true
     }
 
+    /// List every folder and image that anonymous visitors can see. Hidden folders
+    /// and folders with `require_auth` or `allowed_users` are left out together
+    /// with everything beneath them. Folders report the newest modification time
+    /// of any image they contain.
+    pub async fn list_public_entries(&self) -> Vec<PublicEntry> {
+        if self.is_folder_hidden_or_restricted("").await {
+            return Vec::new();
+        }
+
+        let mut excluded_folders: Vec<String> = Vec::new();
+        let mut folders = vec![String::new()];
+        let mut images = Vec::new();
+
+        for entry in WalkDir::new(&self.config.source_directory)
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'))
+            .flatten()
+        {
+            let Ok(relative) = entry.path().strip_prefix(&self.config.source_directory) else {
+                continue;
+            };
+            let relative = relative.to_string_lossy().replace('\\', "/");
+
+            let is_in_excluded = excluded_folders.iter().any(|folder| {
+                relative.starts_with(folder.as_str()) && relative[folder.len()..].starts_with('/')
+            });
+            if is_in_excluded {
+                continue;
+            }
+
+            if entry.file_type().is_dir() {
+                if self.is_folder_hidden_or_restricted(&relative).await {
+                    excluded_folders.push(relative);
+                } else {
+                    folders.push(relative);
+                }
+            } else if entry.file_type().is_file() && self.is_image(&relative) {
+                let file_modified = entry.metadata().ok().and_then(|m| m.modified().ok());
+                images.push((relative, file_modified));
+            }
+        }
+
+        let mut folder_modified: HashMap<String, SystemTime> = HashMap::new();
+        let mut entries = Vec::with_capacity(folders.len() + images.len());
+        {
+            let cache = self.metadata_cache.read().await;
+            for (path, file_modified) in images {
+                let last_modified = cache
+                    .get(&path)
+                    .and_then(|m| m.modification_date)
+                    .or(file_modified);
+
+                if let Some(modified) = last_modified {
+                    let mut folder = path.as_str();
+                    while let Some((parent, _)) = folder.rsplit_once('/') {
+                        folder = parent;
+                        Self::update_newest(&mut folder_modified, folder, modified);
+                    }
+                    Self::update_newest(&mut folder_modified, "", modified);
+                }
+
+                entries.push(PublicEntry {
+                    path,
+                    is_directory: false,
+                    last_modified,
+                });
+            }
+        }
+
+        let folder_entries = folders.into_iter().map(|path| PublicEntry {
+            last_modified: folder_modified.get(&path).copied(),
+            path,
+            is_directory: true,
+        });
+        folder_entries.chain(entries).collect()
+    }
+
+    fn update_newest(map: &mut HashMap<String, SystemTime>, key: &str, time: SystemTime) {
+        let newest = map.entry(key.to_string()).or_insert(time);
+        if time > *newest {
+            *newest = time;
+        }
+    }
+
+    /// Check whether a single folder is hidden or restricted, ignoring its parents
+    async fn is_folder_hidden_or_restricted(&self, folder_path: &str) -> bool {
+        self.read_folder_metadata_full(folder_path)
+            .await
+            .map(|m| m.config.hidden || m.config.is_access_restricted())
+            .unwrap_or(false)
+    }
+
     /// Filter gallery items based on user access permissions
     pub(crate) async fn filter_items_by_access(
         &self,
         items: Vec<GalleryItem>,
-        user: Option<&str>,
+        user: Option<&UserIdentity>,
+        share: Option<&Share>,
     ) -> Vec<GalleryItem> {
         let mut filtered_items = Vec::new();
 
         for item in items {
             if item.is_directory {
                 // For directories, check if user has access
-                if self.check_folder_access(&item.path, user).await {
+                if self.check_folder_access(&item.path, user, share).await {
                     filtered_items.push(item);
                 }
             } else {
                 // For images, check access to the parent folder
                 let parent_path = item.parent_path.as_deref().unwrap_or("");
-                if self.check_path_access(parent_path, user).await {
+                if self.check_path_access(parent_path, user, share).await {
                     filtered_items.push(item);
                 }
             }
@@ -923,6 +1225,79 @@ impl Gallery {
     }
 }
 
+/// Directories come first, ordered by the manual `order` list and then by
+/// display name. Images follow in the folder's configured sort order.
+fn compare_items(
+    a: &GalleryItem,
+    b: &GalleryItem,
+    sort: super::FolderSort,
+    manual_order: &HashMap<&str, usize>,
+    modification_dates: &HashMap<String, SystemTime>,
+) -> Ordering {
+    use super::FolderSort;
+
+    match (a.is_directory, b.is_directory) {
+        (true, false) => return Ordering::Less,
+        (false, true) => return Ordering::Greater,
+        _ => {}
+    }
+
+    // Listed entries come before unlisted ones, in list order
+    let by_manual_order = match (
+        manual_order.get(a.name.as_str()),
+        manual_order.get(b.name.as_str()),
+    ) {
+        (Some(a_pos), Some(b_pos)) => a_pos.cmp(b_pos),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    };
+    if by_manual_order != Ordering::Equal {
+        return by_manual_order;
+    }
+
+    if a.is_directory {
+        let a_sort_name = a.display_name.as_ref().unwrap_or(&a.name);
+        let b_sort_name = b.display_name.as_ref().unwrap_or(&b.name);
+        return a_sort_name.cmp(b_sort_name);
+    }
+
+    // Dated images first, undated ones after in name order
+    let by_date =
+        |a_date: Option<&SystemTime>, b_date: Option<&SystemTime>, descending: bool| match (
+            a_date, b_date,
+        ) {
+            (Some(a_date), Some(b_date)) if descending => b_date.cmp(a_date),
+            (Some(a_date), Some(b_date)) => a_date.cmp(b_date),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+
+    let ordering = match sort {
+        FolderSort::CaptureDate | FolderSort::Manual => {
+            by_date(a.capture_date.as_ref(), b.capture_date.as_ref(), false)
+        }
+        FolderSort::CaptureDateDesc => {
+            by_date(a.capture_date.as_ref(), b.capture_date.as_ref(), true)
+        }
+        FolderSort::Modified => by_date(
+            modification_dates.get(&a.path),
+            modification_dates.get(&b.path),
+            false,
+        ),
+        FolderSort::ModifiedDesc => by_date(
+            modification_dates.get(&a.path),
+            modification_dates.get(&b.path),
+            true,
+        ),
+        FolderSort::Filename => Ordering::Equal,
+        FolderSort::FilenameDesc => return b.name.cmp(&a.name),
+    };
+
+    ordering.then_with(|| a.name.cmp(&b.name))
+}
+
 // Helper struct that includes file size
 pub(crate) struct ImageMetadataWithSize {
     pub dimensions: (u32, u32),
@@ -932,6 +1307,9 @@ pub(crate) struct ImageMetadataWithSize {
     pub file_size: u64,
     pub modification_date: Option<SystemTime>,
     pub color_profile: Option<String>,
+    pub placeholder: Option<super::ImagePlaceholder>,
+    pub descriptive_info: Option<super::DescriptiveInfo>,
+    pub duration: Option<f64>,
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -0,0 +1,519 @@
+//! Descriptive metadata embedded by cataloguing tools such as Lightroom:
+//! XMP packets (JPEG APP1, AVIF XMP item) and IPTC-IIM records (JPEG APP13).
+
+use super::DescriptiveInfo;
+use std::path::Path;
+use tracing::trace;
+
+pub(crate) const XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const PHOTOSHOP_SIGNATURE: &[u8] = b"Photoshop 3.0\0";
+/// Photoshop image resource holding IPTC-NAA records
+const IPTC_RESOURCE_ID: u16 = 0x0404;
+
+/// Read titles, captions, keywords, rating, creator and copyright from an
+/// image. XMP wins over IPTC field by field, as Lightroom writes both and XMP
+/// is the one that keeps full Unicode.
+pub(crate) fn read_descriptive_info(path: &Path) -> Option<DescriptiveInfo> {
+    let extension = path
+        .extension()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_lowercase());
+
+    let (xmp, iptc) = match extension.as_deref() {
+        Some("jpg") | Some("jpeg") => {
+            let segments = match super::image_processing::formats::jpeg::read_app_segments(path) {
+                Ok(segments) => segments,
+                Err(e) => {
+                    trace!("Could not read JPEG segments of {:?}: {}", path, e);
+                    return None;
+                }
+            };
+            let xmp = segments
+                .iter()
+                .find(|(marker, data)| *marker == 0xE1 && data.starts_with(XMP_SIGNATURE))
+                .map(|(_, data)| parse_xmp(&data[XMP_SIGNATURE.len()..]));
+            let iptc = iptc_from_app13(&segments).map(|records| parse_iptc(&records));
+            (xmp, iptc)
+        }
+        #[cfg(feature = "avif")]
+        Some("avif") => (
+            super::image_processing::formats::avif::extract_xmp_data(path)
+                .map(|data| parse_xmp(&data)),
+            None,
+        ),
+        #[cfg(feature = "jxl")]
+        Some("jxl") => (
+            std::fs::read(path)
+                .ok()
+                .and_then(|data| {
+                    super::image_processing::formats::jxl_container::metadata_blocks(&data).xmp
+                })
+                .map(|data| parse_xmp(&data)),
+            None,
+        ),
+        _ => (None, None),
+    };
+
+    let info = merge(xmp.unwrap_or_default(), iptc.unwrap_or_default());
+    (info != DescriptiveInfo::default()).then_some(info)
+}
+
+fn merge(xmp: DescriptiveInfo, iptc: DescriptiveInfo) -> DescriptiveInfo {
+    DescriptiveInfo {
+        title: xmp.title.or(iptc.title),
+        caption: xmp.caption.or(iptc.caption),
+        keywords: if xmp.keywords.is_empty() {
+            iptc.keywords
+        } else {
+            xmp.keywords
+        },
+        rating: xmp.rating.or(iptc.rating),
+        creator: xmp.creator.or(iptc.creator),
+        copyright: xmp.copyright.or(iptc.copyright),
+    }
+}
+
+/// Parse the fields we care about out of an XMP packet. This is a plain
+/// text scan rather than a full RDF parser: it understands the element and
+/// attribute forms Lightroom, Photoshop and Capture One produce.
+pub(crate) fn parse_xmp(packet: &[u8]) -> DescriptiveInfo {
+    let xml = String::from_utf8_lossy(packet);
+
+    let rating = ["xmp:Rating", "xap:Rating"]
+        .iter()
+        .find_map(|name| simple_property(&xml, name))
+        .and_then(|value| value.trim().parse::<f32>().ok())
+        // -1 marks a rejected photo; anything outside 0-5 isn't a star rating
+        .filter(|rating| (0.0..=5.0).contains(rating))
+        .map(|rating| rating.round() as u8);
+
+    let creators = list_property(&xml, "dc:creator");
+
+    DescriptiveInfo {
+        title: alt_property(&xml, "dc:title"),
+        caption: alt_property(&xml, "dc:description"),
+        keywords: list_property(&xml, "dc:subject"),
+        rating,
+        creator: (!creators.is_empty()).then(|| creators.join(", ")),
+        copyright: alt_property(&xml, "dc:rights"),
+    }
+}
+
+/// Text of a language alternative (`rdf:Alt`), preferring `x-default`
+fn alt_property(xml: &str, name: &str) -> Option<String> {
+    let content = element_content(xml, name)?;
+    let items = list_items(content);
+    let value = if items.is_empty() {
+        decode_entities(content.trim())
+    } else {
+        items
+            .iter()
+            .find(|(default, _)| *default)
+            .or_else(|| items.first())
+            .map(|(_, text)| text.clone())?
+    };
+    non_empty(value)
+}
+
+/// Entries of an `rdf:Bag` or `rdf:Seq`
+fn list_property(xml: &str, name: &str) -> Vec<String> {
+    element_content(xml, name)
+        .map(list_items)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(_, text)| non_empty(text))
+        .collect()
+}
+
+/// A simple value, written either as an attribute of `rdf:Description` or
+/// as an element of its own
+fn simple_property(xml: &str, name: &str) -> Option<String> {
+    element_content(xml, name)
+        .map(|content| decode_entities(content.trim()))
+        .or_else(|| attribute_value(xml, name))
+}
+
+/// Inner text of the first `<name ...>...</name>` element
+fn element_content<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+
+    let mut search_from = 0;
+    while let Some(found) = xml[search_from..].find(&open) {
+        let tag_start = search_from + found;
+        let after_name = tag_start + open.len();
+        search_from = after_name;
+
+        // Make sure we matched the whole name, not a prefix of a longer one
+        match xml[after_name..].chars().next() {
+            Some(c) if c == '>' || c == '/' || c.is_whitespace() => {}
+            _ => continue,
+        }
+
+        let tag_end = after_name + xml[after_name..].find('>')?;
+        if xml[..tag_end].ends_with('/') {
+            return None;
+        }
+        let content_start = tag_end + 1;
+        let content_end = content_start + xml[content_start..].find(&close)?;
+        return Some(&xml[content_start..content_end]);
+    }
+
+    None
+}
+
+/// `rdf:li` entries as (is the `x-default` language, decoded text)
+fn list_items(content: &str) -> Vec<(bool, String)> {
+    let mut items = Vec::new();
+    let mut rest = content;
+
+    while let Some(start) = rest.find("<rdf:li") {
+        let after = &rest[start..];
+        let Some(tag_end) = after.find('>') else {
+            break;
+        };
+        let tag = &after[..tag_end];
+        if tag.ends_with('/') {
+            rest = &after[tag_end + 1..];
+            continue;
+        }
+
+        let body = &after[tag_end + 1..];
+        let Some(body_end) = body.find("</rdf:li>") else {
+            break;
+        };
+        items.push((
+            tag.contains("x-default"),
+            decode_entities(body[..body_end].trim()),
+        ));
+        rest = &body[body_end..];
+    }
+
+    items
+}
+
+fn attribute_value(xml: &str, name: &str) -> Option<String> {
+    let pattern = format!("{}=", name);
+    let mut search_from = 0;
+
+    while let Some(found) = xml[search_from..].find(&pattern) {
+        let start = search_from + found;
+        search_from = start + pattern.len();
+
+        if !xml[..start]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_whitespace)
+        {
+            continue;
+        }
+
+        let value = &xml[start + pattern.len()..];
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let end = value[1..].find(quote)?;
+        return Some(decode_entities(&value[1..1 + end]));
+    }
+
+    None
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        let entity = &rest[amp..];
+        let Some(semicolon) = entity.find(';') else {
+            decoded.push_str(entity);
+            return decoded;
+        };
+
+        let name = &entity[1..semicolon];
+        let replacement = match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => name
+                .strip_prefix("#x")
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| name.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+
+        match replacement {
+            Some(c) => {
+                decoded.push(c);
+                rest = &entity[semicolon + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &entity[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Pull the IPTC-NAA block out of the Photoshop image resources stored in
+/// APP13. Large resource blocks may continue across several segments.
+pub(crate) fn iptc_from_app13(segments: &[(u8, Vec<u8>)]) -> Option<Vec<u8>> {
+    let mut resources = Vec::new();
+    for (_, data) in segments.iter().filter(|(marker, _)| *marker == 0xED) {
+        resources.extend_from_slice(data.strip_prefix(PHOTOSHOP_SIGNATURE).unwrap_or(data));
+    }
+
+    let mut offset = 0;
+    while resources.len() >= offset + 12 && &resources[offset..offset + 4] == b"8BIM" {
+        let id = u16::from_be_bytes([resources[offset + 4], resources[offset + 5]]);
+        // Pascal string name, padded so length byte plus name is even
+        let name_length = resources[offset + 6] as usize;
+        let name_size = (name_length + 1 + 1) & !1;
+        let size_offset = offset + 6 + name_size;
+        let size = u32::from_be_bytes(
+            resources
+                .get(size_offset..size_offset + 4)?
+                .try_into()
+                .ok()?,
+        ) as usize;
+        let data_start = size_offset + 4;
+        let data = resources.get(data_start..data_start + size)?;
+
+        if id == IPTC_RESOURCE_ID {
+            return Some(data.to_vec());
+        }
+        offset = data_start + size + (size & 1);
+    }
+
+    None
+}
+
+/// Parse IPTC-IIM application records (record 2)
+pub(crate) fn parse_iptc(records: &[u8]) -> DescriptiveInfo {
+    let mut info = DescriptiveInfo::default();
+    let mut creators = Vec::new();
+    let mut offset = 0;
+
+    while records.len() >= offset + 5 && records[offset] == 0x1C {
+        let record = records[offset + 1];
+        let dataset = records[offset + 2];
+        let length = u16::from_be_bytes([records[offset + 3], records[offset + 4]]) as usize;
+        // Extended-length datasets are only used for binary payloads
+        if length & 0x8000 != 0 {
+            break;
+        }
+        let Some(value) = records.get(offset + 5..offset + 5 + length) else {
+            break;
+        };
+        offset += 5 + length;
+
+        if record != 2 {
+            continue;
+        }
+        let Some(text) = non_empty(decode_iptc_text(value)) else {
+            continue;
+        };
+        match dataset {
+            5 => info.title = info.title.or(Some(text)),
+            25 => info.keywords.push(text),
+            80 => creators.push(text),
+            116 => info.copyright = info.copyright.or(Some(text)),
+            120 => info.caption = info.caption.or(Some(text)),
+            _ => {}
+        }
+    }
+
+    if !creators.is_empty() {
+        info.creator = Some(creators.join(", "));
+    }
+    info
+}
+
+/// IPTC text is UTF-8 in anything written this century, Latin-1 before that
+fn decode_iptc_text(value: &[u8]) -> String {
+    match std::str::from_utf8(value) {
+        Ok(text) => text.trim().to_string(),
+        Err(_) => value
+            .iter()
+            .map(|&b| b as char)
+            .collect::<String>()
+            .trim()
+            .to_string(),
+    }
+}
+
+fn non_empty(text: String) -> Option<String> {
+    (!text.is_empty()).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIGHTROOM_XMP: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+   xmp:Rating="4">
+   <dc:title>
+    <rdf:Alt>
+     <rdf:li xml:lang="de">Sonnenuntergang</rdf:li>
+     <rdf:li xml:lang="x-default">Sunset over the bay</rdf:li>
+    </rdf:Alt>
+   </dc:title>
+   <dc:description>
+    <rdf:Alt>
+     <rdf:li xml:lang="x-default">Fog &amp; light at &#x201C;golden hour&#x201D;</rdf:li>
+    </rdf:Alt>
+   </dc:description>
+   <dc:subject>
+    <rdf:Bag>
+     <rdf:li>sunset</rdf:li>
+     <rdf:li>San Francisco</rdf:li>
+     <rdf:li></rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+   <dc:creator>
+    <rdf:Seq>
+     <rdf:li>Jane Doe</rdf:li>
+    </rdf:Seq>
+   </dc:creator>
+   <dc:rights>
+    <rdf:Alt>
+     <rdf:li xml:lang="x-default">© 2024 Jane Doe</rdf:li>
+    </rdf:Alt>
+   </dc:rights>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+    fn iptc_dataset(dataset: u8, value: &[u8]) -> Vec<u8> {
+        let mut record = vec![0x1C, 2, dataset];
+        record.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        record.extend_from_slice(value);
+        record
+    }
+
+    #[test]
+    fn test_parse_lightroom_xmp() {
+        let info = parse_xmp(LIGHTROOM_XMP.as_bytes());
+        assert_eq!(info.title.as_deref(), Some("Sunset over the bay"));
+        assert_eq!(
+            info.caption.as_deref(),
+            Some("Fog & light at \u{201C}golden hour\u{201D}")
+        );
+        assert_eq!(info.keywords, vec!["sunset", "San Francisco"]);
+        assert_eq!(info.rating, Some(4));
+        assert_eq!(info.creator.as_deref(), Some("Jane Doe"));
+        assert_eq!(info.copyright.as_deref(), Some("© 2024 Jane Doe"));
+    }
+
+    #[test]
+    fn test_parse_xmp_element_rating_and_rejects() {
+        let element = r#"<rdf:Description><xap:Rating>5</xap:Rating></rdf:Description>"#;
+        assert_eq!(parse_xmp(element.as_bytes()).rating, Some(5));
+
+        let rejected = r#"<rdf:Description xmp:Rating="-1"/>"#;
+        assert_eq!(parse_xmp(rejected.as_bytes()).rating, None);
+
+        // xmp:RatingPercent must not be mistaken for xmp:Rating
+        let percent = r#"<rdf:Description xmp:RatingPercent="80"/>"#;
+        assert_eq!(parse_xmp(percent.as_bytes()).rating, None);
+    }
+
+    #[test]
+    fn test_parse_iptc_records() {
+        let mut records = iptc_dataset(5, b"Harbour");
+        records.extend(iptc_dataset(25, b"boats"));
+        records.extend(iptc_dataset(25, b"night"));
+        records.extend(iptc_dataset(80, b"John Smith"));
+        records.extend(iptc_dataset(116, b"Copyright John Smith"));
+        // Latin-1 caption
+        records.extend(iptc_dataset(120, b"Caf\xe9 lights"));
+
+        let info = parse_iptc(&records);
+        assert_eq!(info.title.as_deref(), Some("Harbour"));
+        assert_eq!(info.keywords, vec!["boats", "night"]);
+        assert_eq!(info.creator.as_deref(), Some("John Smith"));
+        assert_eq!(info.copyright.as_deref(), Some("Copyright John Smith"));
+        assert_eq!(info.caption.as_deref(), Some("Café lights"));
+        assert_eq!(info.rating, None);
+    }
+
+    #[test]
+    fn test_xmp_takes_precedence_over_iptc() {
+        let xmp = DescriptiveInfo {
+            title: Some("XMP title".to_string()),
+            ..Default::default()
+        };
+        let iptc = DescriptiveInfo {
+            title: Some("IPTC title".to_string()),
+            caption: Some("IPTC caption".to_string()),
+            keywords: vec!["iptc".to_string()],
+            ..Default::default()
+        };
+
+        let merged = merge(xmp, iptc);
+        assert_eq!(merged.title.as_deref(), Some("XMP title"));
+        assert_eq!(merged.caption.as_deref(), Some("IPTC caption"));
+        assert_eq!(merged.keywords, vec!["iptc"]);
+    }
+
+    #[test]
+    fn test_read_descriptive_info_from_jpeg() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("tagged.jpg");
+
+        let mut jpeg = Vec::new();
+        image::DynamicImage::new_rgb8(8, 8)
+            .write_to(
+                &mut std::io::Cursor::new(&mut jpeg),
+                image::ImageFormat::Jpeg,
+            )
+            .unwrap();
+
+        let segment = |marker: u8, payload: &[u8]| {
+            let mut segment = vec![0xFF, marker];
+            segment.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+            segment.extend_from_slice(payload);
+            segment
+        };
+
+        let mut xmp = XMP_SIGNATURE.to_vec();
+        xmp.extend_from_slice(LIGHTROOM_XMP.as_bytes());
+
+        let mut records = iptc_dataset(5, b"IPTC title");
+        records.extend(iptc_dataset(80, b"Someone Else"));
+        let mut app13 = PHOTOSHOP_SIGNATURE.to_vec();
+        app13.extend_from_slice(b"8BIM");
+        app13.extend_from_slice(&IPTC_RESOURCE_ID.to_be_bytes());
+        app13.extend_from_slice(&[0, 0]);
+        app13.extend_from_slice(&(records.len() as u32).to_be_bytes());
+        app13.extend_from_slice(&records);
+
+        let mut output = jpeg[..2].to_vec();
+        output.extend(segment(0xE1, &xmp));
+        output.extend(segment(0xED, &app13));
+        output.extend_from_slice(&jpeg[2..]);
+        std::fs::write(&path, output).unwrap();
+
+        let info = read_descriptive_info(&path).unwrap();
+        assert_eq!(info.title.as_deref(), Some("Sunset over the bay"));
+        assert_eq!(info.creator.as_deref(), Some("Jane Doe"));
+        assert_eq!(info.rating, Some(4));
+
+        // The file still decodes
+        assert!(image::open(&path).is_ok());
+
+        let plain = temp_dir.path().join("plain.jpg");
+        std::fs::write(&plain, &jpeg).unwrap();
+        assert!(read_descriptive_info(&plain).is_none());
+    }
+}
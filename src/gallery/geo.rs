@@ -0,0 +1,324 @@
+use super::search::LocatedImage;
+use super::{Gallery, GalleryError, LocationInfo, Share};
+use crate::LocationPrecision;
+use crate::login::UserIdentity;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
+
+/// Points closer together than this many screen pixels form one cluster
+const CLUSTER_CELL_PIXELS: f64 = 60.0;
+/// Deepest zoom level clustering is done for, as in common web map tiles
+pub(crate) const MAX_ZOOM: u8 = 22;
+/// Web Mercator stops short of the poles
+const MAX_LATITUDE: f64 = 85.051_128_78;
+
+impl LocationInfo {
+    pub fn new(latitude: f64, longitude: f64) -> Self {
+        LocationInfo {
+            latitude,
+            longitude,
+            google_maps_url: format!("https://maps.google.com/?q={},{}", latitude, longitude),
+            apple_maps_url: format!("https://maps.apple.com/?ll={},{}", latitude, longitude),
+        }
+    }
+
+    /// The location as precisely as `precision` allows, or `None` if it
+    /// must not be shown
+    pub fn with_precision(self, precision: LocationPrecision) -> Option<Self> {
+        match precision {
+            LocationPrecision::Exact => Some(self),
+            LocationPrecision::City => Some(LocationInfo::new(
+                round_to_city(self.latitude),
+                round_to_city(self.longitude),
+            )),
+            LocationPrecision::Hidden => None,
+        }
+    }
+}
+
+/// One decimal place is about 11 km, enough to place a town but not a house
+fn round_to_city(degrees: f64) -> f64 {
+    (degrees * 10.0).round() / 10.0
+}
+
+/// Position in Web Mercator pixels at `zoom`, where the world is
+/// 256 * 2^zoom pixels wide
+fn project(latitude: f64, longitude: f64, zoom: u8) -> (f64, f64) {
+    let size = 256.0 * f64::from(1u32 << zoom.min(MAX_ZOOM));
+    let latitude = latitude.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    let x = (longitude + 180.0) / 360.0 * size;
+    let y = (1.0 - (latitude.tan() + 1.0 / latitude.cos()).ln() / PI) / 2.0 * size;
+    (x, y)
+}
+
+/// Group images that would overlap on a map at `zoom` by snapping them to a
+/// grid of `CLUSTER_CELL_PIXELS` cells. Without a zoom level every image is
+/// its own group. Images within a group keep their order.
+pub(crate) fn cluster_images(
+    images: Vec<LocatedImage>,
+    zoom: Option<u8>,
+) -> Vec<Vec<LocatedImage>> {
+    let Some(zoom) = zoom else {
+        return images.into_iter().map(|image| vec![image]).collect();
+    };
+
+    let mut cells: BTreeMap<(i64, i64), Vec<LocatedImage>> = BTreeMap::new();
+    for image in images {
+        let (x, y) = project(image.latitude, image.longitude, zoom);
+        let cell = (
+            (x / CLUSTER_CELL_PIXELS).floor() as i64,
+            (y / CLUSTER_CELL_PIXELS).floor() as i64,
+        );
+        cells.entry(cell).or_default().push(image);
+    }
+    cells.into_values().collect()
+}
+
+/// GeoJSON `FeatureCollection` of map points
+#[derive(Debug, Serialize)]
+pub struct GeoFeatureCollection {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub features: Vec<GeoFeature>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GeoFeature {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub geometry: GeoPoint,
+    pub properties: GeoProperties,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GeoPoint {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// Longitude then latitude, as GeoJSON orders them
+    pub coordinates: [f64; 2],
+}
+
+/// A single image, or a cluster represented by its first image
+#[derive(Debug, Serialize)]
+pub struct GeoProperties {
+    pub cluster: bool,
+    pub point_count: usize,
+    pub path: String,
+    pub name: String,
+    pub thumbnail_url: String,
+    pub detail_url: String,
+    /// West, south, east and north edges of a cluster, for zooming in on it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bbox: Option<[f64; 4]>,
+}
+
+impl Gallery {
+    /// Geotagged images in `folder` and its subfolders that `user` (or a
+    /// share) may see. Hidden subfolders are left out, as they are from
+    /// folder listings; the coordinates are still exact.
+    pub(crate) async fn located_images_in(
+        &self,
+        folder: &str,
+        user: Option<&UserIdentity>,
+        share: Option<&Share>,
+    ) -> Result<Vec<LocatedImage>, GalleryError> {
+        if folder.split('/').any(|part| part == "..")
+            || !self.config.source_directory.join(folder).is_dir()
+        {
+            return Err(GalleryError::NotFound);
+        }
+
+        let mut visible_folders: HashMap<String, bool> = HashMap::new();
+        let mut visible = Vec::new();
+        for image in self.located_images().await {
+            let image_folder = image
+                .path
+                .rsplit_once('/')
+                .map(|(parent, _)| parent)
+                .unwrap_or("");
+            let relative = if folder.is_empty() {
+                image_folder
+            } else if image_folder == folder {
+                ""
+            } else if let Some(rest) = image_folder
+                .strip_prefix(folder)
+                .and_then(|rest| rest.strip_prefix('/'))
+            {
+                rest
+            } else {
+                continue;
+            };
+
+            let is_visible = match visible_folders.get(image_folder) {
+                Some(is_visible) => *is_visible,
+                None => {
+                    let is_visible = self.is_folder_mappable(folder, relative, user, share).await;
+                    visible_folders.insert(image_folder.to_string(), is_visible);
+                    is_visible
+                }
+            };
+            if is_visible {
+                visible.push(image);
+            }
+        }
+        Ok(visible)
+    }
+
+    /// Whether `relative`, a folder below `base`, may be shown on the map of
+    /// `base`: it must be accessible and not hidden itself or below a hidden
+    /// folder under `base`
+    async fn is_folder_mappable(
+        &self,
+        base: &str,
+        relative: &str,
+        user: Option<&UserIdentity>,
+        share: Option<&Share>,
+    ) -> bool {
+        let join = |rest: &str| {
+            if base.is_empty() {
+                rest.to_string()
+            } else if rest.is_empty() {
+                base.to_string()
+            } else {
+                format!("{}/{}", base, rest)
+            }
+        };
+
+        if !self.check_path_access(&join(relative), user, share).await {
+            return false;
+        }
+
+        let mut current = relative;
+        while !current.is_empty() {
+            if self
+                .read_folder_metadata_full(&join(current))
+                .await
+                .is_some_and(|m| m.config.hidden)
+            {
+                return false;
+            }
+            current = current.rsplit_once('/').map(|(p, _)| p).unwrap_or("");
+        }
+        true
+    }
+
+    /// GeoJSON for clustered images: a point at the average position of each
+    /// group, linking to its first image
+    pub(crate) fn geo_features(&self, clusters: Vec<Vec<LocatedImage>>) -> GeoFeatureCollection {
+        let prefix = self.config.url_prefix.trim_start_matches('/');
+        let features = clusters
+            .into_iter()
+            .filter_map(|images| {
+                let first = images.first()?;
+                let count = images.len() as f64;
+                let latitude = images.iter().map(|i| i.latitude).sum::<f64>() / count;
+                let longitude = images.iter().map(|i| i.longitude).sum::<f64>() / count;
+                let bbox = (images.len() > 1).then(|| {
+                    images.iter().fold(
+                        [f64::MAX, f64::MAX, f64::MIN, f64::MIN],
+                        |[west, south, east, north], i| {
+                            [
+                                west.min(i.longitude),
+                                south.min(i.latitude),
+                                east.max(i.longitude),
+                                north.max(i.latitude),
+                            ]
+                        },
+                    )
+                });
+
+                let encoded_path = urlencoding::encode(&first.path);
+                Some(GeoFeature {
+                    kind: "Feature",
+                    geometry: GeoPoint {
+                        kind: "Point",
+                        coordinates: [longitude, latitude],
+                    },
+                    properties: GeoProperties {
+                        cluster: images.len() > 1,
+                        point_count: images.len(),
+                        path: first.path.clone(),
+                        name: first
+                            .path
+                            .rsplit('/')
+                            .next()
+                            .unwrap_or(&first.path)
+                            .to_string(),
+                        thumbnail_url: format!("/{}/image/{}?size=thumbnail", prefix, encoded_path),
+                        detail_url: format!("/{}/detail/{}", prefix, encoded_path),
+                        bbox,
+                    },
+                })
+            })
+            .collect();
+
+        GeoFeatureCollection {
+            kind: "FeatureCollection",
+            features,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn located(path: &str, latitude: f64, longitude: f64) -> LocatedImage {
+        LocatedImage {
+            path: path.to_string(),
+            latitude,
+            longitude,
+        }
+    }
+
+    #[test]
+    fn test_location_precision() {
+        let location = LocationInfo::new(37.774_929, -122.419_416);
+
+        let exact = location.clone().with_precision(LocationPrecision::Exact);
+        assert_eq!(exact.unwrap().latitude, 37.774_929);
+
+        let city = location
+            .clone()
+            .with_precision(LocationPrecision::City)
+            .unwrap();
+        assert_eq!((city.latitude, city.longitude), (37.8, -122.4));
+        assert_eq!(
+            city.google_maps_url,
+            "https://maps.google.com/?q=37.8,-122.4"
+        );
+
+        assert!(location.with_precision(LocationPrecision::Hidden).is_none());
+    }
+
+    #[test]
+    fn test_cluster_images_by_zoom() {
+        let images = || {
+            vec![
+                // Two spots in San Francisco about 1 km apart, and Oakland
+                located("sf/a.jpg", 37.7749, -122.4194),
+                located("sf/b.jpg", 37.7849, -122.4094),
+                located("oakland/c.jpg", 37.8044, -122.2712),
+            ]
+        };
+
+        assert_eq!(cluster_images(images(), None).len(), 3);
+
+        // Zoomed out to the whole world everything overlaps
+        let world = cluster_images(images(), Some(2));
+        assert_eq!(world.len(), 1);
+        assert_eq!(world[0].len(), 3);
+
+        // At city scale San Francisco and Oakland separate
+        let city = cluster_images(images(), Some(10));
+        assert_eq!(city.len(), 2);
+        assert!(city.iter().any(|group| group.len() == 2));
+
+        // At street scale nothing overlaps
+        assert_eq!(cluster_images(images(), Some(17)).len(), 3);
+
+        // Zoom levels past the maximum are treated as the maximum
+        assert_eq!(cluster_images(images(), Some(40)).len(), 3);
+    }
+}
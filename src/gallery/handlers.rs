@@ -1,31 +1,291 @@
-use super::{GalleryQuery, NavigationImage};
-use crate::AppState;
+use super::image_processing::OutputFormat;
+use super::share::{SHARE_GRANTS_COOKIE, Share, ShareKind, parse_grants};
+use super::{BreadcrumbItem, DownloadKind, GalleryItem, GalleryQuery, ImageInfo, NavigationImage};
+use crate::login::UserIdentity;
+use crate::{AppState, LocationPrecision};
 use axum::{
+    body::Body,
     extract::{Path, Query, State},
-    http::{HeaderMap, StatusCode},
-    response::{Html, IntoResponse},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{Html, IntoResponse, Json},
 };
+use serde::{Deserialize, Serialize};
+use tokio_util::io::ReaderStream;
 use tracing::error;
 
-fn has_download_permission(app_state: &AppState, headers: &HeaderMap) -> bool {
+/// Whether `user` may fetch `kind` downloads of images in `folder_path`. Folders
+/// without a configured permission list keep the original rule: anyone signed in.
+pub(crate) async fn has_download_permission(
+    app_state: &AppState,
+    gallery: &super::Gallery,
+    folder_path: &str,
+    kind: DownloadKind,
+    user: Option<&UserIdentity>,
+    share: Option<&Share>,
+) -> bool {
     // If no user database is configured, allow all downloads
     if app_state.config.app.user_database.is_none() {
         return true;
     }
 
-    // Check if user is authenticated with the login system
-    crate::login::is_authenticated(headers, &app_state.config.app.cookie_secret)
+    if share.is_some_and(|s| s.allow_download && s.covers_folder(folder_path)) {
+        return true;
+    }
+
+    if user.is_some_and(|u| u.is_admin()) {
+        return true;
+    }
+
+    match gallery.download_permission(folder_path, kind).await {
+        Some(allowed) => allowed.iter().any(|entry| match entry.as_str() {
+            "everyone" => true,
+            "authenticated" => user.is_some(),
+            principal => user.is_some_and(|u| u.matches(principal)),
+        }),
+        None => user.is_some(),
+    }
 }
 
-fn get_authenticated_user(app_state: &AppState, headers: &HeaderMap) -> Option<String> {
+/// The download permission a `?size=` value needs: `Large` for sizes marked
+/// `requires_auth`, `Original` when no size is given, none otherwise. `Err`
+/// for sizes the gallery doesn't have.
+fn download_kind_for_size(
+    gallery: &super::Gallery,
+    size: Option<&str>,
+) -> Result<Option<DownloadKind>, ()> {
+    let Some(size) = size else {
+        return Ok(Some(DownloadKind::Original));
+    };
+    let size_config = gallery
+        .get_config()
+        .image_size(size.strip_suffix("@2x").unwrap_or(size))
+        .ok_or(())?;
+    Ok(size_config.requires_auth.then_some(DownloadKind::Large))
+}
+
+/// Size names for error messages
+fn valid_sizes(gallery: &super::Gallery) -> String {
+    gallery
+        .get_config()
+        .image_sizes()
+        .into_keys()
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub(crate) async fn get_authenticated_user(
+    app_state: &AppState,
+    headers: &HeaderMap,
+) -> Option<UserIdentity> {
     // If no user database is configured, return None (no authentication)
-    #[allow(clippy::question_mark)]
     if app_state.config.app.user_database.is_none() {
         return None;
     }
 
-    // Get authenticated user from headers
-    crate::login::get_authenticated_user(headers, &app_state.config.app.cookie_secret)
+    // Get authenticated user and their groups from headers
+    crate::login::get_user_identity(
+        headers,
+        &app_state.sessions,
+        app_state.user_database_manager.as_ref(),
+    )
+    .await
+}
+
+/// The share, unlocked earlier through a share link, that covers `path` (a
+/// folder or image) in this gallery
+pub(crate) async fn get_share(
+    app_state: &AppState,
+    gallery_name: &str,
+    headers: &HeaderMap,
+    path: &str,
+) -> Option<Share> {
+    // Shares only matter when there is something to log in to
+    if app_state.config.app.user_database.is_none()
+        || !super::share::is_plain_path(path.trim_matches('/'))
+    {
+        return None;
+    }
+
+    let cookie = crate::api::get_cookie_value(headers, SHARE_GRANTS_COOKIE)?;
+    let now = chrono::Utc::now().timestamp();
+    for id in parse_grants(&app_state.config.app.cookie_secret, &cookie) {
+        if let Some(share) = app_state.shares.get(&id).await
+            && share.gallery == gallery_name
+            && !share.is_expired(now)
+            && share.grants(path)
+        {
+            return Some(share);
+        }
+    }
+    None
+}
+
+/// How precisely `user` may see where images in `folder_path` were taken.
+/// Users without large download permission count as public, as they do for
+/// `approximate_dates_for_public`.
+pub(crate) async fn location_precision(
+    app_state: &AppState,
+    gallery: &super::Gallery,
+    folder_path: &str,
+    user: Option<&UserIdentity>,
+    share: Option<&Share>,
+) -> LocationPrecision {
+    let config = gallery.get_config();
+    if config.location_precision == config.location_precision_for_public {
+        return config.location_precision;
+    }
+
+    if has_download_permission(
+        app_state,
+        gallery,
+        folder_path,
+        DownloadKind::Large,
+        user,
+        share,
+    )
+    .await
+    {
+        config.location_precision
+    } else {
+        config.location_precision_for_public
+    }
+}
+
+/// Limit the location to the user's precision, and if approximate dates are
+/// enabled and the user doesn't have download permission, reduce the capture
+/// date to month and year
+async fn apply_public_metadata_rules(
+    app_state: &AppState,
+    gallery: &super::Gallery,
+    user: Option<&UserIdentity>,
+    share: Option<&Share>,
+    image_info: &mut ImageInfo,
+) {
+    let folder_path = image_info
+        .path
+        .rsplit_once('/')
+        .map(|(parent, _)| parent)
+        .unwrap_or("");
+
+    if let Some(location) = image_info.location_info.take() {
+        let precision = location_precision(app_state, gallery, folder_path, user, share).await;
+        image_info.location_info = location.with_precision(precision);
+    }
+
+    if gallery.get_config().approximate_dates_for_public
+        && !has_download_permission(
+            app_state,
+            gallery,
+            folder_path,
+            DownloadKind::Large,
+            user,
+            share,
+        )
+        .await
+        && let Some(ref capture_date_str) = image_info.capture_date
+    {
+        // Parse the existing date and reformat to show only month and year
+        if let Ok(datetime) =
+            chrono::DateTime::parse_from_str(capture_date_str, "%B %d, %Y at %H:%M:%S")
+        {
+            image_info.capture_date = Some(datetime.format("%B %Y").to_string());
+        } else if let Ok(datetime) =
+            chrono::NaiveDateTime::parse_from_str(capture_date_str, "%B %d, %Y at %H:%M:%S")
+        {
+            image_info.capture_date = Some(datetime.format("%B %Y").to_string());
+        }
+    }
+}
+
+/// Large and original download permissions for images in `folder_path`
+async fn download_permissions(
+    app_state: &AppState,
+    gallery: &super::Gallery,
+    folder_path: &str,
+    user: Option<&UserIdentity>,
+    share: Option<&Share>,
+) -> (bool, bool) {
+    (
+        has_download_permission(
+            app_state,
+            gallery,
+            folder_path,
+            DownloadKind::Large,
+            user,
+            share,
+        )
+        .await,
+        has_download_permission(
+            app_state,
+            gallery,
+            folder_path,
+            DownloadKind::Original,
+            user,
+            share,
+        )
+        .await,
+    )
+}
+
+/// Find the images before and after `path` in its folder for prev/next navigation
+async fn find_neighbour_images(
+    gallery: &super::Gallery,
+    parent_path: &str,
+    path: &str,
+) -> (Option<NavigationImage>, Option<NavigationImage>) {
+    let (_, images, _) = gallery
+        .list_directory(parent_path, 0)
+        .await
+        .unwrap_or_default();
+
+    let to_navigation = |item: &GalleryItem| NavigationImage {
+        path: item.path.clone(),
+        name: item.name.clone(),
+        thumbnail_url: item.thumbnail_url.clone().unwrap_or_default(),
+    };
+
+    match images.iter().position(|img| img.path == path) {
+        Some(index) => (
+            index.checked_sub(1).map(|i| to_navigation(&images[i])),
+            images.get(index + 1).map(to_navigation),
+        ),
+        None => (None, None),
+    }
+}
+
+/// Find the images before and after `path` among those tagged `tag`
+async fn find_tag_neighbour_images(
+    gallery: &super::Gallery,
+    tag: &str,
+    path: &str,
+    user: Option<&UserIdentity>,
+) -> (Option<NavigationImage>, Option<NavigationImage>) {
+    let Some(listing) = gallery.tag_listing(tag, user).await else {
+        return (None, None);
+    };
+
+    let to_navigation = |image_path: &String| {
+        let name = image_path.rsplit('/').next().unwrap_or(image_path);
+        NavigationImage {
+            path: image_path.clone(),
+            name: name.to_string(),
+            thumbnail_url: format!(
+                "/{}/image/{}?size=thumbnail",
+                gallery.get_config().url_prefix.trim_start_matches('/'),
+                urlencoding::encode(image_path)
+            ),
+        }
+    };
+
+    match listing.paths.iter().position(|p| p == path) {
+        Some(index) => (
+            index
+                .checked_sub(1)
+                .map(|i| to_navigation(&listing.paths[i])),
+            listing.paths.get(index + 1).map(to_navigation),
+        ),
+        None => (None, None),
+    }
 }
 
 // Named gallery handlers for multiple gallery support
@@ -62,9 +322,13 @@ pub async fn gallery_handler_for_named(
         }
     };
 
-    // Check if the user has access to this path
-    let user = get_authenticated_user(&app_state, &headers);
-    if !gallery.check_path_access(&path, user.as_deref()).await {
+    // Check if the user, or a share link they opened, has access to this path
+    let user = get_authenticated_user(&app_state, &headers).await;
+    let share = get_share(&app_state, &gallery_name, &headers, &path).await;
+    if !gallery
+        .check_path_access(&path, user.as_ref(), share.as_ref())
+        .await
+    {
         // If folder requires authentication and user is not authenticated, redirect to login
         if user.is_none() && gallery.is_folder_access_restricted(&path).await {
             let return_url = if path.is_empty() {
@@ -83,7 +347,7 @@ pub async fn gallery_handler_for_named(
 
     let page = query.page.unwrap_or(0);
     let (directories, images, total_pages) = match gallery
-        .list_directory_with_user(&path, page, user.as_deref())
+        .list_directory_with_user(&path, page, user.as_ref(), share.as_ref())
         .await
     {
         Ok(result) => {
@@ -152,11 +416,54 @@ pub async fn gallery_handler_for_named(
         (None, None, None)
     };
 
+    // Whole-folder ZIP download, offered as originals when the user may have them
+    let (_, can_download_original) =
+        download_permissions(&app_state, gallery, &path, user.as_ref(), share.as_ref()).await;
+    let download_url = format!(
+        "{}/download/{}.zip",
+        gallery_config.url_prefix,
+        path.trim_matches('/')
+    );
+
+    // Link to the folder's map when it has something to show
+    let show_map = location_precision(
+        &app_state,
+        gallery,
+        path.trim_matches('/'),
+        user.as_ref(),
+        share.as_ref(),
+    )
+    .await
+        != LocationPrecision::Hidden;
+    let map_url = if show_map
+        && gallery
+            .located_images_in(path.trim_matches('/'), user.as_ref(), share.as_ref())
+            .await
+            .is_ok_and(|images| !images.is_empty())
+    {
+        Some(if is_root {
+            format!("{}/map", gallery_config.url_prefix)
+        } else {
+            format!(
+                "{}/map/{}",
+                gallery_config.url_prefix,
+                super::map_handlers::encode_folder_path(path.trim_matches('/'))
+            )
+        })
+    } else {
+        None
+    };
+
     let liquid_context = liquid::object!({
         "gallery_name": gallery_name,
         "gallery_url": gallery_config.url_prefix,
         "gallery_path": path,
         "is_root": is_root,
+        "detail_query": "",
+        "download_url": download_url,
+        "map_url": map_url,
+        "can_download_original": can_download_original,
+        "image_sizes": gallery_config.image_sizes(),
         "breadcrumbs": breadcrumbs,
         "directories": directories,
         "images": images,
@@ -252,6 +559,7 @@ pub async fn gallery_handler_for_named(
 pub async fn image_detail_handler_for_named(
     State(app_state): State<AppState>,
     Path((gallery_name, path)): Path<(String, String)>,
+    Query(query): Query<GalleryQuery>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
     let template_engine = &app_state.template_engine;
@@ -265,7 +573,7 @@ pub async fn image_detail_handler_for_named(
     };
 
     // Check if the user has access to the folder containing this image
-    let user = get_authenticated_user(&app_state, &headers);
+    let user = get_authenticated_user(&app_state, &headers).await;
 
     // Extract the parent folder path from the image path
     let parent_path = if let Some(last_slash) = path.rfind('/') {
@@ -274,8 +582,9 @@ pub async fn image_detail_handler_for_named(
         "" // Image is in root folder
     };
 
+    let share = get_share(&app_state, &gallery_name, &headers, &path).await;
     if !gallery
-        .check_path_access(parent_path, user.as_deref())
+        .check_path_access(parent_path, user.as_ref(), share.as_ref())
         .await
     {
         // If folder requires authentication and user is not authenticated, redirect to login
@@ -297,25 +606,14 @@ pub async fn image_detail_handler_for_named(
         }
     };
 
-    // Check if user has download permission
-    let has_permission = has_download_permission(&app_state, &headers);
-
-    // If approximate dates are enabled and user doesn't have permission, modify the capture date
-    if gallery.get_config().approximate_dates_for_public
-        && !has_permission
-        && let Some(ref capture_date_str) = image_info.capture_date
-    {
-        // Parse the existing date and reformat to show only month and year
-        if let Ok(datetime) =
-            chrono::DateTime::parse_from_str(capture_date_str, "%B %d, %Y at %H:%M:%S")
-        {
-            image_info.capture_date = Some(datetime.format("%B %Y").to_string());
-        } else if let Ok(datetime) =
-            chrono::NaiveDateTime::parse_from_str(capture_date_str, "%B %d, %Y at %H:%M:%S")
-        {
-            image_info.capture_date = Some(datetime.format("%B %Y").to_string());
-        }
-    }
+    apply_public_metadata_rules(
+        &app_state,
+        gallery,
+        user.as_ref(),
+        share.as_ref(),
+        &mut image_info,
+    )
+    .await;
 
     // Get the parent directory for navigation
     let parent_path = std::path::Path::new(&path)
@@ -323,42 +621,30 @@ pub async fn image_detail_handler_for_named(
         .and_then(|p| p.to_str())
         .unwrap_or("");
 
-    // Get all images in the parent directory for navigation
-    let (_, images, _) = gallery
-        .list_directory(parent_path, 0)
-        .await
-        .unwrap_or_default();
-
-    // Find current image index and get prev/next
-    let current_index = images.iter().position(|img| img.path == path);
-
-    let (prev_image, next_image) = if let Some(index) = current_index {
-        let prev = if index > 0 {
-            let prev_item = &images[index - 1];
-            Some(NavigationImage {
-                path: prev_item.path.clone(),
-                name: prev_item.name.clone(),
-                thumbnail_url: prev_item.thumbnail_url.clone().unwrap_or_default(),
-            })
-        } else {
-            None
-        };
-
-        let next = if index + 1 < images.len() {
-            let next_item = &images[index + 1];
-            Some(NavigationImage {
-                path: next_item.path.clone(),
-                name: next_item.name.clone(),
-                thumbnail_url: next_item.thumbnail_url.clone().unwrap_or_default(),
-            })
-        } else {
-            None
-        };
-
-        (prev, next)
-    } else {
+    let (prev_image, next_image) = if share.as_ref().is_some_and(|s| s.kind == ShareKind::Image) {
+        // A shared image has no neighbours the visitor could open
         (None, None)
+    } else if let Some(tag) = &query.tag {
+        find_tag_neighbour_images(gallery, tag, &path, user.as_ref()).await
+    } else {
+        find_neighbour_images(gallery, parent_path, &path).await
     };
+    // Carried on prev/next links so navigation stays within the tag
+    let detail_query = query
+        .tag
+        .as_ref()
+        .map(|tag| format!("?tag={}", urlencoding::encode(tag)))
+        .unwrap_or_default();
+    let (can_download_large, can_download_original) = download_permissions(
+        &app_state,
+        gallery,
+        parent_path,
+        user.as_ref(),
+        share.as_ref(),
+    )
+    .await;
+
+    let tags = gallery.image_tag_links(&path).await;
 
     // Build breadcrumbs for the parent directory, not including the image filename
     let breadcrumbs = gallery.build_breadcrumbs_with_mode(parent_path, true).await;
@@ -371,6 +657,11 @@ pub async fn image_detail_handler_for_named(
         "breadcrumbs": breadcrumbs,
         "prev_image": prev_image,
         "next_image": next_image,
+        "detail_query": detail_query,
+        "tags": tags,
+        "can_download_large": can_download_large,
+        "can_download_original": can_download_original,
+        "image_sizes": gallery_config.image_sizes(),
         "page_title": format!("{} - Photo Gallery", image_info.name),
         "meta_description": format!("View {} in our photo gallery", image_info.name),
         "app_name": app_state.config.app.name,
@@ -411,7 +702,7 @@ pub async fn image_handler_for_named(
     };
 
     // Check if the user has access to the folder containing this image
-    let user = get_authenticated_user(&app_state, &headers);
+    let user = get_authenticated_user(&app_state, &headers).await;
 
     // Extract the parent folder path from the image path
     let parent_path = if let Some(last_slash) = path.rfind('/') {
@@ -420,53 +711,369 @@ pub async fn image_handler_for_named(
         "" // Image is in root folder
     };
 
+    let share = get_share(&app_state, &gallery_name, &headers, &path).await;
     if !gallery
-        .check_path_access(parent_path, user.as_deref())
+        .check_path_access(parent_path, user.as_ref(), share.as_ref())
         .await
     {
         // For image serving, always return 403 instead of redirect to avoid breaking image URLs
         return (StatusCode::FORBIDDEN, "Access denied").into_response();
     }
 
-    // Validate size parameter if provided
-    if let Some(ref size) = query.size {
-        // Check if it's a @2x variant
-        let (base_size, _is_2x) = if size.ends_with("@2x") {
-            (size.trim_end_matches("@2x"), true)
-        } else {
-            (size.as_str(), false)
-        };
+    // Validate size parameter and check the download permission it needs
+    let kind = match download_kind_for_size(gallery, query.size.as_deref()) {
+        Ok(kind) => kind,
+        Err(()) => {
+            tracing::warn!(path = %path, size = ?query.size, "Invalid size parameter requested");
+            let message = format!(
+                "Invalid size parameter. Valid sizes: {} (with optional @2x suffix)",
+                valid_sizes(gallery)
+            );
+            return (StatusCode::BAD_REQUEST, message).into_response();
+        }
+    };
+    if let Some(kind) = kind
+        && !has_download_permission(
+            &app_state,
+            gallery,
+            parent_path,
+            kind,
+            user.as_ref(),
+            share.as_ref(),
+        )
+        .await
+    {
+        tracing::warn!(path = %path, ?kind, "Image download denied - permission required");
+        return (StatusCode::FORBIDDEN, "Download permission required").into_response();
+    }
 
-        match base_size {
-            "thumbnail" | "gallery" | "medium" => {
-                // These sizes are allowed without authentication
-            }
-            "large" => {
-                // Large size requires authentication
-                if !has_download_permission(&app_state, &headers) {
-                    tracing::warn!(path = %path, "Large image request denied - authentication required");
-                    return (StatusCode::FORBIDDEN, "Download permission required").into_response();
-                }
-            }
-            _ => {
-                // Invalid size parameter
-                tracing::warn!(path = %path, size = %size, "Invalid size parameter requested");
-                return (StatusCode::BAD_REQUEST, "Invalid size parameter. Valid sizes: thumbnail, gallery, medium, large (with optional @2x suffix)").into_response();
+    let format = match query.format.as_deref() {
+        Some(name) => match OutputFormat::from_name(name) {
+            Some(format) => Some(format),
+            None => {
+                return (StatusCode::BAD_REQUEST, "Invalid format parameter").into_response();
             }
+        },
+        None => None,
+    };
+
+    gallery
+        .serve_image(&path, query.size, format, &headers)
+        .await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ArchiveQuery {
+    pub size: Option<String>,
+}
+
+/// `GET {prefix}/download/{path}.zip`: stream every image in a folder (not its
+/// subfolders) as a ZIP archive. The top level is plain `.zip`, as no folder
+/// can have an empty name
+pub async fn folder_archive_handler_for_named(
+    State(app_state): State<AppState>,
+    Path((gallery_name, path)): Path<(String, String)>,
+    Query(query): Query<ArchiveQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(gallery) = app_state.galleries.get(&gallery_name) else {
+        error!("Gallery '{}' not found", gallery_name);
+        return (StatusCode::NOT_FOUND, "Gallery not found").into_response();
+    };
+
+    let Some(folder_path) = path.strip_suffix(".zip") else {
+        return (StatusCode::NOT_FOUND, "Not found").into_response();
+    };
+    let folder_path = folder_path.trim_matches('/');
+    if folder_path.split('/').any(|segment| segment == "..") {
+        return (StatusCode::NOT_FOUND, "Not found").into_response();
+    }
+
+    let user = get_authenticated_user(&app_state, &headers).await;
+    let share = get_share(&app_state, &gallery_name, &headers, folder_path).await;
+    if !gallery
+        .check_path_access(folder_path, user.as_ref(), share.as_ref())
+        .await
+    {
+        return (StatusCode::FORBIDDEN, "Access denied").into_response();
+    }
+
+    // Same size rules as single images, plus an explicit "original"
+    let size = query.size.filter(|size| size != "original");
+    let kind = match download_kind_for_size(gallery, size.as_deref()) {
+        Ok(kind) => kind,
+        Err(()) => {
+            let message = format!(
+                "Invalid size parameter. Valid sizes: original, {} (with optional @2x suffix)",
+                valid_sizes(gallery)
+            );
+            return (StatusCode::BAD_REQUEST, message).into_response();
         }
+    };
+    if let Some(kind) = kind
+        && !has_download_permission(
+            &app_state,
+            gallery,
+            folder_path,
+            kind,
+            user.as_ref(),
+            share.as_ref(),
+        )
+        .await
+    {
+        tracing::warn!(path = %folder_path, ?kind, "Folder download denied - permission required");
+        return (StatusCode::FORBIDDEN, "Download permission required").into_response();
+    }
+
+    // Videos go into any archive as they are, so a resized archive only gets
+    // them when the originals may be downloaded too
+    let include_videos = size.is_none()
+        || has_download_permission(
+            &app_state,
+            gallery,
+            folder_path,
+            DownloadKind::Original,
+            user.as_ref(),
+            share.as_ref(),
+        )
+        .await;
+
+    let images: Vec<String> = match gallery
+        .scan_directory_with_user(folder_path, user.as_ref(), share.as_ref())
+        .await
+    {
+        Ok(items) => items
+            .into_iter()
+            .filter(|item| !item.is_directory)
+            .filter(|item| include_videos || !super::is_video_file(&item.path))
+            .map(|item| item.path)
+            .collect(),
+        Err(e) => {
+            error!(
+                "Failed to list folder '{}' for download: {}",
+                folder_path, e
+            );
+            return (StatusCode::NOT_FOUND, "Folder not found").into_response();
+        }
+    };
+
+    let archive_name = folder_path
+        .rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty())
+        .unwrap_or(gallery_name.as_str())
+        .to_string();
+
+    // The archive is written into one end of a pipe while the response body
+    // reads from the other, so only a small buffer is ever held in memory
+    let (reader, writer) = tokio::io::duplex(64 * 1024);
+    let gallery = gallery.clone();
+    let folder = folder_path.to_string();
+    // The archive is still being made after this handler returns, resizing
+    // images as it goes, so it keeps pre-generation waiting until it's done
+    let live = super::LiveRequest::start();
+    tokio::spawn(async move {
+        let _live = live;
+        if let Err(e) = gallery
+            .write_archive(&images, size.as_deref(), writer)
+            .await
+        {
+            tracing::warn!("Folder download of '{}' stopped: {}", folder, e);
+        }
+    });
+
+    let ascii_name: String = archive_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let disposition = format!(
+        "attachment; filename=\"{}.zip\"; filename*=UTF-8''{}.zip",
+        ascii_name,
+        urlencoding::encode(&archive_name)
+    );
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/zip"),
+    );
+    if let Ok(value) = HeaderValue::from_str(&disposition) {
+        response_headers.insert(header::CONTENT_DISPOSITION, value);
+    }
+    response_headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static("private, no-store"),
+    );
+
+    let body = Body::from_stream(ReaderStream::new(reader));
+    (StatusCode::OK, response_headers, body).into_response()
+}
+
+#[derive(Debug, Serialize)]
+pub struct GalleryListResponse {
+    pub gallery_name: String,
+    pub path: String,
+    pub folder_title: Option<String>,
+    pub folder_description: Option<String>,
+    pub breadcrumbs: Vec<BreadcrumbItem>,
+    pub directories: Vec<GalleryItem>,
+    pub images: Vec<GalleryItem>,
+    pub page: usize,
+    pub total_pages: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImageInfoResponse {
+    pub gallery_name: String,
+    pub image: ImageInfo,
+    pub breadcrumbs: Vec<BreadcrumbItem>,
+    pub prev_image: Option<NavigationImage>,
+    pub next_image: Option<NavigationImage>,
+    pub can_download_large: bool,
+    pub can_download_original: bool,
+}
+
+/// Status for a JSON request the user may not make: 401 if logging in could
+/// help, 403 otherwise
+pub(super) async fn api_access_denied(
+    gallery: &super::Gallery,
+    path: &str,
+    user: Option<&UserIdentity>,
+) -> axum::response::Response {
+    if user.is_none() && gallery.is_folder_access_restricted(path).await {
+        (StatusCode::UNAUTHORIZED, "Authentication required").into_response()
     } else {
-        // No size parameter means full-size original image - requires authentication
-        if !has_download_permission(&app_state, &headers) {
-            tracing::warn!(path = %path, "Full-size image request denied - authentication required");
-            return (StatusCode::FORBIDDEN, "Download permission required").into_response();
+        (StatusCode::FORBIDDEN, "Access denied").into_response()
+    }
+}
+
+/// JSON equivalent of `gallery_handler_for_named`
+pub async fn gallery_list_api_handler_for_named(
+    State(app_state): State<AppState>,
+    Path((gallery_name, path)): Path<(String, String)>,
+    Query(query): Query<GalleryQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let gallery = match app_state.galleries.get(&gallery_name) {
+        Some(g) => g,
+        None => {
+            error!("Gallery '{}' not found", gallery_name);
+            return (StatusCode::NOT_FOUND, "Gallery not found").into_response();
         }
+    };
+
+    let path = path.trim_matches('/').to_string();
+    let user = get_authenticated_user(&app_state, &headers).await;
+    let share = get_share(&app_state, &gallery_name, &headers, &path).await;
+    if !gallery
+        .check_path_access(&path, user.as_ref(), share.as_ref())
+        .await
+    {
+        return api_access_denied(gallery, &path, user.as_ref()).await;
     }
 
-    // Extract Accept header for format negotiation
-    let accept_header = headers
-        .get(axum::http::header::ACCEPT)
-        .and_then(|h| h.to_str().ok())
+    let page = query.page.unwrap_or(0);
+    let (directories, images, total_pages) = match gallery
+        .list_directory_with_user(&path, page, user.as_ref(), share.as_ref())
+        .await
+    {
+        Ok(result) => result,
+        Err(e) => {
+            error!("Failed to list directory: {}", e);
+            return (StatusCode::NOT_FOUND, "Directory not found").into_response();
+        }
+    };
+
+    let breadcrumbs = gallery.build_breadcrumbs(&path).await;
+    let (folder_title, folder_description) = gallery.read_folder_metadata(&path).await;
+
+    Json(GalleryListResponse {
+        gallery_name,
+        path,
+        folder_title,
+        folder_description,
+        breadcrumbs,
+        directories,
+        images,
+        page,
+        total_pages,
+    })
+    .into_response()
+}
+
+/// JSON equivalent of `image_detail_handler_for_named`
+pub async fn image_info_api_handler_for_named(
+    State(app_state): State<AppState>,
+    Path((gallery_name, path)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let gallery = match app_state.galleries.get(&gallery_name) {
+        Some(g) => g,
+        None => {
+            error!("Gallery '{}' not found", gallery_name);
+            return (StatusCode::NOT_FOUND, "Gallery not found").into_response();
+        }
+    };
+
+    let user = get_authenticated_user(&app_state, &headers).await;
+    let parent_path = path
+        .rsplit_once('/')
+        .map(|(parent, _)| parent)
         .unwrap_or("");
+    let share = get_share(&app_state, &gallery_name, &headers, &path).await;
+    if !gallery
+        .check_path_access(parent_path, user.as_ref(), share.as_ref())
+        .await
+    {
+        return api_access_denied(gallery, parent_path, user.as_ref()).await;
+    }
+
+    let mut image_info = match gallery.get_image_info(&path).await {
+        Ok(info) => info,
+        Err(e) => {
+            error!("Failed to get image info: {}", e);
+            return (StatusCode::NOT_FOUND, "Image not found").into_response();
+        }
+    };
+
+    apply_public_metadata_rules(
+        &app_state,
+        gallery,
+        user.as_ref(),
+        share.as_ref(),
+        &mut image_info,
+    )
+    .await;
+
+    let (prev_image, next_image) = if share.as_ref().is_some_and(|s| s.kind == ShareKind::Image) {
+        (None, None)
+    } else {
+        find_neighbour_images(gallery, parent_path, &path).await
+    };
+    let breadcrumbs = gallery.build_breadcrumbs_with_mode(parent_path, true).await;
+    let (can_download_large, can_download_original) = download_permissions(
+        &app_state,
+        gallery,
+        parent_path,
+        user.as_ref(),
+        share.as_ref(),
+    )
+    .await;
 
-    gallery.serve_image(&path, query.size, accept_header).await
+    Json(ImageInfoResponse {
+        gallery_name,
+        image: image_info,
+        breadcrumbs,
+        prev_image,
+        next_image,
+        can_download_large,
+        can_download_original,
+    })
+    .into_response()
 }
@@ -6,6 +6,7 @@ use std::path::Path;
 use tracing::debug;
 
 use super::avif_container;
+use crate::gallery::image_processing::metadata_policy::MetadataBlocks;
 
 // Helper functions for fraction conversion
 fn signed_fraction_to_float(frac: &sys::avifSignedFraction) -> f32 {
@@ -555,7 +556,16 @@ pub fn save_with_info(
     // For backward compatibility
     let icc_profile = info.and_then(|i| i.icc_profile.as_deref());
     let preserve_hdr = info.map(|i| i.is_hdr).unwrap_or(false);
-    save_with_profile_and_color(image, path, quality, speed, icc_profile, preserve_hdr, info)
+    save_with_profile_and_color(
+        image,
+        path,
+        quality,
+        speed,
+        icc_profile,
+        preserve_hdr,
+        info,
+        None,
+    )
 }
 
 /// Save a DynamicImage as AVIF with HDR support
@@ -567,11 +577,22 @@ pub fn save_with_profile(
     icc_profile: Option<&[u8]>,
     preserve_hdr: bool,
 ) -> Result<(), GalleryError> {
-    save_with_profile_and_color(image, path, quality, speed, icc_profile, preserve_hdr, None)
+    save_with_profile_and_color(
+        image,
+        path,
+        quality,
+        speed,
+        icc_profile,
+        preserve_hdr,
+        None,
+        None,
+    )
 }
 
-/// Internal save function with full color property support
-fn save_with_profile_and_color(
+/// Save function with full color property support and optional EXIF and
+/// XMP metadata
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn save_with_profile_and_color(
     image: &DynamicImage,
     path: &Path,
     quality: u8,
@@ -579,6 +600,7 @@ fn save_with_profile_and_color(
     icc_profile: Option<&[u8]>,
     preserve_hdr: bool,
     color_info: Option<&AvifImageInfo>,
+    metadata: Option<&MetadataBlocks>,
 ) -> Result<(), GalleryError> {
     let (width, height) = image.dimensions();
 
@@ -660,6 +682,14 @@ fn save_with_profile_and_color(
             sys::avifImageSetProfileICC(avif_image, icc.as_ptr(), icc.len());
         }
 
+        // Set EXIF and XMP if provided; libavif adds the TIFF header offset
+        if let Some(exif) = metadata.and_then(|m| m.exif.as_deref()) {
+            sys::avifImageSetMetadataExif(avif_image, exif.as_ptr(), exif.len());
+        }
+        if let Some(xmp) = metadata.and_then(|m| m.xmp.as_deref()) {
+            sys::avifImageSetMetadataXMP(avif_image, xmp.as_ptr(), xmp.len());
+        }
+
         // Set CLLI data if provided in color_info
         if let Some(info) = color_info
             && (info.max_cll > 0 || info.max_pall > 0)
@@ -1034,42 +1064,29 @@ pub fn extract_color_description(path: &Path) -> Option<String> {
     }
 }
 
-/// Extract EXIF data from an AVIF file
-pub fn extract_exif_data(path: &Path) -> Option<Vec<u8>> {
+/// Exif and XMP items of an AVIF file, read from its `meta` box without
+/// decoding the image
+fn read_metadata_blocks(path: &Path) -> Option<MetadataBlocks> {
     let data = std::fs::read(path).ok()?;
+    Some(avif_container::metadata_blocks(&data))
+}
 
-    unsafe {
-        let decoder = sys::avifDecoderCreate();
-        if decoder.is_null() {
-            return None;
-        }
-
-        let image = sys::avifImageCreateEmpty();
-        if image.is_null() {
-            sys::avifDecoderDestroy(decoder);
-            return None;
-        }
-
-        // Decode the image (we need to decode to access metadata)
-        let result = sys::avifDecoderReadMemory(decoder, image, data.as_ptr(), data.len());
-
-        let exif_data = if result == sys::AVIF_RESULT_OK {
-            if (*image).exif.size > 0 && !(*image).exif.data.is_null() {
-                let exif_slice = std::slice::from_raw_parts((*image).exif.data, (*image).exif.size);
-                debug!("Extracted EXIF data from AVIF: {} bytes", exif_slice.len());
-                Some(exif_slice.to_vec())
-            } else {
-                None
-            }
-        } else {
-            None
-        };
-
-        sys::avifImageDestroy(image);
-        sys::avifDecoderDestroy(decoder);
+/// Extract EXIF data from an AVIF file, starting at the TIFF header
+pub fn extract_exif_data(path: &Path) -> Option<Vec<u8>> {
+    let exif = read_metadata_blocks(path)?
+        .exif
+        .filter(|exif| !exif.is_empty())?;
+    debug!("Extracted EXIF data from AVIF: {} bytes", exif.len());
+    Some(exif)
+}
 
-        exif_data
-    }
+/// Extract the XMP packet from an AVIF file
+pub fn extract_xmp_data(path: &Path) -> Option<Vec<u8>> {
+    let xmp = read_metadata_blocks(path)?
+        .xmp
+        .filter(|xmp| !xmp.is_empty())?;
+    debug!("Extracted XMP data from AVIF: {} bytes", xmp.len());
+    Some(xmp)
 }
 
 /// Extract dimensions from AVIF file without full decode
@@ -1,4 +1,5 @@
 use super::avif::GainMapInfo;
+use crate::gallery::image_processing::metadata_policy::MetadataBlocks;
 use std::path::Path;
 use tracing::debug;
 
@@ -355,3 +356,202 @@ fn extract_aux_type_from_auxc(auxc_data: &[u8]) -> Option<String> {
 
     None
 }
+
+/// Where the Exif and XMP items of an AVIF file are stored
+#[derive(Debug, Default)]
+struct MetadataItems {
+    exif: Option<std::ops::Range<usize>>,
+    xmp: Option<std::ops::Range<usize>>,
+}
+
+/// Child boxes of a box's contents as `(type, contents range)` pairs, with
+/// ranges relative to `data`
+fn child_boxes(
+    data: &[u8],
+    range: std::ops::Range<usize>,
+) -> Vec<([u8; 4], std::ops::Range<usize>)> {
+    let mut boxes = Vec::new();
+    let mut pos = range.start;
+    while pos + 8 <= range.end {
+        let box_size =
+            u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        if box_size < 8 || pos + box_size > range.end {
+            break;
+        }
+        let box_type = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+        boxes.push((box_type, pos + 8..pos + box_size));
+        pos += box_size;
+    }
+    boxes
+}
+
+/// Read a big-endian integer of `size` bytes (0, 4 or 8 in `iloc`)
+fn read_sized(data: &[u8], pos: &mut usize, size: usize) -> Option<u64> {
+    let bytes = data.get(*pos..*pos + size)?;
+    *pos += size;
+    Some(
+        bytes
+            .iter()
+            .fold(0u64, |value, &b| (value << 8) | u64::from(b)),
+    )
+}
+
+/// Find the Exif and XMP items through `iinf` and `iloc`. Only items stored
+/// as a single extent in the file itself are supported, which is what
+/// encoders write. `None` when the boxes can't be read, or when `iinf`
+/// declares an Exif or XMP item that can't be located.
+fn metadata_items(data: &[u8]) -> Option<MetadataItems> {
+    let mut items = MetadataItems::default();
+    let Some((_, meta)) = child_boxes(data, 0..data.len())
+        .into_iter()
+        .find(|(box_type, _)| box_type == b"meta")
+    else {
+        return Some(items);
+    };
+    // meta is a full box: skip version and flags
+    let meta_children = child_boxes(data, meta.start + 4..meta.end);
+
+    let mut exif_id = None;
+    let mut xmp_id = None;
+    if let Some((_, iinf)) = meta_children.iter().find(|(t, _)| t == b"iinf") {
+        let entries_start = iinf.start + if *data.get(iinf.start)? == 0 { 6 } else { 8 };
+        for (_, infe) in child_boxes(data, entries_start..iinf.end)
+            .into_iter()
+            .filter(|(t, _)| t == b"infe")
+        {
+            let version = *data.get(infe.start)?;
+            let mut pos = infe.start + 4;
+            // Older entries have no item type, so can't be Exif or XMP
+            let id = match version {
+                2 => read_sized(data, &mut pos, 2)?,
+                3 => read_sized(data, &mut pos, 4)?,
+                _ => continue,
+            };
+            // Protection index, then the item type
+            pos += 2;
+            let item_type = data.get(pos..pos + 4)?;
+            if item_type == b"Exif" {
+                exif_id = Some(id);
+            } else if item_type == b"mime"
+                && data
+                    .get(pos + 4..infe.end)
+                    .is_some_and(|content_type| content_type.starts_with(b"application/rdf+xml"))
+            {
+                xmp_id = Some(id);
+            }
+        }
+    }
+    if exif_id.is_none() && xmp_id.is_none() {
+        return Some(items);
+    }
+
+    let (_, iloc) = meta_children.iter().find(|(t, _)| t == b"iloc")?;
+    let version = *data.get(iloc.start)?;
+    let mut pos = iloc.start + 4;
+    let sizes = read_sized(data, &mut pos, 2)? as usize;
+    let (offset_size, length_size) = (sizes >> 12, (sizes >> 8) & 0xF);
+    let (base_offset_size, index_size) = ((sizes >> 4) & 0xF, sizes & 0xF);
+    let index_size = if version == 0 { 0 } else { index_size };
+    let id_size = if version < 2 { 2 } else { 4 };
+    let item_count = read_sized(data, &mut pos, id_size)?;
+
+    for _ in 0..item_count {
+        let id = read_sized(data, &mut pos, id_size)?;
+        let construction_method = if version == 0 {
+            0
+        } else {
+            read_sized(data, &mut pos, 2)? & 0xF
+        };
+        // Data reference index
+        pos += 2;
+        let base_offset = read_sized(data, &mut pos, base_offset_size)?;
+        let extent_count = read_sized(data, &mut pos, 2)?;
+        let mut extents = Vec::new();
+        for _ in 0..extent_count {
+            read_sized(data, &mut pos, index_size)?;
+            let offset = read_sized(data, &mut pos, offset_size)?;
+            let length = read_sized(data, &mut pos, length_size)?;
+            extents.push((base_offset + offset, length));
+        }
+
+        if construction_method == 0
+            && let [(offset, length)] = extents.as_slice()
+        {
+            let start = *offset as usize;
+            let end = start.checked_add(*length as usize)?;
+            if end <= data.len() {
+                if Some(id) == exif_id {
+                    items.exif = Some(start..end);
+                } else if Some(id) == xmp_id {
+                    items.xmp = Some(start..end);
+                }
+            }
+        }
+    }
+
+    // Stored in `idat`, in several extents or outside the file
+    if (exif_id.is_some() && items.exif.is_none()) || (xmp_id.is_some() && items.xmp.is_none()) {
+        debug!("Could not locate the AVIF metadata items");
+        return None;
+    }
+    Some(items)
+}
+
+/// Exif and XMP items of AVIF data. The Exif item starts with the offset of
+/// the TIFF header, which is skipped.
+pub(crate) fn metadata_blocks(data: &[u8]) -> MetadataBlocks {
+    let items = metadata_items(data).unwrap_or_default();
+    MetadataBlocks {
+        exif: items.exif.and_then(|range| {
+            let payload = &data[range];
+            let offset = u32::from_be_bytes(payload.get(..4)?.try_into().ok()?) as usize;
+            payload.get(4 + offset..).map(|tiff| tiff.to_vec())
+        }),
+        xmp: items.xmp.map(|range| data[range].to_vec()),
+        iptc: None,
+    }
+}
+
+/// Empty TIFF structure, behind a zero header offset
+const EMPTY_EXIF_ITEM: &[u8] = b"\0\0\0\0MM\0*\0\0\0\x08\0\0\0\0\0\0";
+const EMPTY_XMP_PACKET: &[u8] = b"<?xpacket begin=\"\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?><x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/><?xpacket end=\"w\"?>";
+
+/// AVIF data with its Exif and XMP items overwritten by `blocks`, in place so
+/// no box sizes or offsets change. Content that doesn't fit the existing
+/// item becomes an empty Exif block or XMP packet, and the leftover space is
+/// padded. An item can't be added where there was none. `None` when the
+/// existing items can't be located, so they can't be overwritten either.
+pub(crate) fn replace_metadata_in_place(data: &[u8], blocks: &MetadataBlocks) -> Option<Vec<u8>> {
+    let items = metadata_items(data)?;
+    let mut output = data.to_vec();
+
+    if let Some(range) = items.exif {
+        let mut item = vec![0u8; 4];
+        item.extend_from_slice(blocks.exif.as_deref().unwrap_or_default());
+        if blocks.exif.is_none() || item.len() > range.len() {
+            item = EMPTY_EXIF_ITEM.to_vec();
+        }
+        fill(&mut output[range], &item, 0);
+    }
+    if let Some(range) = items.xmp {
+        let mut item = blocks.xmp.clone().unwrap_or_default();
+        if blocks.xmp.is_none() || item.len() > range.len() {
+            item = EMPTY_XMP_PACKET.to_vec();
+        }
+        // Trailing whitespace is allowed after an XML document
+        fill(&mut output[range], &item, b' ');
+    }
+    Some(output)
+}
+
+/// Copy `item` to the start of `target` and pad the rest, or pad it all if
+/// `item` is too long
+fn fill(target: &mut [u8], item: &[u8], padding: u8) {
+    let written = if item.len() <= target.len() {
+        target[..item.len()].copy_from_slice(item);
+        item.len()
+    } else {
+        0
+    };
+    target[written..].fill(padding);
+}
@@ -4,6 +4,8 @@ use std::path::Path;
 use tracing::debug;
 
 use crate::gallery::GalleryError;
+use crate::gallery::embedded_metadata::XMP_SIGNATURE;
+use crate::gallery::image_processing::metadata_policy::MetadataBlocks;
 
 /// Extract ICC profile from JPEG file
 pub fn extract_icc_profile(path: &Path) -> Option<Vec<u8>> {
@@ -60,6 +62,53 @@ pub fn extract_icc_profile(path: &Path) -> Option<Vec<u8>> {
     None
 }
 
+/// Read the application (APPn) segments of a JPEG, in file order, as
+/// `(marker, data)` pairs. Stops at the start of the image data, so only the
+/// file header is read.
+pub(crate) fn read_app_segments(path: &Path) -> std::io::Result<Vec<(u8, Vec<u8>)>> {
+    use std::io::{BufReader, Seek, SeekFrom};
+
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    let mut soi = [0u8; 2];
+    reader.read_exact(&mut soi)?;
+    if soi != [0xFF, 0xD8] {
+        return Ok(Vec::new());
+    }
+
+    let mut segments = Vec::new();
+    loop {
+        let mut marker = [0u8; 2];
+        if reader.read_exact(&mut marker).is_err() || marker[0] != 0xFF {
+            break;
+        }
+        // Standalone markers carry no length
+        if marker[1] == 0x01 || (0xD0..=0xD7).contains(&marker[1]) {
+            continue;
+        }
+        // Start of scan or end of image: no metadata after this point
+        if marker[1] == 0xDA || marker[1] == 0xD9 {
+            break;
+        }
+
+        let mut length = [0u8; 2];
+        reader.read_exact(&mut length)?;
+        let length = u16::from_be_bytes(length) as usize;
+        if length < 2 {
+            break;
+        }
+
+        if (0xE0..=0xEF).contains(&marker[1]) {
+            let mut data = vec![0u8; length - 2];
+            reader.read_exact(&mut data)?;
+            segments.push((marker[1], data));
+        } else {
+            reader.seek(SeekFrom::Current(length as i64 - 2))?;
+        }
+    }
+
+    Ok(segments)
+}
+
 /// Save image as JPEG with optional ICC profile
 pub fn save_with_profile(
     image: &DynamicImage,
@@ -114,3 +163,114 @@ pub fn save_with_profile(
 
     Ok(())
 }
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const XMP_EXTENSION_SIGNATURE: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+/// Largest payload a JPEG segment can hold, after its two length bytes
+const MAX_SEGMENT_PAYLOAD: usize = 65533;
+
+/// The marker segments before the image data, as `(marker, range)` pairs
+/// where the range covers the whole segment. Ends at the start of scan.
+fn header_segments(data: &[u8]) -> Vec<(u8, std::ops::Range<usize>)> {
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xFF {
+        let marker = data[pos + 1];
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            segments.push((marker, pos..pos + 2));
+            pos += 2;
+            continue;
+        }
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        if length < 2 || pos + 2 + length > data.len() {
+            break;
+        }
+        segments.push((marker, pos..pos + 2 + length));
+        pos += 2 + length;
+    }
+    segments
+}
+
+fn is_metadata_segment(marker: u8, payload: &[u8]) -> bool {
+    (marker == 0xE1
+        && (payload.starts_with(EXIF_HEADER)
+            || payload.starts_with(XMP_SIGNATURE)
+            || payload.starts_with(XMP_EXTENSION_SIGNATURE)))
+        || marker == 0xED
+}
+
+/// EXIF (APP1), XMP (APP1) and IPTC (APP13) of JPEG data
+pub(crate) fn metadata_blocks(data: &[u8]) -> MetadataBlocks {
+    let mut blocks = MetadataBlocks::default();
+    for (marker, range) in header_segments(data) {
+        let Some(payload) = data.get(range.start + 4..range.end) else {
+            continue;
+        };
+        match marker {
+            0xE1 if blocks.exif.is_none() && payload.starts_with(EXIF_HEADER) => {
+                blocks.exif = Some(payload[EXIF_HEADER.len()..].to_vec());
+            }
+            0xE1 if blocks.xmp.is_none() && payload.starts_with(XMP_SIGNATURE) => {
+                blocks.xmp = Some(payload[XMP_SIGNATURE.len()..].to_vec());
+            }
+            0xED if blocks.iptc.is_none() => blocks.iptc = Some(payload.to_vec()),
+            _ => {}
+        }
+    }
+    blocks
+}
+
+/// JPEG data with its EXIF, XMP and IPTC segments replaced by `blocks`. The
+/// new segments go after any JFIF header; blocks too large for a single
+/// segment are left out.
+pub(crate) fn replace_metadata(data: &[u8], blocks: &MetadataBlocks) -> Vec<u8> {
+    let segments = header_segments(data);
+    let scan_start = segments.last().map_or(2, |(_, range)| range.end);
+
+    let mut new_segments: Vec<u8> = Vec::new();
+    let mut write_segment = |marker: u8, header: &[u8], body: &[u8]| {
+        let length = header.len() + body.len();
+        if length > MAX_SEGMENT_PAYLOAD {
+            debug!(
+                "Metadata block of {} bytes too large for a JPEG segment",
+                length
+            );
+            return;
+        }
+        new_segments.extend_from_slice(&[0xFF, marker]);
+        new_segments.extend_from_slice(&((length + 2) as u16).to_be_bytes());
+        new_segments.extend_from_slice(header);
+        new_segments.extend_from_slice(body);
+    };
+    if let Some(exif) = &blocks.exif {
+        write_segment(0xE1, EXIF_HEADER, exif);
+    }
+    if let Some(xmp) = &blocks.xmp {
+        write_segment(0xE1, XMP_SIGNATURE, xmp);
+    }
+    if let Some(iptc) = &blocks.iptc {
+        write_segment(0xED, &[], iptc);
+    }
+
+    let mut output = Vec::with_capacity(data.len() + new_segments.len());
+    output.extend_from_slice(&data[..2]);
+    let mut inserted = false;
+    for (marker, range) in &segments {
+        if !inserted && *marker != 0xE0 {
+            output.extend_from_slice(&new_segments);
+            inserted = true;
+        }
+        let payload = data.get(range.start + 4..range.end).unwrap_or_default();
+        if !is_metadata_segment(*marker, payload) {
+            output.extend_from_slice(&data[range.clone()]);
+        }
+    }
+    if !inserted {
+        output.extend_from_slice(&new_segments);
+    }
+    output.extend_from_slice(&data[scan_start..]);
+    output
+}
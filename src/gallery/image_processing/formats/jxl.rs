@@ -0,0 +1,378 @@
+use crate::gallery::GalleryError;
+use image::{DynamicImage, GenericImageView, ImageBuffer};
+use jpegxl_rs::decode::Pixels;
+use jpegxl_sys::color::color_encoding::JxlColorEncoding;
+use jpegxl_sys::common::types::{JxlBool, JxlDataType, JxlEndianness, JxlPixelFormat};
+use jpegxl_sys::decode::{self as dec, JxlColorProfileTarget, JxlDecoderStatus};
+use jpegxl_sys::encoder::encode::{self as sys, JxlEncoderFrameSettingId, JxlEncoderStatus};
+use jpegxl_sys::metadata::codestream_header::JxlBasicInfo;
+use std::mem::MaybeUninit;
+use std::path::Path;
+use tracing::debug;
+
+pub use super::jxl_container::{extract_dimensions, is_jxl};
+
+/// Encoder effort, 1 (fastest) to 9. libjxl's default, which is quick enough
+/// to resize on request.
+const EFFORT: i64 = 7;
+
+fn processing_error(context: &str, error: impl std::fmt::Display) -> GalleryError {
+    GalleryError::ProcessingError(format!("{}: {}", context, error))
+}
+
+/// Whether a file is JPEG XL, by its first bytes
+pub fn is_jxl_file(path: &Path) -> bool {
+    use std::io::Read;
+
+    let mut start = [0u8; 12];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read(&mut start))
+        .is_ok_and(|read| is_jxl(&start[..read]))
+}
+
+fn image_from_samples8(
+    width: u32,
+    height: u32,
+    channels: u32,
+    samples: Vec<u8>,
+) -> Option<DynamicImage> {
+    match channels {
+        1 => ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageLuma8),
+        2 => ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageLumaA8),
+        3 => ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgb8),
+        4 => ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgba8),
+        _ => None,
+    }
+}
+
+fn image_from_samples16(
+    width: u32,
+    height: u32,
+    channels: u32,
+    samples: Vec<u16>,
+) -> Option<DynamicImage> {
+    match channels {
+        1 => ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageLuma16),
+        2 => ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageLumaA16),
+        3 => ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgb16),
+        4 => ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgba16),
+        _ => None,
+    }
+}
+
+/// Decode a JPEG XL file along with its ICC profile. libjxl applies the
+/// orientation from the header, so the image comes out upright.
+pub fn read_jxl(path: &Path) -> Result<(DynamicImage, Option<Vec<u8>>), GalleryError> {
+    let data = std::fs::read(path)?;
+    let decoder = jpegxl_rs::decoder_builder()
+        .icc_profile(true)
+        .build()
+        .map_err(|e| processing_error("Failed to create JPEG XL decoder", e))?;
+    let (metadata, pixels) = decoder
+        .decode(&data)
+        .map_err(|e| processing_error("Failed to decode JPEG XL", e))?;
+
+    let (width, height) = (metadata.width, metadata.height);
+    let channels = metadata.num_color_channels + u32::from(metadata.has_alpha_channel);
+    let image = match pixels {
+        Pixels::Uint8(samples) => image_from_samples8(width, height, channels, samples),
+        Pixels::Uint16(samples) => image_from_samples16(width, height, channels, samples),
+        // Floating point samples come down to 16 bits, which every encoder
+        // here can take
+        _ => {
+            let (_, samples) = decoder
+                .decode_with::<u16>(&data)
+                .map_err(|e| processing_error("Failed to decode JPEG XL", e))?;
+            image_from_samples16(width, height, channels, samples)
+        }
+    }
+    .ok_or_else(|| {
+        GalleryError::ProcessingError(format!(
+            "Unsupported JPEG XL channel layout in {:?}: {} channels",
+            path, channels
+        ))
+    })?;
+
+    debug!(
+        "Decoded JPEG XL {:?}: {}x{}, {} channels, ICC profile: {}",
+        path,
+        width,
+        height,
+        channels,
+        metadata.icc_profile.is_some()
+    );
+    Ok((image, metadata.icc_profile))
+}
+
+/// Owns a libjxl decoder
+struct Decoder(*mut dec::JxlDecoder);
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        unsafe { dec::JxlDecoderDestroy(self.0) }
+    }
+}
+
+/// Extract ICC profile from JPEG XL file. The decoder only reads up to the
+/// color encoding in the header, so no pixels are decoded.
+pub fn extract_icc_profile(path: &Path) -> Option<Vec<u8>> {
+    let data = std::fs::read(path).ok()?;
+    let decoder = Decoder(unsafe { dec::JxlDecoderCreate(std::ptr::null()) });
+    if decoder.0.is_null() {
+        return None;
+    }
+
+    unsafe {
+        let success = |status: JxlDecoderStatus| matches!(status, JxlDecoderStatus::Success);
+        if !success(dec::JxlDecoderSubscribeEvents(
+            decoder.0,
+            JxlDecoderStatus::ColorEncoding as i32,
+        )) || !success(dec::JxlDecoderSetInput(
+            decoder.0,
+            data.as_ptr(),
+            data.len(),
+        )) {
+            return None;
+        }
+        dec::JxlDecoderCloseInput(decoder.0);
+
+        // Nothing else is subscribed to, so anything else means the header
+        // couldn't be read
+        if !matches!(
+            dec::JxlDecoderProcessInput(decoder.0),
+            JxlDecoderStatus::ColorEncoding
+        ) {
+            return None;
+        }
+
+        let mut size = 0;
+        if !success(dec::JxlDecoderGetICCProfileSize(
+            decoder.0,
+            JxlColorProfileTarget::Data,
+            &mut size,
+        )) {
+            return None;
+        }
+        let mut profile = vec![0u8; size];
+        if !success(dec::JxlDecoderGetColorAsICCProfile(
+            decoder.0,
+            JxlColorProfileTarget::Data,
+            profile.as_mut_ptr(),
+            size,
+        )) {
+            return None;
+        }
+        Some(profile)
+    }
+}
+
+/// Owns a libjxl encoder
+struct Encoder(*mut sys::JxlEncoder);
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        unsafe { sys::JxlEncoderDestroy(self.0) }
+    }
+}
+
+fn check(status: JxlEncoderStatus, step: &str) -> Result<(), GalleryError> {
+    match status {
+        JxlEncoderStatus::Success => Ok(()),
+        _ => Err(GalleryError::ProcessingError(format!(
+            "JPEG XL encoder failed to {}",
+            step
+        ))),
+    }
+}
+
+impl Encoder {
+    fn new() -> Result<Self, GalleryError> {
+        let encoder = unsafe { sys::JxlEncoderCreate(std::ptr::null()) };
+        if encoder.is_null() {
+            return Err(GalleryError::ProcessingError(
+                "Failed to create JPEG XL encoder".to_string(),
+            ));
+        }
+        Ok(Encoder(encoder))
+    }
+
+    fn frame_settings(&self) -> Result<*mut sys::JxlEncoderFrameSettings, GalleryError> {
+        let settings = unsafe { sys::JxlEncoderFrameSettingsCreate(self.0, std::ptr::null()) };
+        if settings.is_null() {
+            return Err(GalleryError::ProcessingError(
+                "Failed to create JPEG XL frame settings".to_string(),
+            ));
+        }
+        Ok(settings)
+    }
+
+    /// Close the input and collect everything the encoder writes
+    fn finish(self) -> Result<Vec<u8>, GalleryError> {
+        unsafe { sys::JxlEncoderCloseInput(self.0) };
+
+        let mut output = vec![0u8; 64 * 1024];
+        let mut written = 0;
+        loop {
+            let mut next_out = unsafe { output.as_mut_ptr().add(written) };
+            let mut avail_out = output.len() - written;
+            let status =
+                unsafe { sys::JxlEncoderProcessOutput(self.0, &mut next_out, &mut avail_out) };
+            written = output.len() - avail_out;
+            match status {
+                JxlEncoderStatus::Success => break,
+                JxlEncoderStatus::NeedMoreOutput => output.resize(output.len() * 2, 0),
+                _ => {
+                    return Err(GalleryError::ProcessingError(
+                        "JPEG XL encoder failed to write the image".to_string(),
+                    ));
+                }
+            }
+        }
+        output.truncate(written);
+        Ok(output)
+    }
+}
+
+/// Encode an image with lossy compression. `quality` is on the same scale as
+/// JPEG's. Images with more than 8 bits per sample keep 16.
+fn encode(
+    image: &DynamicImage,
+    quality: u8,
+    icc_profile: Option<&[u8]>,
+) -> Result<Vec<u8>, GalleryError> {
+    let has_alpha = image.color().has_alpha();
+    let high_bit_depth = !matches!(
+        image,
+        DynamicImage::ImageLuma8(_)
+            | DynamicImage::ImageLumaA8(_)
+            | DynamicImage::ImageRgb8(_)
+            | DynamicImage::ImageRgba8(_)
+    );
+    let (pixels, data_type, bits) = if high_bit_depth {
+        let samples = if has_alpha {
+            image.to_rgba16().into_raw()
+        } else {
+            image.to_rgb16().into_raw()
+        };
+        let bytes: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| sample.to_ne_bytes())
+            .collect();
+        (bytes, JxlDataType::Uint16, 16)
+    } else {
+        let bytes = if has_alpha {
+            image.to_rgba8().into_raw()
+        } else {
+            image.to_rgb8().into_raw()
+        };
+        (bytes, JxlDataType::Uint8, 8)
+    };
+    let (width, height) = image.dimensions();
+
+    let encoder = Encoder::new()?;
+    unsafe {
+        let mut info = MaybeUninit::<JxlBasicInfo>::uninit();
+        sys::JxlEncoderInitBasicInfo(info.as_mut_ptr());
+        let mut info = info.assume_init();
+        info.xsize = width;
+        info.ysize = height;
+        info.bits_per_sample = bits;
+        info.exponent_bits_per_sample = 0;
+        info.num_color_channels = 3;
+        if has_alpha {
+            info.num_extra_channels = 1;
+            info.alpha_bits = bits;
+            info.alpha_exponent_bits = 0;
+        }
+        // Lossy images are stored in libjxl's own color space, converted
+        // from the ICC profile, which is kept for decoding
+        info.uses_original_profile = JxlBool::False;
+        check(
+            sys::JxlEncoderSetBasicInfo(encoder.0, &info),
+            "set the image size",
+        )?;
+
+        if let Some(profile) = icc_profile {
+            check(
+                sys::JxlEncoderSetICCProfile(encoder.0, profile.as_ptr(), profile.len()),
+                "set the ICC profile",
+            )?;
+        } else {
+            let mut color = MaybeUninit::<JxlColorEncoding>::uninit();
+            sys::JxlColorEncodingSetToSRGB(color.as_mut_ptr(), JxlBool::False);
+            check(
+                sys::JxlEncoderSetColorEncoding(encoder.0, color.as_ptr()),
+                "set the color space",
+            )?;
+        }
+
+        let settings = encoder.frame_settings()?;
+        check(
+            sys::JxlEncoderSetFrameDistance(
+                settings,
+                sys::JxlEncoderDistanceFromQuality(f32::from(quality)),
+            ),
+            "set the quality",
+        )?;
+        check(
+            sys::JxlEncoderFrameSettingsSetOption(
+                settings,
+                JxlEncoderFrameSettingId::Effort,
+                EFFORT,
+            ),
+            "set the effort",
+        )?;
+
+        let format = JxlPixelFormat {
+            num_channels: if has_alpha { 4 } else { 3 },
+            data_type,
+            endianness: JxlEndianness::Native,
+            align: 0,
+        };
+        check(
+            sys::JxlEncoderAddImageFrame(settings, &format, pixels.as_ptr().cast(), pixels.len()),
+            "add the image",
+        )?;
+    }
+
+    encoder.finish()
+}
+
+/// Save image as JPEG XL with optional ICC profile
+pub fn save_with_profile(
+    image: &DynamicImage,
+    path: &Path,
+    quality: u8,
+    icc_profile: Option<&[u8]>,
+) -> Result<(), GalleryError> {
+    let data = match encode(image, quality, icc_profile) {
+        Err(e) if icc_profile.is_some() => {
+            debug!(
+                "Failed to encode JPEG XL with ICC profile ({}), using sRGB",
+                e
+            );
+            encode(image, quality, None)?
+        }
+        result => result?,
+    };
+    std::fs::write(path, data)?;
+    Ok(())
+}
+
+/// Recompress a JPEG file losslessly. The result is usually about a fifth
+/// smaller and decodes back to the identical JPEG, metadata included.
+pub fn recompress_jpeg(jpeg: &[u8]) -> Result<Vec<u8>, GalleryError> {
+    let encoder = Encoder::new()?;
+    unsafe {
+        check(
+            sys::JxlEncoderStoreJPEGMetadata(encoder.0, JxlBool::True),
+            "keep the JPEG reconstruction data",
+        )?;
+        let settings = encoder.frame_settings()?;
+        check(
+            sys::JxlEncoderAddJPEGFrame(settings, jpeg.as_ptr(), jpeg.len()),
+            "read the JPEG",
+        )?;
+    }
+    encoder.finish()
+}
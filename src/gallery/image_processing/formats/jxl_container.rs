@@ -0,0 +1,212 @@
+// JPEG XL file structure: a bare codestream, or an ISO BMFF style container
+// holding the codestream next to Exif and XMP boxes
+use std::ops::Range;
+use std::path::Path;
+
+use crate::gallery::image_processing::metadata_policy::MetadataBlocks;
+
+/// Start of a bare codestream
+const CODESTREAM_SIGNATURE: &[u8] = &[0xFF, 0x0A];
+/// First box of a container
+const SIGNATURE_BOX: &[u8] = b"\0\0\0\x0CJXL \r\n\x87\n";
+/// File type box that follows the signature box
+const FTYP_BOX: &[u8] = b"\0\0\0\x14ftypjxl \0\0\0\0jxl ";
+
+pub fn is_jxl(data: &[u8]) -> bool {
+    data.starts_with(CODESTREAM_SIGNATURE) || data.starts_with(SIGNATURE_BOX)
+}
+
+/// Top-level boxes of a container as `(type, range)` pairs, where the range
+/// covers the whole box, and the offset its payload starts at
+fn boxes(data: &[u8]) -> Vec<([u8; 4], Range<usize>, usize)> {
+    let mut boxes = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let size = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as u64;
+        let box_type: [u8; 4] = data[pos + 4..pos + 8].try_into().unwrap();
+        let (size, header) = match size {
+            // Runs to the end of the file
+            0 => ((data.len() - pos) as u64, 8),
+            1 => match data.get(pos + 8..pos + 16) {
+                Some(large) => (u64::from_be_bytes(large.try_into().unwrap()), 16),
+                None => break,
+            },
+            size => (size, 8),
+        };
+        let Some(end) = usize::try_from(size)
+            .ok()
+            .and_then(|size| pos.checked_add(size))
+        else {
+            break;
+        };
+        if size < header as u64 || end > data.len() {
+            break;
+        }
+        boxes.push((box_type, pos..end, pos + header));
+        pos = end;
+    }
+    boxes
+}
+
+/// The start of the codestream: all of a bare one, or the first codestream
+/// box of a container
+fn codestream(data: &[u8]) -> Option<&[u8]> {
+    if data.starts_with(CODESTREAM_SIGNATURE) {
+        return Some(data);
+    }
+    boxes(data)
+        .into_iter()
+        .find_map(|(box_type, range, payload)| match &box_type {
+            b"jxlc" => data.get(payload..range.end),
+            // Partial codestream boxes start with their index
+            b"jxlp" => data.get(payload + 4..range.end),
+            _ => None,
+        })
+}
+
+/// Reads codestream header fields, least significant bit first
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: usize) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self.data.get((self.pos + i) / 8)?;
+            value |= u32::from((byte >> ((self.pos + i) % 8)) & 1) << i;
+        }
+        self.pos += count;
+        Some(value)
+    }
+
+    fn bool(&mut self) -> Option<bool> {
+        self.bits(1).map(|bit| bit == 1)
+    }
+
+    /// An image dimension: a multiple of 8 up to 256 for small images,
+    /// otherwise one of four field widths
+    fn dimension(&mut self, small: bool) -> Option<u32> {
+        if small {
+            return Some((self.bits(5)? + 1) * 8);
+        }
+        let width = [9, 13, 18, 30][self.bits(2)? as usize];
+        Some(self.bits(width)? + 1)
+    }
+}
+
+/// Width and height as displayed, read from the codestream header. The
+/// decoder applies the header's orientation, so transposing orientations
+/// swap the stored dimensions.
+pub(crate) fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let codestream = codestream(data)?.strip_prefix(CODESTREAM_SIGNATURE)?;
+    let mut reader = BitReader {
+        data: codestream,
+        pos: 0,
+    };
+
+    let small = reader.bool()?;
+    let height = reader.dimension(small)?;
+    let width = match reader.bits(3)? {
+        0 => reader.dimension(small)?,
+        ratio => {
+            let (numerator, denominator) =
+                [(1, 1), (12, 10), (4, 3), (3, 2), (16, 9), (5, 4), (2, 1)][ratio as usize - 1];
+            (u64::from(height) * numerator / denominator) as u32
+        }
+    };
+
+    // Image metadata: unless everything is default, orientation comes
+    // straight after a flag for the extra fields
+    let orientation = if !reader.bool()? && reader.bool()? {
+        reader.bits(3)? + 1
+    } else {
+        1
+    };
+    Some(if orientation >= 5 {
+        (height, width)
+    } else {
+        (width, height)
+    })
+}
+
+pub fn extract_dimensions(path: &Path) -> Option<(u32, u32)> {
+    dimensions(&std::fs::read(path).ok()?)
+}
+
+/// The type a box's contents have once decompressed; `brob` boxes hold
+/// Brotli-compressed boxes behind their real type
+fn content_type(data: &[u8], box_type: [u8; 4], payload: usize) -> [u8; 4] {
+    if &box_type == b"brob"
+        && let Some(inner) = data.get(payload..payload + 4)
+    {
+        return inner.try_into().unwrap();
+    }
+    box_type
+}
+
+/// Exif and XMP boxes of JPEG XL data. The Exif box starts with the offset
+/// of the TIFF header, which is skipped. Compressed boxes are left out.
+pub(crate) fn metadata_blocks(data: &[u8]) -> MetadataBlocks {
+    let mut blocks = MetadataBlocks::default();
+    for (box_type, range, payload) in boxes(data) {
+        let contents = &data[payload..range.end];
+        match &box_type {
+            b"Exif" if blocks.exif.is_none() => {
+                blocks.exif = contents.get(..4).and_then(|offset| {
+                    let offset = u32::from_be_bytes(offset.try_into().unwrap()) as usize;
+                    contents.get(4 + offset..).map(|tiff| tiff.to_vec())
+                });
+            }
+            b"xml " if blocks.xmp.is_none() => blocks.xmp = Some(contents.to_vec()),
+            _ => {}
+        }
+    }
+    blocks
+}
+
+fn push_box(output: &mut Vec<u8>, box_type: &[u8; 4], payload: &[u8]) {
+    output.extend_from_slice(&((payload.len() + 8) as u32).to_be_bytes());
+    output.extend_from_slice(box_type);
+    output.extend_from_slice(payload);
+}
+
+/// JPEG XL data with its Exif and XMP boxes replaced by `blocks`, written
+/// straight after the file type box. A bare codestream is wrapped in a
+/// container first. JPEG reconstruction data describes the old metadata, so
+/// it is dropped. JPEG XL has no place for IPTC.
+pub(crate) fn replace_metadata(data: &[u8], blocks: &MetadataBlocks) -> Vec<u8> {
+    let mut metadata = Vec::new();
+    if let Some(exif) = &blocks.exif {
+        let mut payload = vec![0u8; 4];
+        payload.extend_from_slice(exif);
+        push_box(&mut metadata, b"Exif", &payload);
+    }
+    if let Some(xmp) = &blocks.xmp {
+        push_box(&mut metadata, b"xml ", xmp);
+    }
+
+    let mut output = Vec::with_capacity(data.len() + metadata.len() + 40);
+    if data.starts_with(CODESTREAM_SIGNATURE) {
+        output.extend_from_slice(SIGNATURE_BOX);
+        output.extend_from_slice(FTYP_BOX);
+        output.extend_from_slice(&metadata);
+        push_box(&mut output, b"jxlc", data);
+        return output;
+    }
+
+    for (box_type, range, payload) in boxes(data) {
+        if matches!(
+            &content_type(data, box_type, payload),
+            b"Exif" | b"xml " | b"jbrd"
+        ) {
+            continue;
+        }
+        output.extend_from_slice(&data[range]);
+        if &box_type == b"ftyp" {
+            output.extend_from_slice(&metadata);
+        }
+    }
+    output
+}
@@ -3,6 +3,10 @@ pub mod avif;
 #[cfg(feature = "avif")]
 pub mod avif_container;
 pub mod jpeg;
+#[cfg(feature = "jxl")]
+pub mod jxl;
+#[cfg(feature = "jxl")]
+pub mod jxl_container;
 pub mod png;
 pub mod webp;
 
@@ -19,8 +23,13 @@ impl Gallery {
             return OutputFormat::Png;
         }
 
-        // For all sources (including AVIF), check browser support in priority order
+        // For all sources (including AVIF and JPEG XL), check browser support in priority order
         // This allows AVIF sources to be served as WebP/JPEG when browser doesn't support AVIF
+        #[cfg(feature = "jxl")]
+        if accept_header.contains("image/jxl") {
+            return OutputFormat::Jxl;
+        }
+
         #[cfg(feature = "avif")]
         if accept_header.contains("image/avif") {
             return OutputFormat::Avif;
@@ -5,6 +5,7 @@ use std::path::Path;
 use tracing::debug;
 
 use crate::gallery::GalleryError;
+use crate::gallery::image_processing::metadata_policy::MetadataBlocks;
 
 /// Extract ICC profile from PNG file
 pub fn extract_icc_profile(path: &Path) -> Option<Vec<u8>> {
@@ -95,3 +96,99 @@ pub fn save(image: &DynamicImage, path: &Path) -> Result<(), GalleryError> {
     image.write_with_encoder(encoder)?;
     Ok(())
 }
+
+/// iTXt keyword of an embedded XMP packet
+const XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+/// Chunks after the signature as `(type, range)` pairs, where the range
+/// covers the whole chunk including length and CRC
+fn chunks(data: &[u8]) -> Vec<([u8; 4], std::ops::Range<usize>)> {
+    let mut chunks = Vec::new();
+    let mut pos = 8;
+    while pos + 12 <= data.len() {
+        let length =
+            u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let end = pos + 12 + length;
+        if end > data.len() {
+            break;
+        }
+        let chunk_type = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+        chunks.push((chunk_type, pos..end));
+        pos = end;
+    }
+    chunks
+}
+
+/// The packet of an uncompressed XMP iTXt chunk. Compressed ones are
+/// recognised so they can be removed, but yield no packet.
+fn xmp_packet(chunk_type: &[u8; 4], chunk_data: &[u8]) -> Option<Option<Vec<u8>>> {
+    if chunk_type != b"iTXt" || !chunk_data.starts_with(XMP_KEYWORD) {
+        return None;
+    }
+    let rest = chunk_data[XMP_KEYWORD.len()..].strip_prefix(&[0u8])?;
+    let compressed = rest.first().copied().unwrap_or(1) != 0;
+    // Compression method, then null-terminated language tag and translated keyword
+    let text = rest.get(2..).and_then(|rest| {
+        let language_end = rest.iter().position(|&b| b == 0)?;
+        let rest = &rest[language_end + 1..];
+        let keyword_end = rest.iter().position(|&b| b == 0)?;
+        Some(&rest[keyword_end + 1..])
+    });
+    Some(text.filter(|_| !compressed).map(|text| text.to_vec()))
+}
+
+/// EXIF (`eXIf`) and XMP (`iTXt`) of PNG data
+pub(crate) fn metadata_blocks(data: &[u8]) -> MetadataBlocks {
+    let mut blocks = MetadataBlocks::default();
+    for (chunk_type, range) in chunks(data) {
+        let chunk_data = &data[range.start + 8..range.end - 4];
+        if &chunk_type == b"eXIf" && blocks.exif.is_none() {
+            blocks.exif = Some(chunk_data.to_vec());
+        } else if let Some(Some(packet)) = xmp_packet(&chunk_type, chunk_data)
+            && blocks.xmp.is_none()
+        {
+            blocks.xmp = Some(packet);
+        }
+    }
+    blocks
+}
+
+fn write_chunk(output: &mut Vec<u8>, chunk_type: &[u8; 4], chunk_data: &[u8]) {
+    let mut crc = flate2::Crc::new();
+    crc.update(chunk_type);
+    crc.update(chunk_data);
+
+    output.extend_from_slice(&(chunk_data.len() as u32).to_be_bytes());
+    output.extend_from_slice(chunk_type);
+    output.extend_from_slice(chunk_data);
+    output.extend_from_slice(&crc.sum().to_be_bytes());
+}
+
+/// PNG data with its EXIF and XMP chunks replaced by `blocks`, which are
+/// written straight after the header. PNG has no place for IPTC.
+pub(crate) fn replace_metadata(data: &[u8], blocks: &MetadataBlocks) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&data[..8]);
+
+    for (chunk_type, range) in chunks(data) {
+        let chunk_data = &data[range.start + 8..range.end - 4];
+        if &chunk_type == b"eXIf" || xmp_packet(&chunk_type, chunk_data).is_some() {
+            continue;
+        }
+        output.extend_from_slice(&data[range]);
+
+        if &chunk_type == b"IHDR" {
+            if let Some(exif) = &blocks.exif {
+                write_chunk(&mut output, b"eXIf", exif);
+            }
+            if let Some(xmp) = &blocks.xmp {
+                let mut itxt = XMP_KEYWORD.to_vec();
+                // Uncompressed, no language tag or translated keyword
+                itxt.extend_from_slice(&[0, 0, 0, 0, 0]);
+                itxt.extend_from_slice(xmp);
+                write_chunk(&mut output, b"iTXt", &itxt);
+            }
+        }
+    }
+    output
+}
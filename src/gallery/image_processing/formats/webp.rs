@@ -3,6 +3,7 @@ use std::path::Path;
 use tracing::{debug, error};
 
 use crate::gallery::GalleryError;
+use crate::gallery::image_processing::metadata_policy::MetadataBlocks;
 use crate::webp_encoder::{WebPEncoder, WebPError};
 
 /// Save image as WebP with optional ICC profile
@@ -63,3 +64,144 @@ fn save_fallback(image: &DynamicImage, path: &Path, quality: f32) -> Result<(),
     std::fs::write(path, &*encoded_webp)?;
     Ok(())
 }
+
+/// VP8X feature flags
+const VP8X_EXIF: u8 = 0x08;
+const VP8X_XMP: u8 = 0x04;
+const VP8X_ALPHA: u8 = 0x10;
+
+/// RIFF chunks after the `WEBP` header as `(fourcc, range)` pairs, where the
+/// range covers the chunk header, payload and padding
+fn chunks(data: &[u8]) -> Vec<([u8; 4], std::ops::Range<usize>)> {
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let fourcc = [data[pos], data[pos + 1], data[pos + 2], data[pos + 3]];
+        let size = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]])
+            as usize;
+        let end = (pos + 8 + size + (size & 1)).min(data.len());
+        if pos + 8 + size > data.len() {
+            break;
+        }
+        chunks.push((fourcc, pos..end));
+        pos = end;
+    }
+    chunks
+}
+
+/// EXIF and XMP chunks of WebP data
+pub(crate) fn metadata_blocks(data: &[u8]) -> MetadataBlocks {
+    let mut blocks = MetadataBlocks::default();
+    for (fourcc, range) in chunks(data) {
+        let size =
+            u32::from_le_bytes(data[range.start + 4..range.start + 8].try_into().unwrap()) as usize;
+        let payload = &data[range.start + 8..range.start + 8 + size];
+        match &fourcc {
+            // Some writers keep the JPEG-style header
+            b"EXIF" if blocks.exif.is_none() => {
+                blocks.exif = Some(
+                    payload
+                        .strip_prefix(b"Exif\0\0")
+                        .unwrap_or(payload)
+                        .to_vec(),
+                );
+            }
+            b"XMP " if blocks.xmp.is_none() => blocks.xmp = Some(payload.to_vec()),
+            _ => {}
+        }
+    }
+    blocks
+}
+
+/// Canvas size and alpha of a simple format (single `VP8 ` or `VP8L` chunk)
+/// file, read from its bitstream header
+fn simple_format_info(fourcc: &[u8; 4], payload: &[u8]) -> Option<(u32, u32, bool)> {
+    match fourcc {
+        b"VP8 " => {
+            // Frame tag, then start code and 14-bit dimensions
+            let header = payload.get(3..10)?;
+            if header[..3] != [0x9D, 0x01, 0x2A] {
+                return None;
+            }
+            let width = u16::from_le_bytes([header[3], header[4]]) & 0x3FFF;
+            let height = u16::from_le_bytes([header[5], header[6]]) & 0x3FFF;
+            Some((width as u32, height as u32, false))
+        }
+        b"VP8L" => {
+            if *payload.first()? != 0x2F {
+                return None;
+            }
+            let bits = u32::from_le_bytes(payload.get(1..5)?.try_into().ok()?);
+            let width = (bits & 0x3FFF) + 1;
+            let height = ((bits >> 14) & 0x3FFF) + 1;
+            Some((width, height, bits & (1 << 28) != 0))
+        }
+        _ => None,
+    }
+}
+
+fn push_chunk(output: &mut Vec<u8>, fourcc: &[u8; 4], payload: &[u8]) {
+    output.extend_from_slice(fourcc);
+    output.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    output.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        output.push(0);
+    }
+}
+
+/// WebP data with its EXIF and XMP chunks replaced by `blocks`, converting
+/// a simple format file to the extended format when metadata is added.
+/// WebP has no place for IPTC. `None` if the file can't be understood.
+pub(crate) fn replace_metadata(data: &[u8], blocks: &MetadataBlocks) -> Option<Vec<u8>> {
+    let chunks = chunks(data);
+    let mut flags = 0;
+    if blocks.exif.is_some() {
+        flags |= VP8X_EXIF;
+    }
+    if blocks.xmp.is_some() {
+        flags |= VP8X_XMP;
+    }
+
+    let mut body = Vec::with_capacity(data.len());
+    match chunks.first() {
+        Some((fourcc, range)) if fourcc == b"VP8X" => {
+            let mut vp8x = data[range.clone()].to_vec();
+            vp8x[8] = (vp8x[8] & !(VP8X_EXIF | VP8X_XMP)) | flags;
+            body.extend_from_slice(&vp8x);
+        }
+        Some((fourcc, range)) => {
+            if blocks.exif.is_none() && blocks.xmp.is_none() {
+                // A simple format file can't hold metadata to begin with
+                return Some(data.to_vec());
+            }
+            let (width, height, alpha) =
+                simple_format_info(fourcc, &data[range.start + 8..range.end])?;
+            let mut vp8x = vec![0u8; 10];
+            vp8x[0] = flags | if alpha { VP8X_ALPHA } else { 0 };
+            vp8x[4..7].copy_from_slice(&width.saturating_sub(1).to_le_bytes()[..3]);
+            vp8x[7..10].copy_from_slice(&height.saturating_sub(1).to_le_bytes()[..3]);
+            push_chunk(&mut body, b"VP8X", &vp8x);
+        }
+        None => return None,
+    }
+
+    for (fourcc, range) in &chunks {
+        if fourcc != b"VP8X" && fourcc != b"EXIF" && fourcc != b"XMP " {
+            body.extend_from_slice(&data[range.clone()]);
+        }
+    }
+    // Metadata chunks come after the image data
+    if let Some(exif) = &blocks.exif {
+        push_chunk(&mut body, b"EXIF", exif);
+    }
+    if let Some(xmp) = &blocks.xmp {
+        push_chunk(&mut body, b"XMP ", xmp);
+    }
+
+    let mut output = Vec::with_capacity(body.len() + 12);
+    output.extend_from_slice(b"RIFF");
+    output.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+    output.extend_from_slice(b"WEBP");
+    output.extend_from_slice(&body);
+    Some(output)
+}
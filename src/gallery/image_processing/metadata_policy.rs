@@ -0,0 +1,520 @@
+//! Embedded EXIF, XMP and IPTC metadata in served files: reading it from any
+//! supported container, filtering it by a gallery's `MetadataPolicy` and
+//! writing it back without touching the image data.
+
+use crate::MetadataPolicy;
+use crate::gallery::embedded_metadata::{iptc_from_app13, parse_iptc, parse_xmp};
+use crate::sitemap::xml_escape;
+
+use super::formats;
+
+const ORIENTATION_TAG: u16 = 0x0112;
+const ARTIST_TAG: u16 = 0x013B;
+const COPYRIGHT_TAG: u16 = 0x8298;
+const GPS_IFD_TAG: u16 = 0x8825;
+const ASCII_TYPE: u16 = 2;
+const SHORT_TYPE: u16 = 3;
+
+/// Metadata blocks of one image. `exif` is raw TIFF data, `xmp` a packet
+/// and `iptc` a whole Photoshop APP13 payload, which only JPEG can carry.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct MetadataBlocks {
+    pub exif: Option<Vec<u8>>,
+    pub xmp: Option<Vec<u8>>,
+    pub iptc: Option<Vec<u8>>,
+}
+
+/// Image containers whose metadata can be rewritten
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Container {
+    Jpeg,
+    Png,
+    WebP,
+    /// ISO base media files: AVIF, and HEIF in general
+    Isobmff,
+    #[cfg(feature = "jxl")]
+    Jxl,
+}
+
+fn container(data: &[u8]) -> Option<Container> {
+    if data.starts_with(&[0xFF, 0xD8]) {
+        Some(Container::Jpeg)
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(Container::Png)
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some(Container::WebP)
+    } else if data.len() >= 8 && &data[4..8] == b"ftyp" {
+        Some(Container::Isobmff)
+    } else {
+        #[cfg(feature = "jxl")]
+        if formats::jxl_container::is_jxl(data) {
+            return Some(Container::Jxl);
+        }
+        None
+    }
+}
+
+impl MetadataBlocks {
+    /// Read the metadata of an image file's contents. Unknown formats have none.
+    pub(crate) fn read(data: &[u8]) -> Self {
+        match container(data) {
+            Some(Container::Jpeg) => formats::jpeg::metadata_blocks(data),
+            Some(Container::Png) => formats::png::metadata_blocks(data),
+            Some(Container::WebP) => formats::webp::metadata_blocks(data),
+            #[cfg(feature = "avif")]
+            Some(Container::Isobmff) => formats::avif_container::metadata_blocks(data),
+            #[cfg(feature = "jxl")]
+            Some(Container::Jxl) => formats::jxl_container::metadata_blocks(data),
+            _ => MetadataBlocks::default(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.exif.is_none() && self.xmp.is_none() && self.iptc.is_none()
+    }
+
+    /// What `policy` lets through. `upright` is for resized images, which
+    /// have already been rotated: their orientation is reset to normal and
+    /// the EXIF thumbnail of the original is dropped. Otherwise the
+    /// orientation is kept under every policy, since without it the image
+    /// would be shown on its side.
+    pub(crate) fn filtered(&self, policy: MetadataPolicy, upright: bool) -> Self {
+        let orientation = if upright { None } else { self.orientation() };
+        match policy {
+            MetadataPolicy::Strip => MetadataBlocks {
+                exif: minimal_exif(orientation, None, None),
+                xmp: None,
+                iptc: None,
+            },
+            MetadataPolicy::CopyrightOnly => {
+                let (creator, copyright) = self.creator_and_copyright();
+                MetadataBlocks {
+                    exif: minimal_exif(orientation, creator.as_deref(), copyright.as_deref()),
+                    xmp: copyright_xmp(creator.as_deref(), copyright.as_deref(), orientation),
+                    iptc: None,
+                }
+            }
+            MetadataPolicy::StripGps | MetadataPolicy::KeepAll => {
+                let strip_gps = policy == MetadataPolicy::StripGps;
+                let exif = self.exif.as_ref().and_then(|exif| {
+                    let mut tiff = Tiff::new(exif.clone())?;
+                    if strip_gps {
+                        tiff.strip_gps();
+                    }
+                    if upright {
+                        tiff.make_upright();
+                    }
+                    Some(tiff.data)
+                });
+                let xmp = self.xmp.as_ref().map(|xmp| {
+                    let mut xml = String::from_utf8_lossy(xmp).into_owned();
+                    if strip_gps {
+                        xml = remove_xmp_properties(&xml, "exif:GPS");
+                    }
+                    if upright {
+                        xml = remove_xmp_properties(&xml, "tiff:Orientation");
+                    }
+                    xml.into_bytes()
+                });
+                MetadataBlocks {
+                    exif,
+                    xmp,
+                    iptc: self.iptc.clone(),
+                }
+            }
+        }
+    }
+
+    /// The EXIF orientation, when it is anything but normal
+    fn orientation(&self) -> Option<u16> {
+        let tiff = Tiff::new(self.exif.clone()?)?;
+        let entry = tiff.entry(ORIENTATION_TAG)?;
+        tiff.u16(entry + 8).filter(|value| (2..=8).contains(value))
+    }
+
+    /// Creator and copyright notice, from EXIF first, then XMP, then IPTC
+    fn creator_and_copyright(&self) -> (Option<String>, Option<String>) {
+        let tiff = self.exif.clone().and_then(Tiff::new);
+        let xmp = self.xmp.as_deref().map(parse_xmp).unwrap_or_default();
+        let iptc = self
+            .iptc
+            .as_ref()
+            .and_then(|payload| iptc_from_app13(&[(0xED, payload.clone())]))
+            .map(|records| parse_iptc(&records))
+            .unwrap_or_default();
+
+        let creator = tiff
+            .as_ref()
+            .and_then(|tiff| tiff.ascii(ARTIST_TAG))
+            .or(xmp.creator)
+            .or(iptc.creator);
+        let copyright = tiff
+            .as_ref()
+            .and_then(|tiff| tiff.ascii(COPYRIGHT_TAG))
+            .or(xmp.copyright)
+            .or(iptc.copyright);
+        (creator, copyright)
+    }
+}
+
+/// Replace all EXIF, XMP and IPTC in an encoded image with `blocks`, leaving
+/// everything else byte for byte. `None` for formats that can't be rewritten.
+pub(crate) fn replace_metadata(data: &[u8], blocks: &MetadataBlocks) -> Option<Vec<u8>> {
+    match container(data)? {
+        Container::Jpeg => Some(formats::jpeg::replace_metadata(data, blocks)),
+        Container::Png => Some(formats::png::replace_metadata(data, blocks)),
+        Container::WebP => formats::webp::replace_metadata(data, blocks),
+        #[cfg(feature = "avif")]
+        Container::Isobmff => formats::avif_container::replace_metadata_in_place(data, blocks),
+        #[cfg(not(feature = "avif"))]
+        Container::Isobmff => None,
+        #[cfg(feature = "jxl")]
+        Container::Jxl => Some(formats::jxl_container::replace_metadata(data, blocks)),
+    }
+}
+
+/// An original file as `policy` allows it to be downloaded. Formats that
+/// can't carry EXIF or XMP are returned unchanged; `None` means the file's
+/// metadata couldn't be rewritten.
+pub(crate) fn rewrite_original(data: &[u8], policy: MetadataPolicy) -> Option<Vec<u8>> {
+    if policy == MetadataPolicy::KeepAll || container(data).is_none() {
+        return Some(data.to_vec());
+    }
+    let blocks = MetadataBlocks::read(data).filtered(policy, false);
+    replace_metadata(data, &blocks)
+}
+
+/// Byte order aware access to the TIFF structure of an EXIF block
+struct Tiff {
+    data: Vec<u8>,
+    big_endian: bool,
+}
+
+impl Tiff {
+    fn new(data: Vec<u8>) -> Option<Self> {
+        let big_endian = match data.get(..4)? {
+            b"II*\0" => false,
+            b"MM\0*" => true,
+            _ => return None,
+        };
+        Some(Tiff { data, big_endian })
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = [*self.data.get(offset)?, *self.data.get(offset + 1)?];
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn set_u16(&mut self, offset: usize, value: u16) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        if let Some(target) = self.data.get_mut(offset..offset + 2) {
+            target.copy_from_slice(&bytes);
+        }
+    }
+
+    fn set_u32(&mut self, offset: usize, value: u32) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        if let Some(target) = self.data.get_mut(offset..offset + 4) {
+            target.copy_from_slice(&bytes);
+        }
+    }
+
+    /// Offset of IFD0 and its number of entries
+    fn ifd0(&self) -> Option<(usize, usize)> {
+        let ifd0 = self.u32(4)? as usize;
+        Some((ifd0, self.u16(ifd0)? as usize))
+    }
+
+    /// Offset of the IFD0 entry for `tag`
+    fn entry(&self, tag: u16) -> Option<usize> {
+        let (ifd0, entries) = self.ifd0()?;
+        (0..entries)
+            .map(|i| ifd0 + 2 + i * 12)
+            .find(|&entry| self.u16(entry) == Some(tag))
+    }
+
+    /// Size in bytes of an entry's value, and where it is stored
+    fn value_location(&self, entry: usize) -> Option<(usize, usize)> {
+        let unit = match self.u16(entry + 2)? {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 => 4,
+            5 | 10 | 12 => 8,
+            _ => return None,
+        };
+        let size = unit * self.u32(entry + 4)? as usize;
+        let offset = if size <= 4 {
+            entry + 8
+        } else {
+            self.u32(entry + 8)? as usize
+        };
+        Some((offset, size))
+    }
+
+    /// Text of an ASCII entry in IFD0
+    fn ascii(&self, tag: u16) -> Option<String> {
+        let entry = self.entry(tag)?;
+        if self.u16(entry + 2)? != ASCII_TYPE {
+            return None;
+        }
+        let (offset, size) = self.value_location(entry)?;
+        let value = self.data.get(offset..offset + size)?;
+        let text = String::from_utf8_lossy(value);
+        let text = text.trim_end_matches('\0').trim();
+        (!text.is_empty()).then(|| text.to_string())
+    }
+
+    /// Wipe the GPS IFD and unlink it from IFD0
+    fn strip_gps(&mut self) {
+        let Some(entry) = self.entry(GPS_IFD_TAG) else {
+            return;
+        };
+        if let Some(gps) = self.u32(entry + 8).map(|offset| offset as usize)
+            && let Some(count) = self.u16(gps).map(usize::from)
+        {
+            for i in 0..count {
+                if let Some((offset, size)) = self.value_location(gps + 2 + i * 12) {
+                    self.zero(offset, size);
+                }
+            }
+            self.zero(gps, 2 + count * 12 + 4);
+        }
+        self.remove_entry(entry);
+    }
+
+    /// Remove an IFD0 entry, moving the ones after it and the next IFD offset up
+    fn remove_entry(&mut self, entry: usize) {
+        let Some((ifd0, entries)) = self.ifd0() else {
+            return;
+        };
+        let end = ifd0 + 2 + entries * 12 + 4;
+        if end > self.data.len() {
+            return;
+        }
+        self.data.copy_within(entry + 12..end, entry);
+        self.zero(end - 12, 12);
+        self.set_u16(ifd0, entries as u16 - 1);
+    }
+
+    /// Normal orientation, and no IFD1 with a thumbnail of the original
+    fn make_upright(&mut self) {
+        if let Some(entry) = self.entry(ORIENTATION_TAG) {
+            self.set_u16(entry + 8, 1);
+        }
+        if let Some((ifd0, entries)) = self.ifd0() {
+            self.set_u32(ifd0 + 2 + entries * 12, 0);
+        }
+    }
+
+    fn zero(&mut self, offset: usize, size: usize) {
+        let end = (offset + size).min(self.data.len());
+        if offset < end {
+            self.data[offset..end].fill(0);
+        }
+    }
+}
+
+/// A big-endian TIFF block with only Orientation, Artist and Copyright in IFD0
+fn minimal_exif(
+    orientation: Option<u16>,
+    creator: Option<&str>,
+    copyright: Option<&str>,
+) -> Option<Vec<u8>> {
+    let text = |text: Option<&str>| {
+        text.map(|text| {
+            let mut value = text.as_bytes().to_vec();
+            value.push(0);
+            (ASCII_TYPE, value.len() as u32, value)
+        })
+    };
+    // Entries in IFD0 are sorted by tag
+    let entries: Vec<(u16, (u16, u32, Vec<u8>))> = [
+        (
+            ORIENTATION_TAG,
+            orientation.map(|value| (SHORT_TYPE, 1, value.to_be_bytes().to_vec())),
+        ),
+        (ARTIST_TAG, text(creator)),
+        (COPYRIGHT_TAG, text(copyright)),
+    ]
+    .into_iter()
+    .filter_map(|(tag, value)| Some((tag, value?)))
+    .collect();
+    if entries.is_empty() {
+        return None;
+    }
+
+    let mut tiff = b"MM\0*".to_vec();
+    tiff.extend_from_slice(&8u32.to_be_bytes());
+    tiff.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+
+    let mut values = Vec::new();
+    let values_offset = 8 + 2 + entries.len() * 12 + 4;
+    for (tag, (kind, count, value)) in &entries {
+        tiff.extend_from_slice(&tag.to_be_bytes());
+        tiff.extend_from_slice(&kind.to_be_bytes());
+        tiff.extend_from_slice(&count.to_be_bytes());
+        if value.len() <= 4 {
+            let mut inline = [0u8; 4];
+            inline[..value.len()].copy_from_slice(value);
+            tiff.extend_from_slice(&inline);
+        } else {
+            tiff.extend_from_slice(&((values_offset + values.len()) as u32).to_be_bytes());
+            values.extend_from_slice(value);
+        }
+    }
+    tiff.extend_from_slice(&0u32.to_be_bytes());
+    tiff.extend_from_slice(&values);
+    Some(tiff)
+}
+
+/// An XMP packet with only `dc:creator` and `dc:rights`, plus
+/// `tiff:Orientation` to match the EXIF block
+fn copyright_xmp(
+    creator: Option<&str>,
+    copyright: Option<&str>,
+    orientation: Option<u16>,
+) -> Option<Vec<u8>> {
+    if creator.is_none() && copyright.is_none() {
+        return None;
+    }
+
+    let mut properties = String::new();
+    if let Some(creator) = creator {
+        properties.push_str(&format!(
+            "<dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>",
+            xml_escape(creator)
+        ));
+    }
+    if let Some(copyright) = copyright {
+        properties.push_str(&format!(
+            "<dc:rights><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:rights>",
+            xml_escape(copyright)
+        ));
+    }
+    if let Some(orientation) = orientation {
+        properties.push_str(&format!(
+            "<tiff:Orientation>{}</tiff:Orientation>",
+            orientation
+        ));
+    }
+
+    Some(
+        format!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\
+             <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\
+             <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+             <rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+             xmlns:tiff=\"http://ns.adobe.com/tiff/1.0/\">\
+             {}</rdf:Description></rdf:RDF></x:xmpmeta><?xpacket end=\"w\"?>",
+            properties
+        )
+        .into_bytes(),
+    )
+}
+
+/// Remove every XMP property whose qualified name starts with `prefix`, in
+/// both the attribute and the element form. Like the XMP reader this is a
+/// text scan, not an RDF parser.
+fn remove_xmp_properties(xml: &str, prefix: &str) -> String {
+    let mut result = String::with_capacity(xml.len());
+    let mut rest = xml;
+
+    while let Some(found) = rest.find(prefix) {
+        let before = &rest[..found];
+        let name_end = rest[found..]
+            .find(|c: char| !(c.is_alphanumeric() || matches!(c, ':' | '_' | '-' | '.')))
+            .map_or(rest.len(), |end| found + end);
+        let name = &rest[found..name_end];
+
+        if before.ends_with('<') {
+            // Element: up to the end of an empty tag or the matching end tag
+            let start_tag_end = rest[name_end..].find('>').map(|end| name_end + end + 1);
+            let end = start_tag_end.and_then(|tag_end| {
+                if rest[..tag_end].ends_with("/>") {
+                    Some(tag_end)
+                } else {
+                    let closing = format!("</{}>", name);
+                    rest[tag_end..]
+                        .find(&closing)
+                        .map(|end| tag_end + end + closing.len())
+                }
+            });
+            if let Some(end) = end {
+                result.push_str(&before[..before.len() - 1]);
+                rest = &rest[end..];
+                continue;
+            }
+        } else if before.ends_with(|c: char| c.is_whitespace())
+            && rest[name_end..].starts_with('=')
+            && let Some(quote) = rest[name_end + 1..].chars().next()
+            && (quote == '"' || quote == '\'')
+            && let Some(value_end) = rest[name_end + 2..].find(quote)
+        {
+            // Attribute, with the whitespace in front of it
+            result.push_str(before.trim_end());
+            rest = &rest[name_end + 2 + value_end + 1..];
+            continue;
+        }
+
+        // Not a property, such as a namespace declaration or text
+        result.push_str(&rest[..name_end]);
+        rest = &rest[name_end..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_xmp_properties() {
+        let xml = r#"<rdf:Description exif:GPSLatitude="37,46.5N"
+   exif:GPSLongitude='122,25.2W' tiff:Make="Sony"><exif:GPSAltitude>10/1</exif:GPSAltitude><exif:GPSTimeStamp/><dc:title>Bay</dc:title></rdf:Description>"#;
+
+        assert_eq!(
+            remove_xmp_properties(xml, "exif:GPS"),
+            r#"<rdf:Description tiff:Make="Sony"><dc:title>Bay</dc:title></rdf:Description>"#
+        );
+        // A namespace declaration mentioning the prefix is left alone
+        let declaration = r#"<x xmlns:exif="http://ns.adobe.com/exif/1.0/">exif:GPS</x>"#;
+        assert_eq!(remove_xmp_properties(declaration, "exif:GPS"), declaration);
+    }
+
+    #[test]
+    fn test_copyright_exif_round_trip() {
+        let exif = minimal_exif(Some(6), Some("Jane Doe"), Some("(c) 2024")).unwrap();
+        let tiff = Tiff::new(exif).unwrap();
+        assert_eq!(tiff.ascii(ARTIST_TAG).as_deref(), Some("Jane Doe"));
+        assert_eq!(tiff.ascii(COPYRIGHT_TAG).as_deref(), Some("(c) 2024"));
+        let blocks = MetadataBlocks {
+            exif: Some(tiff.data),
+            ..MetadataBlocks::default()
+        };
+        assert_eq!(blocks.orientation(), Some(6));
+        assert_eq!(minimal_exif(None, None, None), None);
+    }
+}
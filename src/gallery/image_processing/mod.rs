@@ -1,10 +1,15 @@
 // Image processing module - handles image resizing, format conversion, and serving
 pub mod formats;
 mod icc;
+pub(crate) mod metadata_policy;
+pub(crate) mod orientation;
+pub(crate) mod placeholder;
 mod resize;
 mod serve;
 mod types;
+pub(crate) mod video;
 mod watermark;
+pub(crate) mod workers;
 
 // Re-export public items
 pub use types::OutputFormat;
@@ -12,6 +17,8 @@ pub use types::OutputFormat;
 // Re-export format-specific ICC profile extraction functions
 #[cfg(feature = "avif")]
 pub use formats::avif::extract_icc_profile as extract_icc_profile_from_avif;
+#[cfg(feature = "jxl")]
+pub use formats::jxl::extract_icc_profile as extract_icc_profile_from_jxl;
 
 pub use formats::{
     jpeg::extract_icc_profile as extract_icc_profile_from_jpeg,
@@ -30,6 +37,13 @@ mod tests {
     mod composite_tests;
     mod icc_profile_tests;
     mod jpeg_tests;
+    #[cfg(feature = "jxl")]
+    mod jxl_tests;
+    mod metadata_policy_tests;
+    mod orientation_tests;
+    mod placeholder_tests;
     mod png_tests;
+    mod resize_tests;
+    mod video_tests;
     mod watermark_tests;
 }
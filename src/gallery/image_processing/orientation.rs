@@ -0,0 +1,201 @@
+use image::DynamicImage;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use tracing::{debug, trace};
+
+/// EXIF Orientation tag
+const ORIENTATION_TAG: u16 = 0x0112;
+/// Largest EXIF block we are willing to read (a JPEG APP1 segment tops out
+/// at 64 KiB; PNG and AVIF allow more but never need it for IFD0)
+const MAX_EXIF_SIZE: usize = 1024 * 1024;
+
+/// How the stored pixels must be transformed to display upright, as
+/// recorded in the EXIF Orientation tag (values 1-8)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Orientation {
+    /// 1: stored upright
+    #[default]
+    Normal,
+    /// 2: mirrored left to right
+    FlipHorizontal,
+    /// 3: upside down
+    Rotate180,
+    /// 4: mirrored top to bottom
+    FlipVertical,
+    /// 5: mirrored along the top-left to bottom-right diagonal
+    Transpose,
+    /// 6: needs a quarter turn clockwise
+    Rotate90,
+    /// 7: mirrored along the top-right to bottom-left diagonal
+    Transverse,
+    /// 8: needs a quarter turn counter-clockwise
+    Rotate270,
+}
+
+impl Orientation {
+    pub(crate) fn from_exif(value: u16) -> Option<Self> {
+        match value {
+            1 => Some(Orientation::Normal),
+            2 => Some(Orientation::FlipHorizontal),
+            3 => Some(Orientation::Rotate180),
+            4 => Some(Orientation::FlipVertical),
+            5 => Some(Orientation::Transpose),
+            6 => Some(Orientation::Rotate90),
+            7 => Some(Orientation::Transverse),
+            8 => Some(Orientation::Rotate270),
+            _ => None,
+        }
+    }
+
+    /// Whether width and height trade places when displayed
+    pub(crate) fn swaps_dimensions(self) -> bool {
+        matches!(
+            self,
+            Orientation::Transpose
+                | Orientation::Rotate90
+                | Orientation::Transverse
+                | Orientation::Rotate270
+        )
+    }
+
+    /// Displayed dimensions of an image stored as `dimensions`
+    pub(crate) fn oriented_dimensions(self, dimensions: (u32, u32)) -> (u32, u32) {
+        if self.swaps_dimensions() {
+            (dimensions.1, dimensions.0)
+        } else {
+            dimensions
+        }
+    }
+
+    /// Rotate and flip stored pixels so the image is upright
+    pub(crate) fn apply(self, img: DynamicImage) -> DynamicImage {
+        match self {
+            Orientation::Normal => img,
+            Orientation::FlipHorizontal => img.fliph(),
+            Orientation::Rotate180 => img.rotate180(),
+            Orientation::FlipVertical => img.flipv(),
+            Orientation::Transpose => img.rotate90().fliph(),
+            Orientation::Rotate90 => img.rotate90(),
+            Orientation::Transverse => img.rotate270().fliph(),
+            Orientation::Rotate270 => img.rotate270(),
+        }
+    }
+}
+
+/// Read the EXIF orientation of a JPEG, PNG (`eXIf` chunk) or AVIF file.
+/// Files without one, or that can't be read, are treated as upright.
+pub(crate) fn read_orientation(path: &Path) -> Orientation {
+    let exif = match read_exif_block(path) {
+        Ok(Some(exif)) => exif,
+        Ok(None) => return Orientation::Normal,
+        Err(e) => {
+            trace!("Could not read EXIF from {:?}: {}", path, e);
+            return Orientation::Normal;
+        }
+    };
+
+    let orientation = orientation_from_exif(&exif).unwrap_or_default();
+    if orientation != Orientation::Normal {
+        debug!("{:?} has EXIF orientation {:?}", path, orientation);
+    }
+    orientation
+}
+
+fn read_exif_block(path: &Path) -> std::io::Result<Option<Vec<u8>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut signature = [0u8; 8];
+    let read = reader.read(&mut signature)?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    if read >= 2 && signature[..2] == [0xFF, 0xD8] {
+        let segments = super::formats::jpeg::read_app_segments(path)?;
+        return Ok(segments
+            .into_iter()
+            .find(|(marker, data)| *marker == 0xE1 && data.starts_with(b"Exif\0\0"))
+            .map(|(_, data)| data));
+    }
+    if read == 8 && &signature == b"\x89PNG\r\n\x1a\n" {
+        return png_exif(&mut reader);
+    }
+
+    #[cfg(feature = "avif")]
+    if path
+        .extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("avif"))
+    {
+        return Ok(super::formats::avif::extract_exif_data(path));
+    }
+
+    Ok(None)
+}
+
+/// Walk PNG chunks looking for `eXIf`
+fn png_exif<R: Read + Seek>(reader: &mut R) -> std::io::Result<Option<Vec<u8>>> {
+    reader.seek(SeekFrom::Start(8))?;
+
+    loop {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let chunk_type = &header[4..8];
+
+        if chunk_type == b"eXIf" {
+            if length > MAX_EXIF_SIZE {
+                return Ok(None);
+            }
+            let mut data = vec![0u8; length];
+            reader.read_exact(&mut data)?;
+            return Ok(Some(data));
+        }
+        if chunk_type == b"IEND" {
+            return Ok(None);
+        }
+
+        // Skip data and CRC
+        reader.seek(SeekFrom::Current(length as i64 + 4))?;
+    }
+}
+
+/// Find the Orientation entry in IFD0 of an EXIF block. Accepts raw TIFF
+/// data as well as data behind an `Exif\0\0` header or AVIF's offset prefix.
+pub(crate) fn orientation_from_exif(exif: &[u8]) -> Option<Orientation> {
+    let start = exif
+        .windows(4)
+        .take(16)
+        .position(|window| window == b"II*\0" || window == b"MM\0*")?;
+    let tiff = &exif[start..];
+    let big_endian = tiff[0] == b'M';
+
+    let read_u16 = |offset: usize| -> Option<u16> {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let read_u32 = |offset: usize| -> Option<u32> {
+        let bytes = [
+            *tiff.get(offset)?,
+            *tiff.get(offset + 1)?,
+            *tiff.get(offset + 2)?,
+            *tiff.get(offset + 3)?,
+        ];
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+
+    let ifd0 = read_u32(4)? as usize;
+    let entries = read_u16(ifd0)? as usize;
+    (0..entries)
+        .map(|i| ifd0 + 2 + i * 12)
+        .find(|&entry| read_u16(entry) == Some(ORIENTATION_TAG))
+        // A single SHORT, stored in the first bytes of the value field
+        .and_then(|entry| read_u16(entry + 8))
+        .and_then(Orientation::from_exif)
+}
@@ -0,0 +1,100 @@
+use super::orientation::read_orientation;
+use crate::gallery::ImagePlaceholder;
+use base64::{Engine, engine::general_purpose};
+use image::{DynamicImage, RgbImage};
+use std::path::Path;
+use tracing::debug;
+
+/// Longest side of the blurred placeholder, in pixels
+const PLACEHOLDER_SIZE: u32 = 16;
+/// Longest side of the copy sampled for the dominant color
+const COLOR_SAMPLE_SIZE: u32 = 64;
+const PLACEHOLDER_WEBP_QUALITY: f32 = 50.0;
+
+/// Decode an image and build its placeholder. Returns `None` for files the
+/// image decoders can't read.
+pub(crate) fn generate_placeholder(path: &Path) -> Option<ImagePlaceholder> {
+    let img = match open_image(path) {
+        Ok(img) => img,
+        Err(e) => {
+            debug!("Could not decode {:?} for placeholder: {}", path, e);
+            return None;
+        }
+    };
+
+    placeholder_for_image(&img)
+}
+
+fn open_image(path: &Path) -> Result<DynamicImage, crate::gallery::GalleryError> {
+    #[cfg(feature = "avif")]
+    if path
+        .extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("avif"))
+        && let Ok((img, _)) = super::formats::avif::read_avif_info(path)
+    {
+        return Ok(read_orientation(path).apply(img));
+    }
+
+    // libjxl turns JPEG XL images upright itself
+    #[cfg(feature = "jxl")]
+    if super::formats::jxl::is_jxl_file(path) {
+        return Ok(super::formats::jxl::read_jxl(path)?.0);
+    }
+
+    Ok(read_orientation(path).apply(image::open(path)?))
+}
+
+/// A ~16px WebP `data:` URI plus the most common color of the image
+pub(crate) fn placeholder_for_image(img: &DynamicImage) -> Option<ImagePlaceholder> {
+    if img.width() == 0 || img.height() == 0 {
+        return None;
+    }
+
+    let sample = img
+        .thumbnail(COLOR_SAMPLE_SIZE, COLOR_SAMPLE_SIZE)
+        .to_rgb8();
+    let dominant_color = dominant_color(&sample)?;
+
+    let tiny = DynamicImage::ImageRgb8(sample)
+        .thumbnail(PLACEHOLDER_SIZE, PLACEHOLDER_SIZE)
+        .to_rgb8();
+    let (width, height) = tiny.dimensions();
+    let webp = crate::webp_encoder::WebPEncoder::new(width, height, tiny.into_raw())
+        .and_then(|encoder| encoder.encode(PLACEHOLDER_WEBP_QUALITY, None))
+        .map_err(|e| debug!("Failed to encode placeholder: {}", e))
+        .ok()?;
+
+    Some(ImagePlaceholder {
+        data_uri: format!(
+            "data:image/webp;base64,{}",
+            general_purpose::STANDARD.encode(webp)
+        ),
+        dominant_color: format!(
+            "#{:02x}{:02x}{:02x}",
+            dominant_color[0], dominant_color[1], dominant_color[2]
+        ),
+    })
+}
+
+/// Average of the most populated bucket when colors are reduced to 4 bits
+/// per channel. Unlike a plain average, this doesn't turn a blue sky over
+/// orange sand into grey.
+fn dominant_color(sample: &RgbImage) -> Option<[u8; 3]> {
+    let mut buckets = vec![(0u64, [0u64; 3]); 1 << 12];
+    for pixel in sample.pixels() {
+        let [r, g, b] = pixel.0;
+        let key = ((r as usize >> 4) << 8) | ((g as usize >> 4) << 4) | (b as usize >> 4);
+        let (count, sums) = &mut buckets[key];
+        *count += 1;
+        sums[0] += r as u64;
+        sums[1] += g as u64;
+        sums[2] += b as u64;
+    }
+
+    let (count, sums) = buckets.iter().max_by_key(|(count, _)| *count)?;
+    if *count == 0 {
+        return None;
+    }
+    Some(sums.map(|sum| (sum / count) as u8))
+}
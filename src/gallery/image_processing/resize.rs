@@ -1,5 +1,6 @@
 use crate::copyright::{CopyrightConfig, add_copyright_notice};
 use crate::gallery::{Gallery, GalleryError};
+use crate::{CropMode, MetadataPolicy, NamedSizeConfig};
 use image::{DynamicImage, ImageFormat, imageops::FilterType};
 use std::path::{Path, PathBuf};
 use tracing::{debug, error};
@@ -7,7 +8,10 @@ use tracing::{debug, error};
 use super::formats;
 #[cfg(feature = "avif")]
 use super::formats::avif::AvifImageInfo;
+use super::metadata_policy::{MetadataBlocks, replace_metadata};
+use super::orientation::read_orientation;
 use super::types::{ImageSize, OutputFormat};
+use super::workers;
 
 // Type alias for AVIF info that works with or without the feature
 #[cfg(feature = "avif")]
@@ -17,32 +21,41 @@ type AvifInfoOption = Option<AvifImageInfo>;
 type AvifInfoOption = Option<()>;
 
 impl Gallery {
-    /// Parse size string and determine dimensions
-    pub(super) fn parse_size(&self, size: &str) -> Result<(ImageSize, bool), GalleryError> {
-        // Check if it's a @2x variant
-        let (base_size, multiplier) = if size.ends_with("@2x") {
-            (size.trim_end_matches("@2x"), 2)
-        } else {
-            (size, 1)
+    /// Resolve a `?size=` value (a configured size name with an optional
+    /// `@2x` suffix) to its settings and final dimensions
+    pub(crate) fn parse_size(
+        &self,
+        size: &str,
+    ) -> Result<(ImageSize, NamedSizeConfig), GalleryError> {
+        let (base_size, multiplier) = match size.strip_suffix("@2x") {
+            Some(base_size) => (base_size, 2),
+            None => (size, 1),
         };
 
-        let base_dimensions = match base_size {
-            "thumbnail" => {
-                ImageSize::new(self.config.thumbnail.width, self.config.thumbnail.height)
-            }
-            "gallery" => ImageSize::new(
-                self.config.gallery_size.width,
-                self.config.gallery_size.height,
-            ),
-            "medium" => ImageSize::new(self.config.medium.width, self.config.medium.height),
-            "large" => ImageSize::new(self.config.large.width, self.config.large.height),
-            _ => return Err(GalleryError::InvalidPath),
-        };
+        let size_config = self
+            .config
+            .image_size(base_size)
+            .ok_or(GalleryError::InvalidPath)?;
+        let dimensions =
+            ImageSize::new(size_config.width, size_config.height).with_multiplier(multiplier);
 
-        let final_dimensions = base_dimensions.with_multiplier(multiplier);
-        let is_medium = base_size == "medium";
+        Ok((dimensions, size_config))
+    }
 
-        Ok((final_dimensions, is_medium))
+    /// JPEG, WebP and AVIF encoder quality for a size. A size's own quality
+    /// beats the gallery-wide settings.
+    pub(crate) fn encoder_qualities(&self, size_config: &NamedSizeConfig) -> (u8, f32, u8) {
+        let jpeg_quality = size_config
+            .quality
+            .or(self.config.jpeg_quality)
+            .unwrap_or(85);
+        let webp_quality = size_config
+            .quality
+            .map(f32::from)
+            .or(self.config.webp_quality)
+            .unwrap_or(85.0);
+        let avif_quality = size_config.quality.unwrap_or(85);
+        (jpeg_quality, webp_quality, avif_quality)
     }
 
     /// Get resized image from cache or generate it
@@ -53,10 +66,19 @@ impl Gallery {
         size: &str,
         output_format: OutputFormat,
     ) -> Result<PathBuf, GalleryError> {
-        let (dimensions, is_medium) = self.parse_size(size)?;
+        let (dimensions, size_config) = self.parse_size(size)?;
+
+        // Videos are resized from their poster
+        let poster;
+        let original_path = if crate::gallery::is_video_file(relative_path) {
+            poster = self.video_poster(original_path, relative_path).await?;
+            poster.as_path()
+        } else {
+            original_path
+        };
 
         // Determine if watermark will be applied
-        let apply_watermark = is_medium && self.config.copyright_holder.is_some();
+        let apply_watermark = size_config.watermark && self.config.copyright_holder.is_some();
 
         // Generate consistent cache keys that include watermark status
         let cache_filename = self.generate_cache_filename(
@@ -69,40 +91,53 @@ impl Gallery {
 
         // Check if cache file exists and is newer than original
         if self.is_cache_valid(&cache_path, original_path).await? {
+            self.note_cache_hit(&cache_filename, relative_path).await;
+            return Ok(cache_path);
+        }
+
+        // Requests arriving while the file is being made wait for it rather
+        // than making it again
+        let _flight = workers::claim(&cache_path).await;
+        if self.is_cache_valid(&cache_path, original_path).await? {
+            self.note_cache_hit(&cache_filename, relative_path).await;
             return Ok(cache_path);
         }
 
         // Ensure cache directory exists
         tokio::fs::create_dir_all(&self.config.cache_directory).await?;
 
-        // Process image in blocking thread
+        // Process image on a worker
         let original_path = original_path.to_path_buf();
-        let cache_path_clone = cache_path.clone();
         let copyright_holder = self.config.copyright_holder.clone();
         let static_dir = std::path::PathBuf::from("static"); // TODO: Make configurable
-        let jpeg_quality = self.config.jpeg_quality.unwrap_or(85);
-        let webp_quality = self.config.webp_quality.unwrap_or(85.0);
+        let (jpeg_quality, webp_quality, avif_quality) = self.encoder_qualities(&size_config);
+        let crop = size_config.crop;
+        let metadata_policy = self.config.metadata.derivatives;
 
-        tokio::task::spawn_blocking(move || -> Result<(), GalleryError> {
+        workers::write_atomically(&cache_path, move |temp_path| {
             process_image(
                 &original_path,
-                &cache_path_clone,
+                temp_path,
                 dimensions,
+                crop,
                 output_format,
                 apply_watermark,
                 copyright_holder,
                 &static_dir,
                 jpeg_quality,
                 webp_quality,
+                avif_quality,
+                metadata_policy,
             )
         })
-        .await??;
+        .await?;
 
+        self.track_cached_file(&cache_filename, relative_path).await;
         Ok(cache_path)
     }
 
     /// Check if cache file is valid (exists and newer than source)
-    async fn is_cache_valid(
+    pub(super) async fn is_cache_valid(
         &self,
         cache_path: &Path,
         original_path: &Path,
@@ -131,23 +166,46 @@ fn process_image(
     original_path: &Path,
     cache_path: &Path,
     dimensions: ImageSize,
+    crop: CropMode,
     output_format: OutputFormat,
     apply_watermark: bool,
     copyright_holder: Option<String>,
     static_dir: &Path,
     jpeg_quality: u8,
     webp_quality: f32,
+    avif_quality: u8,
+    metadata_policy: MetadataPolicy,
 ) -> Result<(), GalleryError> {
     // Detect format and extract ICC profile
     let (icc_profile, detected_format) = extract_image_info(original_path)?;
 
+    // Metadata the policy lets through, for an image that will be upright
+    let metadata = if metadata_policy == MetadataPolicy::Strip {
+        MetadataBlocks::default()
+    } else {
+        MetadataBlocks::read(&std::fs::read(original_path)?).filtered(metadata_policy, true)
+    };
+
     // Load and resize image - special handling for AVIF to preserve color properties
     debug!(
         "Opening image file: {:?}, detected format: {:?}",
         original_path, detected_format
     );
 
-    let (img, _avif_info) = {
+    // libjxl decodes JPEG XL, which the image crate can't read
+    #[cfg(feature = "jxl")]
+    let (jxl_image, icc_profile) = if formats::jxl::is_jxl_file(original_path) {
+        let (img, profile) = formats::jxl::read_jxl(original_path)?;
+        (Some(img), profile)
+    } else {
+        (None, icc_profile)
+    };
+    #[cfg(not(feature = "jxl"))]
+    let jxl_image: Option<DynamicImage> = None;
+
+    let (img, _avif_info) = if let Some(img) = jxl_image {
+        (img, None)
+    } else {
         #[cfg(feature = "avif")]
         {
             if detected_format == Some(ImageFormat::Avif) {
@@ -172,7 +230,23 @@ fn process_image(
         }
     };
 
-    let resized = resize_image(&img, dimensions)?;
+    // Turn the picture upright before resizing, cropping or watermarking
+    let orientation = read_orientation(original_path);
+    let img = orientation.apply(img);
+    #[cfg(feature = "avif")]
+    let _avif_info = _avif_info.map(|mut info| {
+        if let Some(gm_info) = info.gain_map_info.as_mut()
+            && let Some(gm_image) = gm_info.gain_map_image.take()
+        {
+            gm_info.gain_map_image = Some(orientation.apply(gm_image));
+        }
+        info
+    });
+
+    let (resized, source_region) = resize_image(&img, dimensions, crop)?;
+    // Only the gain map needs to know which part of the picture was kept
+    #[cfg(not(feature = "avif"))]
+    let _ = source_region;
 
     // Resize gain map if present
     #[cfg(feature = "avif")]
@@ -185,15 +259,22 @@ fn process_image(
         && let Some(ref gm_info) = info.gain_map_info
         && let Some(ref gm_image) = gm_info.gain_map_image
     {
-        // Resize gain map to match the proportion of the main image resize
+        // Crop the gain map to the same part of the picture as the main image
         let (orig_width, orig_height) = (img.width(), img.height());
-        let (resized_width, resized_height) = (resized.width(), resized.height());
-
-        // Calculate scale factors
-        let scale_x = resized_width as f32 / orig_width as f32;
-        let scale_y = resized_height as f32 / orig_height as f32;
+        let (gm_width, gm_height) = (gm_image.width(), gm_image.height());
+        let gm_scale_x = gm_width as f32 / orig_width as f32;
+        let gm_scale_y = gm_height as f32 / orig_height as f32;
+        let gm_x = ((source_region.x as f32 * gm_scale_x) as u32).min(gm_width.saturating_sub(1));
+        let gm_y = ((source_region.y as f32 * gm_scale_y) as u32).min(gm_height.saturating_sub(1));
+        let gm_region_width =
+            ((source_region.width as f32 * gm_scale_x).round() as u32).clamp(1, gm_width - gm_x);
+        let gm_region_height =
+            ((source_region.height as f32 * gm_scale_y).round() as u32).clamp(1, gm_height - gm_y);
+        let gm_image = gm_image.crop_imm(gm_x, gm_y, gm_region_width, gm_region_height);
 
-        // Apply same scale to gain map
+        // Resize gain map to match the proportion of the main image resize
+        let scale_x = resized.width() as f32 / source_region.width as f32;
+        let scale_y = resized.height() as f32 / source_region.height as f32;
         let (gm_width, gm_height) = (gm_image.width(), gm_image.height());
         let new_gm_width = (gm_width as f32 * scale_x).round() as u32;
         let new_gm_height = (gm_height as f32 * scale_y).round() as u32;
@@ -230,7 +311,9 @@ fn process_image(
         output_format,
         jpeg_quality,
         webp_quality,
+        avif_quality,
         icc_profile.as_deref(),
+        &metadata,
         #[cfg(feature = "avif")]
         resized_avif_info.as_ref(),
         #[cfg(not(feature = "avif"))]
@@ -260,20 +343,148 @@ fn extract_image_info(path: &Path) -> Result<(Option<Vec<u8>>, Option<ImageForma
     Ok((icc_profile, detected_format))
 }
 
-/// Resize image preserving aspect ratio
-fn resize_image(img: &DynamicImage, dimensions: ImageSize) -> Result<DynamicImage, GalleryError> {
+/// The part of the source image that ends up in a resized image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct SourceRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Resize image for a size's box. `Fit` keeps the aspect ratio and the whole
+/// picture; `Fill` and `SmartCrop` return exactly the box's shape. Images are
+/// never upscaled.
+pub(super) fn resize_image(
+    img: &DynamicImage,
+    dimensions: ImageSize,
+    crop: CropMode,
+) -> Result<(DynamicImage, SourceRegion), GalleryError> {
     let (orig_width, orig_height) = (img.width(), img.height());
+    let whole_image = SourceRegion {
+        x: 0,
+        y: 0,
+        width: orig_width,
+        height: orig_height,
+    };
 
-    // Don't upscale - if requested dimensions are larger than original, use original
-    let final_width = dimensions.width.min(orig_width);
-    let final_height = dimensions.height.min(orig_height);
+    if crop == CropMode::Fit {
+        // Don't upscale - if requested dimensions are larger than original, use original
+        let final_width = dimensions.width.min(orig_width);
+        let final_height = dimensions.height.min(orig_height);
 
-    // Only resize if dimensions are different
-    if final_width != orig_width || final_height != orig_height {
-        Ok(img.resize(final_width, final_height, FilterType::Lanczos3))
+        // Only resize if dimensions are different
+        let resized = if final_width != orig_width || final_height != orig_height {
+            img.resize(final_width, final_height, FilterType::Lanczos3)
+        } else {
+            img.clone()
+        };
+        return Ok((resized, whole_image));
+    }
+
+    // Shrink the box, keeping its shape, until it fits inside the original
+    let scale = (orig_width as f64 / dimensions.width.max(1) as f64)
+        .min(orig_height as f64 / dimensions.height.max(1) as f64)
+        .min(1.0);
+    let target_width = ((dimensions.width as f64 * scale).round() as u32).clamp(1, orig_width);
+    let target_height = ((dimensions.height as f64 * scale).round() as u32).clamp(1, orig_height);
+
+    // Largest part of the original with the box's aspect ratio
+    let (region_width, region_height) =
+        if orig_width as u64 * target_height as u64 >= orig_height as u64 * target_width as u64 {
+            let width = orig_height as u64 * target_width as u64 / target_height as u64;
+            ((width as u32).clamp(1, orig_width), orig_height)
+        } else {
+            let height = orig_width as u64 * target_height as u64 / target_width as u64;
+            (orig_width, (height as u32).clamp(1, orig_height))
+        };
+
+    let (x, y) = match crop {
+        CropMode::SmartCrop => smart_crop_offset(img, region_width, region_height),
+        CropMode::Fit | CropMode::Fill => (
+            (orig_width - region_width) / 2,
+            (orig_height - region_height) / 2,
+        ),
+    };
+
+    let cropped = img.crop_imm(x, y, region_width, region_height);
+    let resized = if (region_width, region_height) == (target_width, target_height) {
+        cropped
+    } else {
+        cropped.resize_exact(target_width, target_height, FilterType::Lanczos3)
+    };
+
+    Ok((
+        resized,
+        SourceRegion {
+            x,
+            y,
+            width: region_width,
+            height: region_height,
+        },
+    ))
+}
+
+/// Offset of the `width` x `height` window with the most edge detail. A
+/// cover crop only has slack along one axis, so this scores each column (or
+/// row) of a small grayscale copy and slides the window along it, preferring
+/// the centre on ties.
+fn smart_crop_offset(img: &DynamicImage, width: u32, height: u32) -> (u32, u32) {
+    const ANALYSIS_SIZE: u32 = 256;
+
+    let (orig_width, orig_height) = (img.width(), img.height());
+    let horizontal = width < orig_width;
+    if !horizontal && height >= orig_height {
+        return (0, 0);
+    }
+
+    let sample = img.thumbnail(ANALYSIS_SIZE, ANALYSIS_SIZE).to_luma8();
+    let (sample_width, sample_height) = sample.dimensions();
+    let lines = if horizontal {
+        sample_width
+    } else {
+        sample_height
+    } as usize;
+
+    let mut energy = vec![0u64; lines];
+    for y in 0..sample_height {
+        for x in 0..sample_width {
+            let pixel = sample.get_pixel(x, y)[0] as i32;
+            let dx = if x + 1 < sample_width {
+                (sample.get_pixel(x + 1, y)[0] as i32 - pixel).unsigned_abs()
+            } else {
+                0
+            };
+            let dy = if y + 1 < sample_height {
+                (sample.get_pixel(x, y + 1)[0] as i32 - pixel).unsigned_abs()
+            } else {
+                0
+            };
+            let line = if horizontal { x } else { y } as usize;
+            energy[line] += (dx + dy) as u64;
+        }
+    }
+
+    let (total, window) = if horizontal {
+        (orig_width, width)
     } else {
-        Ok(img.clone())
+        (orig_height, height)
+    };
+    let window_lines = ((window as u64 * lines as u64 / total as u64) as usize).clamp(1, lines);
+
+    let center = (lines - window_lines) / 2;
+    let mut sum: u64 = energy[..window_lines].iter().sum();
+    let mut best = (sum, center.abs_diff(0), 0);
+    for start in 1..=lines - window_lines {
+        sum = sum + energy[start + window_lines - 1] - energy[start - 1];
+        let candidate = (sum, center.abs_diff(start), start);
+        if candidate.0 > best.0 || (candidate.0 == best.0 && candidate.1 < best.1) {
+            best = candidate;
+        }
     }
+
+    let offset = ((best.2 as u64 * total as u64 / lines as u64) as u32).min(total - window);
+    if horizontal { (offset, 0) } else { (0, offset) }
 }
 
 /// Apply copyright watermark to image
@@ -303,30 +514,50 @@ fn apply_copyright_watermark(
     }
 }
 
-/// Save image in specified format
+/// Save image in specified format, with the given EXIF, XMP and IPTC
+#[allow(clippy::too_many_arguments)]
 fn save_image(
     image: &DynamicImage,
     path: &Path,
     format: OutputFormat,
     jpeg_quality: u8,
     webp_quality: f32,
+    #[cfg(feature = "avif")] avif_quality: u8,
+    #[cfg(not(feature = "avif"))] _avif_quality: u8,
     icc_profile: Option<&[u8]>,
+    metadata: &MetadataBlocks,
     #[cfg(feature = "avif")] avif_info: Option<&AvifImageInfo>,
     #[cfg(not(feature = "avif"))] _avif_info: Option<()>,
 ) -> Result<(), GalleryError> {
     match format {
         OutputFormat::Jpeg => {
-            formats::jpeg::save_with_profile(image, path, jpeg_quality, icc_profile)
+            formats::jpeg::save_with_profile(image, path, jpeg_quality, icc_profile)?
         }
         OutputFormat::WebP => {
-            formats::webp::save_with_profile(image, path, webp_quality, icc_profile)
+            formats::webp::save_with_profile(image, path, webp_quality, icc_profile)?
+        }
+        OutputFormat::Png => formats::png::save(image, path)?,
+        // JPEG XL shares JPEG's quality scale
+        #[cfg(feature = "jxl")]
+        OutputFormat::Jxl => {
+            formats::jxl::save_with_profile(image, path, jpeg_quality, icc_profile)?
         }
-        OutputFormat::Png => formats::png::save(image, path),
         #[cfg(feature = "avif")]
         OutputFormat::Avif => {
+            // libavif writes the metadata itself
+            let metadata = (!metadata.is_empty()).then_some(metadata);
             // Use the preserved AVIF info if available
-            if let Some(info) = avif_info {
-                formats::avif::save_with_info(image, path, 85, 6, Some(info))
+            return if let Some(info) = avif_info {
+                formats::avif::save_with_profile_and_color(
+                    image,
+                    path,
+                    avif_quality,
+                    6,
+                    info.icc_profile.as_deref(),
+                    info.is_hdr,
+                    Some(info),
+                    metadata,
+                )
             } else {
                 // Fallback: preserve HDR if the source is 16-bit
                 let preserve_hdr = matches!(
@@ -336,8 +567,26 @@ fn save_image(
                         | DynamicImage::ImageRgb16(_)
                         | DynamicImage::ImageRgba16(_)
                 );
-                formats::avif::save_with_profile(image, path, 85, 6, icc_profile, preserve_hdr)
-            }
+                formats::avif::save_with_profile_and_color(
+                    image,
+                    path,
+                    avif_quality,
+                    6,
+                    icc_profile,
+                    preserve_hdr,
+                    None,
+                    metadata,
+                )
+            };
         }
     }
+
+    // The other encoders can't take metadata, so add it to the written file
+    if !metadata.is_empty() {
+        let encoded = std::fs::read(path)?;
+        if let Some(rewritten) = replace_metadata(&encoded, metadata) {
+            std::fs::write(path, rewritten)?;
+        }
+    }
+    Ok(())
 }
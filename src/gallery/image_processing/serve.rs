@@ -1,12 +1,15 @@
+use super::OutputFormat;
+use super::metadata_policy::rewrite_original;
+use super::video::strip_video_metadata;
+use super::workers;
+use crate::MetadataPolicy;
 use crate::gallery::{Gallery, GalleryError};
 use axum::{
     body::Body,
     http::{HeaderMap, StatusCode, header},
     response::{IntoResponse, Response},
 };
-use std::path::Path;
-use tokio::fs::File;
-use tokio_util::io::ReaderStream;
+use std::path::{Path, PathBuf};
 use tracing::{debug, error};
 
 impl Gallery {
@@ -15,7 +18,8 @@ impl Gallery {
         &self,
         relative_path: &str,
         size: Option<String>,
-        accept_header: &str,
+        format: Option<OutputFormat>,
+        request_headers: &HeaderMap,
     ) -> Response {
         // Security check
         let full_path = self.config.source_directory.join(relative_path);
@@ -29,7 +33,16 @@ impl Gallery {
             return (StatusCode::NOT_FOUND, "Image not found").into_response();
         }
 
-        let output_format = self.determine_output_format(accept_header, relative_path);
+        // An explicit format wins over Accept negotiation, except that PNG
+        // sources stay PNG to preserve transparency
+        let accept_header = request_headers
+            .get(header::ACCEPT)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("");
+        let output_format = match format {
+            Some(format) if !relative_path.to_lowercase().ends_with(".png") => format,
+            _ => self.determine_output_format(accept_header, relative_path),
+        };
         debug!(
             "Serving image: {}, output format: {:?}",
             relative_path, output_format
@@ -38,13 +51,13 @@ impl Gallery {
         // Handle resized images
         if let Some(size) = size.as_deref() {
             // Determine if this size would have a watermark
-            let (_, is_medium) = match self.parse_size(size) {
+            let (_, size_config) = match self.parse_size(size) {
                 Ok(result) => result,
                 Err(_) => {
                     return (StatusCode::BAD_REQUEST, "Invalid size parameter").into_response();
                 }
             };
-            let apply_watermark = is_medium && self.config.copyright_holder.is_some();
+            let apply_watermark = size_config.watermark && self.config.copyright_holder.is_some();
 
             let cache_filename = self.generate_cache_filename(
                 relative_path,
@@ -61,77 +74,187 @@ impl Gallery {
             {
                 Ok(cached_path) => {
                     return self
-                        .serve_file_with_cache_header(&cached_path, was_cached)
+                        .serve_file_with_cache_header(&cached_path, was_cached, request_headers)
                         .await;
                 }
+                // A size doesn't give permission for the original, so it's
+                // never served in place of one
+                Err(e) if crate::gallery::is_video_file(relative_path) => {
+                    error!("Failed to make poster for {}: {}", relative_path, e);
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Failed to prepare poster",
+                    )
+                        .into_response();
+                }
                 Err(e) => {
-                    error!("Failed to resize image: {}", e);
-                    // Fall through to serve original
+                    error!("Failed to resize {}: {}", relative_path, e);
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to resize image")
+                        .into_response();
                 }
             }
         }
 
-        // Serve original file
-        self.serve_file_with_cache_header(&full_path, false).await
+        // An original JPEG asked for as JPEG XL is recompressed losslessly
+        #[cfg(feature = "jxl")]
+        if size.is_none() && format == Some(OutputFormat::Jxl) {
+            let lowercase = relative_path.to_lowercase();
+            if lowercase.ends_with(".jpg") || lowercase.ends_with(".jpeg") {
+                match self.recompressed_original(&full_path, relative_path).await {
+                    Ok(path) => {
+                        return self
+                            .serve_file_with_cache_header(&path, false, request_headers)
+                            .await;
+                    }
+                    Err(e) => {
+                        error!("Failed to recompress original {}: {}", relative_path, e);
+                        // Fall through to serve the JPEG
+                    }
+                }
+            }
+        }
+
+        // Serve original file, with the metadata the gallery allows
+        match self.original_file(&full_path, relative_path).await {
+            Ok(path) => {
+                self.serve_file_with_cache_header(&path, false, request_headers)
+                    .await
+            }
+            Err(e) => {
+                error!("Failed to prepare original {}: {}", relative_path, e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Failed to prepare image").into_response()
+            }
+        }
     }
 
-    /// Serve file with appropriate cache headers
-    pub(crate) async fn serve_file_with_cache_header(
+    /// The file to send when an original is downloaded: the source itself
+    /// when its metadata may be kept, otherwise a cached copy rewritten per
+    /// the gallery's `metadata.originals` policy. Videos lose all their
+    /// metadata boxes under any policy but `keep-all`.
+    pub(crate) async fn original_file(
         &self,
-        path: &Path,
-        was_cached: bool,
-    ) -> Response {
-        let mime_type = mime_guess::from_path(path)
-            .first_or_octet_stream()
-            .to_string();
-        self.serve_file_with_content_type_and_cache_header(path, &mime_type, was_cached)
-            .await
+        original_path: &Path,
+        relative_path: &str,
+    ) -> Result<PathBuf, GalleryError> {
+        let policy = self.config.metadata.originals;
+        if policy == MetadataPolicy::KeepAll {
+            return Ok(original_path.to_path_buf());
+        }
+        let is_video = crate::gallery::is_video_file(relative_path);
+
+        let extension = original_path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        let cache_filename = self.generate_cache_filename(
+            relative_path,
+            &format!("original-{}", policy.as_str()),
+            &extension,
+            false,
+        );
+        let cache_path = self.config.cache_directory.join(&cache_filename);
+        if self.is_cache_valid(&cache_path, original_path).await? {
+            self.note_cache_hit(&cache_filename, relative_path).await;
+            return Ok(cache_path);
+        }
+
+        let _flight = workers::claim(&cache_path).await;
+        if self.is_cache_valid(&cache_path, original_path).await? {
+            self.note_cache_hit(&cache_filename, relative_path).await;
+            return Ok(cache_path);
+        }
+
+        tokio::fs::create_dir_all(&self.config.cache_directory).await?;
+        let original_path = original_path.to_path_buf();
+        workers::write_atomically(&cache_path, move |temp_path| {
+            if is_video {
+                return strip_video_metadata(&original_path, temp_path);
+            }
+            let data = std::fs::read(&original_path)?;
+            let rewritten = rewrite_original(&data, policy).ok_or_else(|| {
+                GalleryError::ProcessingError(format!(
+                    "Can't rewrite the metadata of {:?}",
+                    original_path
+                ))
+            })?;
+            std::fs::write(temp_path, rewritten)?;
+            Ok(())
+        })
+        .await?;
+
+        debug!("Rewrote metadata of original {}", relative_path);
+        self.track_cached_file(&cache_filename, relative_path).await;
+        Ok(cache_path)
     }
 
-    /// Serve file with content type and cache headers
-    async fn serve_file_with_content_type_and_cache_header(
+    /// An original JPEG recompressed losslessly as JPEG XL, from the file
+    /// `original_file` would send, so the gallery's metadata policy holds.
+    /// Decoding it gives that JPEG back bit for bit.
+    #[cfg(feature = "jxl")]
+    pub(crate) async fn recompressed_original(
+        &self,
+        original_path: &Path,
+        relative_path: &str,
+    ) -> Result<PathBuf, GalleryError> {
+        let policy = self.config.metadata.originals;
+        let cache_filename = self.generate_cache_filename(
+            relative_path,
+            &format!("original-{}", policy.as_str()),
+            OutputFormat::Jxl.extension(),
+            false,
+        );
+        let cache_path = self.config.cache_directory.join(&cache_filename);
+        if self.is_cache_valid(&cache_path, original_path).await? {
+            self.note_cache_hit(&cache_filename, relative_path).await;
+            return Ok(cache_path);
+        }
+
+        let _flight = workers::claim(&cache_path).await;
+        if self.is_cache_valid(&cache_path, original_path).await? {
+            self.note_cache_hit(&cache_filename, relative_path).await;
+            return Ok(cache_path);
+        }
+
+        let jpeg_path = self.original_file(original_path, relative_path).await?;
+        tokio::fs::create_dir_all(&self.config.cache_directory).await?;
+        workers::write_atomically(&cache_path, move |temp_path| {
+            let jpeg = std::fs::read(&jpeg_path)?;
+            std::fs::write(temp_path, super::formats::jxl::recompress_jpeg(&jpeg)?)?;
+            Ok(())
+        })
+        .await?;
+
+        debug!("Recompressed original {} as JPEG XL", relative_path);
+        self.track_cached_file(&cache_filename, relative_path).await;
+        Ok(cache_path)
+    }
+
+    /// Serve file with appropriate cache headers, answering conditional and
+    /// range requests
+    pub(crate) async fn serve_file_with_cache_header(
         &self,
         path: &Path,
-        content_type: &str,
         was_cached: bool,
+        request_headers: &HeaderMap,
     ) -> Response {
-        match File::open(path).await {
-            Ok(file) => {
-                let metadata = match file.metadata().await {
-                    Ok(m) => m,
-                    Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR).into_response(),
-                };
-
-                let stream = ReaderStream::new(file);
-                let body = Body::from_stream(stream);
-
-                let mut headers = HeaderMap::new();
-                headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
-                headers.insert(
-                    header::CONTENT_LENGTH,
-                    metadata.len().to_string().parse().unwrap(),
-                );
-
-                // Add cache headers
-                if was_cached {
-                    headers.insert(
-                        header::CACHE_CONTROL,
-                        "public, max-age=31536000, immutable".parse().unwrap(),
-                    );
-                } else {
-                    headers.insert(
-                        header::CACHE_CONTROL,
-                        "public, max-age=86400".parse().unwrap(),
-                    );
-                }
-
-                (StatusCode::OK, headers, body).into_response()
-            }
-            Err(e) => {
-                error!("Failed to open file: {:?}, error: {}", path, e);
-                (StatusCode::NOT_FOUND).into_response()
-            }
-        }
+        // mime_guess doesn't know JPEG XL
+        let mime_type = if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("jxl"))
+        {
+            "image/jxl".to_string()
+        } else {
+            mime_guess::from_path(path)
+                .first_or_octet_stream()
+                .to_string()
+        };
+        let cache_control = if was_cached {
+            "public, max-age=31536000, immutable"
+        } else {
+            "public, max-age=86400"
+        };
+        crate::file_response::serve_file(path, &mime_type, cache_control, request_headers).await
     }
 
     /// Serve cached image by key
@@ -139,7 +262,7 @@ impl Gallery {
         &self,
         cache_key: &str,
         _size: &str,
-        _accept_header: &str,
+        request_headers: &HeaderMap,
     ) -> Result<Response, GalleryError> {
         let cache_path = self.config.cache_directory.join(cache_key);
 
@@ -154,13 +277,19 @@ impl Gallery {
             "image/png"
         } else if cache_key.ends_with(".avif") {
             "image/avif"
+        } else if cache_key.ends_with(".jxl") {
+            "image/jxl"
         } else {
             "image/jpeg"
         };
 
-        Ok(self
-            .serve_file_with_content_type_and_cache_header(&cache_path, mime_type, true)
-            .await)
+        Ok(crate::file_response::serve_file(
+            &cache_path,
+            mime_type,
+            "public, max-age=31536000, immutable",
+            request_headers,
+        )
+        .await)
     }
 
     /// Store and serve composite image
@@ -180,9 +309,6 @@ impl Gallery {
         let cache_filename = format!("{}.{}", hash, output_format.extension());
         let cache_path = self.config.cache_directory.join(&cache_filename);
 
-        // Ensure cache directory exists
-        tokio::fs::create_dir_all(&self.config.cache_directory).await?;
-
         // Convert to RGB (JPEG doesn't support alpha)
         let rgb_image = image.to_rgb8();
 
@@ -198,8 +324,8 @@ impl Gallery {
 
         let image_data = buffer.into_inner();
 
-        // Write to cache
-        tokio::fs::write(&cache_path, &image_data).await?;
+        // Write to cache, renaming into place so it's never served half-written
+        crate::atomic_file::write_atomically(&cache_path, &image_data).await?;
         debug!("Stored composite image: {}", cache_filename);
 
         // Create response
@@ -44,9 +44,17 @@ async fn create_test_gallery() -> (Gallery, TempDir) {
         jpeg_quality: Some(85),
         webp_quality: Some(85.0),
         pregenerate_cache: false,
+        pregenerate_parallelism: None,
         new_threshold_days: None,
         approximate_dates_for_public: false,
+        location_precision: Default::default(),
+        location_precision_for_public: Default::default(),
+        metadata: Default::default(),
+        ffmpeg_path: None,
+        max_cache_size: None,
         copyright_holder: None,
+        watch_filesystem: false,
+        sizes: Default::default(),
     };
 
     let gallery = Gallery {
@@ -58,6 +66,9 @@ async fn create_test_gallery() -> (Gallery, TempDir) {
         })),
         metadata_cache_dirty: Arc::new(std::sync::atomic::AtomicBool::new(false)),
         metadata_updates_since_save: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+        search_index: Arc::new(RwLock::new(Default::default())),
+        cache_index: Arc::new(RwLock::new(Default::default())),
+        cache_index_dirty: Arc::new(std::sync::atomic::AtomicBool::new(false)),
     };
 
     (gallery, temp_dir)
@@ -739,6 +750,27 @@ fn test_avif_exif_extraction() {
     println!("✅ Successfully extracted and parsed EXIF from AVIF");
 }
 
+#[test]
+fn test_avif_metadata_read_from_container() {
+    use crate::gallery::image_processing::metadata_policy::MetadataBlocks;
+
+    let temp_dir = TempDir::new().unwrap();
+    let img = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(64, 64, Rgb([255u8, 128, 64])));
+    let blocks = MetadataBlocks {
+        exif: Some(b"MM\0*\0\0\0\x08\0\0\0\0\0\0".to_vec()),
+        xmp: Some(b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>".to_vec()),
+        iptc: None,
+    };
+
+    let avif_path = temp_dir.path().join("with_metadata.avif");
+    avif::save_with_profile_and_color(&img, &avif_path, 90, 6, None, false, None, Some(&blocks))
+        .unwrap();
+
+    // Both come straight from the meta box items
+    assert_eq!(avif::extract_exif_data(&avif_path), blocks.exif);
+    assert_eq!(avif::extract_xmp_data(&avif_path), blocks.xmp);
+}
+
 #[test]
 fn test_avif_without_exif() {
     let temp_dir = TempDir::new().unwrap();
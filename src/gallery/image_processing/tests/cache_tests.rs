@@ -1,7 +1,8 @@
 use crate::GallerySystemConfig;
 use crate::gallery::Gallery;
-use axum::http::StatusCode;
+use axum::http::{HeaderMap, StatusCode};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tempfile::TempDir;
 use tokio::sync::RwLock;
 
@@ -43,9 +44,17 @@ async fn create_test_gallery() -> (Gallery, TempDir) {
         jpeg_quality: Some(85),
         webp_quality: Some(85.0),
         pregenerate_cache: false,
+        pregenerate_parallelism: None,
         new_threshold_days: None,
         approximate_dates_for_public: false,
+        location_precision: Default::default(),
+        location_precision_for_public: Default::default(),
+        metadata: Default::default(),
+        ffmpeg_path: None,
+        max_cache_size: None,
         copyright_holder: None,
+        watch_filesystem: false,
+        sizes: Default::default(),
     };
 
     let gallery = Gallery {
@@ -57,6 +66,9 @@ async fn create_test_gallery() -> (Gallery, TempDir) {
         })),
         metadata_cache_dirty: Arc::new(std::sync::atomic::AtomicBool::new(false)),
         metadata_updates_since_save: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+        search_index: Arc::new(RwLock::new(Default::default())),
+        cache_index: Arc::new(RwLock::new(Default::default())),
+        cache_index_dirty: Arc::new(std::sync::atomic::AtomicBool::new(false)),
     };
 
     (gallery, temp_dir)
@@ -68,7 +80,7 @@ async fn test_serve_cached_image_not_found() {
 
     // Try to serve non-existent cached file
     let result = gallery
-        .serve_cached_image("non_existent.jpg", "composite", "")
+        .serve_cached_image("non_existent.jpg", "composite", &HeaderMap::new())
         .await;
 
     match result {
@@ -98,7 +110,7 @@ async fn test_serve_cached_image_mime_types() {
     tokio::fs::write(&jpeg_path, test_data).await.unwrap();
 
     let jpeg_response = gallery
-        .serve_cached_image("test.jpg", "", "")
+        .serve_cached_image("test.jpg", "", &HeaderMap::new())
         .await
         .unwrap();
     assert_eq!(
@@ -116,7 +128,7 @@ async fn test_serve_cached_image_mime_types() {
     tokio::fs::write(&webp_path, test_data).await.unwrap();
 
     let webp_response = gallery
-        .serve_cached_image("test.webp", "", "")
+        .serve_cached_image("test.webp", "", &HeaderMap::new())
         .await
         .unwrap();
     assert_eq!(
@@ -134,7 +146,7 @@ async fn test_serve_cached_image_mime_types() {
     tokio::fs::write(&png_path, test_data).await.unwrap();
 
     let png_response = gallery
-        .serve_cached_image("test.png", "", "")
+        .serve_cached_image("test.png", "", &HeaderMap::new())
         .await
         .unwrap();
     assert_eq!(
@@ -163,7 +175,7 @@ async fn test_cache_headers_for_cached_images() {
 
     // Serve the cached file
     let response = gallery
-        .serve_cached_image("cached.jpg", "", "")
+        .serve_cached_image("cached.jpg", "", &HeaderMap::new())
         .await
         .unwrap();
 
@@ -196,7 +208,7 @@ async fn test_serve_file_with_cache_header() {
 
     // Test serving with was_cached = false
     let response_not_cached = gallery
-        .serve_file_with_cache_header(&test_file, false)
+        .serve_file_with_cache_header(&test_file, false, &HeaderMap::new())
         .await;
 
     assert_eq!(response_not_cached.status(), StatusCode::OK);
@@ -207,7 +219,261 @@ async fn test_serve_file_with_cache_header() {
     assert!(headers.get("content-type").is_some());
 
     // Test serving with was_cached = true
-    let response_cached = gallery.serve_file_with_cache_header(&test_file, true).await;
+    let response_cached = gallery
+        .serve_file_with_cache_header(&test_file, true, &HeaderMap::new())
+        .await;
 
     assert_eq!(response_cached.status(), StatusCode::OK);
 }
+
+#[tokio::test]
+async fn test_evict_cached_images() {
+    let (gallery, _temp_dir) = create_test_gallery().await;
+    tokio::fs::create_dir_all(&gallery.config.cache_directory)
+        .await
+        .unwrap();
+
+    let path = "trip/beach.jpg";
+    let thumbnail = gallery.generate_cache_filename(path, "thumbnail", "webp", false);
+    let medium = gallery.generate_cache_filename(path, "medium@2x", "jpg", true);
+    let other = gallery.generate_cache_filename("trip/other.jpg", "thumbnail", "webp", false);
+    let composite_key = Gallery::generate_composite_cache_key("trip");
+    let composite = format!("{}.jpg", gallery.generate_cache_key(&composite_key, "jpg"));
+
+    for filename in [&thumbnail, &medium, &other, &composite] {
+        tokio::fs::write(gallery.config.cache_directory.join(filename), b"data")
+            .await
+            .unwrap();
+    }
+
+    let removed = gallery.evict_cached_images(path).await;
+
+    assert_eq!(removed, 3);
+    assert!(!gallery.config.cache_directory.join(&thumbnail).exists());
+    assert!(!gallery.config.cache_directory.join(&medium).exists());
+    assert!(!gallery.config.cache_directory.join(&composite).exists());
+    assert!(gallery.config.cache_directory.join(&other).exists());
+}
+
+#[tokio::test]
+async fn test_refresh_path_removes_deleted_folder() {
+    let (gallery, _temp_dir) = create_test_gallery().await;
+
+    let placeholder = crate::gallery::ImageMetadata {
+        dimensions: (100, 100),
+        capture_date: None,
+        camera_info: None,
+        location_info: None,
+        modification_date: None,
+        color_profile: None,
+        placeholder: None,
+        descriptive_info: None,
+        duration: None,
+    };
+    {
+        let mut cache = gallery.metadata_cache.write().await;
+        cache.insert("trip/beach.jpg".to_string(), placeholder.clone());
+        cache.insert("trip/day2/sunset.jpg".to_string(), placeholder.clone());
+        cache.insert("trip-notes/map.jpg".to_string(), placeholder);
+    }
+
+    // "trip" no longer exists on disk
+    gallery.refresh_path("trip").await.unwrap();
+
+    let cache = gallery.metadata_cache.read().await;
+    assert_eq!(cache.len(), 1);
+    assert!(cache.contains_key("trip-notes/map.jpg"));
+}
+
+#[tokio::test]
+async fn test_cache_size_limit_evicts_least_recently_used() {
+    let (mut gallery, _temp_dir) = create_test_gallery().await;
+    gallery.config.max_cache_size = Some(250);
+    tokio::fs::create_dir_all(&gallery.config.cache_directory)
+        .await
+        .unwrap();
+
+    let [first, second, third] = ["a.jpg", "b.jpg", "c.jpg"]
+        .map(|path| gallery.generate_cache_filename(path, "thumbnail", "jpg", false));
+    for (filename, source) in [(&first, "a.jpg"), (&second, "b.jpg"), (&first, "a.jpg")] {
+        tokio::fs::write(gallery.config.cache_directory.join(filename), [0u8; 100])
+            .await
+            .unwrap();
+        gallery.track_cached_file(filename, source).await;
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    }
+    assert_eq!(gallery.cache_index.read().await.total_size(), 200);
+
+    // Going over the limit drops the file served longest ago
+    tokio::fs::write(gallery.config.cache_directory.join(&third), [0u8; 100])
+        .await
+        .unwrap();
+    gallery.track_cached_file(&third, "c.jpg").await;
+
+    assert!(gallery.config.cache_directory.join(&first).exists());
+    assert!(!gallery.config.cache_directory.join(&second).exists());
+    assert!(gallery.config.cache_directory.join(&third).exists());
+    assert_eq!(gallery.cache_index.read().await.total_size(), 200);
+}
+
+#[tokio::test]
+async fn test_cache_hits_only_update_stale_access_times() {
+    let (gallery, _temp_dir) = create_test_gallery().await;
+    tokio::fs::create_dir_all(&gallery.config.cache_directory)
+        .await
+        .unwrap();
+    let filename = gallery.generate_cache_filename("a.jpg", "thumbnail", "jpg", false);
+    tokio::fs::write(gallery.config.cache_directory.join(&filename), [0u8; 100])
+        .await
+        .unwrap();
+
+    // A file used moments ago keeps its recorded access
+    let recently = SystemTime::now() - Duration::from_secs(5);
+    gallery
+        .cache_index
+        .write()
+        .await
+        .record(&filename, "a.jpg", 100, recently);
+    gallery.note_cache_hit(&filename, "a.jpg").await;
+    let index = gallery.cache_index.read().await;
+    assert_eq!(index.get(&filename).unwrap().last_access, recently);
+    drop(index);
+
+    // One not used for a while is moved to the front
+    let long_ago = SystemTime::now() - Duration::from_secs(3600);
+    gallery
+        .cache_index
+        .write()
+        .await
+        .record(&filename, "a.jpg", 100, long_ago);
+    gallery.note_cache_hit(&filename, "a.jpg").await;
+    let index = gallery.cache_index.read().await;
+    assert!(index.get(&filename).unwrap().last_access > recently);
+}
+
+#[tokio::test]
+async fn test_collect_garbage_removes_orphans() {
+    let (gallery, temp_dir) = create_test_gallery().await;
+    let cache_dir = gallery.config.cache_directory.clone();
+    tokio::fs::create_dir_all(temp_dir.path().join("trip"))
+        .await
+        .unwrap();
+    tokio::fs::create_dir_all(&cache_dir).await.unwrap();
+    tokio::fs::write(temp_dir.path().join("trip/beach.jpg"), b"image")
+        .await
+        .unwrap();
+
+    let thumbnail = gallery.generate_cache_filename("trip/beach.jpg", "thumbnail", "webp", false);
+    let composite = gallery.composite_cache_filename("trip");
+    let deleted_image = gallery.generate_cache_filename("trip/gone.jpg", "medium", "jpg", false);
+    let deleted_folder = gallery.composite_cache_filename("old-trip");
+    let removed_size = gallery.generate_cache_filename("trip/beach.jpg", "huge", "jpg", false);
+    for filename in [
+        &thumbnail,
+        &composite,
+        &deleted_image,
+        &deleted_folder,
+        &removed_size,
+    ] {
+        tokio::fs::write(cache_dir.join(filename), [0u8; 10])
+            .await
+            .unwrap();
+    }
+    // Not cache files, so never touched
+    tokio::fs::write(cache_dir.join("metadata_cache.json"), b"{}")
+        .await
+        .unwrap();
+    tokio::fs::write(cache_dir.join("notes.txt"), b"keep me")
+        .await
+        .unwrap();
+
+    let stats = gallery.cache_stats().await.unwrap();
+    assert_eq!(stats.files, 5);
+    assert_eq!(stats.total_size, 50);
+    assert_eq!(stats.orphaned_files, 3);
+    assert_eq!(stats.orphaned_size, 30);
+
+    let cleanup = gallery.collect_garbage().await.unwrap();
+    assert_eq!(cleanup.orphans_removed, 3);
+    assert_eq!(cleanup.evicted, 0);
+    assert_eq!(cleanup.bytes_freed, 30);
+
+    assert!(cache_dir.join(&thumbnail).exists());
+    assert!(cache_dir.join(&composite).exists());
+    assert!(!cache_dir.join(&deleted_image).exists());
+    assert!(!cache_dir.join(&deleted_folder).exists());
+    assert!(!cache_dir.join(&removed_size).exists());
+    assert!(cache_dir.join("metadata_cache.json").exists());
+    assert!(cache_dir.join("notes.txt").exists());
+
+    // Files found on disk are tracked with their source from now on
+    let index = crate::gallery::cache_index::load_cache_index(&gallery.config).unwrap();
+    assert_eq!(index.len(), 2);
+    assert_eq!(index.get(&thumbnail).unwrap().source, "trip/beach.jpg");
+    assert_eq!(index.get(&composite).unwrap().source, "trip");
+}
+
+#[tokio::test]
+async fn test_pregenerate_resumes_from_checkpoint() {
+    let (mut gallery, temp_dir) = create_test_gallery().await;
+    gallery.config.name = "pregenerate-test".to_string();
+    let cache_dir = gallery.config.cache_directory.clone();
+    tokio::fs::create_dir_all(&cache_dir).await.unwrap();
+    for name in ["a.jpg", "b.jpg", "c.png"] {
+        image::RgbImage::new(40, 30)
+            .save(temp_dir.path().join(name))
+            .unwrap();
+    }
+    gallery.refresh_all_metadata().await.unwrap();
+
+    // Interrupted after the JPEG thumbnails and the first WebP thumbnail
+    let pass_id = |format| gallery.generate_image_cache_key("", "thumbnail", format, true);
+    let checkpoint = serde_json::json!({
+        "completed": [pass_id("jpg")],
+        "current": pass_id("webp"),
+        "done_through": "a.jpg",
+    });
+    tokio::fs::write(
+        cache_dir.join("pregenerate_progress.json"),
+        checkpoint.to_string(),
+    )
+    .await
+    .unwrap();
+
+    // Held back while the server is busy
+    let gallery = Arc::new(gallery);
+    let live_requests = [
+        crate::gallery::LiveRequest::start(),
+        crate::gallery::LiveRequest::start(),
+    ];
+    let run = tokio::spawn(gallery.clone().pregenerate_all_images_cache());
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while gallery.pregenerate_progress().await.state != crate::gallery::PregenerateState::Paused
+        {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .expect("pre-generation never paused for live requests");
+    drop(live_requests);
+    run.await.unwrap().unwrap();
+
+    let cached = |path, size, format| {
+        cache_dir
+            .join(gallery.generate_cache_filename(path, size, format, false))
+            .exists()
+    };
+    // Finished passes aren't repeated
+    assert!(!cached("a.jpg", "thumbnail", "jpg"));
+    assert!(!cached("a.jpg", "thumbnail", "webp"));
+    assert!(cached("b.jpg", "thumbnail", "webp"));
+    assert!(!cached("c.png", "thumbnail", "webp"));
+    assert!(cached("a.jpg", "gallery", "jpg"));
+    assert!(cached("c.png", "large", "jpg"));
+
+    let progress = gallery.pregenerate_progress().await;
+    assert_eq!(progress.state, crate::gallery::PregenerateState::Completed);
+    assert_eq!(progress.passes_done, progress.passes_total);
+    assert_eq!(progress.failed, 0);
+    assert!(!cache_dir.join("pregenerate_progress.json").exists());
+}
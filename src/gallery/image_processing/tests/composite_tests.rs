@@ -1,5 +1,6 @@
 use crate::GallerySystemConfig;
 use crate::gallery::Gallery;
+use axum::http::HeaderMap;
 use image::{ImageBuffer, Rgba};
 use std::sync::Arc;
 use tempfile::TempDir;
@@ -43,9 +44,17 @@ async fn create_test_gallery() -> (Gallery, TempDir) {
         jpeg_quality: Some(85),
         webp_quality: Some(85.0),
         pregenerate_cache: false,
+        pregenerate_parallelism: None,
         new_threshold_days: None,
         approximate_dates_for_public: false,
+        location_precision: Default::default(),
+        location_precision_for_public: Default::default(),
+        metadata: Default::default(),
+        ffmpeg_path: None,
+        max_cache_size: None,
         copyright_holder: None,
+        watch_filesystem: false,
+        sizes: Default::default(),
     };
 
     let gallery = Gallery {
@@ -57,6 +66,9 @@ async fn create_test_gallery() -> (Gallery, TempDir) {
         })),
         metadata_cache_dirty: Arc::new(std::sync::atomic::AtomicBool::new(false)),
         metadata_updates_since_save: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+        search_index: Arc::new(RwLock::new(Default::default())),
+        cache_index: Arc::new(RwLock::new(Default::default())),
+        cache_index_dirty: Arc::new(std::sync::atomic::AtomicBool::new(false)),
     };
 
     (gallery, temp_dir)
@@ -92,7 +104,7 @@ async fn test_store_and_serve_composite() {
 
     // Test serving from cache using the generated filename
     let cached_result = gallery
-        .serve_cached_image(&cache_filename, "composite", "")
+        .serve_cached_image(&cache_filename, "composite", &HeaderMap::new())
         .await;
     assert!(
         cached_result.is_ok(),
@@ -204,7 +216,7 @@ async fn test_serve_cached_composite_with_proper_filename() {
 
     // Now try to serve it using the full filename (as the API handler does)
     let serve_result = gallery
-        .serve_cached_image(&cache_filename, "composite", "")
+        .serve_cached_image(&cache_filename, "composite", &HeaderMap::new())
         .await;
     assert!(
         serve_result.is_ok(),
@@ -315,7 +327,7 @@ async fn test_composite_mime_type_for_cached_composite() {
 
     // Serve the composite from cache
     let cached_response = gallery
-        .serve_cached_image(&cache_filename, "composite", "")
+        .serve_cached_image(&cache_filename, "composite", &HeaderMap::new())
         .await;
     assert!(cached_response.is_ok());
 
@@ -411,6 +423,8 @@ async fn test_composite_with_avif_images() {
             dimensions: Some((100, 100)),
             capture_date: None,
             is_new: false,
+            placeholder: None,
+            video: None,
         });
     }
 
@@ -43,9 +43,17 @@ async fn create_test_gallery() -> (Gallery, TempDir) {
         jpeg_quality: Some(85),
         webp_quality: Some(85.0),
         pregenerate_cache: false,
+        pregenerate_parallelism: None,
         new_threshold_days: None,
         approximate_dates_for_public: false,
+        location_precision: Default::default(),
+        location_precision_for_public: Default::default(),
+        metadata: Default::default(),
+        ffmpeg_path: None,
+        max_cache_size: None,
         copyright_holder: None,
+        watch_filesystem: false,
+        sizes: Default::default(),
     };
 
     let gallery = Gallery {
@@ -57,6 +65,9 @@ async fn create_test_gallery() -> (Gallery, TempDir) {
         })),
         metadata_cache_dirty: Arc::new(std::sync::atomic::AtomicBool::new(false)),
         metadata_updates_since_save: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+        search_index: Arc::new(RwLock::new(Default::default())),
+        cache_index: Arc::new(RwLock::new(Default::default())),
+        cache_index_dirty: Arc::new(std::sync::atomic::AtomicBool::new(false)),
     };
 
     (gallery, temp_dir)
@@ -156,6 +167,8 @@ async fn test_icc_profile_preservation_across_formats() {
         crate::gallery::image_processing::OutputFormat::Jpeg,
         crate::gallery::image_processing::OutputFormat::WebP,
         crate::gallery::image_processing::OutputFormat::Png,
+        #[cfg(feature = "jxl")]
+        crate::gallery::image_processing::OutputFormat::Jxl,
     ];
 
     for size in &sizes {
@@ -236,6 +249,17 @@ async fn test_icc_profile_preservation_across_formats() {
                     assert!(avif_data.len() >= 12);
                     assert_eq!(&avif_data[4..8], b"ftyp", "Not a valid AVIF file");
                 }
+                #[cfg(feature = "jxl")]
+                crate::gallery::image_processing::OutputFormat::Jxl => {
+                    // Check JPEG XL has ICC profile
+                    let processed_icc =
+                        crate::gallery::image_processing::extract_icc_profile_from_jxl(&cache_path);
+                    assert!(
+                        processed_icc.is_some(),
+                        "ICC profile missing in {} JPEG XL",
+                        size
+                    );
+                }
             }
         }
     }
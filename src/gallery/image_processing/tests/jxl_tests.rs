@@ -0,0 +1,139 @@
+use crate::gallery::image_processing::OutputFormat;
+use crate::gallery::image_processing::formats::{jxl, jxl_container};
+use crate::gallery::image_processing::metadata_policy::MetadataBlocks;
+use image::{DynamicImage, ImageBuffer, Rgb};
+use tempfile::TempDir;
+
+/// Writes codestream header fields, least significant bit first
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    fn bits(&mut self, count: usize, value: u32) -> &mut Self {
+        for i in 0..count {
+            if self.len % 8 == 0 {
+                self.bytes.push(0);
+            }
+            let bit = ((value >> i) & 1) as u8;
+            *self.bytes.last_mut().unwrap() |= bit << (self.len % 8);
+            self.len += 1;
+        }
+        self
+    }
+
+    fn codestream(&self) -> Vec<u8> {
+        let mut data = vec![0xFF, 0x0A];
+        data.extend_from_slice(&self.bytes);
+        data
+    }
+}
+
+#[test]
+fn test_jxl_dimensions_from_header() {
+    // 4000x300 stored, orientation 6: shown as 300x4000
+    let codestream = BitWriter::default()
+        .bits(1, 0) // not small
+        .bits(2, 0) // 9-bit height
+        .bits(9, 299)
+        .bits(3, 0) // no ratio
+        .bits(2, 1) // 13-bit width
+        .bits(13, 3999)
+        .bits(1, 0) // not all default
+        .bits(1, 1) // extra fields
+        .bits(3, 5) // orientation 6
+        .codestream();
+    assert_eq!(jxl_container::dimensions(&codestream), Some((300, 4000)));
+
+    // Small 64x64 image, width by 1:1 ratio
+    let codestream = BitWriter::default()
+        .bits(1, 1) // small
+        .bits(5, 7)
+        .bits(3, 1) // 1:1
+        .bits(1, 1) // all default
+        .codestream();
+    assert_eq!(jxl_container::dimensions(&codestream), Some((64, 64)));
+
+    // The same codestream inside a container
+    let wrapped = jxl_container::replace_metadata(&codestream, &MetadataBlocks::default());
+    assert!(jxl_container::is_jxl(&wrapped));
+    assert_eq!(jxl_container::dimensions(&wrapped), Some((64, 64)));
+}
+
+#[test]
+fn test_jxl_metadata_round_trip() {
+    let codestream = BitWriter::default()
+        .bits(1, 1)
+        .bits(5, 7)
+        .bits(3, 1)
+        .bits(1, 1)
+        .codestream();
+    let blocks = MetadataBlocks {
+        exif: Some(b"MM\0*\0\0\0\x08\0\0".to_vec()),
+        xmp: Some(b"<x:xmpmeta/>".to_vec()),
+        iptc: None,
+    };
+
+    let with_metadata = jxl_container::replace_metadata(&codestream, &blocks);
+    assert_eq!(jxl_container::metadata_blocks(&with_metadata), blocks);
+    assert_eq!(MetadataBlocks::read(&with_metadata), blocks);
+
+    // Replacing again drops the old boxes and keeps the codestream
+    let stripped = jxl_container::replace_metadata(&with_metadata, &MetadataBlocks::default());
+    assert!(jxl_container::metadata_blocks(&stripped).is_empty());
+    assert!(stripped.ends_with(&codestream));
+}
+
+#[test]
+fn test_jxl_output_format_selection() {
+    assert_eq!(OutputFormat::from_name("jxl"), Some(OutputFormat::Jxl));
+    assert_eq!(OutputFormat::Jxl.extension(), "jxl");
+    assert_eq!(OutputFormat::Jxl.mime_type(), "image/jxl");
+}
+
+#[test]
+fn test_jxl_encode_with_icc_profile() {
+    let temp_dir = TempDir::new().unwrap();
+    let img = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(120, 80, Rgb([200u8, 120, 40])));
+    // A profile libjxl wrote itself, so it's known to be valid
+    let srgb_path = temp_dir.path().join("srgb.jxl");
+    jxl::save_with_profile(&img, &srgb_path, 85, None).unwrap();
+    let icc_profile =
+        jxl::extract_icc_profile(&srgb_path).expect("libjxl describes sRGB with an ICC profile");
+
+    let path = temp_dir.path().join("test_icc.jxl");
+    jxl::save_with_profile(&img, &path, 85, Some(&icc_profile)).unwrap();
+
+    assert_eq!(jxl::extract_dimensions(&path), Some((120, 80)));
+    let (decoded, decoded_icc) = jxl::read_jxl(&path).unwrap();
+    assert_eq!((decoded.width(), decoded.height()), (120, 80));
+    assert!(decoded_icc.is_some(), "ICC profile missing after decoding");
+}
+
+#[test]
+fn test_jxl_recompress_jpeg() {
+    let img = DynamicImage::ImageRgb8(ImageBuffer::from_fn(64, 48, |x, y| {
+        Rgb([(x * 4) as u8, (y * 5) as u8, 128])
+    }));
+    let mut jpeg = Vec::new();
+    img.write_to(
+        &mut std::io::Cursor::new(&mut jpeg),
+        image::ImageFormat::Jpeg,
+    )
+    .unwrap();
+
+    let recompressed = jxl::recompress_jpeg(&jpeg).unwrap();
+    assert!(jxl_container::is_jxl(&recompressed));
+    assert_eq!(jxl_container::dimensions(&recompressed), Some((64, 48)));
+
+    // Lossless: the original JPEG comes back byte for byte
+    let decoder = jpegxl_rs::decoder_builder().build().unwrap();
+    match decoder.reconstruct(&recompressed).unwrap() {
+        (_, jpegxl_rs::decode::Data::Jpeg(reconstructed)) => assert_eq!(reconstructed, jpeg),
+        (_, jpegxl_rs::decode::Data::Pixels(_)) => {
+            panic!("recompressed JPEG decoded to pixels instead of the original file")
+        }
+    }
+}
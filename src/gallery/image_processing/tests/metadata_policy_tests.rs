@@ -0,0 +1,297 @@
+use super::super::metadata_policy::{MetadataBlocks, replace_metadata, rewrite_original};
+use super::super::orientation::{Orientation, orientation_from_exif};
+use super::super::types::OutputFormat;
+use crate::MetadataPolicy;
+use crate::gallery::Gallery;
+use crate::gallery::embedded_metadata::parse_xmp;
+use image::{DynamicImage, GenericImageView, RgbImage};
+use tempfile::TempDir;
+
+const GPS_LATITUDE: [u8; 24] = [
+    0, 0, 0, 37, 0, 0, 0, 1, 0, 0, 0, 46, 0, 0, 0, 1, 0, 0, 0x1D, 0x4C, 0, 0, 0, 100,
+];
+
+/// Big-endian TIFF block with Orientation 6, Artist, Copyright and a GPS IFD
+/// holding a latitude
+fn tiff_with_gps() -> Vec<u8> {
+    let artist = b"Jane Doe\0";
+    let copyright = b"(c) Jane Doe\0";
+    let entries = 4;
+    let values_start = 8 + 2 + entries * 12 + 4;
+    let copyright_offset = values_start + artist.len();
+    let gps_ifd = copyright_offset + copyright.len();
+    let latitude_offset = gps_ifd + 2 + 12 + 4;
+
+    let entry = |tiff: &mut Vec<u8>, tag: u16, kind: u16, count: u32, value: u32| {
+        tiff.extend_from_slice(&tag.to_be_bytes());
+        tiff.extend_from_slice(&kind.to_be_bytes());
+        tiff.extend_from_slice(&count.to_be_bytes());
+        tiff.extend_from_slice(&value.to_be_bytes());
+    };
+
+    let mut tiff = b"MM\0*\0\0\0\x08".to_vec();
+    tiff.extend_from_slice(&(entries as u16).to_be_bytes());
+    // Orientation is a SHORT in the first two bytes of the value field
+    entry(&mut tiff, 0x0112, 3, 1, 6 << 16);
+    entry(
+        &mut tiff,
+        0x013B,
+        2,
+        artist.len() as u32,
+        values_start as u32,
+    );
+    entry(
+        &mut tiff,
+        0x8298,
+        2,
+        copyright.len() as u32,
+        copyright_offset as u32,
+    );
+    entry(&mut tiff, 0x8825, 4, 1, gps_ifd as u32);
+    tiff.extend_from_slice(&0u32.to_be_bytes());
+    tiff.extend_from_slice(artist);
+    tiff.extend_from_slice(copyright);
+
+    // GPS IFD: GPSLatitude as three RATIONALs
+    tiff.extend_from_slice(&1u16.to_be_bytes());
+    entry(&mut tiff, 0x0002, 5, 3, latitude_offset as u32);
+    tiff.extend_from_slice(&0u32.to_be_bytes());
+    tiff.extend_from_slice(&GPS_LATITUDE);
+    tiff
+}
+
+const XMP: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"><rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:exif="http://ns.adobe.com/exif/1.0/" xmlns:tiff="http://ns.adobe.com/tiff/1.0/" exif:GPSLatitude="37,46.5N" tiff:Orientation="6"><dc:title><rdf:Alt><rdf:li xml:lang="x-default">Harbour</rdf:li></rdf:Alt></dc:title><dc:rights><rdf:Alt><rdf:li xml:lang="x-default">(c) Jane Doe</rdf:li></rdf:Alt></dc:rights></rdf:Description></rdf:RDF></x:xmpmeta>"#;
+
+fn encode(img: &RgbImage, format: image::ImageFormat) -> Vec<u8> {
+    let mut data = Vec::new();
+    DynamicImage::ImageRgb8(img.clone())
+        .write_to(&mut std::io::Cursor::new(&mut data), format)
+        .unwrap();
+    data
+}
+
+fn sample_blocks() -> MetadataBlocks {
+    MetadataBlocks {
+        exif: Some(tiff_with_gps()),
+        xmp: Some(XMP.as_bytes().to_vec()),
+        iptc: None,
+    }
+}
+
+/// A JPEG carrying the sample EXIF and XMP
+fn jpeg_with_metadata() -> Vec<u8> {
+    let jpeg = encode(&RgbImage::new(60, 40), image::ImageFormat::Jpeg);
+    replace_metadata(&jpeg, &sample_blocks()).unwrap()
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[test]
+fn test_jpeg_metadata_round_trip() {
+    let data = jpeg_with_metadata();
+    assert_eq!(MetadataBlocks::read(&data), sample_blocks());
+    assert_eq!(
+        image::load_from_memory(&data).unwrap().dimensions(),
+        (60, 40)
+    );
+
+    // Replacing again swaps the segments rather than adding more
+    let stripped = replace_metadata(&data, &MetadataBlocks::default()).unwrap();
+    assert!(MetadataBlocks::read(&stripped).is_empty());
+    assert_eq!(
+        stripped,
+        encode(&RgbImage::new(60, 40), image::ImageFormat::Jpeg)
+    );
+}
+
+#[test]
+fn test_original_policies() {
+    let data = jpeg_with_metadata();
+
+    assert_eq!(
+        rewrite_original(&data, MetadataPolicy::KeepAll).unwrap(),
+        data
+    );
+
+    // Stripped originals keep nothing but their orientation
+    let stripped = rewrite_original(&data, MetadataPolicy::Strip).unwrap();
+    let blocks = MetadataBlocks::read(&stripped);
+    assert_eq!(blocks.xmp, None);
+    let exif = blocks.exif.unwrap();
+    assert!(!contains(&exif, b"Jane Doe"));
+    assert!(!contains(&exif, &GPS_LATITUDE));
+    assert_eq!(orientation_from_exif(&exif), Some(Orientation::Rotate90));
+
+    let without_gps = rewrite_original(&data, MetadataPolicy::StripGps).unwrap();
+    let blocks = MetadataBlocks::read(&without_gps);
+    let exif = blocks.exif.unwrap();
+    assert!(!contains(&exif, &GPS_LATITUDE));
+    assert!(!contains(&exif, &[0x88, 0x25]));
+    assert!(contains(&exif, b"Jane Doe"));
+    // Originals keep their orientation
+    assert_eq!(orientation_from_exif(&exif), Some(Orientation::Rotate90));
+    let xmp = String::from_utf8(blocks.xmp.unwrap()).unwrap();
+    assert!(!xmp.contains("GPSLatitude"));
+    assert!(xmp.contains("Harbour"));
+
+    let copyright = rewrite_original(&data, MetadataPolicy::CopyrightOnly).unwrap();
+    let blocks = MetadataBlocks::read(&copyright);
+    let exif = blocks.exif.unwrap();
+    assert!(contains(&exif, b"Jane Doe\0"));
+    assert!(contains(&exif, b"(c) Jane Doe\0"));
+    assert_eq!(orientation_from_exif(&exif), Some(Orientation::Rotate90));
+    let xmp = blocks.xmp.unwrap();
+    assert!(contains(&xmp, b"<tiff:Orientation>6</tiff:Orientation>"));
+    let info = parse_xmp(&xmp);
+    assert_eq!(info.creator.as_deref(), Some("Jane Doe"));
+    assert_eq!(info.copyright.as_deref(), Some("(c) Jane Doe"));
+    assert_eq!(info.title, None);
+
+    // Formats without metadata are passed through
+    let gif = encode(&RgbImage::new(4, 4), image::ImageFormat::Gif);
+    assert_eq!(rewrite_original(&gif, MetadataPolicy::Strip).unwrap(), gif);
+}
+
+#[test]
+fn test_png_and_webp_metadata_round_trip() {
+    let img = RgbImage::from_fn(30, 20, |x, _| image::Rgb([x as u8 * 8, 0, 0]));
+
+    let png = encode(&img, image::ImageFormat::Png);
+    let with_metadata = replace_metadata(&png, &sample_blocks()).unwrap();
+    assert_eq!(
+        MetadataBlocks::read(&with_metadata).exif,
+        sample_blocks().exif
+    );
+    assert_eq!(
+        MetadataBlocks::read(&with_metadata).xmp,
+        sample_blocks().xmp
+    );
+    assert_eq!(
+        image::load_from_memory(&with_metadata).unwrap().to_rgb8(),
+        img
+    );
+    assert_eq!(
+        replace_metadata(&with_metadata, &MetadataBlocks::default()).unwrap(),
+        png
+    );
+
+    // A lossless simple format file becomes an extended one
+    let webp = encode(&img, image::ImageFormat::WebP);
+    let with_metadata = replace_metadata(&webp, &sample_blocks()).unwrap();
+    assert_eq!(&with_metadata[12..16], b"VP8X");
+    assert_eq!(
+        MetadataBlocks::read(&with_metadata).exif,
+        sample_blocks().exif
+    );
+    assert_eq!(
+        MetadataBlocks::read(&with_metadata).xmp,
+        sample_blocks().xmp
+    );
+    assert_eq!(
+        image::load_from_memory(&with_metadata).unwrap().to_rgb8(),
+        img
+    );
+
+    let stripped = replace_metadata(&with_metadata, &MetadataBlocks::default()).unwrap();
+    assert!(MetadataBlocks::read(&stripped).is_empty());
+    assert_eq!(image::load_from_memory(&stripped).unwrap().to_rgb8(), img);
+}
+
+#[tokio::test]
+async fn test_derivatives_follow_policy() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = crate::Config::default().galleries.unwrap()[0].clone();
+    config.source_directory = temp_dir.path().join("photos");
+    config.cache_directory = temp_dir.path().join("cache");
+    std::fs::create_dir_all(&config.source_directory).unwrap();
+    std::fs::write(
+        config.source_directory.join("photo.jpg"),
+        jpeg_with_metadata(),
+    )
+    .unwrap();
+
+    // Derivatives are stripped by default
+    let gallery = Gallery::new(config.clone());
+    let path = gallery.config.source_directory.join("photo.jpg");
+    let cached = gallery
+        .get_resized_image(&path, "photo.jpg", "thumbnail", OutputFormat::Jpeg)
+        .await
+        .unwrap();
+    assert!(MetadataBlocks::read(&std::fs::read(&cached).unwrap()).is_empty());
+
+    config.metadata.derivatives = MetadataPolicy::StripGps;
+    config.cache_directory = temp_dir.path().join("cache-gps");
+    let gallery = Gallery::new(config);
+    for format in [OutputFormat::Jpeg, OutputFormat::WebP] {
+        let cached = gallery
+            .get_resized_image(&path, "photo.jpg", "thumbnail", format)
+            .await
+            .unwrap();
+        let data = std::fs::read(&cached).unwrap();
+        let blocks = MetadataBlocks::read(&data);
+        let exif = blocks.exif.unwrap();
+        assert!(!contains(&exif, &GPS_LATITUDE), "{:?}", format);
+        assert!(contains(&exif, b"(c) Jane Doe"), "{:?}", format);
+        // Already rotated, so the orientation must not be applied again
+        assert_eq!(orientation_from_exif(&exif), Some(Orientation::Normal));
+        let xmp = String::from_utf8(blocks.xmp.unwrap()).unwrap();
+        assert!(!xmp.contains("tiff:Orientation"));
+        assert_eq!(
+            image::load_from_memory(&data).unwrap().dimensions(),
+            (40, 60)
+        );
+    }
+}
+
+#[cfg(feature = "avif")]
+#[test]
+fn test_avif_original_rewritten_in_place() {
+    let data = std::fs::read("photos/vacation/_A639941.avif").unwrap();
+    assert!(MetadataBlocks::read(&data).exif.is_some());
+
+    let stripped = rewrite_original(&data, MetadataPolicy::Strip).unwrap();
+    assert_eq!(stripped.len(), data.len());
+    let exif = MetadataBlocks::read(&stripped).exif.unwrap();
+    // An empty IFD0 is all that's left
+    assert_eq!(&exif[..10], b"MM\0*\0\0\0\x08\0\0");
+    assert!(exif[10..].iter().all(|&b| b == 0));
+}
+
+#[cfg(feature = "avif")]
+#[test]
+fn test_avif_unlocatable_metadata_fails_closed() {
+    fn isobmff_box(box_type: &[u8; 4], contents: &[u8]) -> Vec<u8> {
+        let mut data = ((contents.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(box_type);
+        data.extend_from_slice(contents);
+        data
+    }
+
+    // An Exif item declared in iinf but stored in idat (construction method 1)
+    let infe = isobmff_box(b"infe", b"\x02\0\0\0\0\x01\0\0Exif\0");
+    let iinf = isobmff_box(b"iinf", &[&[0, 0, 0, 0, 0, 1][..], &infe].concat());
+    let iloc = isobmff_box(
+        b"iloc",
+        // Version 1, 4-byte offsets and lengths, one item with one extent
+        b"\x01\0\0\0\x44\0\0\x01\0\x01\0\x01\0\0\0\x01\0\0\0\0\0\0\0\x10",
+    );
+    let idat = isobmff_box(b"idat", &[0u8; 16]);
+    let meta = isobmff_box(b"meta", &[&[0u8; 4][..], &iinf, &iloc, &idat].concat());
+    let data = [isobmff_box(b"ftyp", b"avif\0\0\0\0avif"), meta].concat();
+
+    assert_eq!(rewrite_original(&data, MetadataPolicy::Strip), None);
+    assert_eq!(rewrite_original(&data, MetadataPolicy::StripGps), None);
+    assert!(rewrite_original(&data, MetadataPolicy::KeepAll).is_some());
+
+    // An empty iinf at the very end of the data is rejected, not a panic
+    let meta = isobmff_box(
+        b"meta",
+        &[&[0u8; 4][..], &isobmff_box(b"iinf", b"")].concat(),
+    );
+    let data = [isobmff_box(b"ftyp", b"avif\0\0\0\0avif"), meta].concat();
+    assert_eq!(rewrite_original(&data, MetadataPolicy::Strip), None);
+}
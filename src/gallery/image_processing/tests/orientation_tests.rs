@@ -0,0 +1,299 @@
+use super::super::orientation::{Orientation, orientation_from_exif, read_orientation};
+use super::super::types::OutputFormat;
+use crate::gallery::Gallery;
+use image::{DynamicImage, GenericImageView, ImageBuffer, Rgb, RgbImage};
+use std::path::Path;
+use tempfile::TempDir;
+
+const RED: Rgb<u8> = Rgb([255, 0, 0]);
+const GREEN: Rgb<u8> = Rgb([0, 255, 0]);
+const BLUE: Rgb<u8> = Rgb([0, 0, 255]);
+const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
+
+const ALL_ORIENTATIONS: [Orientation; 8] = [
+    Orientation::Normal,
+    Orientation::FlipHorizontal,
+    Orientation::Rotate180,
+    Orientation::FlipVertical,
+    Orientation::Transpose,
+    Orientation::Rotate90,
+    Orientation::Transverse,
+    Orientation::Rotate270,
+];
+
+/// A 60x40 image with a red top-left quarter, green top-right, blue
+/// bottom-left and white bottom-right
+fn quadrant_image() -> RgbImage {
+    ImageBuffer::from_fn(60, 40, |x, y| match (x < 30, y < 20) {
+        (true, true) => RED,
+        (false, true) => GREEN,
+        (true, false) => BLUE,
+        (false, false) => WHITE,
+    })
+}
+
+/// Minimal TIFF block with a single IFD0 Orientation entry
+fn tiff_with_orientation(value: u16, big_endian: bool) -> Vec<u8> {
+    let u16_bytes = |v: u16| {
+        if big_endian {
+            v.to_be_bytes()
+        } else {
+            v.to_le_bytes()
+        }
+    };
+    let u32_bytes = |v: u32| {
+        if big_endian {
+            v.to_be_bytes()
+        } else {
+            v.to_le_bytes()
+        }
+    };
+
+    let mut tiff = Vec::new();
+    tiff.extend_from_slice(if big_endian { b"MM" } else { b"II" });
+    tiff.extend_from_slice(&u16_bytes(42));
+    tiff.extend_from_slice(&u32_bytes(8));
+    // IFD0 with one entry: tag, type SHORT, count 1, value
+    tiff.extend_from_slice(&u16_bytes(1));
+    tiff.extend_from_slice(&u16_bytes(0x0112));
+    tiff.extend_from_slice(&u16_bytes(3));
+    tiff.extend_from_slice(&u32_bytes(1));
+    tiff.extend_from_slice(&u16_bytes(value));
+    tiff.extend_from_slice(&[0, 0]);
+    // No next IFD
+    tiff.extend_from_slice(&u32_bytes(0));
+    tiff
+}
+
+/// Encode `img` as JPEG with an EXIF APP1 segment right after SOI
+fn write_jpeg_with_orientation(path: &Path, img: &RgbImage, value: u16) {
+    let mut jpeg = Vec::new();
+    DynamicImage::ImageRgb8(img.clone())
+        .write_to(
+            &mut std::io::Cursor::new(&mut jpeg),
+            image::ImageFormat::Jpeg,
+        )
+        .unwrap();
+
+    let mut payload = b"Exif\0\0".to_vec();
+    payload.extend(tiff_with_orientation(value, true));
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+    segment.extend(payload);
+
+    let mut output = jpeg[..2].to_vec();
+    output.extend(segment);
+    output.extend_from_slice(&jpeg[2..]);
+    std::fs::write(path, output).unwrap();
+}
+
+/// Encode `img` as PNG with an eXIf chunk after IHDR
+fn write_png_with_orientation(path: &Path, img: &RgbImage, value: u16) {
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(img.clone())
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+
+    let data = tiff_with_orientation(value, false);
+    let mut crc = flate2::Crc::new();
+    crc.update(b"eXIf");
+    crc.update(&data);
+    let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+    chunk.extend_from_slice(b"eXIf");
+    chunk.extend(data);
+    chunk.extend_from_slice(&crc.sum().to_be_bytes());
+
+    // Signature (8 bytes) plus IHDR (4 length + 4 type + 13 data + 4 CRC)
+    let ihdr_end = 8 + 25;
+    let mut output = png[..ihdr_end].to_vec();
+    output.extend(chunk);
+    output.extend_from_slice(&png[ihdr_end..]);
+    std::fs::write(path, output).unwrap();
+}
+
+/// Where the stored red (top-left) and green (top-right) corners end up
+/// once displayed, as (red corner, green corner)
+fn expected_corners(orientation: Orientation) -> (&'static str, &'static str) {
+    match orientation {
+        Orientation::Normal => ("top-left", "top-right"),
+        Orientation::FlipHorizontal => ("top-right", "top-left"),
+        Orientation::Rotate180 => ("bottom-right", "bottom-left"),
+        Orientation::FlipVertical => ("bottom-left", "bottom-right"),
+        Orientation::Transpose => ("top-left", "bottom-left"),
+        Orientation::Rotate90 => ("top-right", "bottom-right"),
+        Orientation::Transverse => ("bottom-right", "top-right"),
+        Orientation::Rotate270 => ("bottom-left", "top-left"),
+    }
+}
+
+fn corner_color(img: &DynamicImage, corner: &str) -> Rgb<u8> {
+    let (width, height) = img.dimensions();
+    // Sample a little inside the corner to stay clear of JPEG edge artifacts
+    let (x, y) = match corner {
+        "top-left" => (width / 8, height / 8),
+        "top-right" => (width - 1 - width / 8, height / 8),
+        "bottom-left" => (width / 8, height - 1 - height / 8),
+        "bottom-right" => (width - 1 - width / 8, height - 1 - height / 8),
+        _ => unreachable!(),
+    };
+    img.to_rgb8().get_pixel(x, y).to_owned()
+}
+
+fn assert_close(actual: Rgb<u8>, expected: Rgb<u8>, context: &str) {
+    let close = actual
+        .0
+        .iter()
+        .zip(expected.0.iter())
+        .all(|(a, e)| (*a as i16 - *e as i16).abs() <= 40);
+    assert!(
+        close,
+        "{}: expected {:?}, got {:?}",
+        context, expected, actual
+    );
+}
+
+#[test]
+fn test_apply_all_orientations() {
+    let stored = DynamicImage::ImageRgb8(quadrant_image());
+
+    for orientation in ALL_ORIENTATIONS {
+        let displayed = orientation.apply(stored.clone());
+        let expected_dimensions = if orientation.swaps_dimensions() {
+            (40, 60)
+        } else {
+            (60, 40)
+        };
+        assert_eq!(
+            displayed.dimensions(),
+            expected_dimensions,
+            "{:?}",
+            orientation
+        );
+        assert_eq!(
+            orientation.oriented_dimensions((60, 40)),
+            expected_dimensions
+        );
+
+        let (red_corner, green_corner) = expected_corners(orientation);
+        assert_eq!(
+            corner_color(&displayed, red_corner),
+            RED,
+            "{:?} red corner",
+            orientation
+        );
+        assert_eq!(
+            corner_color(&displayed, green_corner),
+            GREEN,
+            "{:?} green corner",
+            orientation
+        );
+    }
+}
+
+#[test]
+fn test_orientation_from_exif_values() {
+    for (value, orientation) in (1..=8).zip(ALL_ORIENTATIONS) {
+        assert_eq!(Orientation::from_exif(value), Some(orientation));
+        assert_eq!(
+            orientation_from_exif(&tiff_with_orientation(value, false)),
+            Some(orientation)
+        );
+        assert_eq!(
+            orientation_from_exif(&tiff_with_orientation(value, true)),
+            Some(orientation)
+        );
+    }
+
+    assert_eq!(Orientation::from_exif(0), None);
+    assert_eq!(Orientation::from_exif(9), None);
+
+    // Behind an Exif header, or the 4-byte offset libavif may leave in front
+    let mut with_header = b"Exif\0\0".to_vec();
+    with_header.extend(tiff_with_orientation(6, true));
+    assert_eq!(
+        orientation_from_exif(&with_header),
+        Some(Orientation::Rotate90)
+    );
+    let mut with_offset = vec![0, 0, 0, 6];
+    with_offset.extend(with_header);
+    assert_eq!(
+        orientation_from_exif(&with_offset),
+        Some(Orientation::Rotate90)
+    );
+
+    assert_eq!(orientation_from_exif(b"garbage"), None);
+}
+
+#[test]
+fn test_read_orientation_from_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let img = quadrant_image();
+
+    for (value, orientation) in (1..=8).zip(ALL_ORIENTATIONS) {
+        let jpeg_path = temp_dir.path().join(format!("photo_{}.jpg", value));
+        write_jpeg_with_orientation(&jpeg_path, &img, value);
+        assert_eq!(read_orientation(&jpeg_path), orientation, "JPEG {}", value);
+
+        let png_path = temp_dir.path().join(format!("photo_{}.png", value));
+        write_png_with_orientation(&png_path, &img, value);
+        assert_eq!(read_orientation(&png_path), orientation, "PNG {}", value);
+
+        // The extra metadata must not stop the files from decoding
+        assert_eq!(image::open(&jpeg_path).unwrap().dimensions(), (60, 40));
+        assert_eq!(image::open(&png_path).unwrap().dimensions(), (60, 40));
+    }
+
+    // No EXIF at all
+    let plain = temp_dir.path().join("plain.jpg");
+    img.save(&plain).unwrap();
+    assert_eq!(read_orientation(&plain), Orientation::Normal);
+}
+
+#[tokio::test]
+async fn test_resized_images_and_metadata_are_upright() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = crate::Config::default().galleries.unwrap()[0].clone();
+    config.source_directory = temp_dir.path().join("photos");
+    config.cache_directory = temp_dir.path().join("cache");
+    std::fs::create_dir_all(&config.source_directory).unwrap();
+    let gallery = Gallery::new(config);
+    let img = quadrant_image();
+
+    for (value, orientation) in (1..=8).zip(ALL_ORIENTATIONS) {
+        let name = format!("photo_{}.jpg", value);
+        let path = gallery.config.source_directory.join(&name);
+        write_jpeg_with_orientation(&path, &img, value);
+
+        let metadata = gallery.extract_image_metadata(&path).await.unwrap();
+        assert_eq!(
+            metadata.dimensions,
+            orientation.oriented_dimensions((60, 40)),
+            "metadata for orientation {}",
+            value
+        );
+
+        let cached = gallery
+            .get_resized_image(&path, &name, "thumbnail", OutputFormat::Png)
+            .await
+            .unwrap();
+        let thumbnail = image::open(&cached).unwrap();
+        assert_eq!(
+            thumbnail.dimensions(),
+            orientation.oriented_dimensions((60, 40)),
+            "thumbnail for orientation {}",
+            value
+        );
+
+        let (red_corner, green_corner) = expected_corners(orientation);
+        assert_close(
+            corner_color(&thumbnail, red_corner),
+            RED,
+            &format!("orientation {} red corner", value),
+        );
+        assert_close(
+            corner_color(&thumbnail, green_corner),
+            GREEN,
+            &format!("orientation {} green corner", value),
+        );
+    }
+}
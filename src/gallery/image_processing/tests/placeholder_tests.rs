@@ -0,0 +1,81 @@
+use super::super::placeholder::{generate_placeholder, placeholder_for_image};
+use crate::gallery::Gallery;
+use base64::{Engine, engine::general_purpose};
+use image::{DynamicImage, GenericImageView, ImageBuffer, Rgb};
+use tempfile::TempDir;
+
+fn decode_data_uri(data_uri: &str) -> DynamicImage {
+    let encoded = data_uri
+        .strip_prefix("data:image/webp;base64,")
+        .expect("placeholder should be a WebP data URI");
+    let bytes = general_purpose::STANDARD.decode(encoded).unwrap();
+    image::load_from_memory_with_format(&bytes, image::ImageFormat::WebP).unwrap()
+}
+
+#[test]
+fn test_placeholder_is_tiny_webp_with_same_shape() {
+    let img = DynamicImage::ImageRgb8(ImageBuffer::from_fn(400, 200, |x, y| {
+        Rgb([(x % 256) as u8, (y % 256) as u8, 128])
+    }));
+
+    let placeholder = placeholder_for_image(&img).unwrap();
+    let decoded = decode_data_uri(&placeholder.data_uri);
+
+    assert_eq!(decoded.dimensions(), (16, 8));
+    assert!(placeholder.data_uri.len() < 1024);
+}
+
+#[test]
+fn test_dominant_color_is_most_common_color() {
+    // Three quarters blue sky over orange sand
+    let img = DynamicImage::ImageRgb8(ImageBuffer::from_fn(100, 100, |_, y| {
+        if y < 75 {
+            Rgb([30, 90, 200])
+        } else {
+            Rgb([230, 140, 40])
+        }
+    }));
+
+    let placeholder = placeholder_for_image(&img).unwrap();
+    assert_eq!(placeholder.dominant_color, "#1e5ac8");
+}
+
+#[test]
+fn test_generate_placeholder_from_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("red.png");
+    ImageBuffer::from_pixel(64, 48, Rgb([200u8, 20, 20]))
+        .save(&path)
+        .unwrap();
+
+    let placeholder = generate_placeholder(&path).unwrap();
+    assert_eq!(placeholder.dominant_color, "#c81414");
+    assert_eq!(
+        decode_data_uri(&placeholder.data_uri).dimensions(),
+        (16, 12)
+    );
+
+    // Files that can't be decoded just have no placeholder
+    let broken = temp_dir.path().join("broken.jpg");
+    std::fs::write(&broken, b"not an image").unwrap();
+    assert!(generate_placeholder(&broken).is_none());
+}
+
+#[tokio::test]
+async fn test_extracted_metadata_includes_placeholder() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = crate::Config::default().galleries.unwrap()[0].clone();
+    config.source_directory = temp_dir.path().to_path_buf();
+    config.cache_directory = temp_dir.path().join("cache");
+    let gallery = Gallery::new(config);
+
+    ImageBuffer::from_pixel(120, 80, Rgb([10u8, 120, 60]))
+        .save(temp_dir.path().join("green.jpg"))
+        .unwrap();
+
+    let info = gallery.get_image_info("green.jpg").await.unwrap();
+    let placeholder = info.placeholder.expect("placeholder should be extracted");
+    assert!(placeholder.data_uri.starts_with("data:image/webp;base64,"));
+    assert!(placeholder.dominant_color.starts_with('#'));
+    assert_eq!(placeholder.dominant_color.len(), 7);
+}
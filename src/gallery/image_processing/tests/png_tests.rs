@@ -43,9 +43,17 @@ async fn create_test_gallery() -> (Gallery, TempDir) {
         jpeg_quality: Some(85),
         webp_quality: Some(85.0),
         pregenerate_cache: false,
+        pregenerate_parallelism: None,
         new_threshold_days: None,
         approximate_dates_for_public: false,
+        location_precision: Default::default(),
+        location_precision_for_public: Default::default(),
+        metadata: Default::default(),
+        ffmpeg_path: None,
+        max_cache_size: None,
         copyright_holder: None,
+        watch_filesystem: false,
+        sizes: Default::default(),
     };
 
     let gallery = Gallery {
@@ -57,6 +65,9 @@ async fn create_test_gallery() -> (Gallery, TempDir) {
         })),
         metadata_cache_dirty: Arc::new(std::sync::atomic::AtomicBool::new(false)),
         metadata_updates_since_save: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+        search_index: Arc::new(RwLock::new(Default::default())),
+        cache_index: Arc::new(RwLock::new(Default::default())),
+        cache_index_dirty: Arc::new(std::sync::atomic::AtomicBool::new(false)),
     };
 
     (gallery, temp_dir)
@@ -0,0 +1,261 @@
+use super::super::resize::{SourceRegion, resize_image};
+use super::super::types::{ImageSize, OutputFormat};
+use crate::gallery::Gallery;
+use crate::{CropMode, GallerySystemConfig, NamedSizeConfig};
+use image::{DynamicImage, GenericImageView, ImageBuffer, Rgb};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tempfile::TempDir;
+use tokio::sync::RwLock;
+
+// Helper function to create a test gallery with extra named sizes
+fn create_test_gallery(sizes: BTreeMap<String, NamedSizeConfig>) -> (Gallery, TempDir) {
+    let temp_dir = TempDir::new().unwrap();
+    let cache_dir = temp_dir.path().join("cache");
+
+    let config = GallerySystemConfig {
+        name: "test".to_string(),
+        url_prefix: "gallery".to_string(),
+        gallery_template: "gallery.html.liquid".to_string(),
+        image_detail_template: "image_detail.html.liquid".to_string(),
+        source_directory: temp_dir.path().to_path_buf(),
+        cache_directory: cache_dir,
+        images_per_page: 50,
+        thumbnail: crate::ImageSizeConfig {
+            width: 300,
+            height: 300,
+        },
+        gallery_size: crate::ImageSizeConfig {
+            width: 800,
+            height: 800,
+        },
+        medium: crate::ImageSizeConfig {
+            width: 1200,
+            height: 1200,
+        },
+        large: crate::ImageSizeConfig {
+            width: 1600,
+            height: 1600,
+        },
+        preview: crate::PreviewConfig {
+            max_images: 6,
+            max_depth: 3,
+            max_per_folder: 3,
+        },
+        cache_refresh_interval_minutes: None,
+        jpeg_quality: Some(85),
+        webp_quality: Some(85.0),
+        pregenerate_cache: false,
+        pregenerate_parallelism: None,
+        new_threshold_days: None,
+        approximate_dates_for_public: false,
+        location_precision: Default::default(),
+        location_precision_for_public: Default::default(),
+        metadata: Default::default(),
+        ffmpeg_path: None,
+        max_cache_size: None,
+        copyright_holder: None,
+        watch_filesystem: false,
+        sizes,
+    };
+
+    let gallery = Gallery {
+        config,
+        metadata_cache: Arc::new(RwLock::new(std::collections::HashMap::new())),
+        cache_metadata: Arc::new(RwLock::new(crate::gallery::CacheMetadata {
+            version: String::new(),
+            last_full_refresh: std::time::SystemTime::UNIX_EPOCH,
+        })),
+        metadata_cache_dirty: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+        metadata_updates_since_save: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+        search_index: Arc::new(RwLock::new(Default::default())),
+        cache_index: Arc::new(RwLock::new(Default::default())),
+        cache_index_dirty: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+    };
+
+    (gallery, temp_dir)
+}
+
+fn named_size(width: u32, height: u32, crop: CropMode) -> NamedSizeConfig {
+    NamedSizeConfig {
+        width,
+        height,
+        crop,
+        quality: None,
+        watermark: false,
+        requires_auth: false,
+    }
+}
+
+/// Flat grey image with a checkerboard in the rightmost quarter
+fn image_with_detail_on_right(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(ImageBuffer::from_fn(width, height, |x, y| {
+        if x >= width * 3 / 4 && (x / 4 + y / 4) % 2 == 0 {
+            Rgb([255, 255, 255])
+        } else {
+            Rgb([128, 128, 128])
+        }
+    }))
+}
+
+#[test]
+fn test_fit_keeps_aspect_ratio() {
+    let img = image_with_detail_on_right(400, 200);
+    let (resized, region) = resize_image(&img, ImageSize::new(100, 100), CropMode::Fit).unwrap();
+    assert_eq!(resized.dimensions(), (100, 50));
+    assert_eq!(
+        region,
+        SourceRegion {
+            x: 0,
+            y: 0,
+            width: 400,
+            height: 200
+        }
+    );
+}
+
+#[test]
+fn test_fill_crops_centre_to_exact_size() {
+    let img = image_with_detail_on_right(400, 200);
+    let (resized, region) = resize_image(&img, ImageSize::new(100, 100), CropMode::Fill).unwrap();
+    assert_eq!(resized.dimensions(), (100, 100));
+    assert_eq!(
+        region,
+        SourceRegion {
+            x: 100,
+            y: 0,
+            width: 200,
+            height: 200
+        }
+    );
+}
+
+#[test]
+fn test_fill_never_upscales() {
+    let img = image_with_detail_on_right(400, 200);
+    let (resized, _) = resize_image(&img, ImageSize::new(1000, 500), CropMode::Fill).unwrap();
+    assert_eq!(resized.dimensions(), (400, 200));
+
+    // The box shrinks to fit, keeping its shape
+    let (resized, _) = resize_image(&img, ImageSize::new(800, 800), CropMode::Fill).unwrap();
+    assert_eq!(resized.dimensions(), (200, 200));
+}
+
+#[test]
+fn test_smart_crop_follows_detail() {
+    let img = image_with_detail_on_right(400, 200);
+    let (resized, region) =
+        resize_image(&img, ImageSize::new(100, 100), CropMode::SmartCrop).unwrap();
+    assert_eq!(resized.dimensions(), (100, 100));
+    assert_eq!(region.width, 200);
+    assert!(
+        region.x >= 150,
+        "expected crop towards the detailed right edge, got x = {}",
+        region.x
+    );
+
+    // Nothing to choose between: stay centred
+    let flat = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(400, 200, Rgb([10, 10, 10])));
+    let (_, region) = resize_image(&flat, ImageSize::new(100, 100), CropMode::SmartCrop).unwrap();
+    assert_eq!(region.x, 100);
+}
+
+#[test]
+fn test_parse_size_with_named_sizes() {
+    let mut sizes = BTreeMap::new();
+    sizes.insert("hero".to_string(), named_size(2400, 1200, CropMode::Fit));
+    sizes.insert(
+        "thumbnail".to_string(),
+        named_size(200, 200, CropMode::SmartCrop),
+    );
+    let (gallery, _temp_dir) = create_test_gallery(sizes);
+
+    let (dimensions, size) = gallery.parse_size("hero@2x").unwrap();
+    assert_eq!((dimensions.width, dimensions.height), (4800, 2400));
+    assert_eq!(size.crop, CropMode::Fit);
+
+    // Configured entries replace built-in sizes of the same name
+    let (dimensions, size) = gallery.parse_size("thumbnail").unwrap();
+    assert_eq!((dimensions.width, dimensions.height), (200, 200));
+    assert_eq!(size.crop, CropMode::SmartCrop);
+
+    // Built-ins keep their usual behaviour
+    let (_, medium) = gallery.parse_size("medium").unwrap();
+    assert!(medium.watermark);
+    let (_, large) = gallery.parse_size("large").unwrap();
+    assert!(large.requires_auth);
+
+    assert!(gallery.parse_size("poster").is_err());
+    assert_eq!(
+        gallery
+            .get_config()
+            .image_sizes()
+            .into_keys()
+            .collect::<Vec<_>>(),
+        vec!["gallery", "hero", "large", "medium", "thumbnail"]
+    );
+}
+
+#[tokio::test]
+async fn test_get_resized_image_square_crop() {
+    let mut sizes = BTreeMap::new();
+    sizes.insert("square".to_string(), named_size(64, 64, CropMode::Fill));
+    let (gallery, _temp_dir) = create_test_gallery(sizes);
+
+    let source = gallery.config.source_directory.join("wide.jpg");
+    image_with_detail_on_right(300, 150).save(&source).unwrap();
+
+    let cached = gallery
+        .get_resized_image(&source, "wide.jpg", "square", OutputFormat::Jpeg)
+        .await
+        .unwrap();
+    assert_eq!(image::open(&cached).unwrap().dimensions(), (64, 64));
+
+    let cached = gallery
+        .get_resized_image(&source, "wide.jpg", "square@2x", OutputFormat::Jpeg)
+        .await
+        .unwrap();
+    assert_eq!(image::open(&cached).unwrap().dimensions(), (128, 128));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_requests_share_one_resize() {
+    let (gallery, _temp_dir) = create_test_gallery(BTreeMap::new());
+    let source = gallery.config.source_directory.join("wide.jpg");
+    image_with_detail_on_right(300, 150).save(&source).unwrap();
+    let gallery = Arc::new(gallery);
+
+    let requests: Vec<_> = (0..8)
+        .map(|_| {
+            let gallery = gallery.clone();
+            let source = source.clone();
+            tokio::spawn(async move {
+                gallery
+                    .get_resized_image(&source, "wide.jpg", "thumbnail", OutputFormat::WebP)
+                    .await
+                    .unwrap()
+            })
+        })
+        .collect();
+
+    let mut paths = Vec::new();
+    for request in requests {
+        paths.push(request.await.unwrap());
+    }
+    assert!(paths.iter().all(|path| path == &paths[0]));
+    assert_eq!(image::open(&paths[0]).unwrap().dimensions(), (300, 150));
+    // Only the first request resized; the others waited and used its file
+    let writes = crate::gallery::image_processing::workers::WRITES
+        .lock()
+        .unwrap()
+        .get(&paths[0])
+        .copied();
+    assert_eq!(writes, Some(1));
+
+    // Written once and renamed into place, leaving no temporary files
+    let files: Vec<_> = std::fs::read_dir(&gallery.config.cache_directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(files, vec![paths[0].file_name().unwrap().to_os_string()]);
+}
@@ -0,0 +1,174 @@
+use super::super::types::OutputFormat;
+use super::super::video::{placeholder_poster, read_video_properties, video_info};
+use crate::gallery::Gallery;
+use image::GenericImageView;
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
+
+fn mp4_box(kind: &[u8; 4], contents: &[u8]) -> Vec<u8> {
+    let mut data = ((contents.len() + 8) as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(kind);
+    data.extend_from_slice(contents);
+    data
+}
+
+/// 2024-05-01T12:00:00Z
+const CREATION_TIME: u64 = 1_714_564_800;
+
+/// A `moov` box for a 12.5 second, 1920x1080 video track rotated 90 degrees
+/// for portrait display, plus an audio track
+fn sample_moov() -> Vec<u8> {
+    let mut mvhd = vec![0; 4];
+    mvhd.extend_from_slice(&((CREATION_TIME + 2_082_844_800) as u32).to_be_bytes());
+    mvhd.extend_from_slice(&0u32.to_be_bytes());
+    mvhd.extend_from_slice(&1000u32.to_be_bytes());
+    mvhd.extend_from_slice(&12_500u32.to_be_bytes());
+    mvhd.extend_from_slice(&[0; 80]);
+
+    let track = |handler: &[u8; 4], matrix: [u32; 9], width: u32, height: u32| {
+        let mut tkhd = vec![0; 40];
+        for value in matrix {
+            tkhd.extend_from_slice(&value.to_be_bytes());
+        }
+        tkhd.extend_from_slice(&(width << 16).to_be_bytes());
+        tkhd.extend_from_slice(&(height << 16).to_be_bytes());
+
+        let mut hdlr = vec![0; 8];
+        hdlr.extend_from_slice(handler);
+        hdlr.extend_from_slice(&[0; 13]);
+
+        let mut trak = mp4_box(b"tkhd", &tkhd);
+        trak.extend(mp4_box(b"mdia", &mp4_box(b"hdlr", &hdlr)));
+        mp4_box(b"trak", &trak)
+    };
+
+    let mut moov = mp4_box(b"mvhd", &mvhd);
+    // Audio first, so the video track has to be picked out by its handler
+    moov.extend(track(
+        b"soun",
+        [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x40000000],
+        0,
+        0,
+    ));
+    moov.extend(track(
+        b"vide",
+        [0, 0x10000, 0, 0xFFFF0000, 0, 0, 0, 0, 0x40000000],
+        1920,
+        1080,
+    ));
+    moov
+}
+
+/// An MP4 file with its media data ahead of the `moov` box, as cameras
+/// write them
+fn sample_mp4() -> Vec<u8> {
+    let mut data = mp4_box(b"ftyp", b"isom\0\0\x02\0isommp41");
+    data.extend(mp4_box(b"mdat", &[0xAB; 4096]));
+    data.extend(mp4_box(b"moov", &sample_moov()));
+    data
+}
+
+fn test_config(temp_dir: &TempDir) -> crate::GallerySystemConfig {
+    let mut config = crate::Config::default().galleries.unwrap()[0].clone();
+    config.source_directory = temp_dir.path().join("photos");
+    config.cache_directory = temp_dir.path().join("cache");
+    std::fs::create_dir_all(&config.source_directory).unwrap();
+    config
+}
+
+#[test]
+fn test_read_video_properties() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("clip.mp4");
+    std::fs::write(&path, sample_mp4()).unwrap();
+
+    let properties = read_video_properties(&path).unwrap();
+    assert_eq!(properties.duration, Some(12.5));
+    // Displayed as portrait
+    assert_eq!(properties.dimensions, Some((1080, 1920)));
+    assert_eq!(
+        properties.creation_time,
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(CREATION_TIME))
+    );
+
+    // A 64-bit box size ahead of the movie box
+    let mut large = mp4_box(b"ftyp", b"qt  \0\0\0\0qt  ");
+    large.extend_from_slice(&1u32.to_be_bytes());
+    large.extend_from_slice(b"mdat");
+    large.extend_from_slice(&(16u64 + 100).to_be_bytes());
+    large.extend_from_slice(&[0; 100]);
+    large.extend(mp4_box(b"moov", &sample_moov()));
+    std::fs::write(&path, large).unwrap();
+    assert_eq!(read_video_properties(&path).unwrap().duration, Some(12.5));
+
+    // No movie box at all
+    std::fs::write(&path, mp4_box(b"ftyp", b"isom")).unwrap();
+    assert_eq!(read_video_properties(&path), None);
+}
+
+#[test]
+fn test_video_info() {
+    let info = video_info("trip/clip.MOV", Some(83.4)).unwrap();
+    assert_eq!(info.mime_type, "video/mp4");
+    assert_eq!(info.duration.as_deref(), Some("1:23"));
+    assert_eq!(video_info("clip.mp4", None).unwrap().duration, None);
+    assert_eq!(video_info("photo.jpg", Some(1.0)), None);
+}
+
+#[test]
+fn test_placeholder_poster_keeps_aspect_ratio() {
+    assert_eq!(
+        placeholder_poster(Some((1080, 1920))).dimensions(),
+        (720, 1280)
+    );
+    assert_eq!(
+        placeholder_poster(Some((3840, 2160))).dimensions(),
+        (1280, 720)
+    );
+    assert_eq!(placeholder_poster(None).dimensions(), (1280, 720));
+
+    // A light play symbol on a dark background
+    let poster = placeholder_poster(None);
+    assert!(poster.get_pixel(640, 360)[0] > 200);
+    assert!(poster.get_pixel(10, 10)[0] < 60);
+}
+
+#[tokio::test]
+async fn test_video_metadata_and_placeholder_poster() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = test_config(&temp_dir);
+    // A missing ffmpeg falls back to the placeholder
+    config.ffmpeg_path = Some(temp_dir.path().join("no-such-ffmpeg"));
+    let path = config.source_directory.join("clip.mp4");
+    std::fs::write(&path, sample_mp4()).unwrap();
+    let gallery = Gallery::new(config);
+
+    let metadata = gallery.extract_image_metadata(&path).await.unwrap();
+    assert_eq!(metadata.dimensions, (1080, 1920));
+    assert_eq!(metadata.duration, Some(12.5));
+    assert_eq!(
+        metadata.capture_date,
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(CREATION_TIME))
+    );
+    assert!(metadata.placeholder.is_some());
+
+    // Sizes are resized from the poster
+    let thumbnail = gallery
+        .get_resized_image(&path, "clip.mp4", "thumbnail", OutputFormat::Jpeg)
+        .await
+        .unwrap();
+    let (width, height) = image::open(&thumbnail).unwrap().dimensions();
+    assert!(width < height);
+    assert!(width <= 300 && height <= 300);
+
+    // The original is served untouched
+    assert_eq!(
+        gallery.original_file(&path, "clip.mp4").await.unwrap(),
+        path
+    );
+
+    assert!(gallery.evict_cached_images("clip.mp4").await >= 2);
+    let [frame, placeholder] = gallery.poster_cache_filenames("clip.mp4");
+    assert!(!gallery.config.cache_directory.join(frame).exists());
+    assert!(!gallery.config.cache_directory.join(placeholder).exists());
+}
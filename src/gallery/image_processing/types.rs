@@ -7,9 +7,25 @@ pub enum OutputFormat {
     Png,
     #[cfg(feature = "avif")]
     Avif,
+    #[cfg(feature = "jxl")]
+    Jxl,
 }
 
 impl OutputFormat {
+    /// Parse a `?format=` value
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "jpeg" | "jpg" => Some(OutputFormat::Jpeg),
+            "webp" => Some(OutputFormat::WebP),
+            "png" => Some(OutputFormat::Png),
+            #[cfg(feature = "avif")]
+            "avif" => Some(OutputFormat::Avif),
+            #[cfg(feature = "jxl")]
+            "jxl" => Some(OutputFormat::Jxl),
+            _ => None,
+        }
+    }
+
     pub fn extension(&self) -> &'static str {
         match self {
             OutputFormat::Jpeg => "jpg",
@@ -17,17 +33,22 @@ impl OutputFormat {
             OutputFormat::Png => "png",
             #[cfg(feature = "avif")]
             OutputFormat::Avif => "avif",
+            #[cfg(feature = "jxl")]
+            OutputFormat::Jxl => "jxl",
         }
     }
 
+    /// The `image` crate's format, which JPEG XL doesn't have
     #[allow(dead_code)]
-    pub fn image_format(&self) -> ImageFormat {
+    pub fn image_format(&self) -> Option<ImageFormat> {
         match self {
-            OutputFormat::Jpeg => ImageFormat::Jpeg,
-            OutputFormat::WebP => ImageFormat::WebP,
-            OutputFormat::Png => ImageFormat::Png,
+            OutputFormat::Jpeg => Some(ImageFormat::Jpeg),
+            OutputFormat::WebP => Some(ImageFormat::WebP),
+            OutputFormat::Png => Some(ImageFormat::Png),
             #[cfg(feature = "avif")]
-            OutputFormat::Avif => ImageFormat::Avif,
+            OutputFormat::Avif => Some(ImageFormat::Avif),
+            #[cfg(feature = "jxl")]
+            OutputFormat::Jxl => None,
         }
     }
 
@@ -38,6 +59,8 @@ impl OutputFormat {
             OutputFormat::Png => "image/png",
             #[cfg(feature = "avif")]
             OutputFormat::Avif => "image/avif",
+            #[cfg(feature = "jxl")]
+            OutputFormat::Jxl => "image/jxl",
         }
     }
 }
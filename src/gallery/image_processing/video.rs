@@ -0,0 +1,501 @@
+use super::placeholder::generate_placeholder;
+use super::workers;
+use crate::gallery::{Gallery, GalleryError, ImageMetadata, VideoInfo};
+use image::{ImageFormat, Rgb, RgbImage};
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, warn};
+
+/// Seconds from the QuickTime epoch (1904-01-01) to the Unix epoch
+const QUICKTIME_EPOCH_OFFSET: u64 = 2_082_844_800;
+/// Larger `moov` boxes aren't read into memory
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+/// Longest side of a drawn placeholder poster, in pixels
+const PLACEHOLDER_POSTER_SIZE: u32 = 1280;
+
+/// What the `moov` box of an MP4 or QuickTime file says about the video
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct VideoProperties {
+    /// Running time in seconds
+    pub duration: Option<f64>,
+    /// Size of the first video track as displayed, after its rotation
+    pub dimensions: Option<(u32, u32)>,
+    pub creation_time: Option<SystemTime>,
+}
+
+/// Offset and length of the contents of the top-level `moov` box, seeking
+/// past the media data so large files aren't read
+fn find_moov(file: &mut std::fs::File) -> Option<(u64, u64)> {
+    let file_len = file.metadata().ok()?.len();
+
+    let mut offset = 0;
+    while offset + 8 <= file_len {
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        let mut header = [0u8; 16];
+        file.read_exact(&mut header[..8]).ok()?;
+        let (size, header_len) = match u32::from_be_bytes(header[..4].try_into().ok()?) {
+            // Extends to the end of the file
+            0 => (file_len - offset, 8),
+            // 64-bit size follows the type
+            1 => {
+                file.read_exact(&mut header[8..]).ok()?;
+                (u64::from_be_bytes(header[8..].try_into().ok()?), 16)
+            }
+            size => (u64::from(size), 8),
+        };
+        if size < header_len {
+            return None;
+        }
+
+        if &header[4..8] == b"moov" {
+            let contents_len = size - header_len;
+            return (contents_len <= MAX_MOOV_SIZE).then_some((offset + header_len, contents_len));
+        }
+        offset = offset.checked_add(size)?;
+    }
+    None
+}
+
+fn read_moov(file: &mut std::fs::File) -> Option<(u64, Vec<u8>)> {
+    let (offset, len) = find_moov(file)?;
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut moov = vec![0; len as usize];
+    file.read_exact(&mut moov).ok()?;
+    Some((offset, moov))
+}
+
+/// Read the properties of an MP4 or QuickTime file
+pub(crate) fn read_video_properties(path: &Path) -> Option<VideoProperties> {
+    let mut file = std::fs::File::open(path).ok()?;
+    let (_, moov) = read_moov(&mut file)?;
+    Some(parse_moov(&moov))
+}
+
+/// Copy a video to `output` without its user data and metadata boxes, where
+/// cameras and phones put the location, device and software
+pub(crate) fn strip_video_metadata(path: &Path, output: &Path) -> Result<(), GalleryError> {
+    use std::io::Write;
+
+    let mut file = std::fs::File::open(path)?;
+    let (offset, mut moov) = read_moov(&mut file).ok_or_else(|| {
+        GalleryError::ProcessingError(format!("No readable movie header in {:?}", path))
+    })?;
+    let len = moov.len();
+    blank_metadata_boxes(&mut moov, 0..len);
+
+    std::fs::copy(path, output)?;
+    let mut copy = std::fs::OpenOptions::new().write(true).open(output)?;
+    copy.seek(SeekFrom::Start(offset))?;
+    copy.write_all(&moov)?;
+    Ok(())
+}
+
+/// Turn the `udta` and `meta` boxes in `range` and in its tracks into zeroed
+/// `free` boxes. Every box keeps its size, so the sample tables' offsets
+/// into the media data stay valid.
+fn blank_metadata_boxes(data: &mut [u8], range: Range<usize>) {
+    for (box_type, start, contents) in child_box_headers(data, range) {
+        match &box_type {
+            b"udta" | b"meta" => {
+                data[start + 4..start + 8].copy_from_slice(b"free");
+                data[contents].fill(0);
+            }
+            b"trak" => blank_metadata_boxes(data, contents),
+            _ => {}
+        }
+    }
+}
+
+/// Properties from a `moov` box's contents
+pub(crate) fn parse_moov(moov: &[u8]) -> VideoProperties {
+    let mut properties = VideoProperties::default();
+    for (box_type, range) in child_boxes(moov, 0..moov.len()) {
+        match &box_type {
+            b"mvhd" => parse_mvhd(&moov[range], &mut properties),
+            b"trak" if properties.dimensions.is_none() => {
+                properties.dimensions = video_track_dimensions(moov, range);
+            }
+            _ => {}
+        }
+    }
+    properties
+}
+
+/// Duration and creation time from the movie header
+fn parse_mvhd(mvhd: &[u8], properties: &mut VideoProperties) {
+    let Some(&version) = mvhd.first() else {
+        return;
+    };
+    // Version 1 widens the times and duration to 64 bits
+    let (creation_time, timescale, duration) = if version == 1 {
+        (
+            read_u64(mvhd, 4),
+            read_u32(mvhd, 20),
+            read_u64(mvhd, 24).filter(|&d| d != u64::MAX),
+        )
+    } else {
+        (
+            read_u32(mvhd, 4).map(u64::from),
+            read_u32(mvhd, 12),
+            read_u32(mvhd, 16).filter(|&d| d != u32::MAX).map(u64::from),
+        )
+    };
+
+    if let (Some(timescale), Some(duration)) = (timescale.filter(|&t| t > 0), duration) {
+        properties.duration = Some(duration as f64 / f64::from(timescale));
+    }
+    // Zero means unset
+    properties.creation_time = creation_time
+        .and_then(|seconds| seconds.checked_sub(QUICKTIME_EPOCH_OFFSET))
+        .filter(|&seconds| seconds > 0)
+        .map(|seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds));
+}
+
+/// Displayed size of a track, if it is a video track
+fn video_track_dimensions(data: &[u8], trak: Range<usize>) -> Option<(u32, u32)> {
+    let children = child_boxes(data, trak);
+
+    let is_video = children
+        .iter()
+        .filter(|(box_type, _)| box_type == b"mdia")
+        .flat_map(|(_, mdia)| child_boxes(data, mdia.clone()))
+        .any(|(box_type, hdlr)| {
+            box_type == *b"hdlr" && data[hdlr].get(8..12) == Some(&b"vide"[..])
+        });
+    if !is_video {
+        return None;
+    }
+
+    let (_, tkhd) = children.iter().find(|(box_type, _)| box_type == b"tkhd")?;
+    let tkhd = &data[tkhd.clone()];
+    // The matrix and size follow the version-dependent times and duration
+    let matrix_start = if *tkhd.first()? == 1 { 52 } else { 40 };
+    let a = read_u32(tkhd, matrix_start)? as i32;
+    let b = read_u32(tkhd, matrix_start + 4)? as i32;
+    // Width and height are 16.16 fixed point
+    let width = read_u32(tkhd, matrix_start + 36)? >> 16;
+    let height = read_u32(tkhd, matrix_start + 40)? >> 16;
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    // Phones record portrait video as landscape with a 90 degree rotation
+    if a == 0 && b != 0 {
+        Some((height, width))
+    } else {
+        Some((width, height))
+    }
+}
+
+/// Child boxes of a box's contents as `(type, contents range)` pairs, with
+/// ranges relative to `data`
+fn child_boxes(data: &[u8], range: Range<usize>) -> Vec<([u8; 4], Range<usize>)> {
+    child_box_headers(data, range)
+        .into_iter()
+        .map(|(box_type, _, contents)| (box_type, contents))
+        .collect()
+}
+
+/// Child boxes as `(type, start, contents range)`, where `start` is the
+/// offset of the box header
+fn child_box_headers(data: &[u8], range: Range<usize>) -> Vec<([u8; 4], usize, Range<usize>)> {
+    let mut boxes = Vec::new();
+    let mut pos = range.start;
+    while pos + 8 <= range.end {
+        let Some(size) = read_u32(data, pos) else {
+            break;
+        };
+        let (size, header_len) = match size {
+            0 => (range.end - pos, 8),
+            1 => match read_u64(data, pos + 8) {
+                Some(size) => (size as usize, 16),
+                None => break,
+            },
+            size => (size as usize, 8),
+        };
+        if size < header_len || size > range.end - pos {
+            break;
+        }
+        let box_type = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+        boxes.push((box_type, pos, pos + header_len..pos + size));
+        pos += size;
+    }
+    boxes
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn read_u64(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+/// Running time as `m:ss`, or `h:mm:ss` for an hour or more
+pub(crate) fn format_duration(seconds: f64) -> String {
+    let total = seconds.round().max(0.0) as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// Playback details for a video item, or `None` for images
+pub(crate) fn video_info(file_name: &str, duration: Option<f64>) -> Option<VideoInfo> {
+    if !crate::gallery::is_video_file(file_name) {
+        return None;
+    }
+
+    // Browsers skip `video/quicktime` sources they would happily play, and
+    // QuickTime files from phones hold the same H.264/HEVC streams as MP4
+    Some(VideoInfo {
+        mime_type: "video/mp4".to_string(),
+        duration: duration.map(format_duration),
+    })
+}
+
+/// Poster for videos without a grabbed frame: a play symbol on a dark
+/// background, in the video's aspect ratio when it's known
+pub(crate) fn placeholder_poster(dimensions: Option<(u32, u32)>) -> RgbImage {
+    let (width, height) = match dimensions.filter(|&(w, h)| w > 0 && h > 0) {
+        Some((w, h)) if w >= h => (
+            PLACEHOLDER_POSTER_SIZE,
+            (u64::from(PLACEHOLDER_POSTER_SIZE) * u64::from(h) / u64::from(w)).max(1) as u32,
+        ),
+        Some((w, h)) => (
+            (u64::from(PLACEHOLDER_POSTER_SIZE) * u64::from(w) / u64::from(h)).max(1) as u32,
+            PLACEHOLDER_POSTER_SIZE,
+        ),
+        None => (PLACEHOLDER_POSTER_SIZE, PLACEHOLDER_POSTER_SIZE * 9 / 16),
+    };
+
+    // A right-pointing triangle a quarter of the shorter side tall
+    let size = f64::from(width.min(height)) / 4.0;
+    let (center_x, center_y) = (f64::from(width) / 2.0, f64::from(height) / 2.0);
+    let (left, right) = (center_x - size * 0.4, center_x + size * 0.6);
+    RgbImage::from_fn(width, height, |x, y| {
+        let (x, y) = (f64::from(x) + 0.5, f64::from(y) + 0.5);
+        let half_height = (right - x) / (right - left) * size / 2.0;
+        if x >= left && x <= right && (y - center_y).abs() <= half_height {
+            Rgb([230, 230, 230])
+        } else {
+            Rgb([40, 40, 40])
+        }
+    })
+}
+
+/// Grab the frame `seek` seconds in as a JPEG, written to `output` only once
+/// ffmpeg has succeeded. ffmpeg counts as one of the image workers.
+async fn extract_frame(
+    ffmpeg: &Path,
+    video_path: &Path,
+    seek: f64,
+    output: &Path,
+) -> Result<(), GalleryError> {
+    let _worker = workers::acquire_worker().await;
+    let partial = crate::atomic_file::temp_path(output);
+    let result = tokio::process::Command::new(ffmpeg)
+        .args(["-nostdin", "-v", "error", "-y", "-ss"])
+        .arg(format!("{:.3}", seek))
+        .arg("-i")
+        .arg(video_path)
+        .args(["-frames:v", "1", "-q:v", "2", "-f", "mjpeg"])
+        .arg(&partial)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output()
+        .await?;
+
+    let written = tokio::fs::metadata(&partial)
+        .await
+        .is_ok_and(|m| m.len() > 0);
+    if !result.status.success() || !written {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(GalleryError::ProcessingError(format!(
+            "ffmpeg exited with {}: {}",
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )));
+    }
+
+    tokio::fs::rename(&partial, output).await?;
+    Ok(())
+}
+
+impl Gallery {
+    /// Cache file names a video's poster can have. Grabbed and drawn posters
+    /// are cached apart so configuring ffmpeg replaces drawn ones.
+    pub(crate) fn poster_cache_filenames(&self, relative_path: &str) -> [String; 2] {
+        ["poster-frame", "poster-placeholder"]
+            .map(|kind| self.generate_cache_filename(relative_path, kind, "jpg", false))
+    }
+
+    /// JPEG poster standing in for a video wherever an image is needed:
+    /// a frame grabbed with the configured `ffmpeg`, or a drawn placeholder
+    pub(crate) async fn video_poster(
+        &self,
+        video_path: &Path,
+        relative_path: &str,
+    ) -> Result<PathBuf, GalleryError> {
+        let [frame_filename, placeholder_filename] = self.poster_cache_filenames(relative_path);
+        let filename = if self.config.ffmpeg_path.is_some() {
+            frame_filename
+        } else {
+            placeholder_filename
+        };
+        let cache_path = self.config.cache_directory.join(&filename);
+        if self.is_cache_valid(&cache_path, video_path).await? {
+            self.note_cache_hit(&filename, relative_path).await;
+            return Ok(cache_path);
+        }
+
+        let _flight = workers::claim(&cache_path).await;
+        if self.is_cache_valid(&cache_path, video_path).await? {
+            self.note_cache_hit(&filename, relative_path).await;
+            return Ok(cache_path);
+        }
+
+        tokio::fs::create_dir_all(&self.config.cache_directory).await?;
+        let properties_path = video_path.to_path_buf();
+        let properties =
+            tokio::task::spawn_blocking(move || read_video_properties(&properties_path))
+                .await?
+                .unwrap_or_default();
+
+        if let Some(ffmpeg) = &self.config.ffmpeg_path {
+            // A little way in skips fade-ins, without passing the end of
+            // short clips
+            let seek = properties.duration.map_or(0.0, |d| (d / 10.0).min(1.0));
+            match extract_frame(ffmpeg, video_path, seek, &cache_path).await {
+                Ok(()) => {
+                    debug!("Extracted poster frame for {}", relative_path);
+                    self.track_cached_file(&filename, relative_path).await;
+                    return Ok(cache_path);
+                }
+                Err(e) => warn!("Using a placeholder poster for {}: {}", relative_path, e),
+            }
+        }
+
+        workers::write_atomically(&cache_path, move |temp_path| {
+            placeholder_poster(properties.dimensions)
+                .save_with_format(temp_path, ImageFormat::Jpeg)?;
+            Ok(())
+        })
+        .await?;
+
+        self.track_cached_file(&filename, relative_path).await;
+        Ok(cache_path)
+    }
+
+    /// Metadata of a video: what its container records, plus a loading
+    /// placeholder made from its poster
+    pub(crate) async fn extract_video_metadata(
+        &self,
+        path: &Path,
+    ) -> Result<ImageMetadata, GalleryError> {
+        let properties_path = path.to_path_buf();
+        let properties =
+            tokio::task::spawn_blocking(move || read_video_properties(&properties_path))
+                .await?
+                .unwrap_or_default();
+        let modification_date = tokio::fs::metadata(path).await?.modified().ok();
+
+        let relative_path = path
+            .strip_prefix(&self.config.source_directory)
+            .map_err(|_| GalleryError::InvalidPath)?
+            .to_string_lossy()
+            .to_string();
+        let poster = match self.video_poster(path, &relative_path).await {
+            Ok(poster) => Some(poster),
+            Err(e) => {
+                warn!("No poster for {}: {}", relative_path, e);
+                None
+            }
+        };
+
+        let dimensions = properties.dimensions;
+        let (dimensions, placeholder) = workers::run_blocking(move || {
+            let Some(poster) = poster else {
+                return (dimensions, None);
+            };
+            let dimensions = dimensions.or_else(|| image::image_dimensions(&poster).ok());
+            (dimensions, generate_placeholder(&poster))
+        })
+        .await?;
+
+        Ok(ImageMetadata {
+            dimensions: dimensions.unwrap_or((0, 0)),
+            capture_date: properties.creation_time,
+            camera_info: None,
+            location_info: None,
+            modification_date,
+            color_profile: None,
+            placeholder,
+            descriptive_info: None,
+            duration: properties.duration,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(box_type: &[u8; 4], contents: &[u8]) -> Vec<u8> {
+        let mut data = ((contents.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(box_type);
+        data.extend_from_slice(contents);
+        data
+    }
+
+    #[test]
+    fn test_strip_video_metadata() {
+        let location = mp4_box(b"\xA9xyz", b"+52.3700+004.8900/");
+        let trak = [mp4_box(b"tkhd", &[0; 84]), mp4_box(b"udta", &location)].concat();
+        let moov = [
+            mp4_box(b"mvhd", &[0; 100]),
+            mp4_box(b"trak", &trak),
+            mp4_box(b"udta", &location),
+            mp4_box(b"meta", b"location"),
+        ]
+        .concat();
+        let mdat = mp4_box(b"mdat", b"frames");
+        let file = [
+            mp4_box(b"ftyp", b"isom"),
+            mp4_box(b"moov", &moov),
+            mdat.clone(),
+        ]
+        .concat();
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let (source, output) = (dir.path().join("in.mp4"), dir.path().join("out.mp4"));
+        std::fs::write(&source, &file).unwrap();
+        strip_video_metadata(&source, &output).unwrap();
+
+        let stripped = std::fs::read(&output).unwrap();
+        assert_eq!(stripped.len(), file.len());
+        assert!(stripped.ends_with(&mdat));
+        assert!(
+            !stripped
+                .windows(4)
+                .any(|window| window == b"udta" || window == b"meta")
+        );
+        assert!(!stripped.windows(4).any(|window| window == b"+52."));
+        assert!(stripped.windows(4).any(|window| window == b"tkhd"));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0.4), "0:00");
+        assert_eq!(format_duration(7.6), "0:08");
+        assert_eq!(format_duration(754.0), "12:34");
+        assert_eq!(format_duration(3723.0), "1:02:03");
+    }
+}
@@ -0,0 +1,194 @@
+// Shared limits for image work: a bounded pool for decoding and encoding, and
+// single-flight generation of cache files
+use crate::atomic_file::temp_path;
+use crate::gallery::GalleryError;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
+use tokio::sync::{OwnedMutexGuard, OwnedSemaphorePermit, Semaphore};
+
+/// Image decodes and encodes allowed at once, shared by every gallery
+static WORKERS: OnceLock<Arc<Semaphore>> = OnceLock::new();
+
+/// Cache files being generated right now, so concurrent requests for the same
+/// file wait for it instead of making their own
+static IN_FLIGHT: LazyLock<Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(Default::default);
+
+/// Files written by `write_atomically`, so tests can tell how often each one
+/// was generated
+#[cfg(test)]
+pub(crate) static WRITES: LazyLock<Mutex<HashMap<PathBuf, usize>>> =
+    LazyLock::new(Default::default);
+
+/// Leave a core free for handling requests
+fn default_workers() -> usize {
+    std::thread::available_parallelism()
+        .map_or(1, |cores| cores.get().saturating_sub(1))
+        .max(1)
+}
+
+/// Size the worker pool. Only the first call counts, so it has to come before
+/// any image is processed.
+pub(crate) fn configure_workers(workers: Option<usize>) {
+    let workers = workers.unwrap_or_else(default_workers).max(1);
+    let _ = WORKERS.set(Arc::new(Semaphore::new(workers)));
+}
+
+/// Wait for a free worker. Image work runs while the permit is held.
+pub(crate) async fn acquire_worker() -> OwnedSemaphorePermit {
+    WORKERS
+        .get_or_init(|| Arc::new(Semaphore::new(default_workers())))
+        .clone()
+        .acquire_owned()
+        .await
+        .expect("the worker pool is never closed")
+}
+
+/// Run CPU-heavy image work on the blocking thread pool once a worker is
+/// free. The worker stays busy until the work is done, even if the caller
+/// stops waiting.
+pub(crate) async fn run_blocking<T, F>(work: F) -> Result<T, tokio::task::JoinError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let permit = acquire_worker().await;
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        work()
+    })
+    .await
+}
+
+/// Held while a cache file is generated
+pub(crate) struct Flight {
+    path: PathBuf,
+    _lock: OwnedMutexGuard<()>,
+}
+
+/// Wait until nobody else is generating the cache file at `path`, then claim
+/// it. Check the cache again once claimed, since whoever held it before has
+/// usually just written the file.
+pub(crate) async fn claim(path: &Path) -> Flight {
+    let lock = IN_FLIGHT
+        .lock()
+        .unwrap()
+        .entry(path.to_path_buf())
+        .or_default()
+        .clone();
+    Flight {
+        path: path.to_path_buf(),
+        _lock: lock.lock_owned().await,
+    }
+}
+
+impl Drop for Flight {
+    fn drop(&mut self) {
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        // Only the map and this claim hold the lock when nobody is waiting
+        if in_flight
+            .get(&self.path)
+            .is_some_and(|lock| Arc::strong_count(lock) <= 2)
+        {
+            in_flight.remove(&self.path);
+        }
+    }
+}
+
+/// Produce a cache file with `write` on a worker, writing to a temporary file
+/// that is renamed into place once complete, so a half-written file is never
+/// served
+pub(crate) async fn write_atomically<F>(path: &Path, write: F) -> Result<(), GalleryError>
+where
+    F: FnOnce(&Path) -> Result<(), GalleryError> + Send + 'static,
+{
+    #[cfg(test)]
+    {
+        *WRITES
+            .lock()
+            .unwrap()
+            .entry(path.to_path_buf())
+            .or_default() += 1;
+    }
+
+    let temp = temp_path(path);
+    let temp_clone = temp.clone();
+    let result = match run_blocking(move || write(&temp_clone)).await {
+        Ok(result) => result,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(()) => Ok(tokio::fs::rename(&temp, path).await?),
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&temp).await;
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_claim_waits_for_holder() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("claimed.jpg");
+        let first = claim(&path).await;
+
+        let (claimed_tx, mut claimed_rx) = tokio::sync::oneshot::channel();
+        let waiter = tokio::spawn({
+            let path = path.clone();
+            async move {
+                let _flight = claim(&path).await;
+                let _ = claimed_tx.send(());
+            }
+        });
+        // The map, the first claim and the waiter share the lock once the
+        // waiter has queued up behind it
+        while IN_FLIGHT
+            .lock()
+            .unwrap()
+            .get(&path)
+            .map_or(0, Arc::strong_count)
+            < 3
+        {
+            tokio::task::yield_now().await;
+        }
+        tokio::task::yield_now().await;
+        assert!(claimed_rx.try_recv().is_err());
+
+        drop(first);
+        claimed_rx.await.unwrap();
+        waiter.await.unwrap();
+        // The last claim cleans up after itself
+        assert!(!IN_FLIGHT.lock().unwrap().contains_key(&path));
+    }
+
+    #[tokio::test]
+    async fn test_write_atomically() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.jpg");
+
+        write_atomically(&path, |temp| {
+            assert!(temp.to_string_lossy().ends_with(".tmp"));
+            std::fs::write(temp, b"done")?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"done");
+
+        // A failed write leaves neither the file nor its temporary behind
+        let failed = temp_dir.path().join("failed.jpg");
+        let result = write_atomically(&failed, |temp| {
+            std::fs::write(temp, b"partial")?;
+            Err(GalleryError::ProcessingError("encoder failed".to_string()))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+}
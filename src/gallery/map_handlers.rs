@@ -0,0 +1,175 @@
+use super::geo::{MAX_ZOOM, cluster_images};
+use super::handlers::{api_access_denied, get_authenticated_user, get_share, location_precision};
+use super::{LocationInfo, MapQuery};
+use crate::AppState;
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse},
+};
+use std::collections::HashMap;
+use tracing::error;
+
+const MAP_TEMPLATE: &str = "modules/map.html.liquid";
+
+/// GeoJSON of the geotagged images in a folder and its subfolders, with
+/// coordinates reduced to the precision the user may see. With `?zoom=` the
+/// points are clustered for that map zoom level.
+pub async fn geojson_api_handler_for_named(
+    State(app_state): State<AppState>,
+    Path((gallery_name, path)): Path<(String, String)>,
+    Query(query): Query<MapQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(gallery) = app_state.galleries.get(&gallery_name) else {
+        error!("Gallery '{}' not found", gallery_name);
+        return (StatusCode::NOT_FOUND, "Gallery not found").into_response();
+    };
+
+    let path = path.trim_matches('/').to_string();
+    let user = get_authenticated_user(&app_state, &headers).await;
+    let share = get_share(&app_state, &gallery_name, &headers, &path).await;
+    if !gallery
+        .check_path_access(&path, user.as_ref(), share.as_ref())
+        .await
+    {
+        return api_access_denied(gallery, &path, user.as_ref()).await;
+    }
+
+    let images = match gallery
+        .located_images_in(&path, user.as_ref(), share.as_ref())
+        .await
+    {
+        Ok(images) => images,
+        Err(_) => return (StatusCode::NOT_FOUND, "Directory not found").into_response(),
+    };
+
+    // Precision depends on download permission, which can vary by folder.
+    // Rounding happens before clustering so clusters can't reveal more.
+    let mut folder_precision = HashMap::new();
+    let mut visible = Vec::with_capacity(images.len());
+    for mut image in images {
+        let folder = image
+            .path
+            .rsplit_once('/')
+            .map(|(parent, _)| parent)
+            .unwrap_or("")
+            .to_string();
+        let precision = match folder_precision.get(&folder) {
+            Some(precision) => *precision,
+            None => {
+                let precision =
+                    location_precision(&app_state, gallery, &folder, user.as_ref(), share.as_ref())
+                        .await;
+                folder_precision.insert(folder, precision);
+                precision
+            }
+        };
+
+        if let Some(location) =
+            LocationInfo::new(image.latitude, image.longitude).with_precision(precision)
+        {
+            image.latitude = location.latitude;
+            image.longitude = location.longitude;
+            visible.push(image);
+        }
+    }
+
+    let zoom = query.zoom.map(|zoom| zoom.min(MAX_ZOOM));
+    Json(gallery.geo_features(cluster_images(visible, zoom))).into_response()
+}
+
+/// Percent-encode each segment of a folder or image path for use in a URL,
+/// keeping the slashes between them
+pub(crate) fn encode_folder_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| urlencoding::encode(segment).into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Handler for `{prefix}/map` and `{prefix}/map/{path}`: a map of the
+/// geotagged images in a folder, loaded from the GeoJSON endpoint
+pub async fn map_handler_for_named(
+    State(app_state): State<AppState>,
+    Path((gallery_name, path)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(gallery) = app_state.galleries.get(&gallery_name) else {
+        error!("Gallery '{}' not found", gallery_name);
+        return (StatusCode::NOT_FOUND, "Gallery not found").into_response();
+    };
+
+    let path = path.trim_matches('/').to_string();
+    let user = get_authenticated_user(&app_state, &headers).await;
+    let share = get_share(&app_state, &gallery_name, &headers, &path).await;
+    let gallery_config = gallery.get_config();
+    if !gallery
+        .check_path_access(&path, user.as_ref(), share.as_ref())
+        .await
+    {
+        if user.is_none() && gallery.is_folder_access_restricted(&path).await {
+            let return_url = if path.is_empty() {
+                format!("{}/map", gallery_config.url_prefix)
+            } else {
+                format!(
+                    "{}/map/{}",
+                    gallery_config.url_prefix,
+                    encode_folder_path(&path)
+                )
+            };
+            let login_url = format!("/_login?return={}", urlencoding::encode(&return_url));
+            return axum::response::Redirect::temporary(&login_url).into_response();
+        }
+        return (StatusCode::FORBIDDEN, "Access denied").into_response();
+    }
+
+    if path.split('/').any(|part| part == "..")
+        || !gallery_config.source_directory.join(&path).is_dir()
+    {
+        return (StatusCode::NOT_FOUND, "Directory not found").into_response();
+    }
+
+    let breadcrumbs = gallery.build_breadcrumbs_with_mode(&path, true).await;
+    let title = breadcrumbs
+        .last()
+        .map(|crumb| crumb.display_name.clone())
+        .unwrap_or_default();
+    let page_title = format!("Map of {}", title);
+
+    let geojson_url = if path.is_empty() {
+        format!("/api/gallery/{}/geo", gallery_name)
+    } else {
+        format!(
+            "/api/gallery/{}/geo/{}",
+            gallery_name,
+            encode_folder_path(&path)
+        )
+    };
+
+    let liquid_context = liquid::object!({
+        "gallery_name": gallery_name,
+        "gallery_url": gallery_config.url_prefix,
+        "gallery_path": path,
+        "breadcrumbs": breadcrumbs,
+        "geojson_url": geojson_url,
+        "page_title": page_title,
+        "meta_description": format!("Where the photos in {} were taken", title),
+        "app_name": app_state.config.app.name,
+        "copyright_holder": gallery.config.copyright_holder,
+        "base_url": app_state.config.app.base_url,
+    });
+
+    match app_state
+        .template_engine
+        .render_template(MAP_TEMPLATE, liquid_context)
+        .await
+    {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => {
+            error!("Template rendering error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
+        }
+    }
+}
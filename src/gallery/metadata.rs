@@ -46,6 +46,33 @@ impl Gallery {
                     }
                 }
             }
+            #[cfg(feature = "jxl")]
+            Some("jxl") => {
+                // JPEG XL keeps EXIF in a box of its own
+                let exif_bytes = std::fs::read(image_path).ok().and_then(|data| {
+                    super::image_processing::formats::jxl_container::metadata_blocks(&data).exif
+                });
+                match exif_bytes.map(|bytes| rexif::parse_buffer(&bytes)) {
+                    Some(Ok(exif_data)) => {
+                        let capture_date = self.extract_capture_date(&exif_data);
+                        let camera_info = self.extract_camera_info(&exif_data);
+                        let location_info = self.extract_location_info(&exif_data);
+                        (capture_date, camera_info, location_info)
+                    }
+                    Some(Err(e)) => {
+                        trace!(
+                            "Failed to parse EXIF data from JPEG XL {}: {}",
+                            image_path.display(),
+                            e
+                        );
+                        (None, None, None)
+                    }
+                    None => {
+                        trace!("No EXIF data found in JPEG XL: {}", image_path.display());
+                        (None, None, None)
+                    }
+                }
+            }
             _ => {
                 // For other formats (JPEG, etc), use rexif's file parser
                 match rexif::parse_file(image_path) {
@@ -231,12 +258,7 @@ impl Gallery {
                 lon = -lon;
             }
 
-            Some(LocationInfo {
-                latitude: lat,
-                longitude: lon,
-                google_maps_url: format!("https://maps.google.com/?q={},{}", lat, lon),
-                apple_maps_url: format!("https://maps.apple.com/?ll={},{}", lat, lon),
-            })
+            Some(LocationInfo::new(lat, lon))
         } else {
             None
         }
@@ -297,6 +319,8 @@ impl Gallery {
                     .store(true, std::sync::atomic::Ordering::Relaxed);
                 debug!("Removed deleted image from cache: {}", relative_path);
             }
+            drop(cache);
+            self.remove_from_search_index(relative_path).await;
             return Ok(());
         }
 
@@ -310,6 +334,81 @@ impl Gallery {
         Ok(())
     }
 
+    /// Bring the cache in line with a single changed path, as reported by the
+    /// filesystem watcher. The path may be an image, a folder, or something that
+    /// has just been deleted.
+    pub async fn refresh_path(&self, relative_path: &str) -> Result<(), super::GalleryError> {
+        use walkdir::WalkDir;
+
+        let full_path = self.config.source_directory.join(relative_path);
+
+        if full_path.is_dir() {
+            for entry in WalkDir::new(&full_path)
+                .follow_links(true)
+                .into_iter()
+                .filter_map(|e| e.ok())
+            {
+                let path = entry.path();
+                if path.is_file()
+                    && self.is_image(&path.file_name().unwrap_or_default().to_string_lossy())
+                    && let Ok(relative) = path.strip_prefix(&self.config.source_directory)
+                {
+                    let relative_str = relative.to_string_lossy().to_string();
+                    self.evict_cached_images(&relative_str).await;
+                    self.refresh_single_image_metadata(&relative_str).await?;
+                }
+            }
+            return Ok(());
+        }
+
+        // Captions and folder descriptions only feed the search index
+        if relative_path.ends_with(".md") {
+            self.refresh_search_for_markdown(relative_path).await;
+            return Ok(());
+        }
+
+        if full_path.exists() {
+            // Other non-image files don't affect the cache
+            if self.is_image(relative_path) {
+                self.evict_cached_images(relative_path).await;
+                self.refresh_single_image_metadata(relative_path).await?;
+            }
+            return Ok(());
+        }
+
+        // The path is gone: drop it and anything that lived beneath it
+        let folder_prefix = format!("{}/", relative_path);
+        let removed: Vec<String> = {
+            let mut cache = self.metadata_cache.write().await;
+            let removed: Vec<String> = cache
+                .keys()
+                .filter(|key| *key == relative_path || key.starts_with(&folder_prefix))
+                .cloned()
+                .collect();
+            for key in &removed {
+                cache.remove(key);
+            }
+            removed
+        };
+
+        if !removed.is_empty() {
+            self.metadata_cache_dirty
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+            debug!(
+                "Removed {} deleted images under {} from cache",
+                removed.len(),
+                relative_path
+            );
+        }
+
+        for key in &removed {
+            self.evict_cached_images(key).await;
+            self.remove_from_search_index(key).await;
+        }
+
+        Ok(())
+    }
+
     pub async fn refresh_directory_metadata(
         &self,
         directory_path: &str,
@@ -399,6 +498,10 @@ impl Gallery {
         &self,
         path: &Path,
     ) -> Result<ImageMetadata, super::GalleryError> {
+        if super::is_video_file(&path.to_string_lossy()) {
+            return self.extract_video_metadata(path).await;
+        }
+
         // Get image dimensions
         #[allow(unused_variables)] // ext is used conditionally based on features
         let ext = path
@@ -407,6 +510,11 @@ impl Gallery {
             .map(|s| s.to_lowercase());
         let dimensions = match image::image_dimensions(path) {
             Ok((w, h)) => (w, h),
+            // JPEG XL headers hold the size, already as displayed
+            #[cfg(feature = "jxl")]
+            Err(_) if ext.as_deref() == Some("jxl") => {
+                super::image_processing::formats::jxl::extract_dimensions(path).unwrap_or((0, 0))
+            }
             Err(_) => {
                 // For AVIF, try our custom dimension extraction
                 #[cfg(feature = "avif")]
@@ -423,6 +531,10 @@ impl Gallery {
             }
         };
 
+        // Report dimensions as displayed, after EXIF rotation
+        let dimensions = super::image_processing::orientation::read_orientation(path)
+            .oriented_dimensions(dimensions);
+
         // Extract EXIF data
         let (capture_date, camera_info, location_info) = self.extract_all_exif_data(path).await;
 
@@ -455,9 +567,34 @@ impl Gallery {
                 // For AVIF files, generate a descriptive color space string
                 super::image_processing::formats::avif::extract_color_description(path)
             }
+            #[cfg(feature = "jxl")]
+            Some("jxl") => super::image_processing::extract_icc_profile_from_jxl(path)
+                .and_then(|icc_data| super::image_processing::extract_icc_profile_name(&icc_data)),
             _ => None,
         };
 
+        // Decode a tiny copy for the loading placeholder
+        let placeholder_path = path.to_path_buf();
+        let placeholder = super::image_processing::workers::run_blocking(move || {
+            super::image_processing::placeholder::generate_placeholder(&placeholder_path)
+        })
+        .await
+        .unwrap_or_else(|e| {
+            error!("Placeholder generation task failed: {}", e);
+            None
+        });
+
+        // Titles, captions and keywords written by Lightroom and friends
+        let descriptive_path = path.to_path_buf();
+        let descriptive_info = tokio::task::spawn_blocking(move || {
+            super::embedded_metadata::read_descriptive_info(&descriptive_path)
+        })
+        .await
+        .unwrap_or_else(|e| {
+            error!("Descriptive metadata task failed: {}", e);
+            None
+        });
+
         Ok(ImageMetadata {
             dimensions,
             capture_date,
@@ -465,6 +602,9 @@ impl Gallery {
             location_info,
             modification_date,
             color_profile,
+            placeholder,
+            descriptive_info,
+            duration: None,
         })
     }
 
@@ -475,6 +615,8 @@ impl Gallery {
     ) {
         use std::sync::atomic::Ordering;
 
+        self.update_search_index(&path, &metadata).await;
+
         let mut cache = self.metadata_cache.write().await;
         cache.insert(path, metadata);
 
@@ -547,9 +689,17 @@ mod tests {
             jpeg_quality: Some(85),
             webp_quality: Some(85.0),
             pregenerate_cache: false,
+            pregenerate_parallelism: None,
             new_threshold_days: None,
             approximate_dates_for_public: false,
+            location_precision: Default::default(),
+            location_precision_for_public: Default::default(),
+            metadata: Default::default(),
+            ffmpeg_path: None,
+            max_cache_size: None,
             copyright_holder: None,
+            watch_filesystem: false,
+            sizes: Default::default(),
         };
 
         let gallery = Gallery::new(gallery_config);
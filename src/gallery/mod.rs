@@ -1,18 +1,47 @@
 // Gallery module - Main entry point
+mod archive;
 mod cache;
+mod cache_index;
 mod core;
+mod embedded_metadata;
 mod error;
+mod geo;
 mod handlers;
 pub mod image_processing;
+mod map_handlers;
 mod metadata;
+mod pregenerate;
+mod search;
+mod share;
+mod share_handlers;
+mod tag_handlers;
+mod tags;
 mod types;
 
 // Re-export public items
+pub use self::core::BreadcrumbItem;
+pub use cache_index::{CacheCleanup, CacheStats};
 pub use error::GalleryError;
+pub use geo::{GeoFeature, GeoFeatureCollection, GeoPoint, GeoProperties};
 pub use handlers::{
-    gallery_handler_for_named, gallery_root_handler_for_named, image_detail_handler_for_named,
-    image_handler_for_named,
+    ArchiveQuery, GalleryListResponse, ImageInfoResponse, folder_archive_handler_for_named,
+    gallery_handler_for_named, gallery_list_api_handler_for_named, gallery_root_handler_for_named,
+    image_detail_handler_for_named, image_handler_for_named, image_info_api_handler_for_named,
 };
+pub(crate) use handlers::{get_authenticated_user, has_download_permission};
+pub(crate) use map_handlers::encode_folder_path;
+pub use map_handlers::{geojson_api_handler_for_named, map_handler_for_named};
+pub(crate) use pregenerate::LiveRequest;
+pub use pregenerate::{PregenerateProgress, PregenerateState, pregenerate_progress_handler};
+pub use search::SearchHit;
+pub use share::{
+    SHARE_GRANTS_COOKIE, Share, ShareKind, ShareStore, detect_share_kind, share_token, share_url,
+};
+pub use share_handlers::{
+    create_share_handler, list_shares_handler, open_share_handler, revoke_share_handler,
+    unlock_share_handler,
+};
+pub use tag_handlers::{tag_handler_for_named, tag_index_handler_for_named};
 pub use types::*;
 
 use std::{
@@ -29,12 +58,38 @@ use tracing::info;
 
 pub type SharedGallery = Arc<Gallery>;
 
+pub(crate) fn is_image_file(file_name: &str) -> bool {
+    let lower = file_name.to_lowercase();
+    lower.ends_with(".jpg")
+        || lower.ends_with(".jpeg")
+        || lower.ends_with(".png")
+        || lower.ends_with(".gif")
+        || lower.ends_with(".webp")
+        || lower.ends_with(".bmp")
+        || lower.ends_with(".avif")
+        // Only listed when they can be decoded
+        || (cfg!(feature = "jxl") && lower.ends_with(".jxl"))
+}
+
+/// Videos shown alongside images. Only ISO media files (MP4 and QuickTime),
+/// whose duration and size can be read without external tools.
+pub(crate) fn is_video_file(file_name: &str) -> bool {
+    let lower = file_name.to_lowercase();
+    lower.ends_with(".mp4") || lower.ends_with(".m4v") || lower.ends_with(".mov")
+}
+
+/// Clones share every cache with the original, so one can be handed to a
+/// blocking task
+#[derive(Clone)]
 pub struct Gallery {
     pub(crate) config: crate::GallerySystemConfig,
     pub(crate) metadata_cache: Arc<RwLock<HashMap<String, ImageMetadata>>>,
     pub(crate) cache_metadata: Arc<RwLock<CacheMetadata>>,
     pub(crate) metadata_cache_dirty: Arc<AtomicBool>,
     pub(crate) metadata_updates_since_save: Arc<AtomicUsize>,
+    pub(crate) search_index: Arc<RwLock<search::SearchIndex>>,
+    pub(crate) cache_index: Arc<RwLock<cache_index::CacheIndex>>,
+    pub(crate) cache_index_dirty: Arc<AtomicBool>,
 }
 
 impl Gallery {
@@ -45,6 +100,7 @@ impl Gallery {
                 version: String::new(), // Empty version will trigger full refresh
                 last_full_refresh: SystemTime::UNIX_EPOCH,
             });
+        let cache_index = cache_index::load_cache_index(&config).unwrap_or_default();
 
         Self {
             config,
@@ -52,18 +108,15 @@ impl Gallery {
             cache_metadata: Arc::new(RwLock::new(cache_metadata)),
             metadata_cache_dirty: Arc::new(AtomicBool::new(false)),
             metadata_updates_since_save: Arc::new(AtomicUsize::new(0)),
+            search_index: Arc::new(RwLock::new(search::SearchIndex::default())),
+            cache_index: Arc::new(RwLock::new(cache_index)),
+            cache_index_dirty: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// Whether a file is a gallery item: an image or a video
     pub(crate) fn is_image(&self, file_name: &str) -> bool {
-        let lower = file_name.to_lowercase();
-        lower.ends_with(".jpg")
-            || lower.ends_with(".jpeg")
-            || lower.ends_with(".png")
-            || lower.ends_with(".gif")
-            || lower.ends_with(".webp")
-            || lower.ends_with(".bmp")
-            || lower.ends_with(".avif")
+        is_image_file(file_name) || is_video_file(file_name)
     }
 
     pub fn source_directory(&self) -> &std::path::Path {
@@ -96,6 +149,12 @@ impl Gallery {
         // First refresh metadata
         self.clone().refresh_all_metadata().await?;
 
+        // Clear out derivatives of images that have gone while we weren't
+        // watching
+        if let Err(e) = self.collect_garbage().await {
+            error!("Failed to clean up the image cache: {}", e);
+        }
+
         // Then optionally pre-generate cache in background
         if pregenerate {
             info!("Spawning background task for cache pre-generation");
@@ -0,0 +1,525 @@
+// Cache pre-generation: passes over every image, one size and format at a
+// time with the smallest size first, run a few images at a time, checkpointed
+// to disk and held back while live requests are being served
+use super::image_processing::OutputFormat;
+use super::{Gallery, GalleryError};
+use crate::AppState;
+use crate::atomic_file::write_atomically;
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tracing::{debug, error, info};
+
+/// Where an unfinished run is recorded, so a restart resumes it
+const CHECKPOINT_FILE: &str = "pregenerate_progress.json";
+
+/// Images pre-generated at once when `pregenerate_parallelism` isn't set
+const DEFAULT_PARALLELISM: usize = 2;
+
+/// Images finished between checkpoint saves
+const CHECKPOINT_INTERVAL: usize = 100;
+
+/// How often a paused run checks whether the server has gone quiet
+const BUSY_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Requests in flight at once that pause a run. One visitor browsing
+/// shouldn't stop it every few seconds.
+const BUSY_REQUESTS: usize = 2;
+
+/// How long the server has to stay below `BUSY_REQUESTS` before a paused run
+/// carries on, so it doesn't start and stop between bursts of requests
+const QUIET_PERIOD: Duration = Duration::from_secs(1);
+
+/// Requests being handled right now, across all galleries
+static LIVE_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Each gallery's run by gallery name. Kept outside `Gallery`, since the
+/// instance running pre-generation isn't the one serving the admin endpoint.
+static PROGRESS: LazyLock<Mutex<HashMap<String, PregenerateProgress>>> =
+    LazyLock::new(Default::default);
+
+/// Counts as a live request until dropped
+pub(crate) struct LiveRequest(());
+
+impl LiveRequest {
+    pub(crate) fn start() -> Self {
+        LIVE_REQUESTS.fetch_add(1, Ordering::Relaxed);
+        LiveRequest(())
+    }
+}
+
+impl Drop for LiveRequest {
+    fn drop(&mut self) {
+        LIVE_REQUESTS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PregenerateState {
+    #[default]
+    Idle,
+    Running,
+    /// Waiting for live requests to finish
+    Paused,
+    Completed,
+}
+
+/// Progress of a gallery's pre-generation run, as reported to admins
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PregenerateProgress {
+    pub state: PregenerateState,
+    /// Passes (one size in one format over every image) finished, counting
+    /// those finished before a restart
+    pub passes_done: usize,
+    pub passes_total: usize,
+    /// Size and format of the pass under way
+    pub size: Option<String>,
+    pub format: Option<String>,
+    /// Images the pass under way has finished, out of `images`
+    pub images_done: usize,
+    pub images: usize,
+    /// Images that couldn't be generated during this run
+    pub failed: usize,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Checkpoint {
+    /// Passes finished for every image
+    completed: BTreeSet<String>,
+    /// The pass under way
+    current: Option<String>,
+    /// The last image, in path order, that the pass under way has finished
+    /// along with every image before it
+    done_through: Option<String>,
+}
+
+/// One size in one format, generated for every image
+struct Pass {
+    id: String,
+    size: String,
+    format: OutputFormat,
+}
+
+fn update_progress(gallery_name: &str, update: impl FnOnce(&mut PregenerateProgress)) {
+    update(
+        PROGRESS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(gallery_name.to_string())
+            .or_default(),
+    );
+}
+
+/// Sets a run's final state when dropped, so a run that panics or is
+/// cancelled goes back to idle instead of staying running for good
+struct RunGuard {
+    gallery_name: String,
+    state: PregenerateState,
+}
+
+impl RunGuard {
+    fn new(gallery_name: &str) -> Self {
+        RunGuard {
+            gallery_name: gallery_name.to_string(),
+            state: PregenerateState::Idle,
+        }
+    }
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        let state = self.state;
+        update_progress(&self.gallery_name, |progress| progress.state = state);
+    }
+}
+
+impl Gallery {
+    /// Every size in every format, smallest size first so thumbnails are
+    /// ready before anything else
+    fn pregenerate_passes(&self) -> Vec<Pass> {
+        let mut sizes: Vec<(u64, String)> = self
+            .config
+            .image_sizes()
+            .into_iter()
+            .map(|(name, size)| (u64::from(size.width) * u64::from(size.height), name))
+            .collect();
+        sizes.sort();
+
+        let formats = [
+            OutputFormat::Jpeg,
+            OutputFormat::WebP,
+            #[cfg(feature = "avif")]
+            OutputFormat::Avif,
+            #[cfg(feature = "jxl")]
+            OutputFormat::Jxl,
+        ];
+
+        let mut passes = Vec::new();
+        for (_, size) in sizes {
+            for format in formats {
+                passes.push(Pass {
+                    // Follows the settings the files are made with, so
+                    // changing them in the config runs the pass again
+                    id: self.generate_image_cache_key("", &size, format.extension(), true),
+                    size: size.clone(),
+                    format,
+                });
+            }
+        }
+        passes
+    }
+
+    fn checkpoint_path(&self) -> PathBuf {
+        self.config.cache_directory.join(CHECKPOINT_FILE)
+    }
+
+    /// Whether a run was interrupted before it finished
+    pub fn pregeneration_interrupted(&self) -> bool {
+        self.checkpoint_path().exists()
+    }
+
+    async fn load_checkpoint(&self) -> Checkpoint {
+        match tokio::fs::read_to_string(self.checkpoint_path()).await {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_default(),
+            Err(_) => Checkpoint::default(),
+        }
+    }
+
+    async fn save_checkpoint(&self, checkpoint: &Checkpoint) {
+        let result: Result<(), GalleryError> = async {
+            let json = serde_json::to_string(checkpoint)?;
+            write_atomically(&self.checkpoint_path(), json.as_bytes()).await?;
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            error!("Failed to save cache pre-generation progress: {}", e);
+        }
+    }
+
+    /// Progress of this gallery's pre-generation. Between runs, how far the
+    /// last unfinished one got.
+    pub async fn pregenerate_progress(&self) -> PregenerateProgress {
+        let progress = PROGRESS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&self.config.name)
+            .cloned();
+        if let Some(progress) = progress {
+            return progress;
+        }
+
+        let passes = self.pregenerate_passes();
+        let checkpoint = self.load_checkpoint().await;
+        PregenerateProgress {
+            passes_done: passes
+                .iter()
+                .filter(|pass| checkpoint.completed.contains(&pass.id))
+                .count(),
+            passes_total: passes.len(),
+            ..Default::default()
+        }
+    }
+
+    /// Hold off while the server is busy with requests, which need the image
+    /// workers more than pre-generation does
+    async fn wait_until_quiet(&self) {
+        if LIVE_REQUESTS.load(Ordering::Relaxed) < BUSY_REQUESTS {
+            return;
+        }
+
+        update_progress(&self.config.name, |progress| {
+            progress.state = PregenerateState::Paused
+        });
+        let mut quiet_since = None;
+        loop {
+            tokio::time::sleep(BUSY_POLL_INTERVAL).await;
+            if LIVE_REQUESTS.load(Ordering::Relaxed) >= BUSY_REQUESTS {
+                quiet_since = None;
+            } else if quiet_since.get_or_insert_with(Instant::now).elapsed() >= QUIET_PERIOD {
+                break;
+            }
+        }
+        update_progress(&self.config.name, |progress| {
+            progress.state = PregenerateState::Running
+        });
+    }
+
+    /// Pre-generate every size of every image, resuming an interrupted run
+    /// from its checkpoint
+    pub async fn pregenerate_all_images_cache(self: Arc<Self>) -> Result<(), GalleryError> {
+        let name = self.config.name.clone();
+
+        // Scheduled refreshes ask again while a long run is still going
+        {
+            let mut progress = PROGRESS.lock().unwrap_or_else(PoisonError::into_inner);
+            let progress = progress.entry(name.clone()).or_default();
+            if matches!(
+                progress.state,
+                PregenerateState::Running | PregenerateState::Paused
+            ) {
+                debug!(
+                    "Cache pre-generation for gallery '{}' is already running",
+                    name
+                );
+                return Ok(());
+            }
+            *progress = PregenerateProgress {
+                state: PregenerateState::Running,
+                ..Default::default()
+            };
+        }
+
+        let mut guard = RunGuard::new(&name);
+        let result = self.run_pregeneration().await;
+        if result.is_ok() {
+            guard.state = PregenerateState::Completed;
+        }
+        result
+    }
+
+    async fn run_pregeneration(self: &Arc<Self>) -> Result<(), GalleryError> {
+        let name = &self.config.name;
+        let mut images: Vec<String> = {
+            let metadata_cache = self.metadata_cache.read().await;
+            metadata_cache
+                .keys()
+                .filter(|path| self.is_image(path))
+                .cloned()
+                .collect()
+        };
+        images.sort();
+
+        let passes = self.pregenerate_passes();
+        let mut checkpoint = self.load_checkpoint().await;
+        // Passes whose settings have since changed run again
+        checkpoint
+            .completed
+            .retain(|id| passes.iter().any(|pass| &pass.id == id));
+        let parallelism = self
+            .config
+            .pregenerate_parallelism
+            .unwrap_or(DEFAULT_PARALLELISM)
+            .max(1);
+
+        info!(
+            "Starting cache pre-generation for gallery '{}': {} images, {} of {} passes already done",
+            name,
+            images.len(),
+            checkpoint.completed.len(),
+            passes.len()
+        );
+        update_progress(name, |progress| {
+            progress.passes_done = checkpoint.completed.len();
+            progress.passes_total = passes.len();
+        });
+
+        for pass in &passes {
+            if checkpoint.completed.contains(&pass.id) {
+                continue;
+            }
+            if checkpoint.current.as_ref() != Some(&pass.id) {
+                checkpoint.current = Some(pass.id.clone());
+                checkpoint.done_through = None;
+            }
+
+            // Images are sorted, so everything up to the checkpoint is done
+            let skip_webp = pass.format == OutputFormat::WebP;
+            let todo: Vec<&String> = images
+                .iter()
+                .filter(|path| {
+                    checkpoint
+                        .done_through
+                        .as_ref()
+                        .is_none_or(|done| *path > done)
+                })
+                // Skip WebP for PNGs to preserve transparency
+                .filter(|path| !(skip_webp && path.to_lowercase().ends_with(".png")))
+                .collect();
+            update_progress(name, |progress| {
+                progress.size = Some(pass.size.clone());
+                progress.format = Some(pass.format.extension().to_string());
+                progress.images = images.len();
+                progress.images_done = images.len() - todo.len();
+            });
+
+            let mut jobs = JoinSet::new();
+            let mut next = 0;
+            // Every image before `done` is finished; `finished` holds those
+            // after it that finished early
+            let mut done = 0;
+            let mut finished = BTreeSet::new();
+            let mut since_checkpoint = 0;
+
+            while next < todo.len() || !jobs.is_empty() {
+                if next < todo.len() && jobs.len() < parallelism {
+                    self.wait_until_quiet().await;
+
+                    let gallery = self.clone();
+                    let relative_path = todo[next].clone();
+                    let size = pass.size.clone();
+                    let format = pass.format;
+                    let index = next;
+                    jobs.spawn(async move {
+                        let full_path = gallery.config.source_directory.join(&relative_path);
+                        if !full_path.exists() {
+                            return (index, true);
+                        }
+                        match gallery
+                            .get_resized_image(&full_path, &relative_path, &size, format)
+                            .await
+                        {
+                            Ok(_) => (index, true),
+                            Err(e) => {
+                                error!(
+                                    "Failed to pre-generate {} {} for {}: {}",
+                                    size,
+                                    format.extension(),
+                                    relative_path,
+                                    e
+                                );
+                                (index, false)
+                            }
+                        }
+                    });
+                    next += 1;
+                    continue;
+                }
+
+                let Some(joined) = jobs.join_next().await else {
+                    break;
+                };
+                let (index, succeeded) = joined?;
+                finished.insert(index);
+                while finished.remove(&done) {
+                    done += 1;
+                }
+                update_progress(name, |progress| {
+                    progress.images_done += 1;
+                    if !succeeded {
+                        progress.failed += 1;
+                    }
+                });
+
+                since_checkpoint += 1;
+                if since_checkpoint >= CHECKPOINT_INTERVAL && done > 0 {
+                    checkpoint.done_through = Some(todo[done - 1].clone());
+                    self.save_checkpoint(&checkpoint).await;
+                    since_checkpoint = 0;
+                }
+            }
+
+            checkpoint.completed.insert(pass.id.clone());
+            checkpoint.current = None;
+            checkpoint.done_through = None;
+            self.save_checkpoint(&checkpoint).await;
+            update_progress(name, |progress| {
+                progress.passes_done = checkpoint.completed.len()
+            });
+            info!(
+                "Pre-generated {} {} for gallery '{}' ({}/{} passes)",
+                pass.size,
+                pass.format.extension(),
+                name,
+                checkpoint.completed.len(),
+                passes.len()
+            );
+        }
+
+        // Finished, so the next run goes over everything again and picks up
+        // images added since
+        if let Err(e) = tokio::fs::remove_file(self.checkpoint_path()).await
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            error!("Failed to remove cache pre-generation progress: {}", e);
+        }
+
+        info!(
+            "Completed cache pre-generation for gallery '{}'",
+            self.config.name
+        );
+        Ok(())
+    }
+}
+
+/// `GET /api/gallery/{name}/pregenerate`
+pub async fn pregenerate_progress_handler(
+    State(app_state): State<AppState>,
+    Path(gallery_name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(response) = super::share_handlers::require_admin(&app_state, &headers).await {
+        return response;
+    }
+    let Some(gallery) = app_state.galleries.get(&gallery_name) else {
+        return (StatusCode::NOT_FOUND, "Gallery not found").into_response();
+    };
+
+    Json(gallery.pregenerate_progress().await).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_panicked_run_goes_back_to_idle() {
+        let name = "panicked-run";
+        update_progress(name, |progress| progress.state = PregenerateState::Running);
+
+        let result = std::panic::catch_unwind(|| {
+            let _guard = RunGuard::new(name);
+            panic!("pass failed");
+        });
+        assert!(result.is_err());
+        let state = PROGRESS.lock().unwrap().get(name).unwrap().state;
+        assert_eq!(state, PregenerateState::Idle);
+    }
+
+    #[test]
+    fn test_passes_start_with_smallest_size() {
+        let mut config = crate::Config::default().galleries.unwrap()[0].clone();
+        config.sizes.insert(
+            "tiny".to_string(),
+            crate::NamedSizeConfig {
+                width: 64,
+                height: 64,
+                crop: Default::default(),
+                quality: None,
+                watermark: false,
+                requires_auth: false,
+            },
+        );
+        let gallery = Gallery::new(config);
+
+        let passes = gallery.pregenerate_passes();
+        let mut sizes: Vec<&str> = passes.iter().map(|pass| pass.size.as_str()).collect();
+        sizes.dedup();
+        assert_eq!(sizes, ["tiny", "thumbnail", "gallery", "medium", "large"]);
+        assert_eq!(passes[0].format, OutputFormat::Jpeg);
+
+        // Changing a size's settings gives its passes new ids
+        let thumbnail_id = passes
+            .iter()
+            .find(|pass| pass.size == "thumbnail")
+            .unwrap()
+            .id
+            .clone();
+        let mut config = gallery.config.clone();
+        config.thumbnail.width += 1;
+        let changed = Gallery::new(config).pregenerate_passes();
+        assert!(!changed.iter().any(|pass| pass.id == thumbnail_id));
+        assert!(changed.iter().any(|pass| pass.id == passes[0].id));
+    }
+}
@@ -0,0 +1,489 @@
+use super::{Gallery, ImageMetadata};
+use crate::login::UserIdentity;
+use crate::search::SearchQuery;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::Mutex;
+use tracing::debug;
+use walkdir::WalkDir;
+
+/// Searchable view of a single image. Text fields are stored lowercased so
+/// matching is case-insensitive.
+#[derive(Debug, Clone)]
+pub(crate) struct SearchDocument {
+    /// File name, sidecar caption, and the titles and descriptions of every
+    /// folder above the image
+    text: String,
+    camera: Option<String>,
+    lens: Option<String>,
+    iso: Option<u32>,
+    capture_date: Option<SystemTime>,
+    /// Embedded keywords plus sidecar `tags`, as written, without duplicates
+    tags: Vec<String>,
+    /// GPS latitude and longitude
+    location: Option<(f64, f64)>,
+}
+
+/// An image carrying at least one tag, as listed by `tagged_images`
+#[derive(Debug, Clone)]
+pub(crate) struct TaggedImage {
+    pub path: String,
+    pub tags: Vec<String>,
+    pub capture_date: Option<SystemTime>,
+}
+
+/// An image with GPS coordinates, as listed by `located_images`
+#[derive(Debug, Clone)]
+pub(crate) struct LocatedImage {
+    pub path: String,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SearchIndex {
+    /// The index is built lazily from the metadata cache on the first search;
+    /// until then incremental updates are skipped
+    built: bool,
+    documents: HashMap<String, SearchDocument>,
+    /// Held while the index is built, so concurrent first searches wait for
+    /// one build instead of each walking the gallery
+    build_lock: Arc<Mutex<()>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub path: String,
+    pub capture_date: Option<SystemTime>,
+}
+
+impl Gallery {
+    /// Find images matching `query` that `user` is allowed to see, newest
+    /// first. Date bounds are left to the caller, which knows how precisely
+    /// each folder's dates may be shown to this user.
+    pub async fn search(&self, query: &SearchQuery, user: Option<&UserIdentity>) -> Vec<SearchHit> {
+        if let Some(gallery) = &query.gallery
+            && !gallery.eq_ignore_ascii_case(&self.config.name)
+        {
+            return Vec::new();
+        }
+
+        self.ensure_search_index().await;
+
+        let mut hits: Vec<SearchHit> = {
+            let index = self.search_index.read().await;
+            index
+                .documents
+                .iter()
+                .filter(|(_, doc)| Self::matches(doc, query))
+                .map(|(path, doc)| SearchHit {
+                    path: path.clone(),
+                    capture_date: doc.capture_date,
+                })
+                .collect()
+        };
+
+        // Drop anything in a folder this user can't see, checking each folder once
+        let mut visible_folders: HashMap<String, bool> = HashMap::new();
+        let mut visible_hits = Vec::with_capacity(hits.len());
+        for hit in hits.drain(..) {
+            let folder = hit
+                .path
+                .rsplit_once('/')
+                .map(|(parent, _)| parent)
+                .unwrap_or("")
+                .to_string();
+            let visible = match visible_folders.get(&folder) {
+                Some(visible) => *visible,
+                None => {
+                    let visible = self.is_folder_searchable(&folder, user).await;
+                    visible_folders.insert(folder, visible);
+                    visible
+                }
+            };
+            if visible {
+                visible_hits.push(hit);
+            }
+        }
+
+        visible_hits.sort_by(|a, b| {
+            b.capture_date
+                .cmp(&a.capture_date)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        visible_hits
+    }
+
+    fn matches(doc: &SearchDocument, query: &SearchQuery) -> bool {
+        if !query.terms.iter().all(|term| doc.text.contains(term)) {
+            return false;
+        }
+
+        let field_matches = |field: &Option<String>, wanted: &Option<String>| match wanted {
+            Some(wanted) => field.as_ref().is_some_and(|value| value.contains(wanted)),
+            None => true,
+        };
+        if !field_matches(&doc.camera, &query.camera) || !field_matches(&doc.lens, &query.lens) {
+            return false;
+        }
+
+        if query.iso_min.is_some() || query.iso_max.is_some() {
+            let Some(iso) = doc.iso else {
+                return false;
+            };
+            if query.iso_min.is_some_and(|min| iso < min)
+                || query.iso_max.is_some_and(|max| iso > max)
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Every indexed image that has tags. Visibility is left to the caller.
+    pub(crate) async fn tagged_images(&self) -> Vec<TaggedImage> {
+        self.ensure_search_index().await;
+
+        let index = self.search_index.read().await;
+        index
+            .documents
+            .iter()
+            .filter(|(_, doc)| !doc.tags.is_empty())
+            .map(|(path, doc)| TaggedImage {
+                path: path.clone(),
+                tags: doc.tags.clone(),
+                capture_date: doc.capture_date,
+            })
+            .collect()
+    }
+
+    /// Every indexed image that has GPS coordinates, sorted by path.
+    /// Visibility and precision are left to the caller.
+    pub(crate) async fn located_images(&self) -> Vec<LocatedImage> {
+        self.ensure_search_index().await;
+
+        let index = self.search_index.read().await;
+        let mut images: Vec<LocatedImage> = index
+            .documents
+            .iter()
+            .filter_map(|(path, doc)| {
+                let (latitude, longitude) = doc.location?;
+                Some(LocatedImage {
+                    path: path.clone(),
+                    latitude,
+                    longitude,
+                })
+            })
+            .collect();
+        images.sort_by(|a, b| a.path.cmp(&b.path));
+        images
+    }
+
+    /// Tags of a single image, as indexed
+    pub(crate) async fn image_tags(&self, path: &str) -> Vec<String> {
+        self.ensure_search_index().await;
+
+        self.search_index
+            .read()
+            .await
+            .documents
+            .get(path)
+            .map(|doc| doc.tags.clone())
+            .unwrap_or_default()
+    }
+
+    /// Hidden folders stay out of search results even though they can be opened
+    /// by direct link
+    pub(crate) async fn is_folder_searchable(
+        &self,
+        folder: &str,
+        user: Option<&UserIdentity>,
+    ) -> bool {
+        if !self.check_path_access(folder, user, None).await {
+            return false;
+        }
+
+        let mut current = folder;
+        while !current.is_empty() {
+            if self
+                .read_folder_metadata_full(current)
+                .await
+                .is_some_and(|m| m.config.hidden)
+            {
+                return false;
+            }
+            current = current.rsplit_once('/').map(|(p, _)| p).unwrap_or("");
+        }
+        true
+    }
+
+    /// Build the index on first use. Images missing from the metadata cache have
+    /// their metadata extracted (and cached) here, so results don't depend on
+    /// which pages have been visited.
+    async fn ensure_search_index(&self) {
+        let build_lock = {
+            let index = self.search_index.read().await;
+            if index.built {
+                return;
+            }
+            index.build_lock.clone()
+        };
+        let _building = build_lock.lock().await;
+        if self.search_index.read().await.built {
+            return;
+        }
+
+        let image_paths: Vec<String> = WalkDir::new(&self.config.source_directory)
+            .follow_links(true)
+            .into_iter()
+            .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'))
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter_map(|e| {
+                let relative = e.path().strip_prefix(&self.config.source_directory).ok()?;
+                let relative = relative.to_string_lossy().replace('\\', "/");
+                self.is_image(&relative).then_some(relative)
+            })
+            .collect();
+
+        let mut folder_text = HashMap::new();
+        let mut documents = HashMap::with_capacity(image_paths.len());
+        for path in image_paths {
+            let cached = self.metadata_cache.read().await.get(&path).cloned();
+            let metadata = match cached {
+                Some(metadata) => metadata,
+                None => {
+                    let full_path = self.config.source_directory.join(&path);
+                    match self.extract_image_metadata(&full_path).await {
+                        Ok(metadata) => {
+                            self.insert_metadata_with_tracking(path.clone(), metadata.clone())
+                                .await;
+                            metadata
+                        }
+                        Err(e) => {
+                            debug!("Skipping {} in search index: {}", path, e);
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            let document = self
+                .build_search_document(&path, &metadata, &mut folder_text)
+                .await;
+            documents.insert(path, document);
+        }
+
+        let mut index = self.search_index.write().await;
+        if !index.built {
+            debug!(
+                "Built search index for gallery '{}' with {} images",
+                self.config.name,
+                documents.len()
+            );
+            index.documents = documents;
+            index.built = true;
+        }
+    }
+
+    /// Update the search entry for an image after its metadata changed
+    pub(crate) async fn update_search_index(&self, path: &str, metadata: &ImageMetadata) {
+        if !self.search_index.read().await.built {
+            return;
+        }
+
+        let document = self
+            .build_search_document(path, metadata, &mut HashMap::new())
+            .await;
+        self.search_index
+            .write()
+            .await
+            .documents
+            .insert(path.to_string(), document);
+    }
+
+    pub(crate) async fn remove_from_search_index(&self, path: &str) {
+        self.search_index.write().await.documents.remove(path);
+    }
+
+    /// Throw the index away so the next search rebuilds it from the metadata cache
+    pub(crate) async fn clear_search_index(&self) {
+        let mut index = self.search_index.write().await;
+        index.documents.clear();
+        index.built = false;
+    }
+
+    /// Re-index the images affected by an edited sidecar caption or `_folder.md`
+    pub(crate) async fn refresh_search_for_markdown(&self, markdown_path: &str) {
+        let (folder, file_name) = markdown_path
+            .rsplit_once('/')
+            .unwrap_or(("", markdown_path));
+
+        let affected: Vec<String> = {
+            let index = self.search_index.read().await;
+            if !index.built {
+                return;
+            }
+            if file_name == "_folder.md" {
+                let prefix = format!("{}/", folder);
+                index
+                    .documents
+                    .keys()
+                    .filter(|path| folder.is_empty() || path.starts_with(&prefix))
+                    .cloned()
+                    .collect()
+            } else {
+                let stem = file_name.trim_end_matches(".md");
+                index
+                    .documents
+                    .keys()
+                    .filter(|path| {
+                        let (image_folder, image_name) =
+                            path.rsplit_once('/').unwrap_or(("", path.as_str()));
+                        image_folder == folder
+                            && image_name.rsplit_once('.').map(|(s, _)| s) == Some(stem)
+                    })
+                    .cloned()
+                    .collect()
+            }
+        };
+
+        for path in affected {
+            let metadata = self.metadata_cache.read().await.get(&path).cloned();
+            if let Some(metadata) = metadata {
+                self.update_search_index(&path, &metadata).await;
+            }
+        }
+    }
+
+    async fn build_search_document(
+        &self,
+        path: &str,
+        metadata: &ImageMetadata,
+        folder_text: &mut HashMap<String, String>,
+    ) -> SearchDocument {
+        let mut text = path
+            .rsplit('/')
+            .next()
+            .unwrap_or(path)
+            .replace(['_', '-', '.'], " ");
+
+        let mut tags: Vec<String> = Vec::new();
+        if let Some(sidecar) = self.read_sidecar_full(path).await {
+            text.push('\n');
+            text.push_str(&sidecar.caption_markdown);
+            tags.extend(sidecar.config.tags);
+        }
+
+        if let Some(info) = &metadata.descriptive_info {
+            for field in info.title.iter().chain(info.caption.iter()) {
+                text.push('\n');
+                text.push_str(field);
+            }
+            tags.extend(info.keywords.iter().cloned());
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        let tags: Vec<String> = tags
+            .iter()
+            .map(|tag| tag.trim())
+            .filter(|tag| !tag.is_empty() && seen.insert(super::tags::tag_key(tag)))
+            .map(str::to_string)
+            .collect();
+        for tag in &tags {
+            text.push('\n');
+            text.push_str(tag);
+        }
+
+        let mut folder = path.rsplit_once('/').map(|(p, _)| p).unwrap_or("");
+        loop {
+            if !folder_text.contains_key(folder) {
+                let mut description = folder.rsplit('/').next().unwrap_or("").to_string();
+                if let Some(meta) = self.read_folder_metadata_full(folder).await {
+                    if let Some(title) = meta.config.title {
+                        description.push('\n');
+                        description.push_str(&title);
+                    }
+                    description.push('\n');
+                    description.push_str(&meta.description_markdown);
+                }
+                folder_text.insert(folder.to_string(), description);
+            }
+            text.push('\n');
+            text.push_str(&folder_text[folder]);
+
+            if folder.is_empty() {
+                break;
+            }
+            folder = folder.rsplit_once('/').map(|(p, _)| p).unwrap_or("");
+        }
+
+        let camera_info = metadata.camera_info.as_ref();
+        let camera = camera_info.and_then(|info| {
+            let camera = [info.camera_make.as_deref(), info.camera_model.as_deref()]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(" ");
+            (!camera.is_empty()).then(|| camera.to_lowercase())
+        });
+        let lens = camera_info
+            .and_then(|info| info.lens_model.as_ref())
+            .map(|lens| lens.to_lowercase());
+
+        for value in [&camera, &lens].into_iter().flatten() {
+            text.push('\n');
+            text.push_str(value);
+        }
+
+        SearchDocument {
+            text: text.to_lowercase(),
+            camera,
+            lens,
+            iso: camera_info.and_then(|info| info.iso),
+            capture_date: metadata.capture_date,
+            tags,
+            location: metadata
+                .location_info
+                .as_ref()
+                .map(|location| (location.latitude, location.longitude)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document() -> SearchDocument {
+        SearchDocument {
+            text: "beach sunset\nsummer vacation".to_string(),
+            camera: Some("canon eos r5".to_string()),
+            lens: Some("rf 24-70mm f2.8".to_string()),
+            iso: Some(400),
+            capture_date: Some(
+                chrono::DateTime::parse_from_rfc3339("2024-07-04T18:00:00Z")
+                    .unwrap()
+                    .into(),
+            ),
+            tags: Vec::new(),
+            location: None,
+        }
+    }
+
+    #[test]
+    fn test_matches_filters() {
+        let doc = document();
+        let matches = |q: &str| Gallery::matches(&doc, &SearchQuery::parse(q));
+
+        assert!(matches("sunset"));
+        assert!(matches("Beach VACATION"));
+        assert!(!matches("mountain"));
+        assert!(matches("camera:canon lens:24-70"));
+        assert!(!matches("camera:nikon"));
+        assert!(matches("iso>100 iso<800"));
+        assert!(!matches("iso>400"));
+    }
+}
@@ -0,0 +1,533 @@
+use crate::api::{create_signed_cookie, verify_signed_cookie};
+use crate::atomic_file::write_atomically;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::fs;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, error, info};
+
+/// Cookie holding the share grants a visitor has unlocked
+pub const SHARE_GRANTS_COOKIE: &str = "share_grants";
+
+/// Grants kept in the cookie; the oldest are dropped beyond this
+const MAX_GRANTS_PER_COOKIE: usize = 16;
+
+/// PBKDF2 rounds for share passwords. Checking one takes a fraction of a
+/// second, which together with the unlock rate limit makes guessing slow.
+const PASSWORD_ITERATIONS: u32 = 200_000;
+
+/// Prefix of stored password hashes, followed by `$rounds$salt$key`
+const PASSWORD_SCHEME: &str = "pbkdf2-sha256";
+
+/// Grants are signed over a different message than link tokens, so knowing a
+/// link is not enough to forge access to a password-protected share
+const GRANT_PREFIX: &str = "grant-";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShareKind {
+    Folder,
+    Image,
+}
+
+/// Access to one folder (and everything beneath it) or one image, handed out
+/// as a link to people without an account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Share {
+    pub id: String,
+    pub gallery: String,
+    pub path: String,
+    pub kind: ShareKind,
+    pub created_at: i64,
+    #[serde(default)]
+    pub created_by: Option<String>,
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    #[serde(default)]
+    pub password_hash: Option<String>,
+    #[serde(default)]
+    pub allow_download: bool,
+    #[serde(default)]
+    pub max_views: Option<u64>,
+    #[serde(default)]
+    pub views: u64,
+}
+
+impl Share {
+    pub fn new(gallery: &str, path: &str, kind: ShareKind) -> Self {
+        use rand::{Rng, rng};
+
+        let id: String = rng()
+            .random::<[u8; 16]>()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        Self {
+            id,
+            gallery: gallery.to_string(),
+            path: path.trim_matches('/').to_string(),
+            kind,
+            created_at: chrono::Utc::now().timestamp(),
+            created_by: None,
+            expires_at: None,
+            password_hash: None,
+            allow_download: false,
+            max_views: None,
+            views: 0,
+        }
+    }
+
+    pub fn set_password(&mut self, password: &str) {
+        use rand::{Rng, rng};
+
+        let salt = rng().random::<[u8; 16]>();
+        let key = derive_key(password, &salt, PASSWORD_ITERATIONS);
+        self.password_hash = Some(format!(
+            "{}${}${}${}",
+            PASSWORD_SCHEME,
+            PASSWORD_ITERATIONS,
+            URL_SAFE_NO_PAD.encode(salt),
+            URL_SAFE_NO_PAD.encode(key)
+        ));
+    }
+
+    pub fn has_password(&self) -> bool {
+        self.password_hash.is_some()
+    }
+
+    /// Slow on purpose; call it off the async runtime
+    pub fn check_password(&self, password: &str) -> bool {
+        let Some(hash) = self.password_hash.as_deref() else {
+            return false;
+        };
+        let mut parts = hash.split('$');
+        if parts.next() != Some(PASSWORD_SCHEME) {
+            return false;
+        }
+        let (Some(Ok(iterations)), Some(Ok(salt)), Some(Ok(expected)), None) = (
+            parts.next().map(str::parse::<u32>),
+            parts.next().map(|salt| URL_SAFE_NO_PAD.decode(salt)),
+            parts.next().map(|key| URL_SAFE_NO_PAD.decode(key)),
+            parts.next(),
+        ) else {
+            return false;
+        };
+        constant_time_eq(&derive_key(password, &salt, iterations), &expected)
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Whether the link may be opened again. Visitors who already opened it
+    /// keep access until it expires or is revoked.
+    pub fn has_views_left(&self) -> bool {
+        self.max_views.is_none_or(|max| self.views < max)
+    }
+
+    /// Whether the share covers a requested folder or image path
+    pub fn grants(&self, path: &str) -> bool {
+        let path = path.trim_matches('/');
+        if !is_plain_path(path) {
+            return false;
+        }
+        match self.kind {
+            ShareKind::Folder => is_within(path, &self.path),
+            ShareKind::Image => path == self.path,
+        }
+    }
+
+    /// Whether folder access checks for `folder_path` should pass. An image
+    /// share only opens the folder containing it.
+    pub fn covers_folder(&self, folder_path: &str) -> bool {
+        let folder_path = folder_path.trim_matches('/');
+        if !is_plain_path(folder_path) {
+            return false;
+        }
+        match self.kind {
+            ShareKind::Folder => is_within(folder_path, &self.path),
+            ShareKind::Image => {
+                folder_path == self.path.rsplit_once('/').map(|(p, _)| p).unwrap_or("")
+            }
+        }
+    }
+
+    /// URL path of the shared folder or image detail page
+    pub fn target_url(&self, url_prefix: &str) -> String {
+        gallery_url(url_prefix, &self.path, self.kind)
+    }
+}
+
+/// URL path of a gallery folder page or image detail page
+pub(crate) fn gallery_url(url_prefix: &str, path: &str, kind: ShareKind) -> String {
+    let url_prefix = url_prefix.trim_end_matches('/');
+    match kind {
+        ShareKind::Folder if path.is_empty() => url_prefix.to_string(),
+        ShareKind::Folder => format!("{}/{}", url_prefix, path),
+        ShareKind::Image => format!("{}/detail/{}", url_prefix, urlencoding::encode(path)),
+    }
+}
+
+/// Whether a gallery-relative path is free of `.`, `..` and empty segments,
+/// which would let a prefix test pass for a path outside the shared folder
+pub(crate) fn is_plain_path(path: &str) -> bool {
+    path.is_empty()
+        || path
+            .split('/')
+            .all(|segment| !matches!(segment, "" | "." | ".."))
+}
+
+fn is_within(path: &str, root: &str) -> bool {
+    root.is_empty() || path == root || path.starts_with(&format!("{}/", root))
+}
+
+/// PBKDF2-HMAC-SHA256 with a single output block
+fn derive_key(password: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mac = Hmac::<Sha256>::new_from_slice(password.as_bytes())
+        .expect("HMAC accepts keys of any length");
+
+    let mut round = {
+        let mut block = mac.clone();
+        block.update(salt);
+        block.update(&1u32.to_be_bytes());
+        block.finalize().into_bytes()
+    };
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&round);
+    for _ in 1..iterations {
+        let mut block = mac.clone();
+        block.update(&round);
+        round = block.finalize().into_bytes();
+        key.iter_mut().zip(round.iter()).for_each(|(k, r)| *k ^= r);
+    }
+    key
+}
+
+/// Compare without returning early, so timing doesn't reveal how much matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Work out whether `path` names a folder or an image inside `source_directory`
+pub fn detect_share_kind(source_directory: &Path, path: &str) -> Option<ShareKind> {
+    let path = path.trim_matches('/');
+    if path
+        .split('/')
+        .any(|segment| segment == ".." || segment.starts_with('.'))
+    {
+        return None;
+    }
+
+    let full_path = source_directory.join(path);
+    if full_path.is_dir() {
+        Some(ShareKind::Folder)
+    } else if full_path.is_file() && (super::is_image_file(path) || super::is_video_file(path)) {
+        Some(ShareKind::Image)
+    } else {
+        None
+    }
+}
+
+/// Link token for a share: its id signed with the cookie secret
+pub fn share_token(secret: &str, id: &str) -> Option<String> {
+    create_signed_cookie(secret, id).ok()
+}
+
+/// Full link to hand out for a share token
+pub fn share_url(base_url: &str, url_prefix: &str, token: &str) -> String {
+    format!(
+        "{}{}/s/{}",
+        base_url.trim_end_matches('/'),
+        url_prefix.trim_end_matches('/'),
+        token
+    )
+}
+
+/// Share id from a link token, if the signature is valid
+pub fn verify_share_token<'a>(secret: &str, token: &'a str) -> Option<&'a str> {
+    if !verify_signed_cookie(secret, token) {
+        return None;
+    }
+    token
+        .split_once(':')
+        .map(|(id, _)| id)
+        .filter(|id| !id.starts_with(GRANT_PREFIX))
+}
+
+/// Share ids from a `share_grants` cookie value, dropping tampered entries
+pub fn parse_grants(secret: &str, cookie: &str) -> Vec<String> {
+    cookie
+        .split('|')
+        .filter(|grant| verify_signed_cookie(secret, grant))
+        .filter_map(|grant| grant.split_once(':'))
+        .filter_map(|(value, _)| value.strip_prefix(GRANT_PREFIX))
+        .map(str::to_string)
+        .collect()
+}
+
+/// `share_grants` cookie value with a grant for `id` added to `existing`
+pub fn add_grant(secret: &str, existing: Option<&str>, id: &str) -> Option<String> {
+    let grant = create_signed_cookie(secret, &format!("{}{}", GRANT_PREFIX, id)).ok()?;
+    let mut grants: Vec<&str> = existing
+        .unwrap_or("")
+        .split('|')
+        .filter(|g| !g.is_empty() && *g != grant)
+        .collect();
+    grants.push(&grant);
+    let skip = grants.len().saturating_sub(MAX_GRANTS_PER_COOKIE);
+    Some(grants[skip..].join("|"))
+}
+
+/// Shares are kept in memory and written to a JSON file after every change.
+/// The file is re-read when it changes on disk, so shares created or revoked
+/// from the command line apply to a running server.
+pub struct ShareStore {
+    shares: RwLock<HashMap<String, Share>>,
+    file_path: Option<PathBuf>,
+    loaded_modified: RwLock<Option<SystemTime>>,
+    /// Held while saving, so a slower save of an older snapshot can't
+    /// replace a newer one
+    save_lock: Mutex<()>,
+}
+
+impl ShareStore {
+    pub fn in_memory() -> Self {
+        Self {
+            shares: RwLock::new(HashMap::new()),
+            file_path: None,
+            loaded_modified: RwLock::new(None),
+            save_lock: Mutex::new(()),
+        }
+    }
+
+    pub async fn load(path: PathBuf) -> Result<Self, std::io::Error> {
+        let store = Self {
+            shares: RwLock::new(HashMap::new()),
+            file_path: Some(path),
+            loaded_modified: RwLock::new(None),
+            save_lock: Mutex::new(()),
+        };
+        store.reload().await?;
+        Ok(store)
+    }
+
+    async fn reload(&self) -> Result<(), std::io::Error> {
+        let Some(path) = &self.file_path else {
+            return Ok(());
+        };
+        if !path.exists() {
+            return Ok(());
+        }
+
+        let modified = fs::metadata(path).await?.modified().ok();
+        let contents = fs::read_to_string(path).await?;
+        let shares: Vec<Share> = serde_json::from_str(&contents)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+        *self.shares.write().await = shares.into_iter().map(|s| (s.id.clone(), s)).collect();
+        *self.loaded_modified.write().await = modified;
+        Ok(())
+    }
+
+    async fn reload_if_changed(&self) {
+        let Some(path) = &self.file_path else {
+            return;
+        };
+        let modified = match fs::metadata(path).await {
+            Ok(metadata) => metadata.modified().ok(),
+            Err(_) => return,
+        };
+        if modified == *self.loaded_modified.read().await {
+            return;
+        }
+
+        debug!("Share store changed on disk, reloading");
+        if let Err(e) = self.reload().await {
+            error!("Failed to reload shares from {:?}: {}", path, e);
+        }
+    }
+
+    async fn save(&self) -> Result<(), std::io::Error> {
+        let Some(path) = &self.file_path else {
+            return Ok(());
+        };
+
+        let _saving = self.save_lock.lock().await;
+        let json = {
+            let shares = self.shares.read().await;
+            let mut shares: Vec<&Share> = shares.values().collect();
+            shares.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+            serde_json::to_string_pretty(&shares)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
+        };
+
+        write_atomically(path, json.as_bytes()).await?;
+        *self.loaded_modified.write().await = fs::metadata(path).await?.modified().ok();
+        Ok(())
+    }
+
+    pub async fn insert(&self, share: Share) -> Result<(), std::io::Error> {
+        self.reload_if_changed().await;
+        info!(
+            "Created share {} for {}:{}",
+            share.id, share.gallery, share.path
+        );
+        self.shares.write().await.insert(share.id.clone(), share);
+        self.save().await
+    }
+
+    pub async fn get(&self, id: &str) -> Option<Share> {
+        self.reload_if_changed().await;
+        self.shares.read().await.get(id).cloned()
+    }
+
+    /// Count an opening of the link. Returns the updated share, or `None` if it
+    /// doesn't exist, has expired or has no views left.
+    pub async fn record_view(&self, id: &str) -> Option<Share> {
+        self.reload_if_changed().await;
+        let now = chrono::Utc::now().timestamp();
+        let share = {
+            let mut shares = self.shares.write().await;
+            let share = shares.get_mut(id)?;
+            if share.is_expired(now) || !share.has_views_left() {
+                return None;
+            }
+            share.views += 1;
+            share.clone()
+        };
+        if let Err(e) = self.save().await {
+            error!("Failed to save shares: {}", e);
+        }
+        Some(share)
+    }
+
+    pub async fn remove(&self, id: &str) -> Result<bool, std::io::Error> {
+        self.reload_if_changed().await;
+        let removed = self.shares.write().await.remove(id).is_some();
+        if removed {
+            info!("Revoked share {}", id);
+            self.save().await?;
+        }
+        Ok(removed)
+    }
+
+    /// Shares, optionally limited to one gallery, newest first
+    pub async fn list(&self, gallery: Option<&str>) -> Vec<Share> {
+        self.reload_if_changed().await;
+        let mut shares: Vec<Share> = self
+            .shares
+            .read()
+            .await
+            .values()
+            .filter(|s| gallery.is_none_or(|g| s.gallery == g))
+            .cloned()
+            .collect();
+        shares.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        shares
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_share_scope() {
+        let folder = Share::new("main", "clients/smith", ShareKind::Folder);
+        assert!(folder.grants("clients/smith"));
+        assert!(folder.grants("clients/smith/day1/img.jpg"));
+        assert!(!folder.grants("clients/smithers"));
+        assert!(!folder.grants("clients"));
+        // Dot and empty segments could climb out of the shared folder
+        assert!(!folder.grants("clients/smith/../jones"));
+        assert!(!folder.grants("clients/smith/./img.jpg"));
+        assert!(!folder.grants("clients/smith//img.jpg"));
+        assert!(!folder.covers_folder("clients/smith/.."));
+        assert!(folder.covers_folder("clients/smith/day1"));
+
+        let image = Share::new("main", "clients/smith/best.jpg", ShareKind::Image);
+        assert!(image.grants("clients/smith/best.jpg"));
+        assert!(!image.grants("clients/smith/other.jpg"));
+        assert!(!image.grants("clients/smith"));
+        assert!(image.covers_folder("clients/smith"));
+        assert!(!image.covers_folder("clients"));
+        assert_eq!(
+            image.target_url("/gallery"),
+            "/gallery/detail/clients%2Fsmith%2Fbest.jpg"
+        );
+    }
+
+    #[test]
+    fn test_tokens_and_grants() {
+        let mut share = Share::new("main", "clients", ShareKind::Folder);
+        share.set_password("hunter2");
+        assert!(share.check_password("hunter2"));
+        assert!(!share.check_password("hunter3"));
+        // Salted, so the same password hashes differently for another share
+        let mut other = Share::new("main", "clients", ShareKind::Folder);
+        other.set_password("hunter2");
+        assert_ne!(other.password_hash, share.password_hash);
+        assert!(other.check_password("hunter2"));
+
+        let token = share_token("secret", &share.id).unwrap();
+        assert_eq!(
+            verify_share_token("secret", &token),
+            Some(share.id.as_str())
+        );
+        assert_eq!(verify_share_token("other-secret", &token), None);
+
+        // A link token is not a grant, and a grant is not a link token
+        assert!(parse_grants("secret", &token).is_empty());
+        let cookie = add_grant("secret", None, &share.id).unwrap();
+        assert_eq!(verify_share_token("secret", &cookie), None);
+        assert_eq!(parse_grants("secret", &cookie), vec![share.id.clone()]);
+
+        // Re-adding a grant moves it to the end instead of duplicating it
+        let cookie = add_grant("secret", Some(&cookie), "other").unwrap();
+        let cookie = add_grant("secret", Some(&cookie), &share.id).unwrap();
+        assert_eq!(
+            parse_grants("secret", &cookie),
+            vec!["other".to_string(), share.id.clone()]
+        );
+        let tampered = cookie.replace("grant-other", "grant-otter");
+        assert_eq!(parse_grants("secret", &tampered), vec![share.id.clone()]);
+    }
+
+    #[tokio::test]
+    async fn test_store_views_and_reload() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("shares.json");
+
+        let store = ShareStore::load(path.clone()).await.unwrap();
+        let mut share = Share::new("main", "clients", ShareKind::Folder);
+        share.max_views = Some(2);
+        let id = share.id.clone();
+        store.insert(share).await.unwrap();
+
+        assert_eq!(store.record_view(&id).await.unwrap().views, 1);
+        assert_eq!(store.record_view(&id).await.unwrap().views, 2);
+        assert!(store.record_view(&id).await.is_none());
+
+        // A second handle on the same file, as the CLI would use
+        let cli_store = ShareStore::load(path.clone()).await.unwrap();
+        assert_eq!(cli_store.get(&id).await.unwrap().views, 2);
+        assert!(cli_store.remove(&id).await.unwrap());
+
+        // Make sure the modification time differs from the last save
+        let later = SystemTime::now() + std::time::Duration::from_secs(5);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(store.get(&id).await.is_none());
+    }
+}
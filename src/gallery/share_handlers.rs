@@ -0,0 +1,372 @@
+use super::share::{
+    SHARE_GRANTS_COOKIE, Share, ShareKind, add_grant, detect_share_kind, gallery_url, parse_grants,
+    share_token, share_url, verify_share_token,
+};
+use crate::AppState;
+use crate::login::UserIdentity;
+use axum::{
+    Form,
+    extract::{ConnectInfo, Path, State},
+    http::{Extensions, HeaderMap, StatusCode, header::SET_COOKIE},
+    response::{Html, IntoResponse, Json, Redirect, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use tracing::error;
+
+/// Share grant cookies outlive most shares; expiry is enforced server-side
+const SHARE_GRANTS_MAX_AGE_SECONDS: i64 = 30 * 86_400;
+
+#[derive(Debug, Deserialize)]
+pub struct UnlockShareForm {
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateShareRequest {
+    pub path: String,
+    #[serde(default)]
+    pub expires_in_days: Option<u64>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub allow_download: bool,
+    #[serde(default)]
+    pub max_views: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShareResponse {
+    pub id: String,
+    pub path: String,
+    pub kind: ShareKind,
+    pub url: String,
+    pub created_at: i64,
+    pub created_by: Option<String>,
+    pub expires_at: Option<i64>,
+    pub has_password: bool,
+    pub allow_download: bool,
+    pub max_views: Option<u64>,
+    pub views: u64,
+}
+
+impl ShareResponse {
+    fn new(app_state: &AppState, url_prefix: &str, share: Share) -> Self {
+        let token = share_token(&app_state.config.app.cookie_secret, &share.id).unwrap_or_default();
+        Self {
+            url: share_url(
+                app_state.config.app.base_url.as_deref().unwrap_or(""),
+                url_prefix,
+                &token,
+            ),
+            has_password: share.has_password(),
+            id: share.id,
+            path: share.path,
+            kind: share.kind,
+            created_at: share.created_at,
+            created_by: share.created_by,
+            expires_at: share.expires_at,
+            allow_download: share.allow_download,
+            max_views: share.max_views,
+            views: share.views,
+        }
+    }
+}
+
+/// Look up the share behind a link token, rejecting links that no longer work
+async fn resolve_share(
+    app_state: &AppState,
+    gallery_name: &str,
+    token: &str,
+) -> Result<Share, Response> {
+    let not_found = || (StatusCode::NOT_FOUND, "Share link not found").into_response();
+
+    let id =
+        verify_share_token(&app_state.config.app.cookie_secret, token).ok_or_else(not_found)?;
+    let share = app_state
+        .shares
+        .get(id)
+        .await
+        .filter(|share| share.gallery == gallery_name)
+        .ok_or_else(not_found)?;
+
+    if share.is_expired(chrono::Utc::now().timestamp()) {
+        return Err((StatusCode::GONE, "This share link has expired").into_response());
+    }
+    if !share.has_views_left() {
+        return Err((
+            StatusCode::GONE,
+            "This share link has reached its view limit",
+        )
+            .into_response());
+    }
+
+    Ok(share)
+}
+
+async fn render_password_page(
+    app_state: &AppState,
+    gallery_name: &str,
+    status: StatusCode,
+    error_message: Option<&str>,
+) -> Response {
+    let context = liquid::object!({
+        "gallery_name": gallery_name,
+        "error": error_message,
+        "app_name": app_state.config.app.name,
+        "base_url": app_state.config.app.base_url.as_deref().unwrap_or(""),
+    });
+
+    match app_state
+        .template_engine
+        .render_template("modules/share_password.html.liquid", context)
+        .await
+    {
+        Ok(html) => (status, Html(html)).into_response(),
+        Err(e) => {
+            error!("Failed to render share password page: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Count the view, remember the share in the visitor's grant cookie and send
+/// them to the shared folder or image
+async fn grant_and_redirect(
+    app_state: &AppState,
+    gallery: &super::Gallery,
+    share: &Share,
+    sub_path: &str,
+    headers: &HeaderMap,
+) -> Response {
+    let Some(share) = app_state.shares.record_view(&share.id).await else {
+        return (StatusCode::GONE, "This share link is no longer available").into_response();
+    };
+
+    let url_prefix = &gallery.get_config().url_prefix;
+    let sub_path = sub_path.trim_matches('/');
+    let target = if sub_path.is_empty() || share.kind == ShareKind::Image {
+        share.target_url(url_prefix)
+    } else {
+        // Deep links into a shared folder land on the folder or image they name
+        let path = if share.path.is_empty() {
+            sub_path.to_string()
+        } else {
+            format!("{}/{}", share.path, sub_path)
+        };
+        match detect_share_kind(gallery.source_directory(), &path) {
+            Some(kind) if share.grants(&path) => gallery_url(url_prefix, &path, kind),
+            _ => return (StatusCode::NOT_FOUND, "Not found").into_response(),
+        }
+    };
+
+    let existing = crate::api::get_cookie_value(headers, SHARE_GRANTS_COOKIE);
+    let Some(grants) = add_grant(
+        &app_state.config.app.cookie_secret,
+        existing.as_deref(),
+        &share.id,
+    ) else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    let mut response_headers = HeaderMap::new();
+    let cookie = format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+        SHARE_GRANTS_COOKIE, grants, SHARE_GRANTS_MAX_AGE_SECONDS
+    );
+    if let Ok(value) = cookie.parse() {
+        response_headers.insert(SET_COOKIE, value);
+    }
+
+    (response_headers, Redirect::to(&target)).into_response()
+}
+
+/// `GET {prefix}/s/{token}/{*path}`: open a share link, asking for the
+/// password first if it has one
+pub async fn open_share_handler(
+    State(app_state): State<AppState>,
+    Path((gallery_name, token, sub_path)): Path<(String, String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(gallery) = app_state.galleries.get(&gallery_name) else {
+        return (StatusCode::NOT_FOUND, "Gallery not found").into_response();
+    };
+
+    let share = match resolve_share(&app_state, &gallery_name, &token).await {
+        Ok(share) => share,
+        Err(response) => return response,
+    };
+
+    // Visitors who already entered the password aren't asked again
+    let unlocked =
+        crate::api::get_cookie_value(&headers, SHARE_GRANTS_COOKIE).is_some_and(|cookie| {
+            parse_grants(&app_state.config.app.cookie_secret, &cookie).contains(&share.id)
+        });
+    if share.has_password() && !unlocked {
+        return render_password_page(&app_state, &gallery_name, StatusCode::OK, None).await;
+    }
+
+    grant_and_redirect(&app_state, gallery, &share, &sub_path, &headers).await
+}
+
+/// `POST {prefix}/s/{token}/{*path}`: password form submission
+pub async fn unlock_share_handler(
+    State(app_state): State<AppState>,
+    Path((gallery_name, token, sub_path)): Path<(String, String, String)>,
+    headers: HeaderMap,
+    extensions: Extensions,
+    Form(form): Form<UnlockShareForm>,
+) -> Response {
+    let Some(gallery) = app_state.galleries.get(&gallery_name) else {
+        return (StatusCode::NOT_FOUND, "Gallery not found").into_response();
+    };
+
+    let share = match resolve_share(&app_state, &gallery_name, &token).await {
+        Ok(share) => share,
+        Err(response) => return response,
+    };
+
+    if share.has_password() {
+        // Guesses are limited per share and client address, sharing the
+        // login attempt window
+        let client_ip = extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string())
+            .unwrap_or_default();
+        let rate_limited = app_state
+            .login_state
+            .write()
+            .await
+            .check_rate_limit(&format!("share:{}:{}", share.id, client_ip))
+            .is_err();
+        if rate_limited {
+            return render_password_page(
+                &app_state,
+                &gallery_name,
+                StatusCode::TOO_MANY_REQUESTS,
+                Some("Too many attempts. Please try again later."),
+            )
+            .await;
+        }
+
+        let candidate = share.clone();
+        let correct = tokio::task::spawn_blocking(move || candidate.check_password(&form.password))
+            .await
+            .unwrap_or(false);
+        if !correct {
+            return render_password_page(
+                &app_state,
+                &gallery_name,
+                StatusCode::UNAUTHORIZED,
+                Some("Incorrect password"),
+            )
+            .await;
+        }
+    }
+
+    grant_and_redirect(&app_state, gallery, &share, &sub_path, &headers).await
+}
+
+/// Managing shares is limited to admins
+pub(super) async fn require_admin(
+    app_state: &AppState,
+    headers: &HeaderMap,
+) -> Result<UserIdentity, Response> {
+    match super::get_authenticated_user(app_state, headers).await {
+        Some(user) if user.is_admin() => Ok(user),
+        Some(_) => Err((StatusCode::FORBIDDEN, "Admin role required").into_response()),
+        None => Err((StatusCode::UNAUTHORIZED, "Authentication required").into_response()),
+    }
+}
+
+/// `POST /api/gallery/{name}/shares`
+pub async fn create_share_handler(
+    State(app_state): State<AppState>,
+    Path(gallery_name): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<CreateShareRequest>,
+) -> Response {
+    let user = match require_admin(&app_state, &headers).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let Some(gallery) = app_state.galleries.get(&gallery_name) else {
+        return (StatusCode::NOT_FOUND, "Gallery not found").into_response();
+    };
+
+    let path = request.path.trim_matches('/');
+    let Some(kind) = detect_share_kind(gallery.source_directory(), path) else {
+        return (StatusCode::NOT_FOUND, "No folder or image at that path").into_response();
+    };
+
+    let mut share = Share::new(&gallery_name, path, kind);
+    share.created_by = Some(user.username);
+    share.expires_at = request
+        .expires_in_days
+        .map(|days| share.created_at + (days as i64).saturating_mul(86_400));
+    share.allow_download = request.allow_download;
+    share.max_views = request.max_views;
+    if let Some(password) = request.password.as_deref().filter(|p| !p.is_empty()) {
+        share.set_password(password);
+    }
+
+    if let Err(e) = app_state.shares.insert(share.clone()).await {
+        error!("Failed to save share: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    let response = ShareResponse::new(&app_state, &gallery.get_config().url_prefix, share);
+    (StatusCode::CREATED, Json(response)).into_response()
+}
+
+/// `GET /api/gallery/{name}/shares`
+pub async fn list_shares_handler(
+    State(app_state): State<AppState>,
+    Path(gallery_name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(response) = require_admin(&app_state, &headers).await {
+        return response;
+    }
+    let Some(gallery) = app_state.galleries.get(&gallery_name) else {
+        return (StatusCode::NOT_FOUND, "Gallery not found").into_response();
+    };
+
+    let url_prefix = &gallery.get_config().url_prefix;
+    let shares: Vec<ShareResponse> = app_state
+        .shares
+        .list(Some(&gallery_name))
+        .await
+        .into_iter()
+        .map(|share| ShareResponse::new(&app_state, url_prefix, share))
+        .collect();
+    Json(shares).into_response()
+}
+
+/// `DELETE /api/gallery/{name}/shares/{id}`
+pub async fn revoke_share_handler(
+    State(app_state): State<AppState>,
+    Path((gallery_name, id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(response) = require_admin(&app_state, &headers).await {
+        return response;
+    }
+
+    let belongs_to_gallery = app_state
+        .shares
+        .get(&id)
+        .await
+        .is_some_and(|share| share.gallery == gallery_name);
+    if !belongs_to_gallery {
+        return (StatusCode::NOT_FOUND, "Share not found").into_response();
+    }
+
+    match app_state.shares.remove(&id).await {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            error!("Failed to revoke share {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
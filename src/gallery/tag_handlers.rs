@@ -0,0 +1,159 @@
+use super::handlers::get_authenticated_user;
+use super::{BreadcrumbItem, GalleryQuery};
+use crate::AppState;
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse},
+};
+use tracing::error;
+
+const TAGS_TEMPLATE: &str = "modules/tags.html.liquid";
+
+/// Handler for `{prefix}/tags`: every tag with its image count
+pub async fn tag_index_handler_for_named(
+    State(app_state): State<AppState>,
+    Path(gallery_name): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(gallery) = app_state.galleries.get(&gallery_name) else {
+        error!("Gallery '{}' not found", gallery_name);
+        return (StatusCode::NOT_FOUND, "Gallery not found").into_response();
+    };
+
+    let user = get_authenticated_user(&app_state, &headers).await;
+    let tags = gallery.list_tags_with_user(user.as_ref()).await;
+    let gallery_config = gallery.get_config();
+
+    let liquid_context = liquid::object!({
+        "gallery_name": gallery_name,
+        "gallery_url": gallery_config.url_prefix,
+        "tags": tags,
+        "page_title": "Tags",
+        "meta_description": format!("Browse {} photos by tag", gallery_name),
+        "app_name": app_state.config.app.name,
+        "copyright_holder": gallery.config.copyright_holder,
+        "base_url": app_state.config.app.base_url,
+    });
+
+    match app_state
+        .template_engine
+        .render_template(TAGS_TEMPLATE, liquid_context)
+        .await
+    {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => {
+            error!("Template rendering error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
+        }
+    }
+}
+
+/// Handler for `{prefix}/tags/{tag}`: the tagged images as a virtual folder,
+/// rendered with the gallery template
+pub async fn tag_handler_for_named(
+    State(app_state): State<AppState>,
+    Path((gallery_name, tag)): Path<(String, String)>,
+    Query(query): Query<GalleryQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(gallery) = app_state.galleries.get(&gallery_name) else {
+        error!("Gallery '{}' not found", gallery_name);
+        return (StatusCode::NOT_FOUND, "Gallery not found").into_response();
+    };
+
+    // Each image is checked against its own folder's access rules; a tag with
+    // nothing visible is indistinguishable from an unknown one
+    let user = get_authenticated_user(&app_state, &headers).await;
+    let page = query.page.unwrap_or(0);
+    let (tag_name, images, total_pages) =
+        match gallery.list_tag_with_user(&tag, page, user.as_ref()).await {
+            Ok(result) => result,
+            Err(_) => return (StatusCode::NOT_FOUND, "Tag not found").into_response(),
+        };
+
+    let gallery_config = gallery.get_config();
+    let images_json = serde_json::to_string(&images).unwrap_or_else(|_| "[]".to_string());
+    let breadcrumbs = vec![
+        BreadcrumbItem {
+            name: "Gallery".to_string(),
+            display_name: "Gallery".to_string(),
+            path: String::new(),
+            is_current: false,
+        },
+        BreadcrumbItem {
+            name: "tags".to_string(),
+            display_name: "Tags".to_string(),
+            path: "tags".to_string(),
+            is_current: false,
+        },
+        BreadcrumbItem {
+            name: tag.clone(),
+            display_name: tag_name.clone(),
+            path: format!("tags/{}", urlencoding::encode(&tag)),
+            is_current: true,
+        },
+    ];
+
+    let og_image = images.first().map(|image| {
+        format!(
+            "{}{}",
+            app_state
+                .config
+                .app
+                .base_url
+                .as_ref()
+                .unwrap_or(&String::new()),
+            image.gallery_url.as_ref().unwrap_or(&String::new())
+        )
+    });
+    let folder_title = format!("Tagged \u{201C}{}\u{201D}", tag_name);
+
+    let liquid_context = liquid::object!({
+        "gallery_name": gallery_name,
+        "gallery_url": gallery_config.url_prefix,
+        "gallery_path": "",
+        "is_root": false,
+        "tag": tag_name,
+        "tags_url": format!("{}/tags", gallery_config.url_prefix),
+        // Keeps prev/next on the detail page within the tag
+        "detail_query": format!("?tag={}", urlencoding::encode(&tag)),
+        "can_download_original": false,
+        "image_sizes": gallery_config.image_sizes(),
+        "breadcrumbs": breadcrumbs,
+        "directories": Vec::<super::GalleryItem>::new(),
+        "images": images,
+        "items": images,
+        "images_json": images_json,
+        "page": page,
+        "current_page": page,
+        "total_pages": total_pages,
+        "has_prev": page > 0,
+        "has_next": page + 1 < total_pages,
+        "prev_page": page.saturating_sub(1),
+        "next_page": page + 1,
+        "folder_title": folder_title,
+        "folder_description": None::<String>,
+        "page_title": folder_title,
+        "meta_description": format!("Photos tagged {}", tag_name),
+        "app_name": app_state.config.app.name,
+        "copyright_holder": gallery.config.copyright_holder,
+        "base_url": app_state.config.app.base_url,
+        "og_title": format!("{} - Photo Gallery", folder_title),
+        "og_description": format!("Photos tagged {}", tag_name),
+        "og_image": og_image,
+        "twitter_card_type": "summary_large_image",
+    });
+
+    match app_state
+        .template_engine
+        .render_template(&gallery_config.gallery_template, liquid_context)
+        .await
+    {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => {
+            error!("Template rendering error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR).into_response()
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use super::image_processing::video::video_info;
+use super::search::TaggedImage;
+use super::{Gallery, GalleryError, GalleryItem, TagLink, TagSummary};
+use crate::login::UserIdentity;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+
+/// Tags match case-insensitively, so "Sunset" and "sunset" are one tag
+pub(crate) fn tag_key(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+/// The spelling used by most images, so a stray lowercase keyword doesn't
+/// rename a tag. Ties go to the alphabetically first spelling.
+fn display_name(spellings: HashMap<String, usize>) -> String {
+    spellings
+        .into_iter()
+        .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then_with(|| b.cmp(a)))
+        .map(|(spelling, _)| spelling)
+        .unwrap_or_default()
+}
+
+/// Oldest capture date first, undated images last, then by path
+fn compare_tagged(a: &TaggedImage, b: &TaggedImage) -> Ordering {
+    match (a.capture_date, b.capture_date) {
+        (Some(a_date), Some(b_date)) => a_date.cmp(&b_date),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+    .then_with(|| a.path.cmp(&b.path))
+}
+
+/// Images carrying one tag, in display order
+pub(crate) struct TagListing {
+    pub name: String,
+    pub paths: Vec<String>,
+}
+
+impl Gallery {
+    /// Page listing the images tagged `tag`
+    pub fn tag_url(&self, tag: &str) -> String {
+        format!(
+            "/{}/tags/{}",
+            self.config.url_prefix.trim_start_matches('/'),
+            urlencoding::encode(&tag_key(tag))
+        )
+    }
+
+    /// Links to the pages of an image's own tags
+    pub async fn image_tag_links(&self, path: &str) -> Vec<TagLink> {
+        self.image_tags(path)
+            .await
+            .into_iter()
+            .map(|tag| TagLink {
+                url: self.tag_url(&tag),
+                name: tag,
+            })
+            .collect()
+    }
+
+    /// Tagged images in folders `user` may open. Hidden folders are left out,
+    /// as they are from search.
+    async fn visible_tagged_images(&self, user: Option<&UserIdentity>) -> Vec<TaggedImage> {
+        let mut visible_folders: HashMap<String, bool> = HashMap::new();
+        let mut visible = Vec::new();
+        for image in self.tagged_images().await {
+            let folder = image
+                .path
+                .rsplit_once('/')
+                .map(|(parent, _)| parent)
+                .unwrap_or("")
+                .to_string();
+            let is_visible = match visible_folders.get(&folder) {
+                Some(is_visible) => *is_visible,
+                None => {
+                    let is_visible = self.is_folder_searchable(&folder, user).await;
+                    visible_folders.insert(folder, is_visible);
+                    is_visible
+                }
+            };
+            if is_visible {
+                visible.push(image);
+            }
+        }
+        visible
+    }
+
+    /// Every tag on an image `user` can see with the number of such images,
+    /// sorted by name
+    pub async fn list_tags_with_user(&self, user: Option<&UserIdentity>) -> Vec<TagSummary> {
+        // Image count and how often each spelling is used, per tag
+        let mut tags: BTreeMap<String, (usize, HashMap<String, usize>)> = BTreeMap::new();
+        for image in self.visible_tagged_images(user).await {
+            for tag in image.tags {
+                let (count, spellings) = tags.entry(tag_key(&tag)).or_default();
+                *count += 1;
+                *spellings.entry(tag).or_default() += 1;
+            }
+        }
+
+        tags.into_iter()
+            .map(|(key, (count, spellings))| TagSummary {
+                name: display_name(spellings),
+                count,
+                url: self.tag_url(&key),
+            })
+            .collect()
+    }
+
+    /// The images tagged `tag` that `user` can see. `None` when there are none.
+    pub(crate) async fn tag_listing(
+        &self,
+        tag: &str,
+        user: Option<&UserIdentity>,
+    ) -> Option<TagListing> {
+        let key = tag_key(tag);
+        let mut spellings: HashMap<String, usize> = HashMap::new();
+        let mut images: Vec<TaggedImage> = self
+            .visible_tagged_images(user)
+            .await
+            .into_iter()
+            .filter(|image| {
+                let spelling = image.tags.iter().find(|t| tag_key(t) == key);
+                if let Some(spelling) = spelling {
+                    *spellings.entry(spelling.clone()).or_default() += 1;
+                }
+                spelling.is_some()
+            })
+            .collect();
+
+        if images.is_empty() {
+            return None;
+        }
+        images.sort_by(compare_tagged);
+
+        Some(TagListing {
+            name: display_name(spellings),
+            paths: images.into_iter().map(|image| image.path).collect(),
+        })
+    }
+
+    /// One page of the images tagged `tag`, as a virtual folder: the tag's
+    /// display name, the images and the total number of pages
+    pub async fn list_tag_with_user(
+        &self,
+        tag: &str,
+        page: usize,
+        user: Option<&UserIdentity>,
+    ) -> Result<(String, Vec<GalleryItem>, usize), GalleryError> {
+        let listing = self
+            .tag_listing(tag, user)
+            .await
+            .ok_or(GalleryError::NotFound)?;
+
+        let total_pages = listing
+            .paths
+            .len()
+            .div_ceil(self.config.images_per_page)
+            .max(1);
+
+        let mut images = Vec::new();
+        for item_path in listing
+            .paths
+            .iter()
+            .skip(page * self.config.images_per_page)
+            .take(self.config.images_per_page)
+        {
+            let Ok(metadata) = self.get_image_metadata_cached(item_path).await else {
+                continue;
+            };
+
+            let encoded_path = urlencoding::encode(item_path);
+            let (parent_path, file_name) = item_path.rsplit_once('/').unwrap_or(("", item_path));
+            images.push(GalleryItem {
+                name: file_name.to_string(),
+                display_name: None,
+                description: None,
+                path: item_path.clone(),
+                parent_path: Some(parent_path.to_string()),
+                is_directory: false,
+                thumbnail_url: Some(format!(
+                    "/{}/image/{}?size=thumbnail",
+                    self.config.url_prefix.trim_start_matches('/'),
+                    encoded_path
+                )),
+                gallery_url: Some(format!(
+                    "/{}/image/{}?size=gallery",
+                    self.config.url_prefix.trim_start_matches('/'),
+                    encoded_path
+                )),
+                preview_images: None,
+                item_count: None,
+                dimensions: Some(metadata.dimensions),
+                capture_date: metadata.capture_date,
+                is_new: self.is_new(metadata.modification_date),
+                placeholder: metadata.placeholder,
+                video: video_info(item_path, metadata.duration),
+            });
+        }
+
+        Ok((listing.name, images, total_pages))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_name_prefers_most_used_spelling() {
+        let spellings = HashMap::from([
+            ("San Francisco".to_string(), 3),
+            ("san francisco".to_string(), 1),
+        ]);
+        assert_eq!(display_name(spellings), "San Francisco");
+
+        let tied = HashMap::from([("sunset".to_string(), 2), ("Sunset".to_string(), 2)]);
+        assert_eq!(display_name(tied), "Sunset");
+
+        assert_eq!(tag_key("  Night Sky "), "night sky");
+    }
+}
@@ -16,6 +16,8 @@ pub struct GalleryItem {
     pub dimensions: Option<(u32, u32)>,
     pub capture_date: Option<SystemTime>,
     pub is_new: bool,
+    pub placeholder: Option<ImagePlaceholder>,
+    pub video: Option<VideoInfo>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -34,6 +36,41 @@ pub struct ImageInfo {
     pub capture_date: Option<String>,
     pub is_new: bool,
     pub color_profile: Option<String>,
+    pub placeholder: Option<ImagePlaceholder>,
+    pub descriptive_info: Option<DescriptiveInfo>,
+    pub video: Option<VideoInfo>,
+}
+
+/// Playback details of a video item. Its thumbnails and other sizes are
+/// resized from a poster frame.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VideoInfo {
+    /// Type for the `<video>` element's `<source>`
+    pub mime_type: String,
+    /// Running time as `m:ss`, or `h:mm:ss` for an hour or more
+    pub duration: Option<String>,
+}
+
+/// Stand-in shown while an image loads
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImagePlaceholder {
+    /// Blurred ~16px WebP as a `data:` URI
+    pub data_uri: String,
+    /// Most common color as `#rrggbb`
+    pub dominant_color: String,
+}
+
+/// Titles, captions and rights embedded as XMP or IPTC by cataloguing tools
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DescriptiveInfo {
+    pub title: Option<String>,
+    pub caption: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Star rating from 0 to 5
+    pub rating: Option<u8>,
+    pub creator: Option<String>,
+    pub copyright: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -66,6 +103,16 @@ pub struct NavigationImage {
 pub struct GalleryQuery {
     pub page: Option<usize>,
     pub size: Option<String>,
+    /// Output format for resized images, overriding `Accept` negotiation
+    pub format: Option<String>,
+    /// Tag an image detail page was opened from, for prev/next navigation
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct MapQuery {
+    /// Map zoom level to cluster points for; unclustered when absent
+    pub zoom: Option<u8>,
 }
 
 // Internal types
@@ -83,6 +130,13 @@ pub(crate) struct ImageMetadata {
     pub location_info: Option<LocationInfo>,
     pub modification_date: Option<SystemTime>,
     pub color_profile: Option<String>,
+    #[serde(default)]
+    pub placeholder: Option<ImagePlaceholder>,
+    #[serde(default)]
+    pub descriptive_info: Option<DescriptiveInfo>,
+    /// Running time in seconds, for videos
+    #[serde(default)]
+    pub duration: Option<f64>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -95,6 +149,109 @@ pub(crate) struct FolderConfig {
     #[serde(default)]
     pub require_auth: bool,
     pub allowed_users: Option<Vec<String>>,
+    pub allowed_groups: Option<Vec<String>>,
+
+    // Download permissions. Entries are usernames, group names, or the special
+    // values "everyone" and "authenticated". Unset inherits from parent folders.
+    pub download_large: Option<Vec<String>>,
+    pub download_original: Option<Vec<String>>,
+
+    // Ordering fields. An unknown value falls back to the default order
+    // rather than failing the whole front matter.
+    #[serde(default, deserialize_with = "lenient_sort")]
+    pub sort: Option<FolderSort>,
+    /// File or folder names in display order, used by `sort = "manual"`
+    pub order: Option<Vec<String>>,
+    /// Image (relative to this folder) shown first in this folder's preview
+    pub cover: Option<String>,
+}
+
+fn lenient_sort<'de, D>(deserializer: D) -> Result<Option<FolderSort>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::IntoDeserializer;
+
+    let Some(value) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let parsed: Result<FolderSort, serde::de::value::Error> =
+        FolderSort::deserialize(value.as_str().into_deserializer());
+    match parsed {
+        Ok(sort) => Ok(Some(sort)),
+        Err(_) => {
+            tracing::warn!("Unknown folder sort {:?}, using the default order", value);
+            Ok(None)
+        }
+    }
+}
+
+impl FolderConfig {
+    /// Config for a folder whose `_folder.md` can't be read as intended:
+    /// hidden, and visible only to admins, until the front matter is fixed
+    pub fn locked() -> Self {
+        FolderConfig {
+            hidden: true,
+            title: None,
+            require_auth: true,
+            allowed_users: Some(Vec::new()),
+            allowed_groups: Some(Vec::new()),
+            download_large: Some(Vec::new()),
+            download_original: Some(Vec::new()),
+            sort: None,
+            order: None,
+            cover: None,
+        }
+    }
+
+    pub fn is_access_restricted(&self) -> bool {
+        self.require_auth || self.allowed_users.is_some() || self.allowed_groups.is_some()
+    }
+
+    pub fn download_permission(&self, kind: DownloadKind) -> Option<&Vec<String>> {
+        match kind {
+            DownloadKind::Large => self.download_large.as_ref(),
+            DownloadKind::Original => self.download_original.as_ref(),
+        }
+    }
+
+    /// A manual `order` list without an explicit `sort` implies manual sorting
+    pub fn sort_order(&self) -> FolderSort {
+        self.sort.unwrap_or(if self.order.is_some() {
+            FolderSort::Manual
+        } else {
+            FolderSort::CaptureDate
+        })
+    }
+}
+
+/// Image sizes that are only served to permitted users
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadKind {
+    /// `?size=large`, and other sizes marked `requires_auth`
+    Large,
+    /// The untouched source file
+    Original,
+}
+
+/// How images within a folder are ordered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FolderSort {
+    /// Oldest capture date first, images without a date last
+    #[default]
+    CaptureDate,
+    /// Newest capture date first, images without a date last
+    CaptureDateDesc,
+    Filename,
+    FilenameDesc,
+    /// Oldest file modification time first
+    Modified,
+    /// Newest file modification time first
+    ModifiedDesc,
+    /// Entries follow the folder's `order` list; anything not listed comes
+    /// after, by capture date
+    Manual,
 }
 
 #[derive(Debug, Clone)]
@@ -102,3 +259,38 @@ pub(crate) struct FolderMetadata {
     pub config: FolderConfig,
     pub description_markdown: String,
 }
+
+/// TOML front matter of an image's sidecar caption (`<stem>.md`)
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct SidecarConfig {
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct SidecarMetadata {
+    pub config: SidecarConfig,
+    pub caption_markdown: String,
+}
+
+/// A tag and how many visible images carry it
+#[derive(Debug, Clone, Serialize)]
+pub struct TagSummary {
+    pub name: String,
+    pub count: usize,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagLink {
+    pub name: String,
+    pub url: String,
+}
+
+/// A folder or image that is visible without logging in
+#[derive(Debug, Clone)]
+pub struct PublicEntry {
+    pub path: String,
+    pub is_directory: bool,
+    pub last_modified: Option<SystemTime>,
+}
@@ -1,19 +1,26 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 pub mod api;
+mod atomic_file;
 pub mod commands;
 pub mod composite;
 pub mod copyright;
 pub mod email;
 pub mod favicon;
+pub mod feeds;
+pub mod file_response;
 pub mod gallery;
 pub mod login;
 pub mod posts;
 pub mod robots;
+pub mod search;
+pub mod sitemap;
 pub mod startup_checks;
 pub mod static_files;
 pub mod templating;
+pub mod watcher;
 pub mod webp_encoder;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -45,6 +52,34 @@ pub struct AppConfig {
     pub base_url: Option<String>,
     #[serde(default)]
     pub user_database: Option<PathBuf>,
+    /// File login sessions are saved to. Defaults to `sessions.json` next to
+    /// the user database.
+    #[serde(default)]
+    pub session_store: Option<PathBuf>,
+    /// Days a login session stays valid before the user has to sign in again
+    #[serde(default = "default_session_lifetime_days")]
+    pub session_lifetime_days: u64,
+    /// File share links are saved to. Defaults to `shares.json` next to the
+    /// user database.
+    #[serde(default)]
+    pub share_store: Option<PathBuf>,
+    /// Images decoded and encoded at once across all galleries. Defaults to
+    /// one less than the number of CPU cores.
+    #[serde(default)]
+    pub image_workers: Option<usize>,
+}
+
+impl AppConfig {
+    /// Where share links are stored; `None` when logins are disabled, since
+    /// everything is already public
+    pub fn share_store_path(&self) -> Option<PathBuf> {
+        let db_path = self.user_database.as_ref()?;
+        Some(
+            self.share_store
+                .clone()
+                .unwrap_or_else(|| db_path.with_file_name("shares.json")),
+        )
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -200,22 +235,233 @@ pub struct GallerySystemConfig {
     pub webp_quality: Option<f32>,
     #[serde(default)]
     pub pregenerate_cache: bool,
+    /// Images pre-generated at once. Defaults to 2, leaving the rest of the
+    /// image workers to live requests.
+    #[serde(default)]
+    pub pregenerate_parallelism: Option<usize>,
     /// Number of days to consider an image as "new" (based on file modification date)
     pub new_threshold_days: Option<u32>,
     /// When true, show only approximate capture dates (month/year) to non-authenticated users
     #[serde(default = "default_false")]
     pub approximate_dates_for_public: bool,
+    /// How precisely GPS locations are shown to users who may download large images
+    #[serde(default)]
+    pub location_precision: LocationPrecision,
+    /// How precisely GPS locations are shown to everyone else, as with
+    /// `approximate_dates_for_public`
+    #[serde(default)]
+    pub location_precision_for_public: LocationPrecision,
+    /// Which embedded metadata survives in resized images and original downloads
+    #[serde(default)]
+    pub metadata: MetadataConfig,
+    /// `ffmpeg` binary used to grab poster frames from videos. Without one,
+    /// videos get a generated placeholder poster.
+    #[serde(default)]
+    pub ffmpeg_path: Option<PathBuf>,
+    /// Most bytes the cache directory may hold. The least recently served
+    /// files are deleted to stay under it. Unlimited when unset.
+    #[serde(default)]
+    pub max_cache_size: Option<u64>,
     /// Copyright holder name for watermarking medium-sized images
     #[serde(default)]
     pub copyright_holder: Option<String>,
+    /// Watch the source directory for changes and update metadata immediately.
+    /// Periodic refresh still runs as a fallback when notifications are unavailable.
+    #[serde(default = "default_true")]
+    pub watch_filesystem: bool,
+    /// Named image sizes, served as `?size=<name>`. Entries named thumbnail,
+    /// gallery, medium or large replace the built-in sizes.
+    #[serde(default)]
+    pub sizes: BTreeMap<String, NamedSizeConfig>,
 }
 
+impl GallerySystemConfig {
+    /// Look up a named size, falling back to the built-in thumbnail, gallery,
+    /// medium and large sizes
+    pub fn image_size(&self, name: &str) -> Option<NamedSizeConfig> {
+        if let Some(size) = self.sizes.get(name) {
+            return Some(size.clone());
+        }
+
+        let builtin = |dimensions: &ImageSizeConfig| NamedSizeConfig {
+            width: dimensions.width,
+            height: dimensions.height,
+            crop: CropMode::Fit,
+            quality: None,
+            watermark: false,
+            requires_auth: false,
+        };
+        match name {
+            "thumbnail" => Some(builtin(&self.thumbnail)),
+            "gallery" => Some(builtin(&self.gallery_size)),
+            "medium" => Some(NamedSizeConfig {
+                watermark: true,
+                ..builtin(&self.medium)
+            }),
+            "large" => Some(NamedSizeConfig {
+                requires_auth: true,
+                ..builtin(&self.large)
+            }),
+            _ => None,
+        }
+    }
+
+    /// All named sizes, built-in ones included
+    pub fn image_sizes(&self) -> BTreeMap<String, NamedSizeConfig> {
+        BUILTIN_IMAGE_SIZES
+            .iter()
+            .map(|name| name.to_string())
+            .chain(self.sizes.keys().cloned())
+            .filter_map(|name| self.image_size(&name).map(|size| (name, size)))
+            .collect()
+    }
+}
+
+/// Sizes every gallery has, configured by the `thumbnail`, `gallery_size`,
+/// `medium` and `large` settings
+pub const BUILTIN_IMAGE_SIZES: [&str; 4] = ["thumbnail", "gallery", "medium", "large"];
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ImageSizeConfig {
     pub width: u32,
     pub height: u32,
 }
 
+/// How an image is fitted into a size's width and height
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CropMode {
+    /// Scale down to fit inside the box, keeping the whole image
+    #[default]
+    #[serde(rename = "fit")]
+    Fit,
+    /// Scale to cover the box and crop the overflow evenly from both sides
+    #[serde(rename = "fill")]
+    Fill,
+    /// Like `fill`, but keep the most detailed part of the image
+    #[serde(rename = "smart-crop", alias = "smart")]
+    SmartCrop,
+}
+
+/// How much of an image's GPS location is revealed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LocationPrecision {
+    /// Coordinates as recorded
+    #[default]
+    Exact,
+    /// Rounded to one decimal place, roughly the nearest town
+    City,
+    /// Not shown at all
+    Hidden,
+}
+
+/// Which embedded EXIF, XMP and IPTC metadata is kept in a served file. ICC
+/// profiles are always kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MetadataPolicy {
+    /// Remove all of it
+    Strip,
+    /// Keep only the creator and copyright notice
+    CopyrightOnly,
+    /// Keep everything except the GPS location
+    StripGps,
+    /// Keep everything
+    KeepAll,
+}
+
+impl MetadataPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MetadataPolicy::Strip => "strip",
+            MetadataPolicy::CopyrightOnly => "copyright-only",
+            MetadataPolicy::StripGps => "strip-gps",
+            MetadataPolicy::KeepAll => "keep-all",
+        }
+    }
+}
+
+/// `[galleries.metadata]`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MetadataConfig {
+    /// Resized images, which are re-encoded
+    #[serde(default = "default_derivative_metadata")]
+    pub derivatives: MetadataPolicy,
+    /// Original downloads, rewritten without re-encoding
+    #[serde(default = "default_original_metadata")]
+    pub originals: MetadataPolicy,
+}
+
+impl Default for MetadataConfig {
+    fn default() -> Self {
+        MetadataConfig {
+            derivatives: default_derivative_metadata(),
+            originals: default_original_metadata(),
+        }
+    }
+}
+
+fn default_derivative_metadata() -> MetadataPolicy {
+    MetadataPolicy::Strip
+}
+
+fn default_original_metadata() -> MetadataPolicy {
+    MetadataPolicy::KeepAll
+}
+
+/// An entry in `[galleries.sizes]`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct NamedSizeConfig {
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub crop: CropMode,
+    /// Encoder quality for this size, overriding `jpeg_quality` and `webp_quality`
+    #[serde(default)]
+    pub quality: Option<u8>,
+    /// Stamp the copyright notice on this size when `copyright_holder` is set
+    #[serde(default)]
+    pub watermark: bool,
+    /// Only serve this size to users with `large` download permission
+    #[serde(default)]
+    pub requires_auth: bool,
+}
+
+impl NamedSizeConfig {
+    /// Pixel dimensions of this size (times `multiplier` for `@2x`) when
+    /// generated from a `source` image, matching what the resizer produces
+    pub fn output_dimensions(&self, source: (u32, u32), multiplier: u32) -> (u32, u32) {
+        let (orig_width, orig_height) = (source.0.max(1), source.1.max(1));
+        let box_width = self.width.saturating_mul(multiplier).max(1);
+        let box_height = self.height.saturating_mul(multiplier).max(1);
+
+        match self.crop {
+            CropMode::Fit => {
+                let box_width = box_width.min(orig_width);
+                let box_height = box_height.min(orig_height);
+                if (box_width, box_height) == (orig_width, orig_height) {
+                    return (orig_width, orig_height);
+                }
+                let ratio = (box_width as f64 / orig_width as f64)
+                    .min(box_height as f64 / orig_height as f64);
+                (
+                    ((orig_width as f64 * ratio).round() as u32).max(1),
+                    ((orig_height as f64 * ratio).round() as u32).max(1),
+                )
+            }
+            CropMode::Fill | CropMode::SmartCrop => {
+                let scale = (orig_width as f64 / box_width as f64)
+                    .min(orig_height as f64 / box_height as f64)
+                    .min(1.0);
+                (
+                    ((box_width as f64 * scale).round() as u32).clamp(1, orig_width),
+                    ((box_height as f64 * scale).round() as u32).clamp(1, orig_height),
+                )
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct PreviewConfig {
     pub max_images: usize,
@@ -235,6 +481,9 @@ pub struct PostsSystemConfig {
     #[serde(default = "default_posts_per_page")]
     pub posts_per_page: usize,
     pub refresh_interval_minutes: Option<u64>,
+    /// Watch the source directory and reload posts as soon as they change
+    #[serde(default = "default_true")]
+    pub watch_filesystem: bool,
 }
 
 fn default_posts_index_template() -> String {
@@ -253,6 +502,14 @@ fn default_false() -> bool {
     false
 }
 
+fn default_true() -> bool {
+    true
+}
+
+fn default_session_lifetime_days() -> u64 {
+    7
+}
+
 fn default_gallery_template() -> String {
     "modules/gallery.html.liquid".to_string()
 }
@@ -314,6 +571,10 @@ impl Default for Config {
                 cookie_secret: "change-me-in-production-use-a-long-random-string".to_string(),
                 base_url: None,
                 user_database: None,
+                session_store: None,
+                session_lifetime_days: default_session_lifetime_days(),
+                share_store: None,
+                image_workers: None,
             },
             templates: TemplateConfig {
                 directories: vec![PathBuf::from("templates")],
@@ -338,9 +599,17 @@ impl Default for Config {
                 jpeg_quality: Some(85),
                 webp_quality: Some(85.0),
                 pregenerate_cache: false,
+                pregenerate_parallelism: None,
                 new_threshold_days: None,
                 approximate_dates_for_public: false,
+                location_precision: LocationPrecision::Exact,
+                location_precision_for_public: LocationPrecision::Exact,
+                metadata: MetadataConfig::default(),
+                ffmpeg_path: None,
+                max_cache_size: None,
                 copyright_holder: None,
+                watch_filesystem: true,
+                sizes: BTreeMap::new(),
             }]),
             posts: None,
             email: None,
@@ -357,7 +626,7 @@ use axum::{
 };
 use std::{collections::HashMap, sync::Arc};
 use tower_http::trace::TraceLayer;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 #[derive(Clone)]
 pub struct AppState {
@@ -368,6 +637,8 @@ pub struct AppState {
     pub posts_managers: Arc<HashMap<String, Arc<posts::PostsManager>>>,
     pub login_state: Arc<tokio::sync::RwLock<login::LoginState>>,
     pub user_database_manager: Option<login::types::UserDatabaseManager>,
+    pub sessions: login::SessionManager,
+    pub shares: Arc<gallery::ShareStore>,
     pub email_provider: Option<email::DynEmailProvider>,
     pub webauthn: Option<Arc<webauthn_rs::Webauthn>>,
     pub config: Config,
@@ -377,10 +648,14 @@ async fn static_file_handler(
     State(app_state): State<AppState>,
     Path(path): Path<String>,
     axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+    headers: axum::http::HeaderMap,
 ) -> impl IntoResponse {
     // Check if request has version parameter
     let has_version = params.contains_key("v");
-    app_state.static_handler.serve(&path, has_version).await
+    app_state
+        .static_handler
+        .serve(&path, has_version, &headers)
+        .await
 }
 
 async fn server_header_middleware(
@@ -399,7 +674,79 @@ async fn server_header_middleware(
     response
 }
 
+/// Cache pre-generation holds back while the server is busy. Static files
+/// and the progress endpoint don't use the image workers, and an admin
+/// watching progress shouldn't pause the run, so they don't count. Work that
+/// carries on after the handler returns, like a ZIP archive being written,
+/// holds its own `LiveRequest` until it ends.
+async fn live_request_middleware(
+    request: Request<axum::body::Body>,
+    next: Next,
+) -> impl IntoResponse {
+    let path = request.uri().path();
+    let counts = !(path.starts_with("/static/")
+        || path.starts_with("/favicon")
+        || path == "/robots.txt"
+        || (path.starts_with("/api/gallery/") && path.ends_with("/pregenerate")));
+    let _live = counts.then(gallery::LiveRequest::start);
+    next.run(request).await
+}
+
+/// Sessions are persisted only when logins are enabled; otherwise an empty
+/// in-memory store keeps `AppState` uniform
+async fn create_session_manager(config: &Config) -> login::SessionManager {
+    let Some(db_path) = config.app.user_database.as_ref() else {
+        return login::SessionManager::new(
+            Arc::new(login::FileSessionStore::in_memory()),
+            config.app.session_lifetime_days,
+        );
+    };
+
+    let session_path = config
+        .app
+        .session_store
+        .clone()
+        .unwrap_or_else(|| db_path.with_file_name("sessions.json"));
+    let store = match login::FileSessionStore::load(session_path.clone()).await {
+        Ok(store) => {
+            info!("Session store loaded from {:?}", session_path);
+            store
+        }
+        Err(e) => {
+            error!(
+                "Failed to load sessions from {:?}, starting empty: {}",
+                session_path, e
+            );
+            login::FileSessionStore::in_memory()
+        }
+    };
+
+    login::SessionManager::new(Arc::new(store), config.app.session_lifetime_days)
+}
+
+async fn create_share_store(config: &Config) -> gallery::ShareStore {
+    let Some(share_path) = config.app.share_store_path() else {
+        return gallery::ShareStore::in_memory();
+    };
+
+    match gallery::ShareStore::load(share_path.clone()).await {
+        Ok(store) => {
+            info!("Share store loaded from {:?}", share_path);
+            store
+        }
+        Err(e) => {
+            error!(
+                "Failed to load shares from {:?}, starting empty: {}",
+                share_path, e
+            );
+            gallery::ShareStore::in_memory()
+        }
+    }
+}
+
 pub async fn create_app(config: Config) -> axum::Router {
+    gallery::image_processing::workers::configure_workers(config.app.image_workers);
+
     let mut template_engine = templating::TemplateEngine::new(config.templates.directories.clone());
 
     let static_handler =
@@ -417,6 +764,12 @@ pub async fn create_app(config: Config) -> axum::Router {
     // Update file versions for the template engine
     template_engine.update_file_versions().await;
 
+    // Named sizes for the image URL filters
+    for gallery_config in config.galleries.iter().flatten() {
+        template_engine
+            .set_gallery_image_sizes(&gallery_config.url_prefix, gallery_config.image_sizes());
+    }
+
     let template_engine = Arc::new(template_engine);
 
     let favicon_renderer = favicon::FaviconRenderer::new(config.static_files.directories.clone());
@@ -426,6 +779,14 @@ pub async fn create_app(config: Config) -> axum::Router {
     if let Some(gallery_configs) = &config.galleries {
         for gallery_config in gallery_configs {
             let gallery = Arc::new(gallery::Gallery::new(gallery_config.clone()));
+            if gallery_config.watch_filesystem
+                && let Err(e) = watcher::watch_gallery(gallery.clone())
+            {
+                warn!(
+                    "Could not watch {:?} for gallery '{}', relying on periodic refresh: {}",
+                    gallery_config.source_directory, gallery_config.name, e
+                );
+            }
             galleries.insert(gallery_config.name.clone(), gallery);
         }
     }
@@ -461,18 +822,33 @@ pub async fn create_app(config: Config) -> axum::Router {
                 );
             }
 
+            if posts_config.watch_filesystem
+                && let Err(e) = watcher::watch_posts(
+                    posts_manager.clone(),
+                    posts_config.source_directory.clone(),
+                )
+            {
+                warn!(
+                    "Could not watch {:?} for posts '{}', relying on periodic refresh: {}",
+                    posts_config.source_directory, posts_config.name, e
+                );
+            }
+
             posts_managers.insert(posts_config.name.clone(), posts_manager);
         }
     }
 
     let posts_managers_arc = Arc::new(posts_managers);
 
+    let sessions = create_session_manager(&config).await;
+    let shares = Arc::new(create_share_store(&config).await);
+
     // Initialize login state and user database only if user database is configured
     let (login_state, user_database_manager) =
         if let Some(db_path) = config.app.user_database.as_ref() {
             let state = Arc::new(tokio::sync::RwLock::new(login::LoginState::new()));
-            // Start periodic cleanup for login tokens and rate limits
-            login::start_periodic_cleanup(state.clone());
+            // Start periodic cleanup for login tokens, rate limits and sessions
+            login::start_periodic_cleanup(state.clone(), sessions.clone());
 
             // Initialize user database manager
             let db_manager = match login::types::UserDatabaseManager::new(db_path.clone()).await {
@@ -535,6 +911,8 @@ pub async fn create_app(config: Config) -> axum::Router {
         posts_managers: posts_managers_arc.clone(),
         login_state,
         user_database_manager,
+        sessions,
+        shares,
         email_provider,
         webauthn,
         config: config.clone(),
@@ -565,6 +943,16 @@ pub async fn create_app(config: Config) -> axum::Router {
             "/robots.txt",
             axum::routing::get(robots::robots_txt_handler),
         )
+        .route("/sitemap.xml", axum::routing::get(sitemap::sitemap_handler))
+        .route(
+            "/sitemap/{page}",
+            axum::routing::get(sitemap::sitemap_page_handler),
+        )
+        .route("/search", axum::routing::get(search::search_page_handler))
+        .route(
+            "/api/search",
+            axum::routing::get(search::search_api_handler),
+        )
         .route("/static/{*path}", axum::routing::get(static_file_handler));
 
     // Add login routes only if user database is configured
@@ -574,6 +962,14 @@ pub async fn create_app(config: Config) -> axum::Router {
             .route("/_login/request", axum::routing::post(login::login_request))
             .route("/_login/verify", axum::routing::get(login::verify_login))
             .route("/_login/logout", axum::routing::get(login::logout))
+            .route(
+                "/_login/logout-everywhere",
+                axum::routing::post(login::logout_everywhere),
+            )
+            .route(
+                "/_login/sessions/{session_id}/revoke",
+                axum::routing::post(login::revoke_session),
+            )
             .route(
                 "/_login/passkeys",
                 axum::routing::get(templating::template_with_gallery_handler),
@@ -678,22 +1074,176 @@ pub async fn create_app(config: Config) -> axum::Router {
                 }),
             );
 
+            // Whole-folder ZIP downloads
+            router = router.route(
+                &format!("{}/download/{{*path}}", prefix),
+                axum::routing::get({
+                    let name = name.clone();
+                    move |state, path: Path<String>, query, headers| {
+                        let archive_path = path.0;
+                        gallery::folder_archive_handler_for_named(
+                            state,
+                            Path((name, archive_path)),
+                            query,
+                            headers,
+                        )
+                    }
+                }),
+            );
+
+            // Share links, only meaningful when some folders need a login
+            if config.app.user_database.is_some() {
+                router = router
+                    .route(
+                        &format!("{}/s/{{token}}", prefix),
+                        axum::routing::get({
+                            let name = name.clone();
+                            move |state, Path(token): Path<String>, headers| {
+                                gallery::open_share_handler(
+                                    state,
+                                    Path((name, token, String::new())),
+                                    headers,
+                                )
+                            }
+                        })
+                        .post({
+                            let name = name.clone();
+                            move |state, Path(token): Path<String>, headers, form| {
+                                gallery::unlock_share_handler(
+                                    state,
+                                    Path((name, token, String::new())),
+                                    headers,
+                                    form,
+                                )
+                            }
+                        }),
+                    )
+                    .route(
+                        &format!("{}/s/{{token}}/{{*path}}", prefix),
+                        axum::routing::get({
+                            let name = name.clone();
+                            move |state, Path((token, path)): Path<(String, String)>, headers| {
+                                gallery::open_share_handler(
+                                    state,
+                                    Path((name, token, path)),
+                                    headers,
+                                )
+                            }
+                        })
+                        .post({
+                            let name = name.clone();
+                            move |state,
+                                  Path((token, path)): Path<(String, String)>,
+                                  headers,
+                                  form| {
+                                gallery::unlock_share_handler(
+                                    state,
+                                    Path((name, token, path)),
+                                    headers,
+                                    form,
+                                )
+                            }
+                        }),
+                    )
+                    .route(
+                        &format!("/api/gallery/{}/shares", name),
+                        axum::routing::get({
+                            let name = name.clone();
+                            move |state, headers| {
+                                gallery::list_shares_handler(state, Path(name), headers)
+                            }
+                        })
+                        .post({
+                            let name = name.clone();
+                            move |state, headers, body| {
+                                gallery::create_share_handler(state, Path(name), headers, body)
+                            }
+                        }),
+                    )
+                    .route(
+                        &format!("/api/gallery/{}/shares/{{id}}", name),
+                        axum::routing::delete({
+                            let name = name.clone();
+                            move |state, Path(id): Path<String>, headers| {
+                                gallery::revoke_share_handler(state, Path((name, id)), headers)
+                            }
+                        }),
+                    );
+            }
+
+            // Atom feed of recently added images
+            router = router.route(
+                &format!("{}/feed.xml", prefix),
+                axum::routing::get({
+                    let name = name.clone();
+                    move |state| feeds::gallery_atom_handler(state, Path(name))
+                }),
+            );
+
             // Image detail view
             router = router.route(
                 &format!("{}/detail/{{*path}}", prefix),
                 axum::routing::get({
                     let name = name.clone();
-                    move |state, path: Path<String>, headers| {
+                    move |state, path: Path<String>, query, headers| {
                         let detail_path = path.0;
                         gallery::image_detail_handler_for_named(
                             state,
                             Path((name, detail_path)),
+                            query,
                             headers,
                         )
                     }
                 }),
             );
 
+            // Tag index and per-tag virtual folders
+            router = router
+                .route(
+                    &format!("{}/tags", prefix),
+                    axum::routing::get({
+                        let name = name.clone();
+                        move |state, headers| {
+                            gallery::tag_index_handler_for_named(state, Path(name), headers)
+                        }
+                    }),
+                )
+                .route(
+                    &format!("{}/tags/{{tag}}", prefix),
+                    axum::routing::get({
+                        let name = name.clone();
+                        move |state, Path(tag): Path<String>, query, headers| {
+                            gallery::tag_handler_for_named(state, Path((name, tag)), query, headers)
+                        }
+                    }),
+                );
+
+            // Map of geotagged images
+            router = router
+                .route(
+                    &format!("{}/map", prefix),
+                    axum::routing::get({
+                        let name = name.clone();
+                        move |state, headers| {
+                            gallery::map_handler_for_named(
+                                state,
+                                Path((name, String::new())),
+                                headers,
+                            )
+                        }
+                    }),
+                )
+                .route(
+                    &format!("{}/map/{{*path}}", prefix),
+                    axum::routing::get({
+                        let name = name.clone();
+                        move |state, path: Path<String>, headers| {
+                            let map_path = path.0;
+                            gallery::map_handler_for_named(state, Path((name, map_path)), headers)
+                        }
+                    }),
+                );
+
             // API routes for gallery
             router = router.route(
                 &format!("/api/gallery/{}/preview", name),
@@ -705,15 +1255,104 @@ pub async fn create_app(config: Config) -> axum::Router {
                 }),
             );
 
+            router = router.route(
+                &format!("/api/gallery/{}/list", name),
+                axum::routing::get({
+                    let name = name.clone();
+                    move |state, query, headers| {
+                        gallery::gallery_list_api_handler_for_named(
+                            state,
+                            Path((name, String::new())),
+                            query,
+                            headers,
+                        )
+                    }
+                }),
+            );
+
+            router = router.route(
+                &format!("/api/gallery/{}/list/{{*path}}", name),
+                axum::routing::get({
+                    let name = name.clone();
+                    move |state, path: Path<String>, query, headers| {
+                        let list_path = path.0;
+                        gallery::gallery_list_api_handler_for_named(
+                            state,
+                            Path((name, list_path)),
+                            query,
+                            headers,
+                        )
+                    }
+                }),
+            );
+
+            // Cache pre-generation progress, for admins
+            router = router.route(
+                &format!("/api/gallery/{}/pregenerate", name),
+                axum::routing::get({
+                    let name = name.clone();
+                    move |state, headers| {
+                        gallery::pregenerate_progress_handler(state, Path(name), headers)
+                    }
+                }),
+            );
+
+            router = router.route(
+                &format!("/api/gallery/{}/geo", name),
+                axum::routing::get({
+                    let name = name.clone();
+                    move |state, query, headers| {
+                        gallery::geojson_api_handler_for_named(
+                            state,
+                            Path((name, String::new())),
+                            query,
+                            headers,
+                        )
+                    }
+                }),
+            );
+
+            router = router.route(
+                &format!("/api/gallery/{}/geo/{{*path}}", name),
+                axum::routing::get({
+                    let name = name.clone();
+                    move |state, path: Path<String>, query, headers| {
+                        let geo_path = path.0;
+                        gallery::geojson_api_handler_for_named(
+                            state,
+                            Path((name, geo_path)),
+                            query,
+                            headers,
+                        )
+                    }
+                }),
+            );
+
+            router = router.route(
+                &format!("/api/gallery/{}/image/{{*path}}", name),
+                axum::routing::get({
+                    let name = name.clone();
+                    move |state, path: Path<String>, headers| {
+                        let image_path = path.0;
+                        gallery::image_info_api_handler_for_named(
+                            state,
+                            Path((name, image_path)),
+                            headers,
+                        )
+                    }
+                }),
+            );
+
             router = router.route(
                 &format!("/api/gallery/{}/composite/{{*path}}", name),
                 axum::routing::get({
                     let name = name.clone();
-                    move |state, path: Path<String>| {
+                    move |state, path: Path<String>, headers| {
                         let composite_path = path.0;
                         api::gallery_composite_preview_handler_for_named(
                             state,
                             Path((name, composite_path)),
+                            headers,
                         )
                     }
                 }),
@@ -738,6 +1377,23 @@ pub async fn create_app(config: Config) -> axum::Router {
                 }),
             );
 
+            // Atom and RSS feeds
+            router = router.route(
+                &format!("{}/feed.xml", prefix),
+                axum::routing::get({
+                    let name = name.clone();
+                    move |state| feeds::posts_atom_handler(state, Path(name))
+                }),
+            );
+
+            router = router.route(
+                &format!("{}/rss.xml", prefix),
+                axum::routing::get({
+                    let name = name.clone();
+                    move |state| feeds::posts_rss_handler(state, Path(name))
+                }),
+            );
+
             // Detail route for individual posts
             router = router.route(
                 &format!("{}/{{*slug}}", prefix),
@@ -755,7 +1411,9 @@ pub async fn create_app(config: Config) -> axum::Router {
                 &format!("/api/posts/{}/refresh", name),
                 axum::routing::post({
                     let name = name.clone();
-                    move |state| posts::handlers::refresh_posts_handler(state, Path(name))
+                    move |state, headers| {
+                        posts::handlers::refresh_posts_handler(state, Path(name), headers)
+                    }
                 }),
             );
         }
@@ -769,6 +1427,7 @@ pub async fn create_app(config: Config) -> axum::Router {
 
     router
         .layer(middleware::from_fn(server_header_middleware))
+        .layer(middleware::from_fn(live_request_middleware))
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(|request: &axum::http::Request<_>| {
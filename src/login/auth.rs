@@ -1,21 +1,47 @@
-use crate::api::{get_cookie_value, verify_signed_cookie};
+use super::{SessionManager, UserDatabaseManager, UserIdentity};
+use crate::api::get_cookie_value;
 use axum::http::HeaderMap;
 
 /// Check if the user is authenticated and return their username
-pub fn get_authenticated_user(headers: &HeaderMap, secret: &str) -> Option<String> {
-    get_cookie_value(headers, "auth").and_then(|signed_value| {
-        if verify_signed_cookie(secret, &signed_value) {
-            // Extract username from signed value (it's before the signature)
-            signed_value.split(':').next().map(|s| s.to_string())
-        } else {
-            None
-        }
-    })
+pub async fn get_authenticated_user(
+    headers: &HeaderMap,
+    sessions: &SessionManager,
+) -> Option<String> {
+    let token = get_cookie_value(headers, "auth")?;
+    sessions
+        .authenticate(&token)
+        .await
+        .map(|session| session.username)
+}
+
+/// Resolve the signed-in user's groups and roles. Users removed from the
+/// database lose access even if their session is still live.
+pub async fn get_user_identity(
+    headers: &HeaderMap,
+    sessions: &SessionManager,
+    users: Option<&UserDatabaseManager>,
+) -> Option<UserIdentity> {
+    let username = get_authenticated_user(headers, sessions).await?;
+    match users {
+        Some(manager) => manager.database().read().await.get_identity(&username),
+        None => Some(UserIdentity::new(username)),
+    }
+}
+
+/// Check if the signed-in user has the admin role
+pub async fn is_admin(
+    headers: &HeaderMap,
+    sessions: &SessionManager,
+    users: Option<&UserDatabaseManager>,
+) -> bool {
+    get_user_identity(headers, sessions, users)
+        .await
+        .is_some_and(|identity| identity.is_admin())
 }
 
 /// Check if the user is authenticated (returns true/false)
-pub fn is_authenticated(headers: &HeaderMap, secret: &str) -> bool {
-    get_authenticated_user(headers, secret).is_some()
+pub async fn is_authenticated(headers: &HeaderMap, sessions: &SessionManager) -> bool {
+    get_authenticated_user(headers, sessions).await.is_some()
 }
 
 /// Create a login redirect URL with return path
@@ -1,6 +1,6 @@
 use axum::{
     Json,
-    extract::{ConnectInfo, Query, State},
+    extract::{ConnectInfo, Path, Query, State},
     http::{HeaderMap, StatusCode, header::SET_COOKIE},
     response::{Html, IntoResponse, Redirect},
 };
@@ -8,7 +8,7 @@ use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 use tracing::{error, info};
 
-use crate::{AppState, api::create_signed_cookie};
+use crate::{AppState, api::get_cookie_value};
 
 use super::{LoginError, LoginRequest, LoginResponse};
 
@@ -18,6 +18,18 @@ fn is_safe_return_url(url: &str) -> bool {
     url.starts_with('/') && !url.starts_with("//") && !url.contains("://")
 }
 
+/// IP address and user agent recorded against a new session
+pub(crate) fn client_details(
+    addr: &SocketAddr,
+    headers: &HeaderMap,
+) -> (Option<String>, Option<String>) {
+    let user_agent = headers
+        .get(axum::http::header::USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .map(|ua| ua.chars().take(256).collect());
+    (Some(addr.ip().to_string()), user_agent)
+}
+
 /// Extracts the return URL from cookies
 fn get_return_url_from_cookies(headers: &HeaderMap) -> Option<String> {
     headers
@@ -186,6 +198,7 @@ pub async fn login_request(
 
 pub async fn verify_login(
     State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Query(query): Query<VerifyQuery>,
     req_headers: HeaderMap,
 ) -> Result<impl IntoResponse, LoginError> {
@@ -200,17 +213,19 @@ pub async fn verify_login(
     // Get return URL from cookie
     let return_url = get_return_url_from_cookies(&req_headers);
 
-    // Create secure session cookie
-    let signed_value = create_signed_cookie(&app_state.config.app.cookie_secret, &username)
-        .map_err(LoginError::InternalError)?;
-
-    let auth_cookie = format!(
-        "auth={}; Path=/; Max-Age=604800; HttpOnly; SameSite=Lax",
-        signed_value
-    );
+    // Start a server-side session and hand its token to the browser
+    let (ip, user_agent) = client_details(&addr, &req_headers);
+    let token = app_state
+        .sessions
+        .create(&username, ip, user_agent)
+        .await
+        .map_err(|e| LoginError::InternalError(format!("Failed to create session: {}", e)))?;
 
     let mut headers = HeaderMap::new();
-    headers.insert(SET_COOKIE, auth_cookie.parse().unwrap());
+    headers.insert(
+        SET_COOKIE,
+        app_state.sessions.cookie(&token).parse().unwrap(),
+    );
 
     // Clear the return URL cookie
     headers.append(
@@ -259,15 +274,74 @@ pub async fn verify_login(
     Ok((headers, Redirect::to(&redirect_url)))
 }
 
-pub async fn logout() -> impl IntoResponse {
-    let cookie = "auth=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax";
+const CLEAR_AUTH_COOKIE: &str = "auth=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax";
+
+pub async fn logout(
+    State(app_state): State<AppState>,
+    req_headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Some(token) = get_cookie_value(&req_headers, "auth")
+        && let Err(e) = app_state.sessions.end(&token).await
+    {
+        error!("Failed to end session: {}", e);
+    }
 
     let mut headers = HeaderMap::new();
-    headers.insert(SET_COOKIE, cookie.parse().unwrap());
+    headers.insert(SET_COOKIE, CLEAR_AUTH_COOKIE.parse().unwrap());
 
     (headers, Redirect::to("/"))
 }
 
+/// Sign the current user out of every browser, including this one
+pub async fn logout_everywhere(
+    State(app_state): State<AppState>,
+    req_headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    let username = crate::login::get_authenticated_user(&req_headers, &app_state.sessions)
+        .await
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let revoked = app_state
+        .sessions
+        .revoke_all(&username)
+        .await
+        .map_err(|e| {
+            error!("Failed to revoke sessions for {}: {}", username, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    info!("User {} logged out of {} sessions", username, revoked);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(SET_COOKIE, CLEAR_AUTH_COOKIE.parse().unwrap());
+
+    Ok((headers, Redirect::to("/")))
+}
+
+/// Revoke one of the current user's other sessions from the profile page
+pub async fn revoke_session(
+    State(app_state): State<AppState>,
+    Path(session_id): Path<String>,
+    req_headers: HeaderMap,
+) -> Result<Redirect, StatusCode> {
+    let username = crate::login::get_authenticated_user(&req_headers, &app_state.sessions)
+        .await
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let revoked = app_state
+        .sessions
+        .revoke(&username, &session_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to revoke session for {}: {}", username, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !revoked {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Redirect::to("/_login/profile"))
+}
+
 pub async fn login_success(State(app_state): State<AppState>) -> Result<Html<String>, StatusCode> {
     let globals = liquid::object!({
         "base_url": app_state.config.app.base_url.as_deref().unwrap_or(""),
@@ -304,8 +378,7 @@ pub async fn check_auth_status(
         });
     }
 
-    let username =
-        crate::login::get_authenticated_user(&headers, &app_state.config.app.cookie_secret);
+    let username = crate::login::get_authenticated_user(&headers, &app_state.sessions).await;
 
     Json(AuthStatusResponse {
         authorized: username.is_some(),
@@ -319,9 +392,9 @@ pub async fn passkey_enrollment_page(
     Query(query): Query<LoginQuery>,
 ) -> Result<Html<String>, StatusCode> {
     // Check if user is authenticated
-    let username =
-        crate::login::get_authenticated_user(&headers, &app_state.config.app.cookie_secret)
-            .ok_or(StatusCode::UNAUTHORIZED)?;
+    let username = crate::login::get_authenticated_user(&headers, &app_state.sessions)
+        .await
+        .ok_or(StatusCode::UNAUTHORIZED)?;
 
     // Use return URL from query parameter or default to gallery
     let redirect_url = query
@@ -354,9 +427,9 @@ pub async fn profile_page(
     headers: HeaderMap,
 ) -> Result<Html<String>, StatusCode> {
     // Check authentication
-    let username =
-        crate::login::get_authenticated_user(&headers, &app_state.config.app.cookie_secret)
-            .ok_or(StatusCode::UNAUTHORIZED)?;
+    let username = crate::login::get_authenticated_user(&headers, &app_state.sessions)
+        .await
+        .ok_or(StatusCode::UNAUTHORIZED)?;
 
     // Get user information
     let user_info = if let Some(manager) = &app_state.user_database_manager {
@@ -372,12 +445,34 @@ pub async fn profile_page(
     // Check if WebAuthn is available
     let webauthn_available = app_state.webauthn.is_some();
 
+    let current_session_id =
+        get_cookie_value(&headers, "auth").map(|token| crate::login::session::session_id(&token));
+    let sessions: Vec<_> = app_state
+        .sessions
+        .list(&username)
+        .await
+        .into_iter()
+        .map(|session| {
+            let is_current = current_session_id.as_deref() == Some(session.id.as_str());
+            liquid::object!({
+                "id": session.id,
+                "is_current": is_current,
+                "created_at": format_session_time(session.created_at),
+                "last_seen": format_session_time(session.last_seen),
+                "expires_at": format_session_time(session.expires_at),
+                "ip": session.ip.unwrap_or_default(),
+                "user_agent": session.user_agent.unwrap_or_default(),
+            })
+        })
+        .collect();
+
     let globals = liquid::object!({
         "username": username,
         "email": email,
         "passkey_count": passkey_count,
         "has_passkeys": passkey_count > 0,
         "webauthn_available": webauthn_available,
+        "sessions": sessions,
         "base_url": app_state.config.app.base_url.as_deref().unwrap_or(""),
     });
 
@@ -393,3 +488,9 @@ pub async fn profile_page(
         }
     }
 }
+
+fn format_session_time(timestamp: i64) -> String {
+    chrono::DateTime::<chrono::Utc>::from_timestamp(timestamp, 0)
+        .map(|date| date.format("%B %-d, %Y %H:%M UTC").to_string())
+        .unwrap_or_default()
+}
@@ -1,12 +1,14 @@
 pub mod auth;
 pub mod error;
 pub mod handlers;
+pub mod session;
 pub mod types;
 pub mod webauthn;
 
 pub use auth::*;
 pub use error::*;
 pub use handlers::*;
+pub use session::{FileSessionStore, Session, SessionManager, SessionStore, SharedSessionStore};
 pub use types::*;
 
 #[cfg(test)]
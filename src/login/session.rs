@@ -0,0 +1,389 @@
+use crate::atomic_file::write_atomically;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, error};
+
+/// `last_seen` is only updated (and written back) once this many seconds have
+/// passed, so busy sessions don't rewrite the store on every request
+const LAST_SEEN_RESOLUTION_SECONDS: i64 = 60;
+
+/// A signed-in browser. The cookie holds a random token; the store only keeps
+/// its SHA-256 hash as `id`, so a leaked session file can't be replayed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub username: String,
+    pub created_at: i64,
+    pub last_seen: i64,
+    pub expires_at: i64,
+    #[serde(default)]
+    pub ip: Option<String>,
+    #[serde(default)]
+    pub user_agent: Option<String>,
+}
+
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn insert(&self, session: Session) -> Result<(), std::io::Error>;
+    async fn get(&self, id: &str) -> Option<Session>;
+    async fn touch(&self, id: &str, now: i64);
+    async fn remove(&self, id: &str) -> Result<bool, std::io::Error>;
+    async fn remove_for_user(&self, username: &str) -> Result<usize, std::io::Error>;
+    async fn list_for_user(&self, username: &str) -> Vec<Session>;
+    async fn remove_expired(&self, now: i64);
+}
+
+pub type SharedSessionStore = Arc<dyn SessionStore>;
+
+/// Default session store: sessions are held in memory and written to a JSON
+/// file after every change. Without a file path sessions don't survive a restart.
+pub struct FileSessionStore {
+    sessions: RwLock<HashMap<String, Session>>,
+    file_path: Option<PathBuf>,
+    /// Held while saving, so a slower save of an older snapshot can't
+    /// replace a newer one
+    save_lock: Mutex<()>,
+}
+
+impl FileSessionStore {
+    pub fn in_memory() -> Self {
+        Self {
+            sessions: RwLock::new(HashMap::new()),
+            file_path: None,
+            save_lock: Mutex::new(()),
+        }
+    }
+
+    pub async fn load(path: PathBuf) -> Result<Self, std::io::Error> {
+        let sessions = if path.exists() {
+            let contents = fs::read_to_string(&path).await?;
+            let sessions: Vec<Session> = serde_json::from_str(&contents)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            let now = chrono::Utc::now().timestamp();
+            sessions
+                .into_iter()
+                .filter(|s| s.expires_at > now)
+                .map(|s| (s.id.clone(), s))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self {
+            sessions: RwLock::new(sessions),
+            file_path: Some(path),
+            save_lock: Mutex::new(()),
+        })
+    }
+
+    async fn save(&self) -> Result<(), std::io::Error> {
+        let Some(path) = &self.file_path else {
+            return Ok(());
+        };
+
+        let _saving = self.save_lock.lock().await;
+        let json = {
+            let sessions = self.sessions.read().await;
+            let mut sessions: Vec<&Session> = sessions.values().collect();
+            sessions.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+            serde_json::to_string_pretty(&sessions)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
+        };
+
+        write_atomically(path, json.as_bytes()).await
+    }
+
+    async fn save_logging_errors(&self) {
+        if let Err(e) = self.save().await {
+            error!("Failed to save sessions: {}", e);
+        }
+    }
+}
+
+#[async_trait]
+impl SessionStore for FileSessionStore {
+    async fn insert(&self, session: Session) -> Result<(), std::io::Error> {
+        self.sessions
+            .write()
+            .await
+            .insert(session.id.clone(), session);
+        self.save().await
+    }
+
+    async fn get(&self, id: &str) -> Option<Session> {
+        self.sessions.read().await.get(id).cloned()
+    }
+
+    async fn touch(&self, id: &str, now: i64) {
+        let changed = {
+            let mut sessions = self.sessions.write().await;
+            match sessions.get_mut(id) {
+                Some(session) if now - session.last_seen >= LAST_SEEN_RESOLUTION_SECONDS => {
+                    session.last_seen = now;
+                    true
+                }
+                _ => false,
+            }
+        };
+        if changed {
+            self.save_logging_errors().await;
+        }
+    }
+
+    async fn remove(&self, id: &str) -> Result<bool, std::io::Error> {
+        let removed = self.sessions.write().await.remove(id).is_some();
+        if removed {
+            self.save().await?;
+        }
+        Ok(removed)
+    }
+
+    async fn remove_for_user(&self, username: &str) -> Result<usize, std::io::Error> {
+        let removed = {
+            let mut sessions = self.sessions.write().await;
+            let before = sessions.len();
+            sessions.retain(|_, s| s.username != username);
+            before - sessions.len()
+        };
+        if removed > 0 {
+            self.save().await?;
+        }
+        Ok(removed)
+    }
+
+    async fn list_for_user(&self, username: &str) -> Vec<Session> {
+        self.sessions
+            .read()
+            .await
+            .values()
+            .filter(|s| s.username == username)
+            .cloned()
+            .collect()
+    }
+
+    async fn remove_expired(&self, now: i64) {
+        let removed = {
+            let mut sessions = self.sessions.write().await;
+            let before = sessions.len();
+            sessions.retain(|_, s| s.expires_at > now);
+            before - sessions.len()
+        };
+        if removed > 0 {
+            debug!("Removed {} expired sessions", removed);
+            self.save_logging_errors().await;
+        }
+    }
+}
+
+/// Creates, validates and revokes login sessions on top of a `SessionStore`
+#[derive(Clone)]
+pub struct SessionManager {
+    store: SharedSessionStore,
+    lifetime_seconds: i64,
+}
+
+impl SessionManager {
+    pub fn new(store: SharedSessionStore, lifetime_days: u64) -> Self {
+        Self {
+            store,
+            lifetime_seconds: (lifetime_days as i64).saturating_mul(86_400),
+        }
+    }
+
+    /// Start a session for `username`, returning the token for the auth cookie
+    pub async fn create(
+        &self,
+        username: &str,
+        ip: Option<String>,
+        user_agent: Option<String>,
+    ) -> Result<String, std::io::Error> {
+        use rand::{Rng, rng};
+
+        let token: String = rng()
+            .random::<[u8; 32]>()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        let now = chrono::Utc::now().timestamp();
+        self.store
+            .insert(Session {
+                id: session_id(&token),
+                username: username.to_string(),
+                created_at: now,
+                last_seen: now,
+                expires_at: now + self.lifetime_seconds,
+                ip,
+                user_agent,
+            })
+            .await?;
+
+        Ok(token)
+    }
+
+    /// Look up the live session for a cookie token and record the access
+    pub async fn authenticate(&self, token: &str) -> Option<Session> {
+        let id = session_id(token);
+        let session = self.store.get(&id).await?;
+
+        let now = chrono::Utc::now().timestamp();
+        if session.expires_at <= now {
+            return None;
+        }
+
+        self.store.touch(&id, now).await;
+        Some(session)
+    }
+
+    /// End the session belonging to a cookie token
+    pub async fn end(&self, token: &str) -> Result<(), std::io::Error> {
+        self.store.remove(&session_id(token)).await.map(|_| ())
+    }
+
+    /// Revoke one of `username`'s sessions by id. Returns false if no such
+    /// session belongs to them.
+    pub async fn revoke(&self, username: &str, id: &str) -> Result<bool, std::io::Error> {
+        match self.store.get(id).await {
+            Some(session) if session.username == username => self.store.remove(id).await,
+            _ => Ok(false),
+        }
+    }
+
+    /// Sign a user out of every browser
+    pub async fn revoke_all(&self, username: &str) -> Result<usize, std::io::Error> {
+        self.store.remove_for_user(username).await
+    }
+
+    /// Live sessions for a user, most recently used first
+    pub async fn list(&self, username: &str) -> Vec<Session> {
+        let now = chrono::Utc::now().timestamp();
+        let mut sessions: Vec<Session> = self
+            .store
+            .list_for_user(username)
+            .await
+            .into_iter()
+            .filter(|s| s.expires_at > now)
+            .collect();
+        sessions.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+        sessions
+    }
+
+    pub async fn cleanup_expired(&self) {
+        self.store
+            .remove_expired(chrono::Utc::now().timestamp())
+            .await;
+    }
+
+    /// `Set-Cookie` value for a new session token
+    pub fn cookie(&self, token: &str) -> String {
+        format!(
+            "auth={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+            token, self.lifetime_seconds
+        )
+    }
+}
+
+/// Sessions are keyed by a hash of the cookie token rather than the token itself
+pub fn session_id(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_session_lifecycle() {
+        let manager = SessionManager::new(Arc::new(FileSessionStore::in_memory()), 7);
+
+        let token = manager
+            .create("alice", Some("127.0.0.1".to_string()), None)
+            .await
+            .unwrap();
+        let other = manager.create("alice", None, None).await.unwrap();
+        manager.create("bob", None, None).await.unwrap();
+
+        let session = manager.authenticate(&token).await.unwrap();
+        assert_eq!(session.username, "alice");
+        assert_eq!(session.id, session_id(&token));
+        assert_ne!(session.id, token);
+        assert!(manager.authenticate("not-a-session").await.is_none());
+
+        // Users can only revoke their own sessions
+        assert!(!manager.revoke("bob", &session.id).await.unwrap());
+        assert!(manager.revoke("alice", &session.id).await.unwrap());
+        assert!(manager.authenticate(&token).await.is_none());
+
+        assert_eq!(manager.list("alice").await.len(), 1);
+        assert_eq!(manager.revoke_all("alice").await.unwrap(), 1);
+        assert!(manager.authenticate(&other).await.is_none());
+        assert_eq!(manager.list("bob").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sessions_persist_and_expire() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("sessions.json");
+
+        let store = Arc::new(FileSessionStore::load(path.clone()).await.unwrap());
+        let manager = SessionManager::new(store.clone(), 7);
+        let token = manager.create("alice", None, None).await.unwrap();
+
+        let now = chrono::Utc::now().timestamp();
+        store
+            .insert(Session {
+                id: "expired".to_string(),
+                username: "alice".to_string(),
+                created_at: now - 100,
+                last_seen: now - 100,
+                expires_at: now - 1,
+                ip: None,
+                user_agent: None,
+            })
+            .await
+            .unwrap();
+
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        assert!(!contents.contains(&token));
+
+        // Reloading keeps live sessions and drops expired ones
+        let reloaded = SessionManager::new(
+            Arc::new(FileSessionStore::load(path.clone()).await.unwrap()),
+            7,
+        );
+        assert!(reloaded.authenticate(&token).await.is_some());
+        assert_eq!(reloaded.list("alice").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_saves_keep_every_session() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("sessions.json");
+
+        let store = Arc::new(FileSessionStore::load(path.clone()).await.unwrap());
+        let manager = SessionManager::new(store, 7);
+        let creates = (0..16).map(|i| {
+            let manager = manager.clone();
+            tokio::spawn(async move { manager.create(&format!("user{}", i), None, None).await })
+        });
+        for create in creates.collect::<Vec<_>>() {
+            create.await.unwrap().unwrap();
+        }
+
+        let reloaded = FileSessionStore::load(path.clone()).await.unwrap();
+        assert_eq!(reloaded.sessions.read().await.len(), 16);
+        // No temporary files are left behind
+        let entries: Vec<_> = std::fs::read_dir(temp_dir.path()).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+    }
+}
@@ -7,10 +7,7 @@ fn test_user_lookup_by_email() {
     // Add a test user
     db.add_user(
         "alice".to_string(),
-        User {
-            email: "alice@example.com".to_string(),
-            passkeys: Vec::new(),
-        },
+        User::new("alice@example.com".to_string()),
     );
 
     // Test lookup by username
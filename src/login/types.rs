@@ -5,14 +5,32 @@ use std::sync::Arc;
 use tokio::fs;
 use tokio::sync::RwLock;
 
+/// Role that may use the refresh APIs and bypasses folder restrictions
+pub const ROLE_ADMIN: &str = "admin";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub email: String,
+    /// Groups such as "family" or "clients", referenced from `_folder.md`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    /// Roles granting site-wide rights, currently only "admin"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub passkeys: Vec<crate::login::webauthn::UserPasskey>,
 }
 
 impl User {
+    pub fn new(email: String) -> Self {
+        Self {
+            email,
+            groups: Vec::new(),
+            roles: Vec::new(),
+            passkeys: Vec::new(),
+        }
+    }
+
     pub fn add_passkey(&mut self, passkey: crate::login::webauthn::UserPasskey) {
         self.passkeys.push(passkey);
     }
@@ -35,6 +53,40 @@ impl User {
     }
 }
 
+/// The signed-in user as seen by access checks
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserIdentity {
+    pub username: String,
+    pub groups: Vec<String>,
+    pub roles: Vec<String>,
+}
+
+impl UserIdentity {
+    pub fn new(username: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_user(username: &str, user: &User) -> Self {
+        Self {
+            username: username.to_string(),
+            groups: user.groups.clone(),
+            roles: user.roles.clone(),
+        }
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.roles.iter().any(|role| role == ROLE_ADMIN)
+    }
+
+    /// Whether an entry from an access list names this user or one of their groups
+    pub fn matches(&self, principal: &str) -> bool {
+        principal == self.username || self.groups.iter().any(|group| group == principal)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct UserWithUsername<'a> {
     pub username: &'a str,
@@ -112,6 +164,12 @@ impl UserDatabase {
             .map(|user| UserWithUsername::new(username, user))
     }
 
+    pub fn get_identity(&self, username: &str) -> Option<UserIdentity> {
+        self.users
+            .get(username)
+            .map(|user| UserIdentity::from_user(username, user))
+    }
+
     pub fn get_user_by_username_or_email(&self, identifier: &str) -> Option<&User> {
         // First try direct username lookup
         if let Some(user) = self.users.get(identifier) {
@@ -318,17 +376,20 @@ impl UserDatabaseManager {
     }
 }
 
-pub fn start_periodic_cleanup(login_state: Arc<RwLock<LoginState>>) {
+pub fn start_periodic_cleanup(
+    login_state: Arc<RwLock<LoginState>>,
+    sessions: crate::login::SessionManager,
+) {
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(300)); // 5 minutes
 
         loop {
             interval.tick().await;
 
-            let mut state = login_state.write().await;
-            state.cleanup_expired();
+            login_state.write().await.cleanup_expired();
+            sessions.cleanup_expired().await;
 
-            tracing::debug!("Cleaned up expired login tokens and rate limits");
+            tracing::debug!("Cleaned up expired login tokens, rate limits and sessions");
         }
     });
 }
@@ -360,15 +421,9 @@ mod tests {
         // Create database with users
         let mut db = UserDatabase::new();
 
-        let user1 = User {
-            email: "user1@example.com".to_string(),
-            passkeys: vec![],
-        };
+        let user1 = User::new("user1@example.com".to_string());
 
-        let user2 = User {
-            email: "user2@example.com".to_string(),
-            passkeys: vec![],
-        };
+        let user2 = User::new("user2@example.com".to_string());
 
         db.add_user("testuser1".to_string(), user1);
         db.add_user("testuser2".to_string(), user2);
@@ -404,10 +459,7 @@ mod tests {
     async fn test_user_database_with_empty_passkeys() {
         let mut db = UserDatabase::new();
 
-        let user = User {
-            email: "test@example.com".to_string(),
-            passkeys: vec![],
-        };
+        let user = User::new("test@example.com".to_string());
 
         db.add_user("testuser".to_string(), user);
 
@@ -465,13 +517,39 @@ email = "user2@example.com"
         );
     }
 
+    #[tokio::test]
+    async fn test_user_groups_and_roles() {
+        let content = r#"[users.alice]
+email = "alice@example.com"
+groups = ["family", "friends"]
+
+[users.root]
+email = "root@example.com"
+roles = ["admin"]
+"#;
+
+        let temp_file = NamedTempFile::new().unwrap();
+        tokio::fs::write(temp_file.path(), content).await.unwrap();
+        let db = UserDatabase::load_from_file(temp_file.path())
+            .await
+            .unwrap();
+
+        let alice = db.get_identity("alice").unwrap();
+        assert!(alice.matches("alice"));
+        assert!(alice.matches("friends"));
+        assert!(!alice.matches("clients"));
+        assert!(!alice.is_admin());
+
+        let root = db.get_identity("root").unwrap();
+        assert!(root.is_admin());
+        assert!(root.groups.is_empty());
+        assert!(db.get_identity("nobody").is_none());
+    }
+
     // Mock passkey for testing - we'll create a simpler test that doesn't require actual WebAuthn types
     #[tokio::test]
     async fn test_user_operations() {
-        let user = User {
-            email: "test@example.com".to_string(),
-            passkeys: vec![],
-        };
+        let user = User::new("test@example.com".to_string());
 
         assert!(!user.has_passkeys());
 
@@ -484,10 +562,7 @@ email = "user2@example.com"
     async fn test_user_database_operations() {
         let mut db = UserDatabase::new();
 
-        let user = User {
-            email: "test@example.com".to_string(),
-            passkeys: vec![],
-        };
+        let user = User::new("test@example.com".to_string());
 
         // Test add user
         db.add_user("testuser".to_string(), user.clone());
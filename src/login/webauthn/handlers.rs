@@ -1,8 +1,9 @@
 use axum::{
-    extract::{Path, State},
+    extract::{ConnectInfo, Path, State},
     http::{HeaderMap, StatusCode},
     response::Json,
 };
+use std::net::SocketAddr;
 use tracing::{error, info};
 use uuid::Uuid;
 use webauthn_rs::prelude::*;
@@ -27,7 +28,8 @@ pub async fn start_passkey_registration(
     info!("Starting passkey registration");
 
     // Check if user is authenticated
-    let username = get_authenticated_user(&headers, &app_state.config.app.cookie_secret)
+    let username = get_authenticated_user(&headers, &app_state.sessions)
+        .await
         .ok_or_else(|| {
             error!("Passkey registration failed: user not authenticated");
             StatusCode::UNAUTHORIZED
@@ -136,7 +138,8 @@ pub async fn finish_passkey_registration(
     })?;
 
     // Check if user is authenticated
-    let username = get_authenticated_user(&headers, &app_state.config.app.cookie_secret)
+    let username = get_authenticated_user(&headers, &app_state.sessions)
+        .await
         .ok_or_else(|| {
             error!("Finish registration failed: user not authenticated");
             StatusCode::UNAUTHORIZED
@@ -296,7 +299,9 @@ pub async fn start_passkey_authentication(
 
 pub async fn finish_passkey_authentication(
     State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Path(auth_id): Path<String>,
+    req_headers: HeaderMap,
     Json(auth_data): Json<PublicKeyCredential>,
 ) -> Result<(HeaderMap, StatusCode), StatusCode> {
     // Get WebAuthn instance
@@ -359,18 +364,22 @@ pub async fn finish_passkey_authentication(
             .await
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-        // Create session cookie
-        let signed_value =
-            crate::api::create_signed_cookie(&app_state.config.app.cookie_secret, &username)
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-        let cookie = format!(
-            "auth={}; Path=/; Max-Age=604800; HttpOnly; SameSite=Lax",
-            signed_value
-        );
+        // Start a server-side session
+        let (ip, user_agent) = crate::login::handlers::client_details(&addr, &req_headers);
+        let token = app_state
+            .sessions
+            .create(&username, ip, user_agent)
+            .await
+            .map_err(|e| {
+                error!("Failed to create session for {}: {}", username, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
 
         let mut headers = HeaderMap::new();
-        headers.insert("Set-Cookie", cookie.parse().unwrap());
+        headers.insert(
+            "Set-Cookie",
+            app_state.sessions.cookie(&token).parse().unwrap(),
+        );
 
         info!("User {} authenticated via passkey", username);
 
@@ -385,7 +394,8 @@ pub async fn list_passkeys(
     headers: HeaderMap,
 ) -> Result<Json<Vec<PasskeyInfo>>, StatusCode> {
     // Check if user is authenticated
-    let username = get_authenticated_user(&headers, &app_state.config.app.cookie_secret)
+    let username = get_authenticated_user(&headers, &app_state.sessions)
+        .await
         .ok_or(StatusCode::UNAUTHORIZED)?;
 
     // Get user database manager
@@ -420,7 +430,8 @@ pub async fn delete_passkey(
     Path(passkey_id): Path<Uuid>,
 ) -> Result<StatusCode, StatusCode> {
     // Check if user is authenticated
-    let username = get_authenticated_user(&headers, &app_state.config.app.cookie_secret)
+    let username = get_authenticated_user(&headers, &app_state.sessions)
+        .await
         .ok_or(StatusCode::UNAUTHORIZED)?;
 
     // Get user database manager
@@ -486,7 +497,8 @@ pub async fn update_passkey_name(
     Json(name): Json<String>,
 ) -> Result<StatusCode, StatusCode> {
     // Check if user is authenticated
-    let username = get_authenticated_user(&headers, &app_state.config.app.cookie_secret)
+    let username = get_authenticated_user(&headers, &app_state.sessions)
+        .await
         .ok_or(StatusCode::UNAUTHORIZED)?;
 
     // Get user database manager
@@ -6,7 +6,7 @@ use tracing_subscriber::FmtSubscriber;
 
 use tenrankai::{
     Config, create_app,
-    gallery::Gallery,
+    gallery::{self, Gallery},
     login::{User, UserDatabase},
     posts, startup_checks,
 };
@@ -47,6 +47,14 @@ enum Commands {
     #[command(subcommand)]
     User(UserCommands),
 
+    /// Manage share links for private folders and images
+    #[command(subcommand)]
+    Share(ShareCommands),
+
+    /// Inspect and clean up the image caches
+    #[command(subcommand)]
+    Cache(CacheCommands),
+
     /// Debug AVIF image metadata and color properties
     #[cfg(feature = "avif")]
     AvifDebug {
@@ -97,6 +105,57 @@ enum UserCommands {
     },
 }
 
+#[derive(Subcommand, Debug)]
+enum ShareCommands {
+    /// Create a share link for a folder or image
+    Create {
+        /// Gallery name
+        gallery: String,
+        /// Folder or image path within the gallery
+        path: String,
+        /// Days until the link stops working
+        #[arg(long)]
+        expires_in_days: Option<u64>,
+        /// Password visitors must enter before viewing
+        #[arg(long)]
+        password: Option<String>,
+        /// Allow downloading large and original images
+        #[arg(long)]
+        allow_download: bool,
+        /// Number of times the link can be opened
+        #[arg(long)]
+        max_views: Option<u64>,
+    },
+    /// List share links
+    List {
+        /// Only list shares for this gallery
+        #[arg(long)]
+        gallery: Option<String>,
+    },
+    /// Revoke a share link
+    Revoke {
+        /// Share id, as shown by `share list`
+        id: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommands {
+    /// Show how much each gallery's cache holds
+    Stats {
+        /// Only this gallery
+        #[arg(long)]
+        gallery: Option<String>,
+    },
+    /// Delete cached files whose source is gone and trim caches to their
+    /// max_cache_size
+    Gc {
+        /// Only this gallery
+        #[arg(long)]
+        gallery: Option<String>,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
@@ -124,6 +183,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Handle commands
     match cli.command {
         Some(Commands::User(user_cmd)) => handle_user_command(user_cmd).await,
+        Some(Commands::Share(share_cmd)) => handle_share_command(cli.config, share_cmd).await,
+        Some(Commands::Cache(cache_cmd)) => handle_cache_command(cli.config, cache_cmd).await,
         #[cfg(feature = "avif")]
         Some(Commands::AvifDebug {
             image_path,
@@ -180,10 +241,7 @@ async fn handle_user_command(cmd: UserCommands) -> Result<(), Box<dyn std::error
                 std::process::exit(1);
             }
 
-            let user = User {
-                email: email.trim().to_string(),
-                passkeys: Vec::new(),
-            };
+            let user = User::new(email.trim().to_string());
 
             db.add_user(username.clone(), user);
             db.save_to_file(db_path).await?;
@@ -235,19 +293,196 @@ async fn handle_user_command(cmd: UserCommands) -> Result<(), Box<dyn std::error
     Ok(())
 }
 
+async fn handle_share_command(
+    config_path: PathBuf,
+    cmd: ShareCommands,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = load_config(&config_path)?;
+    let Some(store_path) = config.app.share_store_path() else {
+        eprintln!("Error: Share links require app.user_database to be configured");
+        std::process::exit(1);
+    };
+    let store = gallery::ShareStore::load(store_path).await?;
+    let galleries = config.galleries.clone().unwrap_or_default();
+    let base_url = config.app.base_url.as_deref().unwrap_or("");
+
+    match cmd {
+        ShareCommands::Create {
+            gallery,
+            path,
+            expires_in_days,
+            password,
+            allow_download,
+            max_views,
+        } => {
+            let Some(gallery_config) = galleries.iter().find(|g| g.name == gallery) else {
+                eprintln!("Error: Gallery '{}' not found", gallery);
+                std::process::exit(1);
+            };
+            let Some(kind) = gallery::detect_share_kind(&gallery_config.source_directory, &path)
+            else {
+                eprintln!(
+                    "Error: No folder or image '{}' in gallery '{}'",
+                    path, gallery
+                );
+                std::process::exit(1);
+            };
+
+            let mut share = gallery::Share::new(&gallery, &path, kind);
+            share.expires_at =
+                expires_in_days.map(|days| share.created_at + (days as i64).saturating_mul(86_400));
+            share.allow_download = allow_download;
+            share.max_views = max_views;
+            if let Some(password) = password.as_deref().filter(|p| !p.is_empty()) {
+                share.set_password(password);
+            }
+            store.insert(share.clone()).await?;
+
+            let token = gallery::share_token(&config.app.cookie_secret, &share.id)
+                .ok_or("Invalid cookie_secret")?;
+            println!("Created share {}", share.id);
+            println!(
+                "{}",
+                gallery::share_url(base_url, &gallery_config.url_prefix, &token)
+            );
+        }
+        ShareCommands::List { gallery } => {
+            let shares = store.list(gallery.as_deref()).await;
+            if shares.is_empty() {
+                println!("No share links");
+            }
+            for share in shares {
+                let expires = share
+                    .expires_at
+                    .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+                    .map(|t| format!("expires {}", t.format("%Y-%m-%d %H:%M UTC")))
+                    .unwrap_or_else(|| "never expires".to_string());
+                let views = match share.max_views {
+                    Some(max) => format!("{}/{} views", share.views, max),
+                    None => format!("{} views", share.views),
+                };
+                println!(
+                    "  {}  {}:{}  {}, {}{}{}",
+                    share.id,
+                    share.gallery,
+                    share.path,
+                    expires,
+                    views,
+                    if share.has_password() {
+                        ", password"
+                    } else {
+                        ""
+                    },
+                    if share.allow_download {
+                        ", downloads"
+                    } else {
+                        ""
+                    },
+                );
+            }
+        }
+        ShareCommands::Revoke { id } => {
+            if store.remove(&id).await? {
+                println!("Revoked share {}", id);
+            } else {
+                eprintln!("Error: Share '{}' not found", id);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_cache_command(
+    config_path: PathBuf,
+    cmd: CacheCommands,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = load_config(&config_path)?;
+    let only = match &cmd {
+        CacheCommands::Stats { gallery } | CacheCommands::Gc { gallery } => gallery.clone(),
+    };
+    let galleries: Vec<_> = config
+        .galleries
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|g| only.as_ref().is_none_or(|name| &g.name == name))
+        .collect();
+    if galleries.is_empty() {
+        match only {
+            Some(name) => eprintln!("Error: Gallery '{}' not found", name),
+            None => eprintln!("Error: No galleries configured"),
+        }
+        std::process::exit(1);
+    }
+
+    for gallery_config in galleries {
+        let name = gallery_config.name.clone();
+        let gallery = Gallery::new(gallery_config);
+        match cmd {
+            CacheCommands::Stats { .. } => {
+                let stats = gallery.cache_stats().await?;
+                let limit = stats
+                    .max_size
+                    .map(|max| format!(" of {}", format_bytes(max)))
+                    .unwrap_or_default();
+                println!(
+                    "{}: {} files, {}{}, {} orphaned ({})",
+                    name,
+                    stats.files,
+                    format_bytes(stats.total_size),
+                    limit,
+                    stats.orphaned_files,
+                    format_bytes(stats.orphaned_size)
+                );
+            }
+            CacheCommands::Gc { .. } => {
+                let cleanup = gallery.collect_garbage().await?;
+                println!(
+                    "{}: removed {} orphaned and {} least recently used files, freeing {}",
+                    name,
+                    cleanup.orphans_removed,
+                    cleanup.evicted,
+                    format_bytes(cleanup.bytes_freed)
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn load_config(config_path: &std::path::Path) -> Result<Config, Box<dyn std::error::Error>> {
+    if config_path.exists() {
+        let config_content = std::fs::read_to_string(config_path)?;
+        Ok(toml_edit::de::from_str::<Config>(&config_content)?)
+    } else {
+        info!("Config file not found at {:?}, using defaults", config_path);
+        Ok(Config::default())
+    }
+}
+
 async fn run_server(
     config_path: PathBuf,
     port: Option<u16>,
     host: Option<String>,
     quit_after: Option<u64>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let config = if config_path.exists() {
-        let config_content = std::fs::read_to_string(&config_path)?;
-        toml_edit::de::from_str::<Config>(&config_content)?
-    } else {
-        info!("Config file not found at {:?}, using defaults", config_path);
-        Config::default()
-    };
+    let config = load_config(&config_path)?;
 
     let host = host.unwrap_or(config.server.host.clone());
     let port = port.unwrap_or(config.server.port);
@@ -286,6 +521,8 @@ async fn run_server(
                     e,
                     startup_checks::StartupCheckError::GallerySourceDirectoryMissing(_)
                         | startup_checks::StartupCheckError::CacheDirectoryCreationFailed(_)
+                        | startup_checks::StartupCheckError::SharedCacheDirectory(..)
+                        | startup_checks::StartupCheckError::InvalidImageSize(..)
                 )
             });
 
@@ -340,6 +577,17 @@ async fn run_server(
                         e
                     );
                 }
+            } else if gallery_config.pregenerate_cache && gallery.pregeneration_interrupted() {
+                info!(
+                    "Resuming cache pre-generation for gallery '{}'",
+                    gallery_config.name
+                );
+                let gallery = gallery.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = gallery.pregenerate_all_images_cache().await {
+                        tracing::error!("Failed to pre-generate image cache: {}", e);
+                    }
+                });
             }
 
             // Start background cache refresh if configured
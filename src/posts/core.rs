@@ -38,12 +38,7 @@ impl PostsManager {
         self.scan_directory(&self.config.source_directory, &mut new_posts)
             .await?;
 
-        let mut sorted_slugs: Vec<String> = new_posts.keys().cloned().collect();
-        sorted_slugs.sort_by(|a, b| {
-            let post_a = &new_posts[a];
-            let post_b = &new_posts[b];
-            post_b.date.cmp(&post_a.date)
-        });
+        let sorted_slugs = Self::sort_slugs_by_date(&new_posts);
 
         info!("Found {} posts", new_posts.len());
 
@@ -55,6 +50,46 @@ impl PostsManager {
         Ok(())
     }
 
+    /// Reload only the posts affected by a change at `path`, as reported by the
+    /// filesystem watcher. Handles created, modified and deleted files as well as
+    /// whole directories.
+    pub async fn refresh_post_at_path(&self, path: &Path) -> Result<(), PostsError> {
+        let mut changed = HashMap::new();
+        if path.is_dir() {
+            self.scan_directory(path, &mut changed).await?;
+        } else if path.is_file()
+            && let Some(extension) = path.extension()
+            && (extension == "md" || extension == "markdown")
+        {
+            let post = self.load_post(path).await?;
+            changed.insert(post.slug.clone(), post);
+        }
+
+        let mut posts = self.posts.write().await;
+
+        // Anything previously loaded from under this path that wasn't just
+        // reloaded has been deleted or renamed away
+        posts.retain(|slug, post| !post.path.starts_with(path) || changed.contains_key(slug));
+
+        debug!("Reloaded {} posts under {}", changed.len(), path.display());
+        posts.extend(changed);
+
+        let mut slugs = self.sorted_slugs.write().await;
+        *slugs = Self::sort_slugs_by_date(&posts);
+
+        Ok(())
+    }
+
+    fn sort_slugs_by_date(posts: &HashMap<String, Post>) -> Vec<String> {
+        let mut sorted_slugs: Vec<String> = posts.keys().cloned().collect();
+        sorted_slugs.sort_by(|a, b| {
+            let post_a = &posts[a];
+            let post_b = &posts[b];
+            post_b.date.cmp(&post_a.date)
+        });
+        sorted_slugs
+    }
+
     pub fn start_background_refresh(posts_manager: Arc<PostsManager>, interval_minutes: u64) {
         tokio::spawn(async move {
             let mut interval =
@@ -237,6 +272,17 @@ impl PostsManager {
             .collect()
     }
 
+    /// All posts, newest first
+    pub async fn get_all_posts(&self) -> Vec<Post> {
+        let posts = self.posts.read().await;
+        let slugs = self.sorted_slugs.read().await;
+
+        slugs
+            .iter()
+            .filter_map(|slug| posts.get(slug).cloned())
+            .collect()
+    }
+
     pub async fn get_post(&self, slug: &str) -> Option<Post> {
         // First check if the post needs reloading
         if let Some(post) = self.get_post_if_fresh(slug).await {
@@ -363,17 +409,19 @@ impl PostsManager {
         let gallery_name = parts[1];
         let image_path = parts[2];
 
-        // Determine size from the URL/hint (default to thumbnail)
-        let size = match size_hint.to_lowercase().as_str() {
-            "gallery" | "medium" | "large" => size_hint,
-            _ => "thumbnail",
-        };
-
         // Get the gallery
         let galleries = self.galleries.as_ref()?;
         let gallery = galleries.get(gallery_name)?;
         let gallery_config = gallery.get_config();
 
+        // Determine size from the URL/hint (default to thumbnail)
+        let size_hint = size_hint.to_lowercase();
+        let size = if gallery_config.image_size(&size_hint).is_some() {
+            size_hint.as_str()
+        } else {
+            "thumbnail"
+        };
+
         // Generate URLs
         let encoded_path = urlencoding::encode(image_path);
         let image_url = format!(
@@ -1,7 +1,7 @@
 use crate::AppState;
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::{Html, IntoResponse},
 };
 use chrono::Datelike;
@@ -182,7 +182,20 @@ pub async fn post_detail_handler(
 pub async fn refresh_posts_handler(
     State(app_state): State<AppState>,
     Path(posts_name): Path<String>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
+    // With logins enabled, only admins may trigger a refresh
+    if app_state.config.app.user_database.is_some()
+        && !crate::login::is_admin(
+            &headers,
+            &app_state.sessions,
+            app_state.user_database_manager.as_ref(),
+        )
+        .await
+    {
+        return (StatusCode::FORBIDDEN, "Admin role required").into_response();
+    }
+
     let posts_manager = match app_state.posts_managers.get(&posts_name) {
         Some(manager) => manager,
         None => {
@@ -90,6 +90,46 @@ This is a tutorial post in a subdirectory."#;
         assert_eq!(posts[2].title, "First Post");
     }
 
+    #[tokio::test]
+    async fn test_refresh_post_at_path() {
+        let (temp_dir, config) = setup_test_posts_dir().await;
+        let manager = PostsManager::new(config);
+        manager.refresh_posts().await.unwrap();
+
+        // Edit one post and add another
+        let first = temp_dir.path().join("first-post.md");
+        fs::write(
+            &first,
+            "+++\ntitle = \"First Post, Revised\"\nsummary = \"Updated\"\ndate = \"2024-01-01\"\n+++\n\nNew body.",
+        )
+        .unwrap();
+        let newest = temp_dir.path().join("newest.md");
+        fs::write(
+            &newest,
+            "+++\ntitle = \"Newest\"\nsummary = \"Just added\"\ndate = \"2024-02-01\"\n+++\n\nHello.",
+        )
+        .unwrap();
+
+        manager.refresh_post_at_path(&first).await.unwrap();
+        manager.refresh_post_at_path(&newest).await.unwrap();
+
+        let posts = manager.get_posts_page(0).await;
+        assert_eq!(posts.len(), 4);
+        assert_eq!(posts[0].title, "Newest");
+        assert_eq!(
+            manager.get_post("first-post").await.unwrap().title,
+            "First Post, Revised"
+        );
+
+        // Removing a directory drops every post beneath it
+        let tutorials = temp_dir.path().join("tutorials");
+        fs::remove_dir_all(&tutorials).unwrap();
+        manager.refresh_post_at_path(&tutorials).await.unwrap();
+
+        assert!(manager.get_post("tutorials/tutorial").await.is_none());
+        assert_eq!(manager.get_posts_page(0).await.len(), 3);
+    }
+
     #[tokio::test]
     async fn test_get_post() {
         let (_temp_dir, config) = setup_test_posts_dir().await;
@@ -356,9 +396,17 @@ Regular markdown image (not a gallery reference):
             jpeg_quality: Some(85),
             webp_quality: Some(85.0),
             pregenerate_cache: false,
+            pregenerate_parallelism: None,
             new_threshold_days: None,
             approximate_dates_for_public: false,
+            location_precision: Default::default(),
+            location_precision_for_public: Default::default(),
+            metadata: Default::default(),
+            ffmpeg_path: None,
+            max_cache_size: None,
             copyright_holder: None,
+            watch_filesystem: false,
+            sizes: Default::default(),
         };
 
         let main_gallery = Arc::new(Gallery::new(main_gallery_config.clone()));
@@ -398,9 +446,17 @@ Regular markdown image (not a gallery reference):
             jpeg_quality: Some(90),
             webp_quality: Some(90.0),
             pregenerate_cache: false,
+            pregenerate_parallelism: None,
             new_threshold_days: None,
             approximate_dates_for_public: false,
+            location_precision: Default::default(),
+            location_precision_for_public: Default::default(),
+            metadata: Default::default(),
+            ffmpeg_path: None,
+            max_cache_size: None,
             copyright_holder: None,
+            watch_filesystem: false,
+            sizes: Default::default(),
         };
 
         let portfolio_gallery = Arc::new(Gallery::new(portfolio_gallery_config));
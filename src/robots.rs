@@ -6,7 +6,7 @@ use axum::{
 };
 
 /// Handler for /robots.txt
-/// Returns a permissive robots.txt that allows all crawlers
+/// Returns a permissive robots.txt that allows all crawlers and points at the sitemap
 pub async fn robots_txt_handler(State(app_state): State<AppState>) -> Response {
     // Check if a custom robots.txt exists in any static directory (in order)
     for (index, static_dir) in app_state.config.static_files.directories.iter().enumerate() {
@@ -38,17 +38,30 @@ pub async fn robots_txt_handler(State(app_state): State<AppState>) -> Response {
         }
     }
 
-    // Return default permissive robots.txt
-    let default_robots = r#"# robots.txt for Tenrankai Gallery
+    // Return default permissive robots.txt. Crawlers expect an absolute sitemap
+    // URL, so only fall back to a relative one when no base_url is configured.
+    let sitemap_url = format!(
+        "{}/sitemap.xml",
+        app_state
+            .config
+            .app
+            .base_url
+            .as_deref()
+            .unwrap_or("")
+            .trim_end_matches('/')
+    );
+    let default_robots = format!(
+        r#"# robots.txt for Tenrankai Gallery
 # This file allows all web crawlers to access all content
 
 User-agent: *
 Allow: /
 Crawl-delay: 1
 
-# Sitemap location (if you have one)
-# Sitemap: /sitemap.xml
-"#;
+Sitemap: {}
+"#,
+        sitemap_url
+    );
 
     (
         StatusCode::OK,
@@ -0,0 +1,383 @@
+use crate::AppState;
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Json},
+};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::SystemTime;
+use tracing::error;
+
+const SEARCH_TEMPLATE: &str = "modules/search.html.liquid";
+const RESULTS_PER_PAGE: usize = 48;
+
+/// A parsed search string. Free text terms must all appear in an image's file
+/// name, caption, folder titles or descriptions, camera or lens. Supported
+/// filters:
+///
+/// - `camera:canon`, `lens:"24-70"` - substring match, quotes allow spaces
+/// - `iso:400`, `iso>800`, `iso>=800`, `iso<3200`, `iso<=3200`
+/// - `date:2024`, `date:2024-07`, `date:2024-07-04`, `date:2024-01..2024-03`
+/// - `after:2024-01-01`, `before:2024-02-01`
+/// - `gallery:main`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchQuery {
+    pub terms: Vec<String>,
+    pub camera: Option<String>,
+    pub lens: Option<String>,
+    pub iso_min: Option<u32>,
+    pub iso_max: Option<u32>,
+    /// Inclusive lower bound on capture date
+    pub date_from: Option<SystemTime>,
+    /// Exclusive upper bound on capture date
+    pub date_to: Option<SystemTime>,
+    pub gallery: Option<String>,
+}
+
+impl SearchQuery {
+    pub fn parse(input: &str) -> Self {
+        let mut query = SearchQuery::default();
+
+        for token in tokenize(input) {
+            if let Some(value) = token.strip_prefix("iso>=") {
+                query.iso_min = value.parse().ok();
+            } else if let Some(value) = token.strip_prefix("iso>") {
+                query.iso_min = value.parse::<u32>().ok().map(|v| v.saturating_add(1));
+            } else if let Some(value) = token.strip_prefix("iso<=") {
+                query.iso_max = value.parse().ok();
+            } else if let Some(value) = token.strip_prefix("iso<") {
+                query.iso_max = value.parse::<u32>().ok().and_then(|v| v.checked_sub(1));
+            } else if let Some((key, value)) = token.split_once(':')
+                && !value.is_empty()
+            {
+                match key.to_lowercase().as_str() {
+                    "camera" => query.camera = Some(value.to_lowercase()),
+                    "lens" => query.lens = Some(value.to_lowercase()),
+                    "gallery" => query.gallery = Some(value.to_string()),
+                    "iso" => {
+                        query.iso_min = value.parse().ok();
+                        query.iso_max = query.iso_min;
+                    }
+                    "date" => {
+                        let (from, to) = value.split_once("..").unwrap_or((value, value));
+                        query.date_from = parse_date_bound(from, false);
+                        query.date_to = parse_date_bound(to, true);
+                    }
+                    "after" => query.date_from = parse_date_bound(value, false),
+                    "before" => query.date_to = parse_date_bound(value, false),
+                    _ => query.terms.push(token.to_lowercase()),
+                }
+            } else {
+                query.terms.push(token.to_lowercase());
+            }
+        }
+
+        query
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == SearchQuery::default()
+    }
+
+    /// Whether a capture date falls within the `date:`, `after:` and
+    /// `before:` bounds. Images without a date only match without bounds.
+    pub fn matches_date(&self, capture_date: Option<SystemTime>) -> bool {
+        if self.date_from.is_none() && self.date_to.is_none() {
+            return true;
+        }
+        let Some(capture_date) = capture_date else {
+            return false;
+        };
+        !(self.date_from.is_some_and(|from| capture_date < from)
+            || self.date_to.is_some_and(|to| capture_date >= to))
+    }
+}
+
+/// Start of the month `date` falls in. Approximated dates show no more than
+/// that, so date filters must not see more either.
+fn month_start(date: SystemTime) -> SystemTime {
+    DateTime::<Utc>::from(date)
+        .date_naive()
+        .with_day(1)
+        .and_then(|first| first.and_hms_opt(0, 0, 0))
+        .map_or(date, |first| first.and_utc().into())
+}
+
+/// Split on whitespace, keeping double-quoted sections together
+fn tokenize(input: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    for c in input.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    tokens
+}
+
+/// Parse `YYYY`, `YYYY-MM` or `YYYY-MM-DD`. With `end` set, returns the start
+/// of the following period so it can be used as an exclusive upper bound.
+fn parse_date_bound(value: &str, end: bool) -> Option<SystemTime> {
+    let parts: Vec<&str> = value.split('-').collect();
+    let year: i32 = parts.first()?.parse().ok()?;
+
+    let date = match parts.len() {
+        1 if end => NaiveDate::from_ymd_opt(year + 1, 1, 1)?,
+        1 => NaiveDate::from_ymd_opt(year, 1, 1)?,
+        2 => {
+            let month: u32 = parts[1].parse().ok()?;
+            let start = NaiveDate::from_ymd_opt(year, month, 1)?;
+            if end {
+                start.checked_add_months(chrono::Months::new(1))?
+            } else {
+                start
+            }
+        }
+        3 => {
+            let day = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+            if end { day.succ_opt()? } else { day }
+        }
+        _ => return None,
+    };
+
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc().into())
+}
+
+#[derive(Deserialize)]
+pub struct SearchParams {
+    #[serde(default)]
+    q: String,
+    page: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    pub gallery_name: String,
+    pub path: String,
+    pub name: String,
+    pub thumbnail_url: String,
+    pub gallery_url: String,
+    pub detail_url: String,
+    pub capture_date: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResponse {
+    pub query: String,
+    pub results: Vec<SearchResult>,
+    pub total_results: usize,
+    pub page: usize,
+    pub total_pages: usize,
+}
+
+async fn run_search(
+    app_state: &AppState,
+    params: &SearchParams,
+    headers: &HeaderMap,
+) -> SearchResponse {
+    let query = SearchQuery::parse(&params.q);
+    let page = params.page.unwrap_or(0);
+
+    let user = crate::gallery::get_authenticated_user(app_state, headers).await;
+
+    let mut results = Vec::new();
+    if !query.is_empty() {
+        for gallery_config in app_state.config.galleries.iter().flatten() {
+            let Some(gallery) = app_state.galleries.get(&gallery_config.name) else {
+                continue;
+            };
+
+            let prefix = gallery_config.url_prefix.trim_end_matches('/');
+            // Date precision depends on download permission, which can vary by folder
+            let mut approximate_folders: HashMap<String, bool> = HashMap::new();
+
+            for hit in gallery.search(&query, user.as_ref()).await {
+                let folder = hit.path.rsplit_once('/').map(|(p, _)| p).unwrap_or("");
+                let approximate_dates = match approximate_folders.get(folder) {
+                    Some(approximate) => *approximate,
+                    None => {
+                        let approximate = gallery_config.approximate_dates_for_public
+                            && !crate::gallery::has_download_permission(
+                                app_state,
+                                gallery,
+                                folder,
+                                crate::gallery::DownloadKind::Large,
+                                user.as_ref(),
+                                None,
+                            )
+                            .await;
+                        approximate_folders.insert(folder.to_string(), approximate);
+                        approximate
+                    }
+                };
+
+                // Filtering on the exact date would reveal the day of images
+                // whose listing only shows the month
+                let filter_date = if approximate_dates {
+                    hit.capture_date.map(month_start)
+                } else {
+                    hit.capture_date
+                };
+                if !query.matches_date(filter_date) {
+                    continue;
+                }
+
+                let encoded_path = urlencoding::encode(&hit.path);
+                let capture_date = hit.capture_date.map(|date| {
+                    let date = DateTime::<Utc>::from(date);
+                    if approximate_dates {
+                        date.format("%B %Y").to_string()
+                    } else {
+                        date.format("%B %-d, %Y").to_string()
+                    }
+                });
+
+                results.push(SearchResult {
+                    gallery_name: gallery_config.name.clone(),
+                    name: hit.path.rsplit('/').next().unwrap_or(&hit.path).to_string(),
+                    thumbnail_url: format!("{}/image/{}?size=thumbnail", prefix, encoded_path),
+                    gallery_url: format!("{}/image/{}?size=gallery", prefix, encoded_path),
+                    detail_url: format!("{}/detail/{}", prefix, encoded_path),
+                    capture_date,
+                    path: hit.path,
+                });
+            }
+        }
+    }
+
+    let total_results = results.len();
+    let total_pages = total_results.div_ceil(RESULTS_PER_PAGE).max(1);
+    let results = results
+        .into_iter()
+        .skip(page * RESULTS_PER_PAGE)
+        .take(RESULTS_PER_PAGE)
+        .collect();
+
+    SearchResponse {
+        query: params.q.clone(),
+        results,
+        total_results,
+        page,
+        total_pages,
+    }
+}
+
+/// Handler for /api/search
+pub async fn search_api_handler(
+    State(app_state): State<AppState>,
+    Query(params): Query<SearchParams>,
+    headers: HeaderMap,
+) -> Json<SearchResponse> {
+    Json(run_search(&app_state, &params, &headers).await)
+}
+
+/// Handler for /search
+pub async fn search_page_handler(
+    State(app_state): State<AppState>,
+    Query(params): Query<SearchParams>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let response = run_search(&app_state, &params, &headers).await;
+
+    let globals = liquid::object!({
+        "query": response.query,
+        "results": response.results,
+        "total_results": response.total_results,
+        "current_page": response.page,
+        "total_pages": response.total_pages,
+        "has_prev": response.page > 0,
+        "has_next": response.page + 1 < response.total_pages,
+        "prev_page": response.page.saturating_sub(1),
+        "next_page": response.page + 1,
+        "encoded_query": urlencoding::encode(&params.q).to_string(),
+        "app_name": app_state.config.app.name,
+        "base_url": app_state.config.app.base_url,
+    });
+
+    match app_state
+        .template_engine
+        .render_template(SEARCH_TEMPLATE, globals)
+        .await
+    {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => {
+            error!("Template rendering error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filters() {
+        let query =
+            SearchQuery::parse(r#"Sunset camera:Canon lens:"RF 24-70" iso>800 gallery:main"#);
+        assert_eq!(query.terms, vec!["sunset".to_string()]);
+        assert_eq!(query.camera.as_deref(), Some("canon"));
+        assert_eq!(query.lens.as_deref(), Some("rf 24-70"));
+        assert_eq!(query.iso_min, Some(801));
+        assert_eq!(query.iso_max, None);
+        assert_eq!(query.gallery.as_deref(), Some("main"));
+    }
+
+    #[test]
+    fn test_parse_date_ranges() {
+        let bound = |s: &str| -> SystemTime { DateTime::parse_from_rfc3339(s).unwrap().into() };
+
+        let query = SearchQuery::parse("date:2024-02");
+        assert_eq!(query.date_from, Some(bound("2024-02-01T00:00:00Z")));
+        assert_eq!(query.date_to, Some(bound("2024-03-01T00:00:00Z")));
+
+        let query = SearchQuery::parse("date:2023..2024-06-30");
+        assert_eq!(query.date_from, Some(bound("2023-01-01T00:00:00Z")));
+        assert_eq!(query.date_to, Some(bound("2024-07-01T00:00:00Z")));
+
+        let query = SearchQuery::parse("after:2024-01-01 before:2024-12-31");
+        assert_eq!(query.date_from, Some(bound("2024-01-01T00:00:00Z")));
+        assert_eq!(query.date_to, Some(bound("2024-12-31T00:00:00Z")));
+    }
+
+    #[test]
+    fn test_date_filters() {
+        let date = |s: &str| -> SystemTime { DateTime::parse_from_rfc3339(s).unwrap().into() };
+        let fourth = Some(date("2024-07-04T18:00:00Z"));
+        let matches = |q: &str, capture_date| SearchQuery::parse(q).matches_date(capture_date);
+
+        assert!(matches("date:2024-07", fourth));
+        assert!(matches("after:2024-07-01 before:2024-07-05", fourth));
+        assert!(!matches("date:2023", fourth));
+        assert!(!matches("date:2024", None));
+        assert!(matches("sunset", None));
+
+        // Approximated to the month, a day-level query can't single it out
+        let approximated = fourth.map(month_start);
+        assert_eq!(approximated, Some(date("2024-07-01T00:00:00Z")));
+        assert!(matches("date:2024-07", approximated));
+        assert!(!matches("date:2024-07-04", approximated));
+        assert!(!matches("after:2024-07-03", approximated));
+    }
+
+    #[test]
+    fn test_unknown_prefix_is_a_term() {
+        let query = SearchQuery::parse("note:hello");
+        assert_eq!(query.terms, vec!["note:hello".to_string()]);
+        assert!(SearchQuery::parse("   ").is_empty());
+    }
+}
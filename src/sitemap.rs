@@ -0,0 +1,227 @@
+use crate::AppState;
+use crate::gallery::encode_folder_path;
+use axum::{
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::time::SystemTime;
+
+/// The sitemap protocol allows at most 50,000 URLs per file
+const MAX_URLS_PER_SITEMAP: usize = 50_000;
+
+struct SitemapUrl {
+    loc: String,
+    lastmod: Option<SystemTime>,
+}
+
+/// Handler for /sitemap.xml
+/// Lists every public gallery folder, image detail page and post. Once there are
+/// too many URLs for a single file this becomes a sitemap index instead.
+pub async fn sitemap_handler(State(app_state): State<AppState>) -> Response {
+    let base_url = sitemap_base_url(&app_state);
+    let urls = collect_urls(&app_state, &base_url).await;
+
+    let body = if urls.len() <= MAX_URLS_PER_SITEMAP {
+        render_urlset(&urls)
+    } else {
+        let pages = urls.len().div_ceil(MAX_URLS_PER_SITEMAP);
+        render_index(&base_url, pages)
+    };
+
+    xml_response(body)
+}
+
+/// Handler for /sitemap/{page}.xml, the numbered sitemaps referenced by the index
+pub async fn sitemap_page_handler(
+    State(app_state): State<AppState>,
+    Path(page): Path<String>,
+) -> Response {
+    let page = match page
+        .strip_suffix(".xml")
+        .and_then(|n| n.parse::<usize>().ok())
+    {
+        Some(page) if page > 0 => page,
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    let base_url = sitemap_base_url(&app_state);
+    let urls = collect_urls(&app_state, &base_url).await;
+
+    let start = (page - 1) * MAX_URLS_PER_SITEMAP;
+    if start >= urls.len() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let end = (start + MAX_URLS_PER_SITEMAP).min(urls.len());
+
+    xml_response(render_urlset(&urls[start..end]))
+}
+
+pub(crate) fn sitemap_base_url(app_state: &AppState) -> String {
+    app_state
+        .config
+        .app
+        .base_url
+        .as_deref()
+        .unwrap_or("http://localhost:8080")
+        .trim_end_matches('/')
+        .to_string()
+}
+
+async fn collect_urls(app_state: &AppState, base_url: &str) -> Vec<SitemapUrl> {
+    let mut urls = vec![SitemapUrl {
+        loc: format!("{}/", base_url),
+        lastmod: None,
+    }];
+
+    // Walk configs rather than the HashMaps so the output order is stable
+    for gallery_config in app_state.config.galleries.iter().flatten() {
+        let Some(gallery) = app_state.galleries.get(&gallery_config.name) else {
+            continue;
+        };
+        let prefix = gallery_config.url_prefix.trim_end_matches('/');
+
+        for entry in gallery.list_public_entries().await {
+            let loc = if entry.is_directory {
+                if entry.path.is_empty() {
+                    format!("{}{}", base_url, prefix)
+                } else {
+                    format!("{}{}/{}", base_url, prefix, encode_folder_path(&entry.path))
+                }
+            } else {
+                format!(
+                    "{}{}/detail/{}",
+                    base_url,
+                    prefix,
+                    encode_folder_path(&entry.path)
+                )
+            };
+
+            urls.push(SitemapUrl {
+                loc,
+                lastmod: entry.last_modified,
+            });
+        }
+    }
+
+    for posts_config in app_state.config.posts.iter().flatten() {
+        let Some(posts_manager) = app_state.posts_managers.get(&posts_config.name) else {
+            continue;
+        };
+        let prefix = posts_config.url_prefix.trim_end_matches('/');
+
+        let posts = posts_manager.get_all_posts().await;
+        let newest = posts.iter().filter_map(|post| post.last_modified).max();
+        urls.push(SitemapUrl {
+            loc: format!("{}{}", base_url, prefix),
+            lastmod: newest,
+        });
+
+        for post in posts {
+            urls.push(SitemapUrl {
+                loc: format!("{}{}/{}", base_url, prefix, post.slug),
+                lastmod: post.last_modified.or_else(|| Some(post.date.into())),
+            });
+        }
+    }
+
+    urls
+}
+
+fn render_urlset(urls: &[SitemapUrl]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+
+    for url in urls {
+        xml.push_str("  <url>\n");
+        xml.push_str(&format!("    <loc>{}</loc>\n", xml_escape(&url.loc)));
+        if let Some(lastmod) = url.lastmod {
+            xml.push_str(&format!(
+                "    <lastmod>{}</lastmod>\n",
+                format_lastmod(lastmod)
+            ));
+        }
+        xml.push_str("  </url>\n");
+    }
+
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+fn render_index(base_url: &str, pages: usize) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+
+    for page in 1..=pages {
+        xml.push_str("  <sitemap>\n");
+        xml.push_str(&format!(
+            "    <loc>{}</loc>\n",
+            xml_escape(&format!("{}/sitemap/{}.xml", base_url, page))
+        ));
+        xml.push_str("  </sitemap>\n");
+    }
+
+    xml.push_str("</sitemapindex>\n");
+    xml
+}
+
+fn format_lastmod(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+pub(crate) fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn xml_response(body: String) -> Response {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        body,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_render_urlset() {
+        let urls = vec![
+            SitemapUrl {
+                loc: "https://example.com/gallery/detail/a&b.jpg".to_string(),
+                lastmod: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            },
+            SitemapUrl {
+                loc: "https://example.com/blog".to_string(),
+                lastmod: None,
+            },
+        ];
+
+        let xml = render_urlset(&urls);
+        assert!(xml.contains("<loc>https://example.com/gallery/detail/a&amp;b.jpg</loc>"));
+        assert!(xml.contains("<lastmod>2023-11-14T22:13:20Z</lastmod>"));
+        assert!(xml.contains("<loc>https://example.com/blog</loc>\n  </url>"));
+    }
+
+    #[test]
+    fn test_render_index() {
+        let xml = render_index("https://example.com", 2);
+        assert!(xml.contains("<sitemapindex"));
+        assert!(xml.contains("<loc>https://example.com/sitemap/1.xml</loc>"));
+        assert!(xml.contains("<loc>https://example.com/sitemap/2.xml</loc>"));
+        assert!(!xml.contains("sitemap/3.xml"));
+    }
+}
@@ -1,5 +1,6 @@
 use crate::Config;
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 use tracing::{error, info, warn};
 
@@ -16,6 +17,36 @@ pub enum StartupCheckError {
 
     #[error("Required file missing: {0}")]
     RequiredFileMissing(String),
+
+    #[error("Invalid image size '{1}' in gallery '{0}': {2}")]
+    InvalidImageSize(String, String, &'static str),
+
+    #[error("Galleries '{0}' and '{1}' share a cache directory")]
+    SharedCacheDirectory(String, String),
+}
+
+/// Size names end up in `?size=` query strings and cache keys
+fn check_image_size(name: &str, size: &crate::NamedSizeConfig) -> Result<(), &'static str> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err("names may only use letters, digits, '-' and '_'");
+    }
+    if name == "original" {
+        return Err("'original' is reserved for unresized downloads");
+    }
+    if size.width == 0 || size.height == 0 {
+        return Err("width and height must be greater than zero");
+    }
+    if size
+        .quality
+        .is_some_and(|quality| !(1..=100).contains(&quality))
+    {
+        return Err("quality must be between 1 and 100");
+    }
+    Ok(())
 }
 
 pub async fn perform_startup_checks(config: &Config) -> Result<(), Vec<StartupCheckError>> {
@@ -53,6 +84,47 @@ pub async fn perform_startup_checks(config: &Config) -> Result<(), Vec<StartupCh
         }
     }
 
+    // Each gallery's cache index and garbage collection assume it owns its
+    // cache directory
+    if let Some(galleries) = &config.galleries {
+        let mut owners: HashMap<PathBuf, &str> = HashMap::new();
+        for gallery_config in galleries {
+            let cache_dir = &gallery_config.cache_directory;
+            let cache_dir = cache_dir
+                .canonicalize()
+                .unwrap_or_else(|_| cache_dir.clone());
+            if let Some(owner) = owners.insert(cache_dir, &gallery_config.name) {
+                error!(
+                    "Galleries '{}' and '{}' use the same cache directory",
+                    owner, gallery_config.name
+                );
+                errors.push(StartupCheckError::SharedCacheDirectory(
+                    owner.to_string(),
+                    gallery_config.name.clone(),
+                ));
+            }
+        }
+    }
+
+    // Check named image sizes
+    if let Some(galleries) = &config.galleries {
+        for gallery_config in galleries {
+            for (name, size) in &gallery_config.sizes {
+                if let Err(reason) = check_image_size(name, size) {
+                    error!(
+                        "Gallery '{}' has an invalid image size '{}': {}",
+                        gallery_config.name, name, reason
+                    );
+                    errors.push(StartupCheckError::InvalidImageSize(
+                        gallery_config.name.clone(),
+                        name.clone(),
+                        reason,
+                    ));
+                }
+            }
+        }
+    }
+
     // Check static files directories
     for (index, static_dir) in config.static_files.directories.iter().enumerate() {
         if !static_dir.exists() {
@@ -148,3 +220,31 @@ pub async fn perform_startup_checks(config: &Config) -> Result<(), Vec<StartupCh
         Err(errors)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_image_size() {
+        let size = crate::NamedSizeConfig {
+            width: 400,
+            height: 300,
+            crop: Default::default(),
+            quality: Some(90),
+            watermark: false,
+            requires_auth: false,
+        };
+        assert!(check_image_size("hero", &size).is_ok());
+        assert!(check_image_size("hero@2x", &size).is_err());
+        assert!(check_image_size("original", &size).is_err());
+
+        for quality in [0, 101] {
+            let size = crate::NamedSizeConfig {
+                quality: Some(quality),
+                ..size.clone()
+            };
+            assert!(check_image_size("hero", &size).is_err());
+        }
+    }
+}
@@ -1,11 +1,9 @@
 use axum::{
-    body::Body,
-    http::{StatusCode, header},
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
 };
 use std::{collections::HashMap, path::PathBuf, sync::Arc, time::UNIX_EPOCH};
-use tokio::{fs::File, sync::RwLock};
-use tokio_util::io::ReaderStream;
+use tokio::sync::RwLock;
 use tracing::{debug, error, info};
 
 #[derive(Clone)]
@@ -94,12 +92,16 @@ impl StaticFileHandler {
         }
     }
 
-    pub async fn serve(&self, path: &str, has_version: bool) -> Response {
+    pub async fn serve(
+        &self,
+        path: &str,
+        has_version: bool,
+        request_headers: &HeaderMap,
+    ) -> Response {
         let clean_path = path.trim_start_matches('/');
 
         // Try each directory in order until we find the file
         let mut found_file_path = None;
-        let mut found_metadata = None;
 
         for (index, static_dir) in self.static_dirs.iter().enumerate() {
             let file_path = static_dir.join(clean_path);
@@ -121,7 +123,6 @@ impl StaticFileHandler {
             match tokio::fs::metadata(&file_path).await {
                 Ok(m) if m.is_file() => {
                     found_file_path = Some(file_path);
-                    found_metadata = Some(m);
                     debug!("Found file in directory {}", index);
                     break;
                 }
@@ -140,29 +141,15 @@ impl StaticFileHandler {
             }
         }
 
-        let (file_path, metadata) = match (found_file_path, found_metadata) {
-            (Some(path), Some(meta)) => (path, meta),
-            _ => {
-                debug!("File not found in any static directory: {}", clean_path);
-                return (StatusCode::NOT_FOUND, "File not found").into_response();
-            }
-        };
-
-        let file = match File::open(&file_path).await {
-            Ok(file) => file,
-            Err(e) => {
-                debug!("Failed to open file {:?}: {}", file_path, e);
-                return (StatusCode::NOT_FOUND, "File not found").into_response();
-            }
+        let Some(file_path) = found_file_path else {
+            debug!("File not found in any static directory: {}", clean_path);
+            return (StatusCode::NOT_FOUND, "File not found").into_response();
         };
 
         let content_type = mime_guess::from_path(&file_path)
             .first_or_octet_stream()
             .to_string();
 
-        let stream = ReaderStream::new(file);
-        let body = Body::from_stream(stream);
-
         // Determine cache headers based on content type and whether version is present
         let cache_control = if has_version {
             // If file has version parameter, cache forever
@@ -180,23 +167,7 @@ impl StaticFileHandler {
             "public, max-age=3600"
         };
 
-        let mut response = Response::builder()
-            .status(StatusCode::OK)
-            .header(header::CONTENT_TYPE, content_type)
-            .header(header::CACHE_CONTROL, cache_control);
-
-        // Add Last-Modified header
-        if let Ok(modified) = metadata.modified()
-            && let Ok(duration) = modified.duration_since(UNIX_EPOCH)
-        {
-            let http_date = httpdate::fmt_http_date(modified);
-            response = response.header(header::LAST_MODIFIED, http_date);
-
-            // Add ETag based on modification time and file size
-            let etag = format!("\"{}-{}\"", duration.as_secs(), metadata.len());
-            response = response.header(header::ETAG, etag);
-        }
-
-        response.body(body).unwrap()
+        crate::file_response::serve_file(&file_path, &content_type, cache_control, request_headers)
+            .await
     }
 }
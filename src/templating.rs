@@ -1,11 +1,20 @@
+use crate::{CropMode, NamedSizeConfig};
 use axum::{
     extract::{Path, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::{Html, IntoResponse},
 };
 use liquid::Parser;
-use liquid_core::{Filter, FilterReflection, ParseFilter, Runtime, Value, ValueView};
-use std::{collections::HashMap, path::PathBuf, sync::Arc, time::SystemTime};
+use liquid_core::parser::{FilterArguments, ParameterReflection};
+use liquid_core::{
+    Expression, Filter, FilterReflection, ObjectView, ParseFilter, Runtime, Value, ValueView,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::Arc,
+    time::SystemTime,
+};
 use tokio::sync::RwLock;
 use tracing::{debug, error, info};
 
@@ -90,12 +99,423 @@ impl ParseFilter for AssetUrlFilter {
     }
 }
 
+/// Named image sizes of each gallery, keyed by the gallery's URL prefix
+type GalleryImageSizes = Arc<HashMap<String, BTreeMap<String, NamedSizeConfig>>>;
+
+/// Formats offered as `<source>` entries by `picture_tag`, best first
+const PICTURE_SOURCE_FORMATS: &[(&str, &str)] = &[
+    #[cfg(feature = "jxl")]
+    ("jxl", "image/jxl"),
+    #[cfg(feature = "avif")]
+    ("avif", "image/avif"),
+    ("webp", "image/webp"),
+    ("jpeg", "image/jpeg"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ImageFilterKind {
+    /// `{{ image | image_url: 'medium' }}`
+    Url,
+    /// `{{ image | srcset }}` or `{{ image | srcset: 'gallery' }}`
+    Srcset,
+    /// `{{ image | picture_tag: 'gallery', alt: '...', sizes: '...', class: '...' }}`
+    PictureTag,
+}
+
+const IMAGE_URL_PARAMETERS: &[ParameterReflection] = &[ParameterReflection {
+    name: "size",
+    description: "Named size, optionally with @2x; the original when omitted",
+    is_optional: true,
+}];
+
+const SRCSET_PARAMETERS: &[ParameterReflection] = &[ParameterReflection {
+    name: "size",
+    description: "Named size to offer at 1x and 2x; every uncropped public size when omitted",
+    is_optional: true,
+}];
+
+const PICTURE_TAG_PARAMETERS: &[ParameterReflection] = &[ParameterReflection {
+    name: "size",
+    description: "Named size to display, defaults to gallery",
+    is_optional: true,
+}];
+
+const PICTURE_TAG_KEYWORDS: &[ParameterReflection] = &[
+    ParameterReflection {
+        name: "alt",
+        description: "Alternative text, defaults to the image description or name",
+        is_optional: true,
+    },
+    ParameterReflection {
+        name: "sizes",
+        description: "The sizes attribute, defaults to the displayed width",
+        is_optional: true,
+    },
+    ParameterReflection {
+        name: "class",
+        description: "CSS class for the img element",
+        is_optional: true,
+    },
+    ParameterReflection {
+        name: "loading",
+        description: "The loading attribute, defaults to lazy",
+        is_optional: true,
+    },
+];
+
+// Filters building gallery image URLs and responsive markup from an image
+// object (or a plain image URL) and the gallery's named sizes
+#[derive(Clone, Debug)]
+struct ImageFilterParser {
+    kind: ImageFilterKind,
+    galleries: GalleryImageSizes,
+}
+
+impl ImageFilterParser {
+    fn new(kind: ImageFilterKind, galleries: GalleryImageSizes) -> Self {
+        Self { kind, galleries }
+    }
+}
+
+impl FilterReflection for ImageFilterParser {
+    fn name(&self) -> &str {
+        match self.kind {
+            ImageFilterKind::Url => "image_url",
+            ImageFilterKind::Srcset => "srcset",
+            ImageFilterKind::PictureTag => "picture_tag",
+        }
+    }
+
+    fn description(&self) -> &str {
+        match self.kind {
+            ImageFilterKind::Url => "Builds the URL of a gallery image at a named size",
+            ImageFilterKind::Srcset => "Builds a width-descriptor srcset for a gallery image",
+            ImageFilterKind::PictureTag => {
+                "Builds a <picture> element with AVIF, WebP and JPEG sources for a gallery image"
+            }
+        }
+    }
+
+    fn positional_parameters(&self) -> &'static [ParameterReflection] {
+        match self.kind {
+            ImageFilterKind::Url => IMAGE_URL_PARAMETERS,
+            ImageFilterKind::Srcset => SRCSET_PARAMETERS,
+            ImageFilterKind::PictureTag => PICTURE_TAG_PARAMETERS,
+        }
+    }
+
+    fn keyword_parameters(&self) -> &'static [ParameterReflection] {
+        match self.kind {
+            ImageFilterKind::PictureTag => PICTURE_TAG_KEYWORDS,
+            ImageFilterKind::Url | ImageFilterKind::Srcset => &[],
+        }
+    }
+}
+
+impl ParseFilter for ImageFilterParser {
+    fn reflection(&self) -> &dyn FilterReflection {
+        self
+    }
+
+    fn parse(&self, mut arguments: FilterArguments) -> liquid_core::Result<Box<dyn Filter>> {
+        let size = arguments.positional.next();
+        if arguments.positional.next().is_some() {
+            return Err(liquid_core::Error::with_msg(format!(
+                "{} takes at most one positional argument",
+                self.name()
+            )));
+        }
+
+        let mut keywords = HashMap::new();
+        for (name, expression) in arguments.keyword {
+            if !self.keyword_parameters().iter().any(|p| p.name == name) {
+                return Err(liquid_core::Error::with_msg(format!(
+                    "Unknown argument '{}' for {}",
+                    name,
+                    self.name()
+                )));
+            }
+            keywords.insert(name.to_string(), expression);
+        }
+
+        Ok(Box::new(ImageFilter {
+            kind: self.kind,
+            galleries: self.galleries.clone(),
+            size,
+            keywords,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct ImageFilter {
+    kind: ImageFilterKind,
+    galleries: GalleryImageSizes,
+    size: Option<Expression>,
+    keywords: HashMap<String, Expression>,
+}
+
+impl std::fmt::Display for ImageFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            ImageFilterKind::Url => f.write_str("image_url"),
+            ImageFilterKind::Srcset => f.write_str("srcset"),
+            ImageFilterKind::PictureTag => f.write_str("picture_tag"),
+        }
+    }
+}
+
+impl ImageFilter {
+    fn argument(
+        expression: Option<&Expression>,
+        runtime: &dyn Runtime,
+    ) -> liquid_core::Result<Option<String>> {
+        match expression {
+            Some(expression) => {
+                let value = expression.evaluate(runtime)?;
+                Ok((!value.is_nil()).then(|| value.to_kstr().to_string()))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Filter for ImageFilter {
+    fn evaluate(&self, input: &dyn ValueView, runtime: &dyn Runtime) -> liquid_core::Result<Value> {
+        let image = GalleryImageRef::resolve(input, &self.galleries)?;
+        let size = Self::argument(self.size.as_ref(), runtime)?;
+
+        let output = match self.kind {
+            ImageFilterKind::Url => match size {
+                Some(size) => {
+                    image.size(&size)?;
+                    image.url(&size, None)
+                }
+                None => image.base_url.clone(),
+            },
+            ImageFilterKind::Srcset => image.srcset(size.as_deref(), None)?,
+            ImageFilterKind::PictureTag => {
+                let keyword = |name: &str| Self::argument(self.keywords.get(name), runtime);
+                image.picture_tag(
+                    size.as_deref().unwrap_or("gallery"),
+                    keyword("alt")?,
+                    keyword("sizes")?,
+                    keyword("class")?,
+                    keyword("loading")?,
+                )?
+            }
+        };
+
+        Ok(Value::scalar(output))
+    }
+}
+
+/// A gallery image as seen by the image filters
+struct GalleryImageRef<'a> {
+    /// Image URL without any query string
+    base_url: String,
+    sizes: &'a BTreeMap<String, NamedSizeConfig>,
+    dimensions: Option<(u32, u32)>,
+    alt: String,
+}
+
+impl<'a> GalleryImageRef<'a> {
+    /// Accepts an image object with a `url`, `gallery_url` or `thumbnail_url`
+    /// field, or an image URL string
+    fn resolve(
+        input: &dyn ValueView,
+        galleries: &'a HashMap<String, BTreeMap<String, NamedSizeConfig>>,
+    ) -> liquid_core::Result<Self> {
+        let (url, dimensions, alt) = if let Some(object) = input.as_object() {
+            let url = ["url", "gallery_url", "thumbnail_url"]
+                .iter()
+                .find_map(|field| string_field(object, field))
+                .ok_or_else(|| liquid_core::Error::with_msg("Image has no URL"))?;
+            let dimensions = object
+                .get("dimensions")
+                .and_then(|value| value.as_array())
+                .and_then(|array| {
+                    let dimension = |index| {
+                        array
+                            .get(index)
+                            .and_then(|value| value.as_scalar())
+                            .and_then(|value| value.to_integer())
+                            .and_then(|value| u32::try_from(value).ok())
+                    };
+                    Some((dimension(0)?, dimension(1)?))
+                });
+            let alt = ["description", "display_name", "name"]
+                .iter()
+                .find_map(|field| string_field(object, field))
+                .unwrap_or_default();
+            (url, dimensions, alt)
+        } else {
+            (input.to_kstr().to_string(), None, String::new())
+        };
+
+        let base_url = url.split('?').next().unwrap_or_default().to_string();
+        let sizes = galleries
+            .iter()
+            .filter(|(prefix, _)| base_url.starts_with(&format!("{}/image/", prefix)))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, sizes)| sizes)
+            .ok_or_else(|| {
+                liquid_core::Error::with_msg(format!("'{}' is not a gallery image", base_url))
+            })?;
+
+        Ok(Self {
+            base_url,
+            sizes,
+            dimensions,
+            alt,
+        })
+    }
+
+    /// Look up a size such as `gallery` or `gallery@2x`
+    fn size(&self, spec: &str) -> liquid_core::Result<(&'a NamedSizeConfig, u32)> {
+        let (name, multiplier) = match spec.strip_suffix("@2x") {
+            Some(name) => (name, 2),
+            None => (spec, 1),
+        };
+        let size = self.sizes.get(name).ok_or_else(|| {
+            let valid: Vec<&str> = self.sizes.keys().map(String::as_str).collect();
+            liquid_core::Error::with_msg(format!(
+                "Unknown image size '{}'. Valid sizes: {}",
+                spec,
+                valid.join(", ")
+            ))
+        })?;
+        Ok((size, multiplier))
+    }
+
+    fn url(&self, spec: &str, format: Option<&str>) -> String {
+        match format {
+            Some(format) => format!("{}?size={}&format={}", self.base_url, spec, format),
+            None => format!("{}?size={}", self.base_url, spec),
+        }
+    }
+
+    /// Width of a size in pixels, exact when the image dimensions are known
+    fn width(&self, size: &NamedSizeConfig, multiplier: u32) -> u32 {
+        match self.dimensions {
+            Some(dimensions) => size.output_dimensions(dimensions, multiplier).0,
+            None => size.width.saturating_mul(multiplier),
+        }
+    }
+
+    /// `url 400w, url 800w` for one size at 1x and 2x, or every uncropped size
+    /// that needs no download permission
+    fn srcset(&self, spec: Option<&str>, format: Option<&str>) -> liquid_core::Result<String> {
+        let names: Vec<&str> = match spec {
+            Some(spec) => {
+                self.size(spec)?;
+                vec![spec.strip_suffix("@2x").unwrap_or(spec)]
+            }
+            None => self
+                .sizes
+                .iter()
+                .filter(|(_, size)| size.crop == CropMode::Fit && !size.requires_auth)
+                .map(|(name, _)| name.as_str())
+                .collect(),
+        };
+
+        let mut candidates: Vec<(u32, u32, String)> = Vec::new();
+        for name in names {
+            let size = &self.sizes[name];
+            candidates.push((self.width(size, 1), 1, name.to_string()));
+            candidates.push((self.width(size, 2), 2, format!("{}@2x", name)));
+        }
+        // Sizes capped by the original collapse to the same width; keep one
+        candidates.sort();
+        candidates.dedup_by_key(|(width, _, _)| *width);
+
+        Ok(candidates
+            .iter()
+            .map(|(width, _, spec)| format!("{} {}w", self.url(spec, format), width))
+            .collect::<Vec<_>>()
+            .join(", "))
+    }
+
+    fn picture_tag(
+        &self,
+        spec: &str,
+        alt: Option<String>,
+        sizes: Option<String>,
+        class: Option<String>,
+        loading: Option<String>,
+    ) -> liquid_core::Result<String> {
+        let (size, multiplier) = self.size(spec)?;
+        let name = spec.strip_suffix("@2x").unwrap_or(spec);
+        let dimensions = self
+            .dimensions
+            .map(|dimensions| size.output_dimensions(dimensions, multiplier));
+        let display_width = dimensions
+            .map(|(width, _)| width)
+            .unwrap_or_else(|| size.width.saturating_mul(multiplier));
+        let sizes =
+            sizes.unwrap_or_else(|| format!("(max-width: {0}px) 100vw, {0}px", display_width));
+
+        let mut html = String::from("<picture>");
+        // PNG sources are always served as PNG, so there is nothing to choose from
+        if !self.base_url.to_lowercase().ends_with(".png") {
+            for (format, mime_type) in PICTURE_SOURCE_FORMATS {
+                html.push_str(&format!(
+                    r#"<source type="{}" srcset="{}" sizes="{}">"#,
+                    mime_type,
+                    escape_attribute(&self.srcset(Some(name), Some(format))?),
+                    escape_attribute(&sizes)
+                ));
+            }
+        }
+
+        html.push_str(&format!(
+            r#"<img src="{}" srcset="{}" sizes="{}""#,
+            escape_attribute(&self.url(spec, None)),
+            escape_attribute(&self.srcset(Some(name), None)?),
+            escape_attribute(&sizes)
+        ));
+        if let Some((width, height)) = dimensions {
+            html.push_str(&format!(r#" width="{}" height="{}""#, width, height));
+        }
+        html.push_str(&format!(
+            r#" alt="{}""#,
+            escape_attribute(&alt.unwrap_or_else(|| self.alt.clone()))
+        ));
+        if let Some(class) = class {
+            html.push_str(&format!(r#" class="{}""#, escape_attribute(&class)));
+        }
+        html.push_str(&format!(
+            r#" loading="{}" decoding="async"></picture>"#,
+            escape_attribute(loading.as_deref().unwrap_or("lazy"))
+        ));
+
+        Ok(html)
+    }
+}
+
+fn string_field(object: &dyn ObjectView, field: &str) -> Option<String> {
+    object
+        .get(field)
+        .filter(|value| !value.is_nil())
+        .map(|value| value.to_kstr().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
 pub struct TemplateEngine {
     pub template_dirs: Vec<PathBuf>,
     cache: Arc<RwLock<HashMap<String, CachedTemplate>>>,
     static_handler: Option<crate::static_files::StaticFileHandler>,
     has_user_auth: bool,
     file_versions: Arc<RwLock<HashMap<String, u64>>>,
+    image_sizes: GalleryImageSizes,
 }
 
 struct CachedTemplate {
@@ -111,6 +531,7 @@ impl TemplateEngine {
             static_handler: None,
             has_user_auth: false,
             file_versions: Arc::new(RwLock::new(HashMap::new())),
+            image_sizes: Arc::new(HashMap::new()),
         }
     }
 
@@ -136,6 +557,17 @@ impl TemplateEngine {
         self.has_user_auth = has_auth;
     }
 
+    /// Register a gallery's named sizes for the `image_url`, `srcset` and
+    /// `picture_tag` filters
+    pub fn set_gallery_image_sizes(
+        &mut self,
+        url_prefix: &str,
+        sizes: BTreeMap<String, NamedSizeConfig>,
+    ) {
+        let prefix = format!("/{}", url_prefix.trim_start_matches('/'));
+        Arc::make_mut(&mut self.image_sizes).insert(prefix, sizes);
+    }
+
     fn create_parser_with_filters(
         &self,
         partials: liquid::partials::EagerCompiler<liquid::partials::InMemorySource>,
@@ -145,6 +577,18 @@ impl TemplateEngine {
         liquid::ParserBuilder::with_stdlib()
             .partials(partials)
             .filter(asset_filter)
+            .filter(ImageFilterParser::new(
+                ImageFilterKind::Url,
+                self.image_sizes.clone(),
+            ))
+            .filter(ImageFilterParser::new(
+                ImageFilterKind::Srcset,
+                self.image_sizes.clone(),
+            ))
+            .filter(ImageFilterParser::new(
+                ImageFilterKind::PictureTag,
+                self.image_sizes.clone(),
+            ))
             .build()
             .map_err(|e| format!("Failed to create parser: {}", e))
     }
@@ -329,6 +773,7 @@ impl TemplateEngine {
 pub async fn template_with_gallery_handler(
     State(app_state): State<crate::AppState>,
     path: Option<Path<String>>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
     let path = path.map(|p| p.0).unwrap_or_default();
 
@@ -373,7 +818,10 @@ pub async fn template_with_gallery_handler(
                 );
                 // Pass the path without the "static/" prefix to the serve method
                 // Templates don't have version parameters, so pass false
-                return app_state.static_handler.serve(check_path, false).await;
+                return app_state
+                    .static_handler
+                    .serve(check_path, false, &headers)
+                    .await;
             }
         }
 
@@ -494,6 +942,197 @@ mod tests {
 <script src="/static/test.js?v=222222222"></script>"#
         );
     }
+
+    fn image_filter_engine() -> TemplateEngine {
+        let mut template_engine = TemplateEngine::new(vec![PathBuf::from("templates")]);
+        let mut sizes: BTreeMap<String, NamedSizeConfig> = BTreeMap::new();
+        for (name, width, crop) in [
+            ("thumbnail", 300, CropMode::Fit),
+            ("gallery", 800, CropMode::Fit),
+            ("square", 400, CropMode::SmartCrop),
+        ] {
+            sizes.insert(
+                name.to_string(),
+                NamedSizeConfig {
+                    width,
+                    height: width,
+                    crop,
+                    quality: None,
+                    watermark: false,
+                    requires_auth: false,
+                },
+            );
+        }
+        sizes.insert(
+            "large".to_string(),
+            NamedSizeConfig {
+                width: 1600,
+                height: 1600,
+                crop: CropMode::Fit,
+                quality: None,
+                watermark: false,
+                requires_auth: true,
+            },
+        );
+        template_engine.set_gallery_image_sizes("gallery", sizes);
+        template_engine
+    }
+
+    fn render_image_filter(template: &str, globals: liquid::Object) -> Result<String, String> {
+        let partials =
+            liquid::partials::EagerCompiler::new(liquid::partials::InMemorySource::new());
+        let parser = image_filter_engine()
+            .create_parser_with_filters(partials)
+            .unwrap();
+        parser
+            .parse(template)
+            .and_then(|template| template.render(&globals))
+            .map_err(|e| e.to_string())
+    }
+
+    fn landscape_image() -> liquid::Object {
+        liquid::object!({
+            "image": {
+                "name": "sunset.jpg",
+                "description": "Sunset \"over\" the bay",
+                "url": "/gallery/image/trips/sunset.jpg",
+                "thumbnail_url": "/gallery/image/trips/sunset.jpg?size=thumbnail",
+                "dimensions": [1000, 500],
+            }
+        })
+    }
+
+    #[test]
+    fn test_image_url_filter() {
+        let output =
+            render_image_filter("{{ image | image_url: 'gallery' }}", landscape_image()).unwrap();
+        assert_eq!(output, "/gallery/image/trips/sunset.jpg?size=gallery");
+
+        let output = render_image_filter("{{ image | image_url }}", landscape_image()).unwrap();
+        assert_eq!(output, "/gallery/image/trips/sunset.jpg");
+
+        // Plain URLs work too, with any existing size replaced
+        let output = render_image_filter(
+            "{{ image.thumbnail_url | image_url: 'square@2x' }}",
+            landscape_image(),
+        )
+        .unwrap();
+        assert_eq!(output, "/gallery/image/trips/sunset.jpg?size=square@2x");
+
+        let error =
+            render_image_filter("{{ image | image_url: 'huge' }}", landscape_image()).unwrap_err();
+        assert!(error.contains("Unknown image size 'huge'"));
+
+        let error = render_image_filter(
+            "{{ '/elsewhere/photo.jpg' | image_url: 'gallery' }}",
+            liquid::object!({}),
+        )
+        .unwrap_err();
+        assert!(error.contains("not a gallery image"));
+    }
+
+    #[test]
+    fn test_srcset_filter() {
+        // Uncropped public sizes only, with widths following the 1000x500
+        // original, so gallery@2x is capped at 1000w
+        let output = render_image_filter("{{ image | srcset }}", landscape_image()).unwrap();
+        assert_eq!(
+            output,
+            "/gallery/image/trips/sunset.jpg?size=thumbnail 300w, \
+             /gallery/image/trips/sunset.jpg?size=thumbnail@2x 600w, \
+             /gallery/image/trips/sunset.jpg?size=gallery 800w, \
+             /gallery/image/trips/sunset.jpg?size=gallery@2x 1000w"
+        );
+
+        let output =
+            render_image_filter("{{ image | srcset: 'square' }}", landscape_image()).unwrap();
+        assert_eq!(
+            output,
+            "/gallery/image/trips/sunset.jpg?size=square 400w, \
+             /gallery/image/trips/sunset.jpg?size=square@2x 500w"
+        );
+
+        // Without dimensions the configured widths are used
+        let output = render_image_filter(
+            "{{ '/gallery/image/a.jpg' | srcset: 'thumbnail' }}",
+            liquid::object!({}),
+        )
+        .unwrap();
+        assert_eq!(
+            output,
+            "/gallery/image/a.jpg?size=thumbnail 300w, /gallery/image/a.jpg?size=thumbnail@2x 600w"
+        );
+    }
+
+    #[test]
+    fn test_picture_tag_filter() {
+        let output = render_image_filter(
+            "{{ image | picture_tag: 'thumbnail', class: 'photo' }}",
+            landscape_image(),
+        )
+        .unwrap();
+
+        assert!(output.starts_with("<picture>"));
+        assert!(output.ends_with("</picture>"));
+        #[cfg(feature = "avif")]
+        assert!(output.contains(
+            r#"<source type="image/avif" srcset="/gallery/image/trips/sunset.jpg?size=thumbnail&amp;format=avif 300w, /gallery/image/trips/sunset.jpg?size=thumbnail@2x&amp;format=avif 600w""#
+        ));
+        #[cfg(feature = "jxl")]
+        assert!(output.starts_with(r#"<picture><source type="image/jxl""#));
+        assert!(output.contains(r#"<source type="image/webp""#));
+        assert!(output.contains(r#"<source type="image/jpeg""#));
+        assert!(output.contains(r#"<img src="/gallery/image/trips/sunset.jpg?size=thumbnail""#));
+        assert!(output.contains(r#"width="300" height="150""#));
+        assert!(output.contains(r#"sizes="(max-width: 300px) 100vw, 300px""#));
+        assert!(output.contains(r#"alt="Sunset &quot;over&quot; the bay""#));
+        assert!(output.contains(r#"class="photo""#));
+        assert!(output.contains(r#"loading="lazy""#));
+
+        // PNG sources are never converted, so only the img is emitted
+        let output = render_image_filter(
+            "{{ '/gallery/image/logo.png' | picture_tag: alt: 'Logo' }}",
+            liquid::object!({}),
+        )
+        .unwrap();
+        assert!(!output.contains("<source"));
+        assert!(output.contains(r#"<img src="/gallery/image/logo.png?size=gallery""#));
+        assert!(output.contains(r#"alt="Logo""#));
+    }
+
+    #[test]
+    fn test_named_size_output_dimensions() {
+        let size = |crop| NamedSizeConfig {
+            width: 400,
+            height: 300,
+            crop,
+            quality: None,
+            watermark: false,
+            requires_auth: false,
+        };
+
+        assert_eq!(
+            size(CropMode::Fit).output_dimensions((1000, 500), 1),
+            (400, 200)
+        );
+        assert_eq!(
+            size(CropMode::Fit).output_dimensions((1000, 500), 2),
+            (800, 400)
+        );
+        assert_eq!(
+            size(CropMode::Fit).output_dimensions((200, 100), 1),
+            (200, 100)
+        );
+        assert_eq!(
+            size(CropMode::Fill).output_dimensions((1000, 500), 1),
+            (400, 300)
+        );
+        // Cropped sizes shrink, keeping their shape, rather than upscale
+        assert_eq!(
+            size(CropMode::Fill).output_dimensions((1000, 500), 2),
+            (667, 500)
+        );
+    }
 }
 
 #[cfg(test)]
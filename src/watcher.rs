@@ -0,0 +1,198 @@
+//! Filesystem watching for galleries and posts.
+//!
+//! Changes under a source directory are debounced into batches and applied
+//! incrementally, so new photos and edited posts show up without waiting for
+//! the periodic refresh. The periodic refresh keeps running as a fallback for
+//! filesystems that don't deliver notifications (e.g. some network mounts).
+
+use crate::gallery::SharedGallery;
+use crate::posts::PostsManager;
+use notify::{EventKind, RecursiveMode, Watcher, event::ModifyKind};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+/// How long to wait for more events before processing a batch. Copying a folder
+/// of photos produces a burst of events per file; this collapses them.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Start watching a gallery's source directory for changes.
+pub fn watch_gallery(gallery: SharedGallery) -> notify::Result<()> {
+    let root = gallery.source_directory().to_path_buf();
+    let (watcher, mut rx) = spawn_watcher(&root)?;
+
+    info!(
+        "Watching {:?} for changes to gallery '{}'",
+        root,
+        gallery.get_config().name
+    );
+
+    tokio::spawn(async move {
+        // Keep the watcher alive for as long as the task runs
+        let _watcher = watcher;
+
+        while let Some(batch) = next_batch(&mut rx).await {
+            for relative in batch {
+                let relative = relative.to_string_lossy().replace('\\', "/");
+                if relative.is_empty() {
+                    continue;
+                }
+
+                debug!("Gallery change detected: {}", relative);
+                if let Err(e) = gallery.refresh_path(&relative).await {
+                    error!("Failed to refresh {}: {}", relative, e);
+                }
+            }
+
+            if gallery
+                .metadata_cache_dirty
+                .load(std::sync::atomic::Ordering::Relaxed)
+                && let Err(e) = gallery.save_metadata_cache().await
+            {
+                error!("Failed to save metadata cache: {}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Start watching a posts system's source directory for changes.
+pub fn watch_posts(posts_manager: Arc<PostsManager>, root: PathBuf) -> notify::Result<()> {
+    let (watcher, mut rx) = spawn_watcher(&root)?;
+
+    info!("Watching {:?} for post changes", root);
+
+    tokio::spawn(async move {
+        let _watcher = watcher;
+
+        while let Some(batch) = next_batch(&mut rx).await {
+            for relative in batch {
+                // Posts remember the paths they were loaded from under the
+                // configured directory
+                let path = root.join(relative);
+                debug!("Post change detected: {}", path.display());
+                if let Err(e) = posts_manager.refresh_post_at_path(&path).await {
+                    error!("Failed to reload posts at {:?}: {}", path, e);
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Watch `root`, sending changed paths relative to it. notify reports
+/// absolute paths, so the root is canonicalized first to strip it from
+/// them even when it's configured as a relative path.
+fn spawn_watcher(
+    root: &Path,
+) -> notify::Result<(notify::RecommendedWatcher, mpsc::UnboundedReceiver<PathBuf>)> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let root = root.canonicalize()?;
+    let watched_root = root.clone();
+
+    let mut watcher =
+        notify::recommended_watcher(move |result: notify::Result<notify::Event>| match result {
+            Ok(event) => {
+                if !is_relevant_event(&event.kind) {
+                    return;
+                }
+                for path in event.paths {
+                    // Only what's below the root counts, so a hidden
+                    // directory above it doesn't hide every change
+                    if let Ok(relative) = path.strip_prefix(&watched_root)
+                        && !is_ignored_path(relative)
+                    {
+                        let _ = tx.send(relative.to_path_buf());
+                    }
+                }
+            }
+            Err(e) => warn!("Filesystem watch error: {}", e),
+        })?;
+
+    watcher.watch(&root, RecursiveMode::Recursive)?;
+    Ok((watcher, rx))
+}
+
+/// Wait for the next change, then gather everything else that arrives within the
+/// debounce window. Returns `None` once the watcher has gone away.
+async fn next_batch(rx: &mut mpsc::UnboundedReceiver<PathBuf>) -> Option<BTreeSet<PathBuf>> {
+    let mut batch = BTreeSet::new();
+    batch.insert(rx.recv().await?);
+
+    while let Ok(Some(path)) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+        batch.insert(path);
+    }
+
+    Some(batch)
+}
+
+fn is_relevant_event(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Access(_) => false,
+        // Permission and timestamp changes don't alter content
+        EventKind::Modify(ModifyKind::Metadata(_)) => false,
+        _ => true,
+    }
+}
+
+/// Skip hidden files and editor/sync temporaries such as `.foo.jpg.swp`,
+/// `.syncthing.*.tmp` and `post.md~`. `path` is relative to the watched root.
+fn is_ignored_path(path: &Path) -> bool {
+    path.components().any(|component| {
+        let name = component.as_os_str().to_string_lossy();
+        (name.starts_with('.') && name != "." && name != "..") || name.ends_with('~')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_ignored_path() {
+        assert!(!is_ignored_path(Path::new("photos/trip/beach.jpg")));
+        assert!(!is_ignored_path(Path::new("./photos/trip/_folder.md")));
+        assert!(!is_ignored_path(Path::new("../posts/hello.md")));
+        assert!(is_ignored_path(Path::new("photos/.stfolder/marker")));
+        assert!(is_ignored_path(Path::new(
+            "photos/trip/.syncthing.beach.jpg.tmp"
+        )));
+        assert!(is_ignored_path(Path::new("posts/hello.md~")));
+    }
+
+    #[tokio::test]
+    async fn test_watcher_sends_paths_relative_to_root() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        // A hidden directory above the root mustn't hide changes below it
+        let root = temp_dir.path().join(".config").join("photos");
+        std::fs::create_dir_all(&root).unwrap();
+        let (_watcher, mut rx) = spawn_watcher(&root).unwrap();
+
+        std::fs::write(root.join("beach.jpg"), b"jpeg").unwrap();
+        let batch = tokio::time::timeout(Duration::from_secs(5), next_batch(&mut rx))
+            .await
+            .expect("no change reported")
+            .unwrap();
+        assert!(batch.contains(Path::new("beach.jpg")));
+    }
+
+    #[test]
+    fn test_is_relevant_event() {
+        use notify::event::{AccessKind, CreateKind, DataChange, MetadataKind, RemoveKind};
+
+        assert!(is_relevant_event(&EventKind::Create(CreateKind::File)));
+        assert!(is_relevant_event(&EventKind::Remove(RemoveKind::Folder)));
+        assert!(is_relevant_event(&EventKind::Modify(ModifyKind::Data(
+            DataChange::Content
+        ))));
+        assert!(!is_relevant_event(&EventKind::Access(AccessKind::Read)));
+        assert!(!is_relevant_event(&EventKind::Modify(
+            ModifyKind::Metadata(MetadataKind::Permissions)
+        )));
+    }
+}
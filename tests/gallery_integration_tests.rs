@@ -55,9 +55,17 @@ fn create_test_config(temp_dir: &TempDir) -> Config {
             jpeg_quality: Some(85),
             webp_quality: Some(85.0),
             pregenerate_cache: false,
+            pregenerate_parallelism: None,
             new_threshold_days: Some(7),
             approximate_dates_for_public: false,
+            location_precision: Default::default(),
+            location_precision_for_public: Default::default(),
+            metadata: Default::default(),
+            ffmpeg_path: None,
+            max_cache_size: None,
             copyright_holder: None,
+            watch_filesystem: false,
+            sizes: Default::default(),
         },
         GallerySystemConfig {
             name: "portfolio".to_string(),
@@ -92,9 +100,17 @@ fn create_test_config(temp_dir: &TempDir) -> Config {
             jpeg_quality: Some(90),
             webp_quality: Some(90.0),
             pregenerate_cache: false,
+            pregenerate_parallelism: None,
             new_threshold_days: None,
             approximate_dates_for_public: false,
+            location_precision: Default::default(),
+            location_precision_for_public: Default::default(),
+            metadata: Default::default(),
+            ffmpeg_path: None,
+            max_cache_size: None,
             copyright_holder: Some("Test Portfolio".to_string()),
+            watch_filesystem: false,
+            sizes: Default::default(),
         },
     ]);
 
@@ -129,6 +145,42 @@ fn create_folder_with_metadata(dir: &std::path::Path, title: &str, description:
     std::fs::write(dir.join("_folder.md"), metadata_content).unwrap();
 }
 
+/// Configure a user database and pre-seed one live session per user, so
+/// requests can sign in with `cookie(username)`
+fn enable_logins(temp_dir: &TempDir, config: &mut Config, users_toml: &str) {
+    use tenrankai::login::session::{Session, session_id};
+
+    let users_path = temp_dir.path().join("users.toml");
+    std::fs::write(&users_path, users_toml).unwrap();
+    let database: toml_edit::DocumentMut = users_toml.parse().unwrap();
+
+    let now = chrono::Utc::now().timestamp();
+    let sessions: Vec<Session> = database["users"]
+        .as_table()
+        .unwrap()
+        .iter()
+        .map(|(username, _)| Session {
+            id: session_id(&format!("{}-token", username)),
+            username: username.to_string(),
+            created_at: now,
+            last_seen: now,
+            expires_at: now + 3600,
+            ip: None,
+            user_agent: None,
+        })
+        .collect();
+    let sessions_path = temp_dir.path().join("sessions.json");
+    std::fs::write(&sessions_path, serde_json::to_string(&sessions).unwrap()).unwrap();
+
+    config.app.user_database = Some(users_path);
+    config.app.session_store = Some(sessions_path);
+}
+
+/// Cookie header value for a user seeded by `enable_logins`
+fn cookie(username: &str) -> axum::http::HeaderValue {
+    axum::http::HeaderValue::from_str(&format!("auth={}-token", username)).unwrap()
+}
+
 #[tokio::test]
 async fn test_gallery_root_renders_correctly() {
     let temp_dir = TempDir::new().unwrap();
@@ -617,3 +669,1356 @@ async fn test_gallery_preview_partial_in_template() {
     assert!(html.contains("gallery-preview-component"));
     assert!(html.contains("Explore Full Gallery"));
 }
+
+#[tokio::test]
+async fn test_sitemap_excludes_hidden_and_restricted_folders() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_test_config(&temp_dir);
+    let photos_dir = config.galleries.as_ref().unwrap()[0]
+        .source_directory
+        .clone();
+
+    let public_dir = photos_dir.join("public");
+    let hidden_dir = photos_dir.join("hidden");
+    let private_dir = photos_dir.join("private");
+    create_folder_with_metadata(&public_dir, "Public", "Everyone can see this");
+    create_test_images(&public_dir, 1);
+
+    std::fs::create_dir_all(hidden_dir.join("nested")).unwrap();
+    std::fs::write(hidden_dir.join("_folder.md"), "+++\nhidden = true\n+++\n").unwrap();
+    create_test_images(&hidden_dir.join("nested"), 1);
+
+    std::fs::create_dir_all(&private_dir).unwrap();
+    std::fs::write(
+        private_dir.join("_folder.md"),
+        "+++\nallowed_users = [\"alice\"]\n+++\n",
+    )
+    .unwrap();
+    create_test_images(&private_dir, 1);
+
+    let app = create_app(config).await;
+    let server = TestServer::new(app).unwrap();
+
+    let response = server.get("/sitemap.xml").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+
+    let xml = response.text();
+    assert!(xml.contains("<loc>https://example.com/gallery</loc>"));
+    assert!(xml.contains("<loc>https://example.com/gallery/public</loc>"));
+    assert!(xml.contains("<loc>https://example.com/gallery/detail/public/test_000.jpg</loc>"));
+    assert!(xml.contains("<lastmod>"));
+    assert!(!xml.contains("hidden"), "Hidden folders must not be listed");
+    assert!(
+        !xml.contains("private"),
+        "Restricted folders must not be listed"
+    );
+}
+
+#[tokio::test]
+async fn test_gallery_feed_lists_public_images() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_test_config(&temp_dir);
+    let photos_dir = config.galleries.as_ref().unwrap()[0]
+        .source_directory
+        .clone();
+
+    create_test_images(&photos_dir, 2);
+    let trip_dir = photos_dir.join("trip");
+    std::fs::create_dir_all(&trip_dir).unwrap();
+    create_test_images(&trip_dir, 1);
+    let private_dir = photos_dir.join("private");
+    std::fs::create_dir_all(&private_dir).unwrap();
+    std::fs::write(
+        private_dir.join("_folder.md"),
+        "+++\nrequire_auth = true\n+++\n",
+    )
+    .unwrap();
+    create_test_images(&private_dir, 1);
+
+    let app = create_app(config).await;
+    let server = TestServer::new(app).unwrap();
+
+    let response = server.get("/gallery/feed.xml").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+
+    let atom = response.text();
+    assert!(atom.contains("<feed xmlns=\"http://www.w3.org/2005/Atom\">"));
+    assert!(atom.contains("<id>https://example.com/gallery/detail/test_000.jpg</id>"));
+    assert!(atom.contains("<id>https://example.com/gallery/detail/test_001.jpg</id>"));
+    assert!(atom.contains("<id>https://example.com/gallery/detail/trip/test_000.jpg</id>"));
+    assert!(
+        !atom.contains("private"),
+        "Images in restricted folders must not be listed"
+    );
+}
+
+#[tokio::test]
+async fn test_gallery_json_api() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_test_config(&temp_dir);
+    let photos_dir = config.galleries.as_ref().unwrap()[0]
+        .source_directory
+        .clone();
+
+    let trip_dir = photos_dir.join("trip");
+    create_folder_with_metadata(&trip_dir, "Road Trip", "Driving down the coast");
+    create_test_images(&trip_dir, 3);
+
+    let private_dir = photos_dir.join("private");
+    std::fs::create_dir_all(&private_dir).unwrap();
+    std::fs::write(
+        private_dir.join("_folder.md"),
+        "+++\nrequire_auth = true\n+++\n",
+    )
+    .unwrap();
+    create_test_images(&private_dir, 1);
+
+    let app = create_app(config).await;
+    let server = TestServer::new(app).unwrap();
+
+    let response = server.get("/api/gallery/main/list").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let json = response.json::<serde_json::Value>();
+    assert_eq!(json["path"], "");
+    let directories = json["directories"].as_array().unwrap();
+    assert!(directories.iter().any(|d| d["path"] == "trip"));
+
+    let response = server.get("/api/gallery/main/list/trip").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let json = response.json::<serde_json::Value>();
+    assert_eq!(json["folder_title"], "Road Trip");
+    assert_eq!(json["images"].as_array().unwrap().len(), 3);
+    assert_eq!(json["total_pages"], 1);
+    assert_eq!(json["breadcrumbs"].as_array().unwrap().len(), 2);
+
+    let response = server
+        .get("/api/gallery/main/image/trip/test_001.jpg")
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let json = response.json::<serde_json::Value>();
+    assert_eq!(json["image"]["path"], "trip/test_001.jpg");
+    assert_eq!(json["image"]["dimensions"][0], 100);
+    assert_eq!(json["prev_image"]["path"], "trip/test_000.jpg");
+    assert_eq!(json["next_image"]["path"], "trip/test_002.jpg");
+
+    // Restricted folders need a login, and never redirect
+    let response = server.get("/api/gallery/main/list/private").await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    let response = server
+        .get("/api/gallery/main/image/private/test_000.jpg")
+        .await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+
+    let response = server.get("/api/gallery/nonexistent/list").await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_search_captions_and_folders() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_test_config(&temp_dir);
+    let photos_dir = config.galleries.as_ref().unwrap()[0]
+        .source_directory
+        .clone();
+
+    let trip_dir = photos_dir.join("trip");
+    create_folder_with_metadata(&trip_dir, "Road Trip", "Driving down the coast");
+    create_test_images(&trip_dir, 2);
+    std::fs::write(trip_dir.join("test_001.md"), "Lighthouse at dusk").unwrap();
+
+    let private_dir = photos_dir.join("private");
+    std::fs::create_dir_all(&private_dir).unwrap();
+    std::fs::write(
+        private_dir.join("_folder.md"),
+        "+++\nrequire_auth = true\n+++\nCoast photos",
+    )
+    .unwrap();
+    create_test_images(&private_dir, 1);
+
+    let app = create_app(config).await;
+    let server = TestServer::new(app).unwrap();
+
+    let response = server.get("/api/search?q=lighthouse").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let json = response.json::<serde_json::Value>();
+    assert_eq!(json["total_results"], 1);
+    assert_eq!(json["results"][0]["path"], "trip/test_001.jpg");
+    assert_eq!(
+        json["results"][0]["detail_url"],
+        "/gallery/detail/trip%2Ftest_001.jpg"
+    );
+
+    // Folder descriptions match every image beneath them, but restricted
+    // folders stay out of anonymous results
+    let json = server
+        .get("/api/search?q=coast")
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(json["total_results"], 2);
+    let paths: Vec<_> = json["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["path"].as_str().unwrap().to_string())
+        .collect();
+    assert!(paths.iter().all(|p| p.starts_with("trip/")));
+
+    let json = server
+        .get("/api/search?q=coast%20gallery:portfolio")
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(json["total_results"], 0);
+
+    let response = server.get("/search?q=lighthouse").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert!(
+        response
+            .text()
+            .contains("/gallery/detail/trip%2Ftest_001.jpg")
+    );
+}
+
+#[tokio::test]
+async fn test_folder_sort_order_and_cover() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_test_config(&temp_dir);
+    let photos_dir = config.galleries.as_ref().unwrap()[0]
+        .source_directory
+        .clone();
+
+    let events_dir = photos_dir.join("events");
+    std::fs::create_dir_all(&events_dir).unwrap();
+    std::fs::write(
+        events_dir.join("_folder.md"),
+        "+++\nsort = \"filename_desc\"\ncover = \"test_001.jpg\"\n+++\n",
+    )
+    .unwrap();
+    create_test_images(&events_dir, 3);
+
+    let portfolio_dir = photos_dir.join("portfolio");
+    std::fs::create_dir_all(&portfolio_dir).unwrap();
+    std::fs::write(
+        portfolio_dir.join("_folder.md"),
+        "+++\norder = [\"test_002.jpg\", \"test_000.jpg\"]\n+++\n",
+    )
+    .unwrap();
+    create_test_images(&portfolio_dir, 4);
+
+    let app = create_app(config).await;
+    let server = TestServer::new(app).unwrap();
+
+    let image_names = |json: &serde_json::Value| -> Vec<String> {
+        json["images"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|image| image["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    let json = server
+        .get("/api/gallery/main/list/events")
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(
+        image_names(&json),
+        vec!["test_002.jpg", "test_001.jpg", "test_000.jpg"]
+    );
+
+    // Listed images first in list order, then the rest in the default order
+    let json = server
+        .get("/api/gallery/main/list/portfolio")
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(
+        image_names(&json),
+        vec![
+            "test_002.jpg",
+            "test_000.jpg",
+            "test_001.jpg",
+            "test_003.jpg"
+        ]
+    );
+
+    let json = server
+        .get("/api/gallery/main/list")
+        .await
+        .json::<serde_json::Value>();
+    let events = json["directories"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|dir| dir["name"] == "events")
+        .unwrap();
+    let previews = events["preview_images"].as_array().unwrap();
+    assert!(
+        previews[0]
+            .as_str()
+            .unwrap()
+            .contains("events%2Ftest_001.jpg")
+    );
+    assert_eq!(
+        previews
+            .iter()
+            .filter(|url| url.as_str().unwrap().contains("test_001.jpg"))
+            .count(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_folder_front_matter_errors_fail_closed() {
+    use axum::http::header::COOKIE;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_test_config(&temp_dir);
+    let photos_dir = config.galleries.as_ref().unwrap()[0]
+        .source_directory
+        .clone();
+
+    enable_logins(
+        &temp_dir,
+        &mut config,
+        r#"
+[users.alice]
+email = "alice@example.com"
+
+[users.root]
+email = "root@example.com"
+roles = ["admin"]
+"#,
+    );
+
+    // An unknown sort keeps the rest of the front matter
+    let private_dir = photos_dir.join("private");
+    std::fs::create_dir_all(&private_dir).unwrap();
+    std::fs::write(
+        private_dir.join("_folder.md"),
+        "+++\nallowed_users = [\"alice\"]\nsort = \"newest\"\n+++\n",
+    )
+    .unwrap();
+    create_test_images(&private_dir, 1);
+
+    // Front matter that doesn't parse locks the folder
+    let broken_dir = photos_dir.join("broken");
+    std::fs::create_dir_all(&broken_dir).unwrap();
+    std::fs::write(
+        broken_dir.join("_folder.md"),
+        "+++\nrequire_auth = \"yes\"\n+++\n",
+    )
+    .unwrap();
+    create_test_images(&broken_dir, 1);
+
+    let app = create_app(config).await;
+    let server = TestServer::new(app).unwrap();
+
+    let response = server.get("/api/gallery/main/list/private").await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    let response = server
+        .get("/api/gallery/main/list/private")
+        .add_header(COOKIE, cookie("alice"))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+
+    let response = server.get("/api/gallery/main/list/broken").await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    let response = server
+        .get("/api/gallery/main/list/broken")
+        .add_header(COOKIE, cookie("alice"))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+    let response = server
+        .get("/api/gallery/main/list/broken")
+        .add_header(COOKIE, cookie("root"))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+
+    let json = server
+        .get("/api/gallery/main/list")
+        .await
+        .json::<serde_json::Value>();
+    assert!(
+        !json["directories"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|dir| dir["name"] == "broken")
+    );
+}
+
+#[tokio::test]
+async fn test_group_access_and_download_permissions() {
+    use axum::http::header::COOKIE;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_test_config(&temp_dir);
+    let photos_dir = config.galleries.as_ref().unwrap()[0]
+        .source_directory
+        .clone();
+
+    enable_logins(
+        &temp_dir,
+        &mut config,
+        r#"
+[users.alice]
+email = "alice@example.com"
+groups = ["family"]
+
+[users.bob]
+email = "bob@example.com"
+
+[users.root]
+email = "root@example.com"
+roles = ["admin"]
+"#,
+    );
+
+    create_test_images(&photos_dir, 1);
+    let family_dir = photos_dir.join("family");
+    std::fs::create_dir_all(&family_dir).unwrap();
+    std::fs::write(
+        family_dir.join("_folder.md"),
+        "+++\nallowed_groups = [\"family\"]\ndownload_original = [\"family\"]\n+++\n",
+    )
+    .unwrap();
+    create_test_images(&family_dir, 1);
+
+    let app = create_app(config).await;
+    let server = TestServer::new(app).unwrap();
+
+    let response = server.get("/api/gallery/main/list/family").await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+
+    let response = server
+        .get("/api/gallery/main/list/family")
+        .add_header(COOKIE, cookie("bob"))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+
+    for username in ["alice", "root"] {
+        let response = server
+            .get("/api/gallery/main/list/family")
+            .add_header(COOKIE, cookie(username))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+    }
+
+    let response = server
+        .get("/gallery/image/family/test_000.jpg")
+        .add_header(COOKIE, cookie("alice"))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+
+    // Outside folders with their own lists any signed-in user may download
+    let response = server.get("/gallery/image/test_000.jpg").await;
+    assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+    let response = server
+        .get("/gallery/image/test_000.jpg")
+        .add_header(COOKIE, cookie("bob"))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+
+    let json = server
+        .get("/api/gallery/main/image/test_000.jpg")
+        .add_header(COOKIE, cookie("bob"))
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(json["can_download_original"], true);
+
+    // A resize that fails doesn't hand out the original instead
+    std::fs::write(family_dir.join("broken.jpg"), b"not really a JPEG").unwrap();
+    let response = server
+        .get("/gallery/image/family/broken.jpg?size=thumbnail")
+        .add_header(COOKIE, cookie("alice"))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_ne!(response.as_bytes().as_ref(), b"not really a JPEG");
+
+    // Refreshing static file versions is reserved for admins
+    let json = server
+        .post("/api/refresh-static-versions")
+        .add_header(COOKIE, cookie("bob"))
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(json["success"], false);
+    let json = server
+        .post("/api/refresh-static-versions")
+        .add_header(COOKIE, cookie("root"))
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(json["success"], true);
+}
+
+#[tokio::test]
+async fn test_share_links() {
+    use axum::http::header::{COOKIE, LOCATION, SET_COOKIE};
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_test_config(&temp_dir);
+    let photos_dir = config.galleries.as_ref().unwrap()[0]
+        .source_directory
+        .clone();
+    enable_logins(
+        &temp_dir,
+        &mut config,
+        r#"
+[users.root]
+email = "root@example.com"
+roles = ["admin"]
+"#,
+    );
+
+    let clients_dir = photos_dir.join("clients");
+    std::fs::create_dir_all(&clients_dir).unwrap();
+    std::fs::write(
+        clients_dir.join("_folder.md"),
+        "+++\nrequire_auth = true\n+++\n",
+    )
+    .unwrap();
+    create_test_images(&clients_dir.join("smith"), 2);
+    create_test_images(&clients_dir.join("jones"), 1);
+
+    let app = create_app(config).await;
+    let server = TestServer::new(app.clone()).unwrap();
+
+    // Only admins manage shares
+    let response = server
+        .post("/api/gallery/main/shares")
+        .json(&serde_json::json!({ "path": "clients/smith" }))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+
+    let response = server
+        .post("/api/gallery/main/shares")
+        .add_header(COOKIE, cookie("root"))
+        .json(&serde_json::json!({
+            "path": "clients/smith",
+            "password": "letmein",
+            "expires_in_days": 7,
+            "max_views": 3,
+        }))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::CREATED);
+    let share = response.json::<serde_json::Value>();
+    assert_eq!(share["kind"], "folder");
+    let link = share["url"]
+        .as_str()
+        .unwrap()
+        .strip_prefix("https://example.com")
+        .unwrap()
+        .to_string();
+    assert!(link.starts_with("/gallery/s/"));
+
+    // Password-protected links ask for the password before granting access
+    let response = server.get(&link).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert!(response.text().contains("Password Required"));
+    let response = server.post(&link).form(&[("password", "wrong")]).await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+
+    let response = server.post(&link).form(&[("password", "letmein")]).await;
+    assert_eq!(response.status_code(), StatusCode::SEE_OTHER);
+    assert_eq!(response.header(LOCATION), "/gallery/clients/smith");
+    let grants = response
+        .header(SET_COOKIE)
+        .to_str()
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .to_string();
+    let grants = axum::http::HeaderValue::from_str(&grants).unwrap();
+
+    let response = server.get("/api/gallery/main/list/clients/smith").await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    let response = server
+        .get("/api/gallery/main/list/clients/smith")
+        .add_header(COOKIE, grants.clone())
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let response = server
+        .get("/gallery/image/clients/smith/test_000.jpg?size=thumbnail")
+        .add_header(COOKIE, grants.clone())
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+
+    // Downloads weren't allowed, and the share doesn't reach sibling folders
+    let response = server
+        .get("/gallery/image/clients/smith/test_000.jpg")
+        .add_header(COOKIE, grants.clone())
+        .await;
+    assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+    let response = server
+        .get("/api/gallery/main/list/clients/jones")
+        .add_header(COOKIE, grants.clone())
+        .await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+
+    // Nor can `..` climb out of it. Sent as-is, since URL parsing would
+    // resolve the dot segments before the request left the client.
+    let escape = |uri: &str| {
+        axum::http::Request::builder()
+            .uri(uri)
+            .header(COOKIE, grants.clone())
+            .body(axum::body::Body::empty())
+            .unwrap()
+    };
+    let response =
+        tower::ServiceExt::oneshot(app.clone(), escape("/gallery/clients/smith/%2e%2e/jones"))
+            .await
+            .unwrap();
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    let response = tower::ServiceExt::oneshot(
+        app.clone(),
+        escape("/gallery/image/clients/smith/%2e%2e/jones/test_000.jpg?size=thumbnail"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Tampered links are rejected
+    let response = server.get(&format!("{}x", link)).await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+
+    let shares = server
+        .get("/api/gallery/main/shares")
+        .add_header(COOKIE, cookie("root"))
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(shares[0]["views"], 1);
+
+    // Password guesses are limited, after which even the right one waits
+    for _ in 0..3 {
+        let response = server.post(&link).form(&[("password", "guess")]).await;
+        assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    }
+    let response = server.post(&link).form(&[("password", "letmein")]).await;
+    assert_eq!(response.status_code(), StatusCode::TOO_MANY_REQUESTS);
+
+    let id = share["id"].as_str().unwrap();
+    let response = server
+        .delete(&format!("/api/gallery/main/shares/{}", id))
+        .add_header(COOKIE, cookie("root"))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::NO_CONTENT);
+    let response = server
+        .get("/api/gallery/main/list/clients/smith")
+        .add_header(COOKIE, grants)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    let response = server.get(&link).await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_folder_zip_download() {
+    use axum::http::header::COOKIE;
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_test_config(&temp_dir);
+    let photos_dir = config.galleries.as_ref().unwrap()[0]
+        .source_directory
+        .clone();
+
+    enable_logins(
+        &temp_dir,
+        &mut config,
+        r#"
+[users.alice]
+email = "alice@example.com"
+groups = ["family"]
+
+[users.bob]
+email = "bob@example.com"
+"#,
+    );
+
+    create_test_images(&photos_dir, 2);
+    std::fs::write(photos_dir.join("test_000.md"), "Sunset over the bay").unwrap();
+    let family_dir = photos_dir.join("family");
+    std::fs::create_dir_all(&family_dir).unwrap();
+    std::fs::write(
+        family_dir.join("_folder.md"),
+        "+++\nallowed_groups = [\"family\"]\n+++\n",
+    )
+    .unwrap();
+    create_test_images(&family_dir, 1);
+
+    let app = create_app(config).await;
+    let server = TestServer::new(app).unwrap();
+
+    // Originals need download permission, just like single images
+    let response = server.get("/gallery/download/.zip").await;
+    assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+
+    let response = server
+        .get("/gallery/download/.zip")
+        .add_header(COOKIE, cookie("bob"))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert_eq!(response.header("content-type"), "application/zip");
+    assert!(
+        response
+            .header("content-disposition")
+            .to_str()
+            .unwrap()
+            .contains("filename=\"main.zip\"")
+    );
+    let archive = response.as_bytes();
+    assert!(archive.starts_with(b"PK\x03\x04"));
+    assert!(contains(archive, b"test_000.jpg"));
+    assert!(contains(archive, b"test_001.jpg"));
+    assert!(contains(archive, b"test_000.txt"));
+    assert!(contains(archive, b"Sunset over the bay"));
+    // Subfolders aren't included
+    assert!(!contains(archive, b"family"));
+    // End of central directory: test_000.jpg, its caption and test_001.jpg
+    let eocd = archive.len() - 22;
+    assert_eq!(&archive[eocd..eocd + 4], b"PK\x05\x06");
+    assert_eq!(
+        u16::from_le_bytes([archive[eocd + 10], archive[eocd + 11]]),
+        3
+    );
+
+    // Display sizes are open to everyone who can see the folder
+    let response = server
+        .get("/gallery/download/.zip")
+        .add_query_param("size", "thumbnail")
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert!(contains(response.as_bytes(), b"test_001.jpg"));
+
+    let response = server
+        .get("/gallery/download/.zip")
+        .add_query_param("size", "huge")
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+
+    // Folder access rules apply before anything is streamed
+    let response = server
+        .get("/gallery/download/family.zip")
+        .add_header(COOKIE, cookie("bob"))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+    let response = server
+        .get("/gallery/download/family.zip")
+        .add_header(COOKIE, cookie("alice"))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert!(contains(response.as_bytes(), b"test_000.jpg"));
+    assert!(
+        response
+            .header("content-disposition")
+            .to_str()
+            .unwrap()
+            .contains("filename=\"family.zip\"")
+    );
+
+    let response = server
+        .get("/gallery/download/family")
+        .add_header(COOKIE, cookie("alice"))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_conditional_and_range_requests() {
+    use axum::http::header::{IF_NONE_MATCH, IF_RANGE, RANGE};
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_test_config(&temp_dir);
+    let photos_dir = config.galleries.as_ref().unwrap()[0]
+        .source_directory
+        .clone();
+    create_test_images(&photos_dir, 1);
+    let original = std::fs::read(photos_dir.join("test_000.jpg")).unwrap();
+
+    let app = create_app(config).await;
+    let server = TestServer::new(app).unwrap();
+
+    let response = server.get("/gallery/image/test_000.jpg").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert_eq!(response.header("accept-ranges"), "bytes");
+    assert!(response.maybe_header("last-modified").is_some());
+    let etag = response.header("etag");
+
+    let response = server
+        .get("/gallery/image/test_000.jpg")
+        .add_header(IF_NONE_MATCH, etag.clone())
+        .await;
+    assert_eq!(response.status_code(), StatusCode::NOT_MODIFIED);
+    assert!(response.as_bytes().is_empty());
+
+    // Resuming a download
+    let response = server
+        .get("/gallery/image/test_000.jpg")
+        .add_header(RANGE, "bytes=100-".parse().unwrap())
+        .add_header(IF_RANGE, etag.clone())
+        .await;
+    assert_eq!(response.status_code(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        response.header("content-range"),
+        format!("bytes 100-{}/{}", original.len() - 1, original.len()).as_str()
+    );
+    assert_eq!(response.as_bytes().as_ref(), &original[100..]);
+
+    // A stale If-Range gets the whole file
+    let response = server
+        .get("/gallery/image/test_000.jpg")
+        .add_header(RANGE, "bytes=100-".parse().unwrap())
+        .add_header(IF_RANGE, "\"stale\"".parse().unwrap())
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert_eq!(response.as_bytes().len(), original.len());
+
+    // Resized derivatives and static files share the same handling
+    let response = server
+        .get("/gallery/image/test_000.jpg?size=thumbnail")
+        .await;
+    let etag = response.header("etag");
+    let response = server
+        .get("/gallery/image/test_000.jpg?size=thumbnail")
+        .add_header(IF_NONE_MATCH, etag)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::NOT_MODIFIED);
+
+    let response = server
+        .get("/static/style.css")
+        .add_header(RANGE, "bytes=0-9".parse().unwrap())
+        .await;
+    assert_eq!(response.status_code(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.as_bytes().len(), 10);
+}
+
+#[tokio::test]
+async fn test_image_format_parameter() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_test_config(&temp_dir);
+    let photos_dir = config.galleries.as_ref().unwrap()[0]
+        .source_directory
+        .clone();
+    create_test_images(&photos_dir, 1);
+
+    let app = create_app(config).await;
+    let server = TestServer::new(app).unwrap();
+
+    // An explicit format wins over the Accept header
+    let response = server
+        .get("/gallery/image/test_000.jpg?size=thumbnail&format=webp")
+        .add_header(axum::http::header::ACCEPT, "image/jpeg".parse().unwrap())
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert_eq!(response.header("content-type"), "image/webp");
+
+    let response = server
+        .get("/gallery/image/test_000.jpg?size=thumbnail&format=jpeg")
+        .add_header(axum::http::header::ACCEPT, "image/webp".parse().unwrap())
+        .await;
+    assert_eq!(response.header("content-type"), "image/jpeg");
+
+    let response = server
+        .get("/gallery/image/test_000.jpg?size=thumbnail&format=tiff")
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_tag_pages_respect_folder_access() {
+    use axum::http::header::COOKIE;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_test_config(&temp_dir);
+    let photos_dir = config.galleries.as_ref().unwrap()[0]
+        .source_directory
+        .clone();
+    enable_logins(
+        &temp_dir,
+        &mut config,
+        r#"
+[users.alice]
+email = "alice@example.com"
+groups = ["family"]
+"#,
+    );
+
+    create_test_images(&photos_dir, 2);
+    std::fs::write(
+        photos_dir.join("test_000.md"),
+        "+++\ntags = [\"Sunset\", \"travel\"]\n+++\nEvening at the pier",
+    )
+    .unwrap();
+    std::fs::write(photos_dir.join("test_001.md"), "No front matter here").unwrap();
+
+    let family_dir = photos_dir.join("family");
+    std::fs::create_dir_all(&family_dir).unwrap();
+    std::fs::write(
+        family_dir.join("_folder.md"),
+        "+++\nallowed_groups = [\"family\"]\n+++\n",
+    )
+    .unwrap();
+    create_test_images(&family_dir, 1);
+    std::fs::write(
+        family_dir.join("test_000.md"),
+        "+++\ntags = [\"sunset\", \"Grandma\"]\n+++\n",
+    )
+    .unwrap();
+
+    let app = create_app(config).await;
+    let server = TestServer::new(app).unwrap();
+
+    // Anonymous visitors only see tags from public folders
+    let response = server.get("/gallery/tags").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let html = response.text();
+    assert!(html.contains("/gallery/tags/sunset"));
+    assert!(html.contains("/gallery/tags/travel"));
+    assert!(html.contains(r#"<span class="tag-count">1</span>"#));
+    assert!(!html.contains("Grandma"));
+
+    let response = server.get("/gallery/tags/grandma").await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    let response = server.get("/gallery/tags/nonexistent").await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+
+    // Tags are case-insensitive and render as a virtual folder
+    let response = server.get("/gallery/tags/SUNSET").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let html = response.text();
+    assert!(html.contains("Tagged \u{201C}Sunset\u{201D}"));
+    assert!(html.contains(r#""path":"test_000.jpg""#));
+    assert!(!html.contains("family/test_000.jpg"));
+    assert!(html.contains("?tag=SUNSET"));
+
+    // Members of the family group see both images
+    let response = server
+        .get("/gallery/tags")
+        .add_header(COOKIE, cookie("alice"))
+        .await;
+    let html = response.text();
+    assert!(html.contains(r#"<span class="tag-count">2</span>"#));
+    assert!(html.contains("Grandma"));
+
+    let response = server
+        .get("/gallery/tags/grandma")
+        .add_header(COOKIE, cookie("alice"))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+
+    // Prev/next on the detail page stays within the tag
+    let response = server
+        .get("/gallery/detail/test_000.jpg?tag=sunset")
+        .add_header(COOKIE, cookie("alice"))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let html = response.text();
+    assert!(html.contains("/gallery/detail/family/test_000.jpg?tag=sunset"));
+    assert!(!html.contains("/gallery/detail/test_001.jpg"));
+
+    // Front matter is not part of the caption
+    assert!(html.contains("Evening at the pier"));
+    assert!(!html.contains("tags = "));
+    assert!(html.contains(r#"href="/gallery/tags/travel""#));
+
+    // Without a tag, navigation follows the folder
+    let response = server.get("/gallery/detail/test_000.jpg").await;
+    let html = response.text();
+    assert!(html.contains("/gallery/detail/test_001.jpg"));
+}
+
+/// Save a small JPEG with GPS coordinates given as whole degrees and decimal
+/// minutes, e.g. (37, 46.4958, 'N')
+fn create_geotagged_image(
+    path: &std::path::Path,
+    latitude: (u32, f64, u8),
+    longitude: (u32, f64, u8),
+) {
+    use image::{DynamicImage, ImageBuffer, Rgb};
+
+    let mut jpeg = Vec::new();
+    DynamicImage::ImageRgb8(ImageBuffer::from_pixel(40, 30, Rgb([90u8, 120, 200])))
+        .write_to(
+            &mut std::io::Cursor::new(&mut jpeg),
+            image::ImageFormat::Jpeg,
+        )
+        .unwrap();
+
+    // Big-endian TIFF: IFD0 at 8 pointing to the GPS IFD at 26, whose
+    // rational values follow at 80
+    let entry = |tag: u16, kind: u16, count: u32, value: [u8; 4]| {
+        let mut entry = tag.to_be_bytes().to_vec();
+        entry.extend_from_slice(&kind.to_be_bytes());
+        entry.extend_from_slice(&count.to_be_bytes());
+        entry.extend_from_slice(&value);
+        entry
+    };
+    let rationals = |(degrees, minutes, _): (u32, f64, u8)| {
+        let mut data = Vec::new();
+        for (numerator, denominator) in [
+            (degrees, 1),
+            ((minutes * 10000.0).round() as u32, 10000),
+            (0, 1),
+        ] {
+            data.extend_from_slice(&u32::to_be_bytes(numerator));
+            data.extend_from_slice(&u32::to_be_bytes(denominator));
+        }
+        data
+    };
+
+    let mut tiff = b"MM\0\x2a".to_vec();
+    tiff.extend_from_slice(&8u32.to_be_bytes());
+    tiff.extend_from_slice(&1u16.to_be_bytes());
+    tiff.extend(entry(0x8825, 4, 1, 26u32.to_be_bytes()));
+    tiff.extend_from_slice(&0u32.to_be_bytes());
+    tiff.extend_from_slice(&4u16.to_be_bytes());
+    tiff.extend(entry(1, 2, 2, [latitude.2, 0, 0, 0]));
+    tiff.extend(entry(2, 5, 3, 80u32.to_be_bytes()));
+    tiff.extend(entry(3, 2, 2, [longitude.2, 0, 0, 0]));
+    tiff.extend(entry(4, 5, 3, 104u32.to_be_bytes()));
+    tiff.extend_from_slice(&0u32.to_be_bytes());
+    tiff.extend(rationals(latitude));
+    tiff.extend(rationals(longitude));
+
+    let mut payload = b"Exif\0\0".to_vec();
+    payload.extend(tiff);
+    let mut output = jpeg[..2].to_vec();
+    output.extend_from_slice(&[0xFF, 0xE1]);
+    output.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+    output.extend(payload);
+    output.extend_from_slice(&jpeg[2..]);
+    std::fs::write(path, output).unwrap();
+}
+
+#[tokio::test]
+async fn test_geojson_respects_access_and_location_precision() {
+    use axum::http::header::COOKIE;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_test_config(&temp_dir);
+    config.galleries.as_mut().unwrap()[0].location_precision_for_public =
+        tenrankai::LocationPrecision::City;
+    let photos_dir = config.galleries.as_ref().unwrap()[0]
+        .source_directory
+        .clone();
+    enable_logins(
+        &temp_dir,
+        &mut config,
+        r#"
+[users.alice]
+email = "alice@example.com"
+"#,
+    );
+
+    // San Francisco and Oakland, plus one image without a location
+    create_test_images(&photos_dir, 1);
+    create_geotagged_image(
+        &photos_dir.join("sf.jpg"),
+        (37, 46.4958, b'N'),
+        (122, 25.1650, b'W'),
+    );
+    let trip_dir = photos_dir.join("trip");
+    std::fs::create_dir_all(&trip_dir).unwrap();
+    create_geotagged_image(
+        &trip_dir.join("oakland.jpg"),
+        (37, 48.2640, b'N'),
+        (122, 16.2720, b'W'),
+    );
+    let private_dir = photos_dir.join("private");
+    std::fs::create_dir_all(&private_dir).unwrap();
+    std::fs::write(
+        private_dir.join("_folder.md"),
+        "+++\nrequire_auth = true\n+++\n",
+    )
+    .unwrap();
+    create_geotagged_image(
+        &private_dir.join("home.jpg"),
+        (37, 45.0, b'N'),
+        (122, 27.0, b'W'),
+    );
+
+    let app = create_app(config).await;
+    let server = TestServer::new(app).unwrap();
+
+    let coordinates = |json: &serde_json::Value, path: &str| -> Option<(f64, f64)> {
+        json["features"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find_map(|feature| {
+                (feature["properties"]["path"] == path).then(|| {
+                    let point = &feature["geometry"]["coordinates"];
+                    (point[0].as_f64().unwrap(), point[1].as_f64().unwrap())
+                })
+            })
+    };
+
+    // Anonymous visitors get city-level points from public folders only
+    let response = server.get("/api/gallery/main/geo").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let json = response.json::<serde_json::Value>();
+    assert_eq!(json["type"], "FeatureCollection");
+    assert_eq!(json["features"].as_array().unwrap().len(), 2);
+    assert_eq!(coordinates(&json, "sf.jpg"), Some((-122.4, 37.8)));
+    assert_eq!(coordinates(&json, "trip/oakland.jpg"), Some((-122.3, 37.8)));
+    assert_eq!(coordinates(&json, "private/home.jpg"), None);
+    let sf = json["features"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|feature| feature["properties"]["path"] == "sf.jpg")
+        .unwrap();
+    assert_eq!(
+        sf["properties"]["thumbnail_url"],
+        "/gallery/image/sf.jpg?size=thumbnail"
+    );
+    assert_eq!(sf["properties"]["detail_url"], "/gallery/detail/sf.jpg");
+
+    // Zoomed out, both public images fall into one cluster
+    let json = server
+        .get("/api/gallery/main/geo?zoom=3")
+        .await
+        .json::<serde_json::Value>();
+    let features = json["features"].as_array().unwrap();
+    assert_eq!(features.len(), 1);
+    assert_eq!(features[0]["properties"]["cluster"], true);
+    assert_eq!(features[0]["properties"]["point_count"], 2);
+    assert!(features[0]["properties"]["bbox"].is_array());
+
+    // Folder endpoints cover the folder and its subfolders
+    let json = server
+        .get("/api/gallery/main/geo/trip")
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(json["features"].as_array().unwrap().len(), 1);
+
+    let response = server.get("/api/gallery/main/geo/private").await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    let response = server.get("/api/gallery/main/geo/missing").await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+
+    // The same rounding applies to the image info API
+    let json = server
+        .get("/api/gallery/main/image/sf.jpg")
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(json["image"]["location_info"]["latitude"], 37.8);
+
+    // Signed-in users see exact locations, including the private folder
+    let json = server
+        .get("/api/gallery/main/geo")
+        .add_header(COOKIE, cookie("alice"))
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(json["features"].as_array().unwrap().len(), 3);
+    let (longitude, latitude) = coordinates(&json, "sf.jpg").unwrap();
+    assert!((latitude - 37.77493).abs() < 1e-4);
+    assert!((longitude + 122.41942).abs() < 1e-4);
+
+    // The folder page links to its map, which loads the folder's GeoJSON
+    let html = server.get("/gallery").await.text();
+    assert!(html.contains(r#"href="/gallery/map""#));
+    let response = server.get("/gallery/map/trip").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert!(response.text().contains("/api/gallery/main/geo/trip"));
+    let response = server.get("/gallery/map/private").await;
+    assert_eq!(response.status_code(), StatusCode::TEMPORARY_REDIRECT);
+}
+
+#[tokio::test]
+async fn test_map_link_follows_visitor_location_precision() {
+    use axum::http::header::COOKIE;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_test_config(&temp_dir);
+    config.galleries.as_mut().unwrap()[0].location_precision_for_public =
+        tenrankai::LocationPrecision::Hidden;
+    let photos_dir = config.galleries.as_ref().unwrap()[0]
+        .source_directory
+        .clone();
+    enable_logins(
+        &temp_dir,
+        &mut config,
+        r#"
+[users.alice]
+email = "alice@example.com"
+"#,
+    );
+    let trip_dir = photos_dir.join("road trip");
+    std::fs::create_dir_all(&trip_dir).unwrap();
+    create_geotagged_image(
+        &trip_dir.join("oakland.jpg"),
+        (37, 48.2640, b'N'),
+        (122, 16.2720, b'W'),
+    );
+
+    let app = create_app(config).await;
+    let server = TestServer::new(app).unwrap();
+
+    // Locations are hidden from the public, so they get no map link
+    let html = server.get("/gallery/road%20trip").await.text();
+    assert!(!html.contains("/gallery/map/"));
+
+    // Signed-in users see locations, and the folder path is encoded
+    let html = server
+        .get("/gallery/road%20trip")
+        .add_header(COOKIE, cookie("alice"))
+        .await
+        .text();
+    assert!(html.contains(r#"href="/gallery/map/road%20trip""#));
+    let html = server
+        .get("/gallery/map/road%20trip")
+        .add_header(COOKIE, cookie("alice"))
+        .await
+        .text();
+    assert!(html.contains("/api/gallery/main/geo/road%20trip"));
+}
+
+#[tokio::test]
+async fn test_original_download_metadata_policy() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_test_config(&temp_dir);
+    let photos_dir = config.galleries.as_ref().unwrap()[0]
+        .source_directory
+        .clone();
+    create_geotagged_image(
+        &photos_dir.join("sf.jpg"),
+        (37, 46.4958, b'N'),
+        (122, 25.1650, b'W'),
+    );
+    let source = std::fs::read(photos_dir.join("sf.jpg")).unwrap();
+
+    // Originals keep everything by default
+    let app = create_app(config.clone()).await;
+    let server = TestServer::new(app).unwrap();
+    let response = server.get("/gallery/image/sf.jpg").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert_eq!(response.as_bytes().to_vec(), source);
+
+    config.galleries.as_mut().unwrap()[0].metadata.originals = tenrankai::MetadataPolicy::StripGps;
+    let app = create_app(config).await;
+    let server = TestServer::new(app).unwrap();
+    let response = server.get("/gallery/image/sf.jpg").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert_eq!(response.header("content-type"), "image/jpeg");
+    let served = response.as_bytes().to_vec();
+    assert_ne!(served, source);
+    // The latitude's minutes are gone but the picture still decodes
+    let minutes = [464958u32.to_be_bytes(), 10000u32.to_be_bytes()].concat();
+    let contains_minutes = |data: &[u8]| data.windows(8).any(|window| window == minutes);
+    assert!(contains_minutes(&source));
+    assert!(!contains_minutes(&served));
+    assert_eq!(
+        image::load_from_memory(&served).unwrap().to_rgb8(),
+        image::load_from_memory(&source).unwrap().to_rgb8()
+    );
+}
+
+/// A minimal MP4: some media data followed by a `moov` box describing a
+/// 640x360 video track that runs for 83 seconds
+fn create_test_video(path: &std::path::Path) -> Vec<u8> {
+    let mp4_box = |kind: &[u8; 4], contents: &[u8]| {
+        let mut data = ((contents.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(contents);
+        data
+    };
+
+    let mut mvhd = vec![0; 12];
+    mvhd.extend_from_slice(&600u32.to_be_bytes());
+    mvhd.extend_from_slice(&(83 * 600u32).to_be_bytes());
+    mvhd.extend_from_slice(&[0; 80]);
+
+    let mut tkhd = vec![0; 40];
+    for value in [0x10000u32, 0, 0, 0, 0x10000, 0, 0, 0, 0x40000000] {
+        tkhd.extend_from_slice(&value.to_be_bytes());
+    }
+    tkhd.extend_from_slice(&(640u32 << 16).to_be_bytes());
+    tkhd.extend_from_slice(&(360u32 << 16).to_be_bytes());
+    let mut hdlr = vec![0; 8];
+    hdlr.extend_from_slice(b"vide");
+    hdlr.extend_from_slice(&[0; 13]);
+
+    let mut trak = mp4_box(b"tkhd", &tkhd);
+    trak.extend(mp4_box(b"mdia", &mp4_box(b"hdlr", &hdlr)));
+    let mut moov = mp4_box(b"mvhd", &mvhd);
+    moov.extend(mp4_box(b"trak", &trak));
+
+    let mut data = mp4_box(b"ftyp", b"isom\0\0\x02\0isommp41");
+    data.extend(mp4_box(b"mdat", &[0x5A; 2048]));
+    data.extend(mp4_box(b"moov", &moov));
+    std::fs::write(path, &data).unwrap();
+    data
+}
+
+#[tokio::test]
+async fn test_video_items() {
+    use axum::http::header::{COOKIE, RANGE};
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_test_config(&temp_dir);
+    let photos_dir = config.galleries.as_ref().unwrap()[0]
+        .source_directory
+        .clone();
+    // Originals need a login, and a video is its original
+    enable_logins(
+        &temp_dir,
+        &mut config,
+        "[users.alice]\nemail = \"alice@example.com\"\n",
+    );
+    create_test_images(&photos_dir, 1);
+    let video = create_test_video(&photos_dir.join("clip.mp4"));
+
+    let app = create_app(config).await;
+    let server = TestServer::new(app).unwrap();
+
+    let response = server.get("/api/gallery/main/list").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let json = response.json::<serde_json::Value>();
+    let images = json["images"].as_array().unwrap();
+    assert_eq!(images.len(), 2);
+    let clip = images.iter().find(|i| i["path"] == "clip.mp4").unwrap();
+    assert_eq!(clip["dimensions"][0], 640);
+    assert_eq!(clip["video"]["duration"], "1:23");
+    let photo = images.iter().find(|i| i["path"] == "test_000.jpg").unwrap();
+    assert!(photo["video"].is_null());
+
+    let response = server.get("/gallery/detail/clip.mp4").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let html = response.text();
+    assert!(html.contains("<video"));
+    assert!(html.contains("poster=\"/gallery/image/clip.mp4?size=medium\""));
+    assert!(html.contains("<source src=\"/gallery/image/clip.mp4\" type=\"video/mp4\">"));
+
+    // The poster stands in for every size
+    let response = server.get("/gallery/image/clip.mp4?size=thumbnail").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert_eq!(response.header("content-type"), "image/jpeg");
+    assert!(image::load_from_memory(response.as_bytes()).is_ok());
+
+    let response = server.get("/gallery/image/clip.mp4").await;
+    assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+    let response = server.get("/gallery/image/test_000.jpg").await;
+    assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+
+    // Resized archives leave out videos the visitor can't download
+    let response = server.get("/gallery/download/.zip?size=thumbnail").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let archive = response.as_bytes().to_vec();
+    assert!(archive.windows(12).any(|name| name == b"test_000.jpg"));
+    assert!(!archive.windows(8).any(|name| name == b"clip.mp4"));
+
+    // Browsers seek by requesting ranges of the original
+    let response = server
+        .get("/gallery/image/clip.mp4")
+        .add_header(COOKIE, cookie("alice"))
+        .add_header(RANGE, "bytes=0-1023".parse().unwrap())
+        .await;
+    assert_eq!(response.status_code(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.header("content-type"), "video/mp4");
+    assert_eq!(response.as_bytes().as_ref(), &video[..1024]);
+}
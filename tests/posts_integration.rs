@@ -131,6 +131,10 @@ This is the content of the second test post."#;
             cookie_secret: "test-cookie-secret".to_string(),
             base_url: Some("http://localhost:3000".to_string()),
             user_database: None,
+            session_store: None,
+            session_lifetime_days: 7,
+            share_store: None,
+            image_workers: None,
         },
         templates: tenrankai::TemplateConfig {
             directories: vec![templates_dir],
@@ -171,9 +175,17 @@ This is the content of the second test post."#;
             jpeg_quality: Some(85),
             webp_quality: Some(85.0),
             pregenerate_cache: false,
+            pregenerate_parallelism: None,
             new_threshold_days: None,
             approximate_dates_for_public: false,
+            location_precision: Default::default(),
+            location_precision_for_public: Default::default(),
+            metadata: Default::default(),
+            ffmpeg_path: None,
+            max_cache_size: None,
             copyright_holder: None,
+            watch_filesystem: false,
+            sizes: Default::default(),
         }]),
         posts: Some(vec![PostsSystemConfig {
             name: "blog".to_string(),
@@ -183,6 +195,7 @@ This is the content of the second test post."#;
             post_template: "modules/post_detail.html.liquid".to_string(),
             posts_per_page: 10,
             refresh_interval_minutes: None,
+            watch_filesystem: false,
         }]),
         email: None,
     };
@@ -354,3 +367,31 @@ This is a tutorial in a subdirectory."#;
     assert!(html.contains("Rust Tutorial"));
     assert!(html.contains("This is a tutorial in a subdirectory"));
 }
+
+#[tokio::test]
+async fn test_posts_feeds() {
+    let (_temp_dir, server) = setup_test_server_with_posts().await;
+
+    let response = server.get("/blog/feed.xml").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert!(
+        response
+            .header("content-type")
+            .to_str()
+            .unwrap()
+            .starts_with("application/atom+xml")
+    );
+    let atom = response.text();
+    assert!(atom.contains("<title>Second Test Post</title>"));
+    assert!(atom.contains("<id>http://localhost:3000/blog/first-post</id>"));
+    assert!(atom.contains("<link rel=\"self\" type=\"application/atom+xml\" href=\"http://localhost:3000/blog/feed.xml\"/>"));
+    // Newest post first
+    assert!(atom.find("Second Test Post").unwrap() < atom.find("First Test Post").unwrap());
+
+    let response = server.get("/blog/rss.xml").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let rss = response.text();
+    assert!(rss.contains("<rss version=\"2.0\""));
+    assert!(rss.contains("<link>http://localhost:3000/blog/second-post</link>"));
+    assert!(rss.contains("<pubDate>Tue, 2 Jan 2024 00:00:00 +0000</pubDate>"));
+}
@@ -73,6 +73,10 @@ async fn setup_test_server() -> (TempDir, TestServer) {
             cookie_secret: "test-cookie-secret".to_string(),
             base_url: Some("http://localhost:3000".to_string()),
             user_database: None,
+            session_store: None,
+            session_lifetime_days: 7,
+            share_store: None,
+            image_workers: None,
         },
         templates: tenrankai::TemplateConfig {
             directories: vec![templates_dir],
@@ -113,9 +117,17 @@ async fn setup_test_server() -> (TempDir, TestServer) {
             jpeg_quality: Some(85),
             webp_quality: Some(85.0),
             pregenerate_cache: false,
+            pregenerate_parallelism: None,
             new_threshold_days: None,
             approximate_dates_for_public: false,
+            location_precision: Default::default(),
+            location_precision_for_public: Default::default(),
+            metadata: Default::default(),
+            ffmpeg_path: None,
+            max_cache_size: None,
             copyright_holder: None,
+            watch_filesystem: false,
+            sizes: Default::default(),
         }]),
         posts: None,
         email: None,